@@ -0,0 +1,17 @@
+// build.rs
+//! 把 build.sh 从上一次链接结果中导出的符号表嵌入内核。
+//! 没有设置 KSYMS_FILE 时嵌入空表，panic 回溯只打印地址。
+
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("ksyms.txt");
+    println!("cargo:rerun-if-env-changed=KSYMS_FILE");
+    match env::var("KSYMS_FILE") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            fs::copy(&path, &out).expect("failed to copy KSYMS_FILE");
+        }
+        Err(_) => fs::write(&out, b"").unwrap(),
+    }
+}
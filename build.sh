@@ -0,0 +1,31 @@
+#!/bin/sh
+# 两遍构建：链接出内核后用 nm 导出函数符号，再重新构建把符号表嵌入 .ksyms 段。
+# .ksyms 位于所有代码之后，符号表大小变化不会移动函数地址，所以两遍的地址一致；
+# 脚本最后会再导出一次核对，不一致时重新嵌入。
+
+KERNEL="target/riscv64gc-unknown-none-elf/release/charlotte_os"
+KSYMS="target/ksyms.txt"
+
+gen_ksyms() {
+    # 只保留代码段符号（跳过 .L 局部标号和 $x 映射符号），去掉 Rust 旧式修饰名末尾的哈希
+    nm -n -C --defined-only "$KERNEL" |
+        awk '$2 ~ /^[tTwW]$/ && $3 !~ /^(\.L|\$)/ { addr = $1; $1 = ""; $2 = ""; sub(/^  /, ""); print addr, $0 }' |
+        sed 's/::h[0-9a-f]\{16\}$//' > "$1"
+}
+
+# 沿用上次导出的符号表，代码没变时一遍即可收敛
+mkdir -p target
+[ -f "$KSYMS" ] || : > "$KSYMS"
+
+for _ in 1 2 3; do
+    KSYMS_FILE="$(pwd)/$KSYMS" cargo build --release || exit 1
+    gen_ksyms "$KSYMS.check"
+    if cmp -s "$KSYMS" "$KSYMS.check"; then
+        rm -f "$KSYMS.check"
+        exit 0
+    fi
+    mv "$KSYMS.check" "$KSYMS"
+done
+
+echo "符号表未能收敛"
+exit 1
@@ -0,0 +1,463 @@
+use crate::PhysMapper;
+use crate::address::{PAGE_SIZE_BITS, PhysAddr, PhysPageNum};
+use core::num::NonZeroUsize;
+use core::ptr::{NonNull, write_volatile};
+
+pub const MAX_ORDER: usize = 16; // 阶数范围是 0..15，共 16 个
+pub const MAX_SHRINKERS: usize = 4;
+
+/// 内存回收回调：分配失败时被调用，回调直接把可回收的页 dealloc 回传入的分配器，
+/// 返回实际回收的页数。回调运行时分配器锁已被持有，所以不能再去锁 BUDDY_ALLOCATOR，
+/// 也不能分配或释放堆内存：SLUB 可能正持有 cache 锁等这次分配，释放对象又可能把空 slab 还给分配器
+pub type Shrinker<M> = fn(&mut BuddySystemFrameAllocator<M>, NonZeroUsize) -> usize;
+#[repr(C)]
+struct ListNode {
+    next: Option<NonNull<ListNode>>,
+}
+
+/// 伙伴系统分配器。空闲链表节点直接写在空闲页里，通过 mapper 访问
+pub struct BuddySystemFrameAllocator<M> {
+    free_lists: [Option<NonNull<ListNode>>; MAX_ORDER], // 按2的幂次管理空闲链表
+    shrinkers: [Option<Shrinker<M>>; MAX_SHRINKERS],    // 内存压力下的回收回调
+    mapper: M,
+}
+impl<M: PhysMapper> BuddySystemFrameAllocator<M> {
+    /// 创建一个空的、未初始化的分配器
+    pub const fn new(mapper: M) -> Self {
+        Self {
+            free_lists: [None; MAX_ORDER],
+            shrinkers: [None; MAX_SHRINKERS],
+            mapper,
+        }
+    }
+
+    fn phys_to_virt(&self, pa: usize) -> usize {
+        self.mapper.phys_to_virt(pa)
+    }
+
+    /// 注册内存回收回调，槽位已满时返回 false
+    pub fn register_shrinker(&mut self, shrinker: Shrinker<M>) -> bool {
+        match self.shrinkers.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(shrinker);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 初始化分配器
+    /// pa_start 和 pa_end 必须是页对齐的
+    ///
+    /// # Safety
+    /// 区间内的物理页必须未被使用，且在分配器存活期间一直可以访问
+    pub unsafe fn add_free_region(&mut self, align_start_ppn: usize, align_end_ppn: usize) {
+        // let start_addr = PhysAddr(pa_start);
+        // let end_addr = PhysAddr(pa_end);
+        // start 必须向上取整 (ceil)，因为如果不满一页，那半页不能用
+        // let align_start_ppn = start_addr.ceil().0;
+        // // end 必须向下取整 (floor)，防止越界到非法的内存去
+        // let align_end_ppn = end_addr.floor().0;
+
+        if align_start_ppn >= align_end_ppn {
+            return; // 这块碎片太小了，连一页 4KB 都凑不齐，直接丢弃
+        }
+
+        let mut current_ppn = align_start_ppn;
+        while current_ppn < align_end_ppn {
+            let remaining_pages = align_end_ppn - current_ppn;
+            if remaining_pages == 0 {
+                break;
+            }
+
+            let max_order_by_remaining = remaining_pages.ilog2() as usize;
+            let max_order_by_alignment = if current_ppn == 0 {
+                MAX_ORDER - 1
+            } else {
+                (current_ppn.trailing_zeros() as usize).min(MAX_ORDER - 1)
+            };
+            let order = max_order_by_remaining
+                .min(max_order_by_alignment)
+                .min(MAX_ORDER - 1);
+            let block_pages = 1usize << order;
+            let block_addr = PhysAddr::from(&PhysPageNum(current_ppn));
+
+            unsafe {
+                self.add_free_block(block_addr.0, order);
+            }
+            current_ppn += block_pages;
+        }
+    }
+
+    /// 分配内存，空闲块不足时依次调用回收回调，然后再尝试一次
+    pub fn alloc(&mut self, pages: NonZeroUsize) -> Option<PhysPageNum> {
+        if let Some(ppn) = self.alloc_block(pages) {
+            return Some(ppn);
+        }
+        let shrinkers = self.shrinkers;
+        let mut reclaimed = 0;
+        for shrinker in shrinkers.iter().flatten() {
+            reclaimed += shrinker(self, pages);
+            if reclaimed >= pages.get() {
+                break;
+            }
+        }
+        if reclaimed == 0 {
+            return None;
+        }
+        self.alloc_block(pages)
+    }
+
+    fn alloc_block(&mut self, pages: NonZeroUsize) -> Option<PhysPageNum> {
+        // ... 伙伴系统的核心分配逻辑 ...
+        // 1. 根据请求大小，计算需要的块大小 (2的幂次) 和对应的阶 (order)。
+        // 2. 在对应阶的空闲链表中查找可用块。
+        // 3. 如果找不到，就去更高阶的链表中找，然后进行分裂。
+        // 4. 分裂出的多余“伙伴”块，放入对应低阶的空闲链表中。
+        // 5. 返回找到的块的指针。
+
+        let required_order = pages_to_order(pages);
+
+        if required_order >= MAX_ORDER {
+            return None; // 请求过大
+        }
+
+        // 2. 寻找一个合适的空闲块，从需要的阶开始，向上查找
+        let mut order = required_order;
+        while order < MAX_ORDER {
+            // 如果当前阶的空闲链表不为空，我们就找到了
+            if self.free_lists[order].is_some() {
+                // --- 找到了足够大的块，开始处理 ---
+
+                // a. 从链表中移除这个块
+                let block_pa = self.free_lists[order].take().unwrap();
+                let block_va = self.phys_to_virt(block_pa.as_ptr() as usize) as *mut ListNode;
+                unsafe {
+                    // 将链表头更新为下一个节点
+                    self.free_lists[order] = (*block_va).next.take();
+                }
+
+                // b. 开始循环分裂，直到块的大小刚刚好
+                let mut current_order = order;
+                let block_addr = block_pa.as_ptr() as usize;
+                while current_order > required_order {
+                    // 计算分裂后的伙伴块的地址和大小
+                    let current_block_size = 1usize << (current_order + PAGE_SIZE_BITS);
+                    let buddy_block_size = current_block_size / 2;
+
+                    let buddy_addr = block_addr + buddy_block_size;
+
+                    // 将分裂出的伙伴块加回到系统中
+                    unsafe {
+                        self.add_free_block(buddy_addr, current_order - 1);
+                    }
+
+                    current_order -= 1;
+                }
+                // println!(
+                //     "the allocated block at 0x{:x} with size {} ({} KB, {} MB)",
+                //     block.as_ptr() as usize,
+                //     required_size,
+                //     required_size / 1024,
+                //     required_size / 1024 / 1024
+                // );
+                // c. 返回最终大小合适的块
+                return Some(PhysAddr(block_addr).into());
+            }
+
+            // 如果当前阶为空，就去更高一阶查找
+            order += 1;
+        }
+
+        // 如果所有阶都找遍了还是没有，说明内存不足
+        None
+    }
+
+    /// 统计每一阶空闲链表上的块数
+    pub fn free_blocks_per_order(&self) -> [usize; MAX_ORDER] {
+        let mut counts = [0; MAX_ORDER];
+        for (order, head) in self.free_lists.iter().enumerate() {
+            let mut current = *head;
+            while let Some(node) = current {
+                counts[order] += 1;
+                let node_va = self.phys_to_virt(node.as_ptr() as usize) as *const ListNode;
+                current = unsafe { (*node_va).next };
+            }
+        }
+        counts
+    }
+
+    /// 释放内存
+    pub fn dealloc(&mut self, ppn: PhysPageNum, pages: NonZeroUsize) {
+        // ... 伙伴系统的核心释放逻辑 ...
+        // 1. 根据释放的地址和大小，计算其阶 (order)。
+        // 2. 将其放入对应阶的空闲链表。
+        // 3. 循环检查：它的“伙伴”块是否也在空闲链表中。
+        // 4. 如果伙伴也空闲，就将两者合并成一个更大的块，放入更高阶的链表中，并继续向上检查合并。
+        // if pages == 0 {
+        //     return;
+        // }
+
+        let order = pages_to_order(pages);
+
+        // 我们实际分配的块大小
+        let mut block_size = 1usize << (order + PAGE_SIZE_BITS);
+        let mut block_addr = PhysAddr::from(&ppn).0;
+
+        // 2. 开始循环，尝试与伙伴合并
+        let mut current_order = order;
+        while current_order < MAX_ORDER - 1 {
+            let buddy_addr = block_addr ^ block_size;
+
+            // 现在，我们只调用一次辅助函数，它完成了查找和移除两个任务
+            if self.try_remove_from_list(buddy_addr, current_order) {
+                // 如果成功移除了伙伴，则进行合并
+                block_addr = block_addr.min(buddy_addr);
+                block_size *= 2;
+                current_order += 1;
+            } else {
+                // 如果没有找到空闲的伙伴，就停止合并
+                break;
+            }
+        }
+
+        unsafe {
+            self.add_free_block(block_addr, current_order);
+        }
+    }
+
+    /// 辅助函数，用于将空闲块添加到链表
+    ///
+    /// # Safety
+    /// phys_addr 必须按 order 对齐，且这块内存未被使用、不在任何空闲链表中
+    pub unsafe fn add_free_block(&mut self, phys_addr: usize, order: usize) {
+        // --- 1. 计算阶 (Order) ---
+        // size.trailing_zeros() 是一个计算 log2(size) 的高效方法
+        // 例如 4096 (2^12) 的 trailing_zeros 就是 12
+        // 假设我们的 order 0 对应 4KB (2^12 字节)，所以需要减去 12
+        // let order = size.trailing_zeros() as usize - PAGE_SIZE_BITS; // PAGE_SIZE 是 4KB (2^12)
+        if order >= MAX_ORDER {
+            // 如果计算出的阶超出了我们的管理范围，进行处理
+            panic!("Requested size is too large for buddy system allocator");
+        }
+        // --- 2. 链表头插法 ---
+        // a. 读取当前阶的链表头
+        let old_head = self.free_lists[order].take(); // .take() 会取出 Some(T) 并留下 None
+
+        // b. 将新节点的 next 指向旧的头节点
+        // (*new_node_ptr).next = old_head;
+        // 使用 write_volatile 更能体现底层操作的意图
+        unsafe {
+            let new_node_pa = phys_addr as *mut ListNode;
+            let new_node_va = self.phys_to_virt(new_node_pa as usize) as *mut ListNode;
+            write_volatile(&mut (*new_node_va).next, old_head);
+
+            // c. 更新链表头为新节点
+            //    NonNull::new_unchecked 假设 ptr 永不为 null，在这里是安全的
+            self.free_lists[order] = Some(NonNull::new_unchecked(new_node_pa));
+        }
+    }
+
+    fn try_remove_from_list(&mut self, phys_addr: usize, order: usize) -> bool {
+        let list_head = match self.free_lists[order] {
+            Some(head) => head,
+            None => return false, // 链表为空，直接返回
+        };
+        unsafe {
+            // Case 1: 要移除的块就是头节点
+            if list_head.as_ptr() as usize == phys_addr {
+                let list_head_va = self.phys_to_virt(list_head.as_ptr() as usize) as *mut ListNode;
+                self.free_lists[order] = (*list_head_va).next.take();
+                return true;
+            }
+
+            // Case 2: 遍历链表查找
+            let current = list_head;
+            let mut current_va = self.phys_to_virt(current.as_ptr() as usize) as *mut ListNode;
+            while let Some(next_node) = (*current_va).next {
+                let next_node_va = self.phys_to_virt(next_node.as_ptr() as usize) as *mut ListNode;
+                if next_node.as_ptr() as usize == phys_addr {
+                    // 找到了，让当前节点的 next 直接指向下一个节点的 next
+                    (*current_va).next = (*next_node_va).next.take();
+                    return true;
+                }
+                current_va = next_node_va;
+            }
+        }
+        // 遍历完整个链表都没找到
+        false
+    }
+}
+/// 辅助函数：根据请求的页数计算出需要的阶
+fn pages_to_order(pages: NonZeroUsize) -> usize {
+    // if pages == 0 {
+    //     return 0;
+    // }
+    let power_of_2_pages = pages.get().next_power_of_two();
+    power_of_2_pages.trailing_zeros() as usize
+}
+unsafe impl<M: Send> Send for BuddySystemFrameAllocator<M> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Arena, ArenaMapper, Rng};
+
+    const ARENA_PAGES: usize = 1024;
+
+    fn allocator(arena: &Arena) -> BuddySystemFrameAllocator<ArenaMapper> {
+        let mut buddy = BuddySystemFrameAllocator::new(arena.mapper());
+        unsafe { buddy.add_free_region(arena.start_ppn(), arena.end_ppn()) };
+        buddy
+    }
+
+    fn free_pages(buddy: &BuddySystemFrameAllocator<ArenaMapper>) -> usize {
+        buddy
+            .free_blocks_per_order()
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum()
+    }
+
+    fn pages(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    #[test]
+    fn unaligned_region_is_split_by_alignment() {
+        let arena = Arena::new(ARENA_PAGES);
+        let mut buddy = BuddySystemFrameAllocator::new(arena.mapper());
+        let start = arena.start_ppn() + 3;
+        let end = arena.end_ppn() - 5;
+        unsafe { buddy.add_free_region(start, end) };
+        assert_eq!(free_pages(&buddy), end - start);
+
+        // 每个块都必须按自身大小对齐
+        let mut seen = Vec::new();
+        for order in (0..MAX_ORDER).rev() {
+            while buddy.free_blocks_per_order()[order] > 0 {
+                let ppn = buddy.alloc_block(pages(1 << order)).unwrap();
+                assert_eq!(ppn.0 % (1 << order), 0, "order {order} block misaligned");
+                assert!(ppn.0 >= start && ppn.0 + (1 << order) <= end);
+                seen.push((ppn, order));
+            }
+        }
+        for (ppn, order) in seen {
+            buddy.dealloc(ppn, pages(1 << order));
+        }
+        assert_eq!(free_pages(&buddy), end - start);
+    }
+
+    #[test]
+    fn single_page_frees_merge_back() {
+        let arena = Arena::new(ARENA_PAGES);
+        let mut buddy = allocator(&arena);
+        let initial = buddy.free_blocks_per_order();
+
+        let frames: Vec<_> = (0..ARENA_PAGES)
+            .map(|_| buddy.alloc(pages(1)).unwrap())
+            .collect();
+        assert!(buddy.alloc(pages(1)).is_none());
+        // 倒序释放，每对伙伴都在后一次释放时才合并
+        for ppn in frames.into_iter().rev() {
+            buddy.dealloc(ppn, pages(1));
+        }
+        assert_eq!(buddy.free_blocks_per_order(), initial);
+    }
+
+    /// 随机分配/释放，检查对齐、不越界、不重叠、内容不被破坏，全部释放后空闲链表复原
+    #[test]
+    fn random_alloc_free_keeps_invariants() {
+        for seed in 0..32 {
+            let arena = Arena::new(ARENA_PAGES);
+            let mapper = arena.mapper();
+            let mut buddy = allocator(&arena);
+            let initial = buddy.free_blocks_per_order();
+            let mut rng = Rng::new(seed);
+            let mut owner = vec![None; ARENA_PAGES];
+            let mut live: Vec<(PhysPageNum, usize, u8)> = Vec::new();
+
+            for step in 0..2000 {
+                if live.is_empty() || rng.chance(55) {
+                    let max = 1 << rng.below(6);
+                    let n = 1 + rng.below(max);
+                    let Some(ppn) = buddy.alloc(pages(n)) else {
+                        continue;
+                    };
+                    let block = n.next_power_of_two();
+                    assert_eq!(ppn.0 % block, 0, "seed {seed}: misaligned block");
+                    let first = ppn.0 - arena.start_ppn();
+                    assert!(first + block <= ARENA_PAGES, "seed {seed}: out of arena");
+                    for (page, slot) in owner.iter_mut().enumerate().skip(first).take(block) {
+                        assert!(slot.is_none(), "seed {seed}: page {page} handed out twice");
+                        *slot = Some(step);
+                    }
+                    // 写满标记字节，释放前校验，确认空闲链表节点没有写进已分配的块
+                    let tag = step as u8;
+                    let va = mapper.phys_to_virt(PhysAddr::from(&ppn).0);
+                    unsafe { core::ptr::write_bytes(va as *mut u8, tag, block << PAGE_SIZE_BITS) };
+                    live.push((ppn, n, tag));
+                } else {
+                    let (ppn, n, tag) = live.swap_remove(rng.below(live.len()));
+                    let block = n.next_power_of_two();
+                    let va = mapper.phys_to_virt(PhysAddr::from(&ppn).0);
+                    let bytes = unsafe {
+                        core::slice::from_raw_parts(va as *const u8, block << PAGE_SIZE_BITS)
+                    };
+                    assert!(
+                        bytes.iter().all(|&b| b == tag),
+                        "seed {seed}: block corrupted"
+                    );
+                    let first = ppn.0 - arena.start_ppn();
+                    owner[first..first + block].fill(None);
+                    buddy.dealloc(ppn, pages(n));
+                }
+                let used: usize = owner.iter().filter(|o| o.is_some()).count();
+                assert_eq!(
+                    free_pages(&buddy) + used,
+                    ARENA_PAGES,
+                    "seed {seed}: pages leaked"
+                );
+            }
+
+            for (ppn, n, _) in live {
+                buddy.dealloc(ppn, pages(n));
+            }
+            assert_eq!(
+                buddy.free_blocks_per_order(),
+                initial,
+                "seed {seed}: not fully merged"
+            );
+        }
+    }
+
+    #[test]
+    fn shrinker_runs_on_exhaustion() {
+        use std::cell::Cell;
+
+        thread_local! {
+            static HELD: Cell<Option<PhysPageNum>> = const { Cell::new(None) };
+        }
+        fn give_back(buddy: &mut BuddySystemFrameAllocator<ArenaMapper>, _: NonZeroUsize) -> usize {
+            match HELD.take() {
+                Some(ppn) => {
+                    buddy.dealloc(ppn, NonZeroUsize::new(1).unwrap());
+                    1
+                }
+                None => 0,
+            }
+        }
+
+        let arena = Arena::new(4);
+        let mut buddy = allocator(&arena);
+        assert!(buddy.register_shrinker(give_back));
+        let frames: Vec<_> = (0..4).map(|_| buddy.alloc(pages(1)).unwrap()).collect();
+        assert!(buddy.alloc(pages(1)).is_none());
+
+        HELD.set(Some(frames[2]));
+        assert_eq!(buddy.alloc(pages(1)), Some(frames[2]));
+        assert!(HELD.get().is_none());
+        assert!(buddy.alloc(pages(1)).is_none());
+    }
+}
@@ -0,0 +1,23 @@
+//! Charlotte OS 中与硬件无关的分配器和数据结构。
+//!
+//! 这里的代码不直接访问物理内存，而是通过 [`PhysMapper`] 把物理地址换成可以读写的地址：
+//! 内核里是固定偏移的线性映射，宿主机上的 `cargo test` 则用一块普通内存模拟物理内存。
+//! 同一份代码既链接进内核，也能在开发机上做随机化测试。
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod address;
+pub mod buddy;
+pub mod memblock;
+pub mod page;
+pub mod ring_buf;
+pub mod slub;
+
+#[cfg(test)]
+mod test_support;
+
+/// 物理地址与当前地址空间中可访问地址之间的转换
+pub trait PhysMapper {
+    fn phys_to_virt(&self, pa: usize) -> usize;
+    fn virt_to_phys(&self, va: usize) -> usize;
+}
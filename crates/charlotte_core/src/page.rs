@@ -0,0 +1,26 @@
+//! 物理页描述符，内核为每个物理页保存一个（MEM_MAP）。
+
+use crate::address::{PhysPageNum, VirtAddr};
+
+pub enum Slab {
+    Slub {
+        freelist: VirtAddr,
+        inuse: u16,
+        has_next: bool,
+        next_partial: PhysPageNum,
+    },
+    SlubTail {
+        head_page_ppn: PhysPageNum,
+    },
+}
+pub enum PageState {
+    Reserved,
+    Slab(Slab),
+    Free,
+    PageTable,
+}
+
+pub struct Page {
+    pub ref_count: u8,
+    pub state: PageState,
+}
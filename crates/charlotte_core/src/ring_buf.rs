@@ -0,0 +1,132 @@
+use core::mem::MaybeUninit;
+
+pub struct RingBuffer<T, const N: usize> {
+    buffer: [MaybeUninit<T>; N],
+    head: usize,
+    tail: usize,
+    len: usize,
+}
+impl<T, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [const { MaybeUninit::uninit() }; N],
+            head: 0,
+            tail: 0,
+            len: 0,
+        }
+    }
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.len == N {
+            Err(item)
+        } else {
+            self.buffer[self.tail].write(item);
+            self.tail = (self.tail + 1) % N;
+            self.len += 1;
+            Ok(())
+        }
+    }
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let old_head = self.head;
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        unsafe {
+            let value = self.buffer[old_head].assume_init_read();
+            Some(value)
+        }
+    }
+    /// 查看队首元素但不取出
+    pub fn peek(&self) -> Option<&T> {
+        if self.len == 0 {
+            return None;
+        }
+        unsafe { Some(self.buffer[self.head].assume_init_ref()) }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+    pub fn capacity(&self) -> usize {
+        N
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+    use crate::test_support::Rng;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn push_pop_is_fifo() {
+        let mut ring: RingBuffer<u32, 4> = RingBuffer::new();
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.peek(), None);
+        for i in 0..4 {
+            assert!(ring.push(i).is_ok());
+        }
+        assert!(ring.is_full());
+        assert_eq!(ring.push(4), Err(4));
+        for i in 0..4 {
+            assert_eq!(ring.peek(), Some(&i));
+            assert_eq!(ring.pop(), Some(i));
+        }
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut ring: RingBuffer<usize, 3> = RingBuffer::new();
+        for i in 0..10 {
+            assert!(ring.push(i).is_ok());
+            if i % 2 == 1 {
+                assert!(ring.push(i + 100).is_ok());
+                assert_eq!(ring.pop(), Some(i - 1));
+                assert_eq!(ring.pop(), Some(i));
+                assert_eq!(ring.pop(), Some(i + 100));
+            }
+        }
+        assert_eq!(ring.len(), 0);
+        assert_eq!(ring.capacity(), 3);
+    }
+
+    /// 与容量受限的 VecDeque 对比随机的 push/pop 序列
+    #[test]
+    fn random_ops_match_vecdeque() {
+        for seed in 0..32 {
+            let mut rng = Rng::new(seed);
+            let mut ring: RingBuffer<u64, 7> = RingBuffer::new();
+            let mut model = VecDeque::new();
+            for _ in 0..1000 {
+                if rng.chance(50) {
+                    let value = rng.next_u64();
+                    let expected = if model.len() < 7 {
+                        model.push_back(value);
+                        Ok(())
+                    } else {
+                        Err(value)
+                    };
+                    assert_eq!(ring.push(value), expected, "seed {seed}");
+                } else {
+                    assert_eq!(ring.pop(), model.pop_front(), "seed {seed}");
+                }
+                assert_eq!(ring.len(), model.len());
+                assert_eq!(ring.is_full(), model.len() == 7);
+            }
+        }
+    }
+}
@@ -0,0 +1,551 @@
+//! SLUB 对象缓存：每个 KmemCache 管理一种大小的对象，按 slab 向页分配器批发整页。
+//! 页的申请、释放和页描述符的查找都交给 [`SlabBackend`]。
+
+use core::{num::NonZeroUsize, ptr::NonNull, ptr::write_volatile};
+
+use crate::{
+    PhysMapper,
+    address::{PAGE_SIZE, PAGE_SIZE_BITS, PhysAddr, PhysPageNum, VirtAddr},
+    page::{Page, PageState, Slab},
+};
+
+/// KmemCache 依赖的页分配器和页描述符表
+pub trait SlabBackend: PhysMapper {
+    /// 申请 pages 个连续物理页，pages 是 2 的幂
+    fn alloc_pages(&self, pages: NonZeroUsize) -> Option<PhysPageNum>;
+    fn free_pages(&self, ppn: PhysPageNum, pages: NonZeroUsize);
+    /// ppn 对应的页描述符
+    fn page(&self, ppn: PhysPageNum) -> NonNull<Page>;
+}
+
+pub struct KmemCache<B> {
+    pub object_size: usize,
+    pub active_slab_ppn: Option<PhysPageNum>,
+    pub partial_slabs_head: Option<PhysPageNum>,
+    pub nr_slabs: usize,
+    pub nr_inuse: usize,
+    backend: B,
+}
+
+impl<B: SlabBackend> KmemCache<B> {
+    pub const fn new(requested_size: usize, backend: B) -> Self {
+        // 获取当前机器硬件字长
+        let align = core::mem::size_of::<usize>();
+        let min_size = align;
+
+        // 向上取整到字长的倍数
+        let mut actual_size = (requested_size + align - 1) & !(align - 1);
+        if actual_size < min_size {
+            actual_size = min_size;
+        }
+
+        Self {
+            object_size: actual_size,
+            active_slab_ppn: None,
+            partial_slabs_head: None,
+            nr_slabs: 0,
+            nr_inuse: 0,
+            backend,
+        }
+    }
+
+    /// 页描述符的生命周期与 slab 无关，由 backend 保证在分配器存活期间有效
+    fn page(&self, ppn: PhysPageNum) -> &'static mut Page {
+        unsafe { &mut *self.backend.page(ppn).as_ptr() }
+    }
+
+    /// 每个 slab 向 Buddy 申请的页数
+    pub fn pages_per_slab(&self) -> usize {
+        let required_pages = (self.object_size + PAGE_SIZE - 1) >> PAGE_SIZE_BITS;
+        required_pages.max(1).next_power_of_two()
+    }
+
+    fn alloc_new_slab(&mut self) -> Option<PhysPageNum> {
+        if self.object_size < PAGE_SIZE {
+            let ppn = self.backend.alloc_pages(NonZeroUsize::new(1).unwrap())?;
+            // 将slab结构分割成对应大小的node节点
+            let start_va = self.backend.phys_to_virt(PhysAddr::from(&ppn).0);
+            let mut va = start_va;
+            let end = va + PAGE_SIZE;
+
+            // 只串起完整落在页内的对象，页尾放不下一个对象的零头不用
+            while va + self.object_size <= end {
+                let memory_block = va as *mut usize;
+
+                let next_va = va + self.object_size;
+                let next_addr = if next_va + self.object_size <= end {
+                    next_va
+                } else {
+                    0
+                };
+
+                unsafe {
+                    write_volatile(memory_block, next_addr);
+                }
+                va += self.object_size;
+            }
+
+            // 新 slab 由 alloc 直接设为 active，不进 partial 链表
+            let slab = Slab::Slub {
+                freelist: VirtAddr(start_va),
+                inuse: 0,
+                has_next: false,
+                next_partial: PhysPageNum(0),
+            };
+            // 更改新获得的内存对应的MEM_MAP的信息为slab
+            let page = self.page(ppn);
+            page.ref_count = 1;
+            page.state = PageState::Slab(slab);
+
+            Some(ppn)
+        } else {
+            // 大页分配逻辑
+            let required_pages = (self.object_size + PAGE_SIZE - 1) >> PAGE_SIZE_BITS;
+
+            // Buddy System 只能分配 2 的次幂个页，向上取整到 2 的幂
+            let allocate_pages = required_pages.next_power_of_two();
+
+            let ppn = self
+                .backend
+                .alloc_pages(NonZeroUsize::new(allocate_pages).unwrap())?;
+
+            let start_va = self.backend.phys_to_virt(PhysAddr::from(&ppn).0);
+            let mut va = start_va;
+            let end = start_va + (allocate_pages << PAGE_SIZE_BITS);
+
+            while va + self.object_size <= end {
+                let memory_block = va as *mut usize;
+                let next_va = va + self.object_size;
+
+                let next_addr = if next_va + self.object_size <= end {
+                    next_va
+                } else {
+                    0
+                };
+
+                unsafe {
+                    write_volatile(memory_block, next_addr);
+                }
+                va += self.object_size;
+            }
+
+            let slab_head = Slab::Slub {
+                freelist: VirtAddr(start_va),
+                inuse: 0,
+                has_next: false,
+                next_partial: PhysPageNum(0),
+            };
+
+            let current_ppn = PhysPageNum(ppn.0);
+            let page = self.page(current_ppn);
+            page.ref_count = 1;
+            page.state = PageState::Slab(slab_head);
+            // 后面的页标记为Tail
+            for i in 1..allocate_pages {
+                let current_ppn = PhysPageNum(ppn.0 + i);
+                let page = self.page(current_ppn);
+                page.ref_count = 1;
+
+                page.state = PageState::Slab(Slab::SlubTail {
+                    head_page_ppn: ppn, // 全部指回头Slab
+                });
+            }
+
+            Some(ppn)
+        }
+    }
+    pub fn alloc(&mut self) -> *mut u8 {
+        loop {
+            // 尝试从当前正在使用的 Active Slab 中分配
+            if let Some(ppn) = self.active_slab_ppn {
+                let page = self.page(ppn);
+
+                if let PageState::Slab(Slab::Slub {
+                    freelist, inuse, ..
+                }) = &mut page.state
+                {
+                    // 检查 Active Slab 是否已满，
+                    // 由 alloc_new_slab 中最后一个对象的 next 写 0 保证
+                    if freelist.0 == 0 {
+                        self.active_slab_ppn = None;
+                        continue;
+                    }
+
+                    // 拿走当前 freelist 指向的内存块
+                    let mem_block_ptr = freelist.0 as *mut u8;
+
+                    // 读取这个内存块开头里存的下一个空闲内存块的地址
+                    let next_free_addr = unsafe { *(mem_block_ptr as *mut usize) };
+
+                    *freelist = VirtAddr(next_free_addr);
+                    *inuse += 1;
+                    self.nr_inuse += 1;
+
+                    return mem_block_ptr;
+                } else {
+                    panic!("FATAL: active_slab_ppn does not point to a Slub Head!");
+                }
+            }
+            // Active 没有可用内存，从 Partial 获取
+            else if let Some(partial_head) = self.partial_slabs_head.take() {
+                let page = self.page(partial_head);
+
+                if let PageState::Slab(Slab::Slub {
+                    has_next,
+                    next_partial,
+                    ..
+                }) = &page.state
+                {
+                    if *has_next {
+                        self.partial_slabs_head = Some(*next_partial);
+                    }
+                    self.active_slab_ppn = Some(partial_head);
+                    continue;
+                } else {
+                    panic!("FATAL: Memory corruption! Found non-Head page in partial_slabs_head");
+                }
+            }
+            // 若partial也没有可用内存，向buddy system申请新的
+            else {
+                match self.alloc_new_slab() {
+                    Some(new_ppn) => {
+                        self.nr_slabs += 1;
+                        self.active_slab_ppn = Some(new_ppn);
+                        continue;
+                    }
+                    None => {
+                        // OOM
+                        return core::ptr::null_mut();
+                    }
+                }
+            }
+        }
+    }
+
+    /// 释放 alloc 返回的对象：先由对象地址找到所在 slab 的头页
+    ///
+    /// # Safety
+    /// ptr 必须是这个 cache 的 alloc 返回、还没有释放的对象
+    pub unsafe fn free(&mut self, ptr: *mut u8) {
+        let ppn = PhysAddr(self.backend.virt_to_phys(ptr as usize)).floor();
+        let slab_ppn = match &self.page(ppn).state {
+            PageState::Slab(Slab::Slub { .. }) => ppn,
+            PageState::Slab(Slab::SlubTail { head_page_ppn }) => *head_page_ppn,
+            _ => panic!("FATAL: Tried to dealloc a pointer not belonging to SLUB!"),
+        };
+        unsafe { self.free_object(ptr, slab_ppn) }
+    }
+
+    /// 把对象放回 slab_ppn 所在 slab 的空闲链表
+    ///
+    /// # Safety
+    /// ptr 必须是属于 slab_ppn 这个 slab、还没有释放的对象
+    pub unsafe fn free_object(&mut self, ptr: *mut u8, slab_ppn: PhysPageNum) {
+        let page = self.page(slab_ppn);
+
+        if let PageState::Slab(Slab::Slub {
+            freelist,
+            inuse,
+            has_next,
+            next_partial,
+            ..
+        }) = &mut page.state
+        {
+            let was_full = freelist.0 == 0;
+            let is_active = self.active_slab_ppn == Some(slab_ppn);
+
+            let obj_ptr = ptr as *mut usize;
+            // 将当前的 freelist (下一个可用地址) 写入被释放对象的开头
+            unsafe {
+                write_volatile(obj_ptr, freelist.0);
+            }
+            // freelist 更新为当前刚释放的对象
+            *freelist = VirtAddr(ptr as usize);
+            *inuse -= 1;
+            self.nr_inuse -= 1;
+
+            if *inuse == 0 {
+                if is_active {
+                    self.active_slab_ppn = None;
+                } else if !was_full {
+                    // 全满又不是 active 的 slab 不在任何链表上（每个 slab 只有一个对象时会直接从满变空）
+                    self.remove_from_partial(slab_ppn);
+                }
+
+                // 当初向 Buddy 申请的页数
+                let required_pages = (self.object_size + PAGE_SIZE - 1) >> PAGE_SIZE_BITS;
+                let allocate_pages = required_pages.max(1).next_power_of_two();
+
+                for i in 0..allocate_pages {
+                    let current_ppn = PhysPageNum(slab_ppn.0 + i);
+                    let current_page = self.page(current_ppn);
+                    current_page.state = PageState::Free;
+                    current_page.ref_count = 0;
+                }
+
+                self.backend
+                    .free_pages(slab_ppn, NonZeroUsize::new(allocate_pages).unwrap());
+                self.nr_slabs -= 1;
+            } else if was_full && !is_active {
+                // 曾经全满,没有保存在kmemcacahe中
+                // 如果它本身就是 active，哪怕曾满了也不用管，因为下一次 alloc 会处理它。
+                // 只有当它既满了，又不是 active 时，才说明它被遗忘了。
+                // 现在它空出了一个位置，把它头插法放回 Partial 备用链表
+
+                let old_head = self.partial_slabs_head;
+                if let Some(old_ppn) = old_head {
+                    *next_partial = old_ppn;
+                    *has_next = true;
+                } else {
+                    *has_next = false;
+                }
+                self.partial_slabs_head = Some(slab_ppn);
+            }
+        } else {
+            panic!(
+                "FATAL: free_object called on a non-Slub page! PPN: {:?}",
+                slab_ppn
+            );
+        }
+    }
+    fn remove_from_partial(&mut self, target_ppn: PhysPageNum) {
+        let mut current_opt = self.partial_slabs_head;
+        let mut prev_opt: Option<PhysPageNum> = None;
+
+        while let Some(current_ppn) = current_opt {
+            let current_page = self.page(current_ppn);
+
+            // 提取当前节点的 next 指针
+            let next_opt = if let PageState::Slab(Slab::Slub {
+                has_next,
+                next_partial,
+                ..
+            }) = &current_page.state
+            {
+                if *has_next { Some(*next_partial) } else { None }
+            } else {
+                panic!("FATAL: Non-Slub page found in partial_slabs_head!");
+            };
+
+            // 命中目标，开始物理摘除
+            if current_ppn == target_ppn {
+                if let Some(prev_ppn) = prev_opt {
+                    // 目标在链表中间或尾部。让上一个节点直接指向下一个节点
+                    let prev_page = self.page(prev_ppn);
+                    if let PageState::Slab(Slab::Slub {
+                        has_next,
+                        next_partial,
+                        ..
+                    }) = &mut prev_page.state
+                    {
+                        if let Some(n) = next_opt {
+                            *next_partial = n;
+                            *has_next = true;
+                        } else {
+                            *has_next = false;
+                        }
+                    }
+                } else {
+                    // 目标就是头节点。直接修改 KmemCache 的头指针
+                    self.partial_slabs_head = next_opt;
+                }
+                return;
+            }
+
+            // 没找到，双指针继续向后推进
+            prev_opt = Some(current_ppn);
+            current_opt = next_opt;
+        }
+
+        panic!(
+            "FATAL: Tried to remove PPN {:?} but it was not in the partial list!",
+            target_ppn
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddySystemFrameAllocator;
+    use crate::test_support::{Arena, ArenaMapper, Rng};
+    use core::cell::{RefCell, UnsafeCell};
+    use std::collections::BTreeMap;
+
+    const ARENA_PAGES: usize = 256;
+
+    /// 伙伴分配器 + 页描述符表，都建在同一块 arena 上
+    struct TestPages {
+        arena: Arena,
+        buddy: RefCell<BuddySystemFrameAllocator<ArenaMapper>>,
+        mem_map: Vec<UnsafeCell<Page>>,
+    }
+
+    impl TestPages {
+        fn new() -> Self {
+            let arena = Arena::new(ARENA_PAGES);
+            let mut buddy = BuddySystemFrameAllocator::new(arena.mapper());
+            unsafe { buddy.add_free_region(arena.start_ppn(), arena.end_ppn()) };
+            let mem_map = (0..ARENA_PAGES)
+                .map(|_| {
+                    UnsafeCell::new(Page {
+                        ref_count: 0,
+                        state: PageState::Free,
+                    })
+                })
+                .collect();
+            Self {
+                arena,
+                buddy: RefCell::new(buddy),
+                mem_map,
+            }
+        }
+
+        fn all_pages_free(&self) -> bool {
+            self.mem_map
+                .iter()
+                .all(|page| matches!(unsafe { &(*page.get()).state }, PageState::Free))
+        }
+    }
+
+    impl PhysMapper for &TestPages {
+        fn phys_to_virt(&self, pa: usize) -> usize {
+            self.arena.mapper().phys_to_virt(pa)
+        }
+        fn virt_to_phys(&self, va: usize) -> usize {
+            self.arena.mapper().virt_to_phys(va)
+        }
+    }
+
+    impl SlabBackend for &TestPages {
+        fn alloc_pages(&self, pages: NonZeroUsize) -> Option<PhysPageNum> {
+            self.buddy.borrow_mut().alloc(pages)
+        }
+        fn free_pages(&self, ppn: PhysPageNum, pages: NonZeroUsize) {
+            self.buddy.borrow_mut().dealloc(ppn, pages)
+        }
+        fn page(&self, ppn: PhysPageNum) -> NonNull<Page> {
+            let index = ppn.0 - self.arena.start_ppn();
+            NonNull::new(self.mem_map[index].get()).unwrap()
+        }
+    }
+
+    #[test]
+    fn objects_fit_inside_slab() {
+        let pages = TestPages::new();
+        // 24 和 3000 都不能整除页大小，最后一个对象不能越过页尾
+        for size in [24, 3000, 5000] {
+            let mut cache = KmemCache::new(size, &pages);
+            let slab_bytes = cache.pages_per_slab() << PAGE_SIZE_BITS;
+            let per_slab = slab_bytes / cache.object_size;
+            let objects: Vec<_> = (0..per_slab).map(|_| cache.alloc()).collect();
+            assert_eq!(cache.nr_slabs, 1, "size {size}");
+            let base = objects.iter().map(|&p| p as usize).min().unwrap();
+            for &obj in &objects {
+                assert!(
+                    obj as usize + cache.object_size <= base + slab_bytes,
+                    "size {size}"
+                );
+            }
+            // 再分配一个必须开新 slab
+            let extra = cache.alloc();
+            assert_eq!(cache.nr_slabs, 2, "size {size}");
+            for obj in objects.into_iter().chain([extra]) {
+                unsafe { cache.free(obj) };
+            }
+            assert_eq!(cache.nr_slabs, 0);
+        }
+        assert!(pages.all_pages_free());
+    }
+
+    #[test]
+    fn empty_slab_is_not_reused() {
+        let pages = TestPages::new();
+        let mut cache = KmemCache::new(64, &pages);
+        // 新 slab 只作为 active，空了还给 Buddy 后不能再从 partial 链表中被取出来
+        for _ in 0..3 {
+            let obj = cache.alloc();
+            assert!(!obj.is_null());
+            unsafe { cache.free(obj) };
+            assert_eq!(cache.nr_slabs, 0);
+            assert!(cache.partial_slabs_head.is_none());
+        }
+        assert!(pages.all_pages_free());
+    }
+
+    #[test]
+    fn single_object_slab_goes_from_full_to_empty() {
+        let pages = TestPages::new();
+        // 每个 slab 只放得下一个对象，释放非 active 的 slab 时它不在 partial 链表上
+        let mut cache = KmemCache::new(3000, &pages);
+        let first = cache.alloc();
+        let second = cache.alloc();
+        assert_eq!(cache.nr_slabs, 2);
+        unsafe { cache.free(first) };
+        assert_eq!(cache.nr_slabs, 1);
+        unsafe { cache.free(second) };
+        assert_eq!(cache.nr_slabs, 0);
+        assert!(pages.all_pages_free());
+    }
+
+    /// 随机分配/释放，检查对象不重叠、内容不被破坏、计数准确，全部释放后页全部归还
+    #[test]
+    fn random_alloc_free_keeps_invariants() {
+        for (seed, size) in [8, 24, 64, 200, 1024, 3000, 4096, 5000, 12000]
+            .into_iter()
+            .enumerate()
+        {
+            let pages = TestPages::new();
+            let initial = pages.buddy.borrow().free_blocks_per_order();
+            let mut cache = KmemCache::new(size, &pages);
+            let object_size = cache.object_size;
+            let mut rng = Rng::new(seed as u64);
+            // 起始地址 -> 标记字节
+            let mut live: BTreeMap<usize, u8> = BTreeMap::new();
+
+            for step in 0..3000 {
+                if live.is_empty() || rng.chance(55) {
+                    let ptr = cache.alloc();
+                    if ptr.is_null() {
+                        continue;
+                    }
+                    let addr = ptr as usize;
+                    if let Some((&prev, _)) = live.range(..addr).next_back() {
+                        assert!(prev + object_size <= addr, "size {size}: overlap");
+                    }
+                    if let Some((&next, _)) = live.range(addr..).next() {
+                        assert!(addr + object_size <= next, "size {size}: overlap");
+                    }
+                    let tag = step as u8;
+                    unsafe { core::ptr::write_bytes(ptr, tag, object_size) };
+                    live.insert(addr, tag);
+                } else {
+                    let nth = rng.below(live.len());
+                    let (&addr, &tag) = live.iter().nth(nth).unwrap();
+                    let bytes =
+                        unsafe { core::slice::from_raw_parts(addr as *const u8, object_size) };
+                    assert!(
+                        bytes.iter().all(|&b| b == tag),
+                        "size {size}: object corrupted"
+                    );
+                    live.remove(&addr);
+                    unsafe { cache.free(addr as *mut u8) };
+                }
+                assert_eq!(cache.nr_inuse, live.len(), "size {size}");
+            }
+
+            for addr in live.into_keys() {
+                unsafe { cache.free(addr as *mut u8) };
+            }
+            assert_eq!(cache.nr_inuse, 0);
+            assert_eq!(cache.nr_slabs, 0, "size {size}: slabs leaked");
+            assert!(cache.active_slab_ppn.is_none() && cache.partial_slabs_head.is_none());
+            assert!(pages.all_pages_free(), "size {size}");
+            assert_eq!(
+                pages.buddy.borrow().free_blocks_per_order(),
+                initial,
+                "size {size}"
+            );
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! 宿主机测试用的模拟物理内存和随机数发生器。
+
+use std::alloc::{Layout, alloc_zeroed, dealloc};
+
+use crate::PhysMapper;
+use crate::address::{PAGE_SIZE, PAGE_SIZE_BITS};
+
+/// 模拟物理内存的起始地址，与 QEMU virt 的 RAM 起点一致
+pub const ARENA_PHYS_BASE: usize = 0x8000_0000;
+
+/// 一块页对齐的宿主机内存，充当 [ARENA_PHYS_BASE, ARENA_PHYS_BASE + pages * PAGE_SIZE) 的物理内存
+pub struct Arena {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Arena {
+    pub fn new(pages: usize) -> Self {
+        let layout = Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap();
+        let ptr = unsafe { alloc_zeroed(layout) };
+        assert!(!ptr.is_null(), "arena allocation failed");
+        Self { ptr, layout }
+    }
+
+    pub fn mapper(&self) -> ArenaMapper {
+        ArenaMapper {
+            host_base: self.ptr as usize,
+            len: self.layout.size(),
+        }
+    }
+
+    pub fn start_ppn(&self) -> usize {
+        ARENA_PHYS_BASE >> PAGE_SIZE_BITS
+    }
+
+    pub fn end_ppn(&self) -> usize {
+        (ARENA_PHYS_BASE + self.layout.size()) >> PAGE_SIZE_BITS
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// 把模拟物理地址平移到 arena 所在的宿主机地址，越界访问直接 panic
+#[derive(Clone, Copy)]
+pub struct ArenaMapper {
+    host_base: usize,
+    len: usize,
+}
+
+impl PhysMapper for ArenaMapper {
+    fn phys_to_virt(&self, pa: usize) -> usize {
+        assert!(
+            (ARENA_PHYS_BASE..ARENA_PHYS_BASE + self.len).contains(&pa),
+            "physical address {pa:#x} outside arena"
+        );
+        pa - ARENA_PHYS_BASE + self.host_base
+    }
+
+    fn virt_to_phys(&self, va: usize) -> usize {
+        assert!(
+            (self.host_base..self.host_base + self.len).contains(&va),
+            "host address {va:#x} outside arena"
+        );
+        va - self.host_base + ARENA_PHYS_BASE
+    }
+}
+
+/// xorshift64*，只用于生成可复现的测试序列
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// [0, n) 内的随机数
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
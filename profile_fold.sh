@@ -0,0 +1,62 @@
+#!/bin/sh
+# 把 shell 中 `profile dump` 输出的采样符号化并折叠成火焰图格式，每行为
+#   task-<id>;最外层函数;...;被打断的函数 <次数>
+# 可以直接交给 flamegraph.pl 或 inferno-flamegraph：
+#   ./profile_fold.sh samples.txt | flamegraph.pl > profile.svg
+# 符号表默认使用 build.sh 导出的 target/ksyms.txt，必须与采样时运行的内核一致。
+# 输入中与采样格式不符的行（控制台上的其他输出）会被忽略。
+
+if [ $# -lt 1 ] || [ $# -gt 2 ]; then
+    echo "usage: $0 <samples.txt> [ksyms.txt]" >&2
+    exit 1
+fi
+
+SAMPLES="$1"
+KSYMS="${2:-target/ksyms.txt}"
+
+if [ ! -s "$KSYMS" ]; then
+    echo "$KSYMS 不存在或为空，请先运行 ./build.sh" >&2
+    exit 1
+fi
+
+# 地址都是 16 位小写十六进制，按字符串比较即按数值比较（awk 的数值精度不够 64 位）
+awk '
+function is_addr(s) {
+    return length(s) == 16 && s ~ /^[0-9a-f]+$/
+}
+# 二分查找包含 pc 的符号。返回地址指向调用指令的下一条，
+# 调用不返回的函数位于末尾时会落到下一个函数的起点，所以返回地址用严格小于比较
+function lookup(pc, is_ret,    lo, hi, mid, found) {
+    lo = 1; hi = nsyms; found = 0
+    while (lo <= hi) {
+        mid = int((lo + hi) / 2)
+        if (addr[mid] "" < pc "" || (!is_ret && addr[mid] "" == pc "")) {
+            found = mid; lo = mid + 1
+        } else {
+            hi = mid - 1
+        }
+    }
+    return found ? name[found] : "0x" pc
+}
+NR == FNR {
+    if (is_addr($1)) {
+        nsyms++
+        addr[nsyms] = $1
+        sub(/^[^ ]+ /, "")
+        name[nsyms] = $0
+    }
+    next
+}
+{
+    sub(/\r$/, "")
+    if (NF < 2 || $1 !~ /^([0-9]+|-)$/) next
+    for (i = 2; i <= NF; i++) if (!is_addr($i)) next
+    stack = ($1 == "-") ? "boot" : "task-" $1
+    for (i = NF; i >= 3; i--) stack = stack ";" lookup($i, 1)
+    stack = stack ";" lookup($2, 0)
+    count[stack]++
+}
+END {
+    for (stack in count) print stack, count[stack]
+}
+' "$KSYMS" "$SAMPLES" | sort
@@ -2,7 +2,10 @@
 KERNEL="target/riscv64gc-unknown-none-elf/release/charlotte_os"
 KERNEL_BIN="target/riscv64gc-unknown-none-elf/release/charlotte_os.bin"
 BIOS="rustsbi.bin"
-cargo build --release
+DISK="target/disk.img"
+# GDB_STUB=端口号 时内核调试桩与控制台共用串口，串口改为在这个 TCP 端口上等待 GDB 连接
+GDB_STUB="${GDB_STUB:-}"
+./build.sh
 
 if [ $? -ne 0 ]; then
     echo "编译失败，调试会话中止。"
@@ -11,9 +14,22 @@ fi
 
 rust-objcopy --binary-architecture=riscv64 $KERNEL --strip-all -O binary $KERNEL_BIN
 
+# virtio-blk 磁盘镜像，不存在时创建一个空镜像
+if [ ! -f $DISK ]; then
+    truncate -s 16M $DISK
+fi
+
+set --
+if [ -n "$GDB_STUB" ]; then
+    set -- -append "gdb=console" -serial tcp::$GDB_STUB,server=on,wait=off
+fi
+
 qemu-system-riscv64 \
     -machine virt \
     -nographic \
     -smp 1 \
     -bios $BIOS \
-    -kernel $KERNEL
+    -kernel $KERNEL \
+    -drive file=$DISK,if=none,format=raw,id=disk0 \
+    -device virtio-blk-device,drive=disk0 \
+    "$@"
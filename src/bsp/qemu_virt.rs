@@ -1,14 +1,27 @@
 // src/bsp/qemu_virt.rs
+use crate::config::PHYS_VIRT_OFFSET;
 use crate::system::SystemControl;
 use core::ptr::write_volatile;
+
+/// 最终页表只对设备 MMIO 区域做了线性映射，寄存器必须通过高半区地址访问
+pub const fn mmio_va(pa: usize) -> usize {
+    pa.wrapping_add(PHYS_VIRT_OFFSET)
+}
 pub const UART_BASE: usize = 0x10_000_000;
 pub const UART0_IRQ: usize = 10;
+// goldfish RTC
+pub const RTC_BASE: usize = 0x101_000;
+pub const RTC_IRQ: usize = 11;
+// 8 个 virtio-mmio 槽位，没有挂设备的槽位 DeviceID 读出 0
+pub const VIRTIO_MMIO_BASE: usize = 0x10_001_000;
+pub const VIRTIO_MMIO_STRIDE: usize = 0x1000;
+pub const VIRTIO_MMIO_COUNT: usize = 8;
 pub const CLINT_BASE: usize = 0x2_000_000;
 pub const PLIC_BASE: usize = 0xC_000_000;
 //PLIC优先级区地址
 pub const PLIC_PRIORITY_BASE: usize = PLIC_BASE + 0x00;
 pub const fn plic_priority_addr(interrupt_id: usize) -> usize {
-    PLIC_PRIORITY_BASE + interrupt_id * 4
+    mmio_va(PLIC_PRIORITY_BASE + interrupt_id * 4)
 }
 
 //PLIC挂起寄存器区地址
@@ -20,7 +33,7 @@ pub const PLIC_ENABLE_BASE: usize = PLIC_BASE + 0x2000;
 pub const PLIC_ENABLE_STRIDE: usize = 0x80;
 pub fn plic_enable_addr(hart_id: usize, irq: usize) -> usize {
     let context_id = plic_context_id_s(hart_id);
-    PLIC_ENABLE_BASE + context_id * PLIC_ENABLE_STRIDE + (irq / 32) * 4
+    mmio_va(PLIC_ENABLE_BASE + context_id * PLIC_ENABLE_STRIDE + (irq / 32) * 4)
 }
 
 //PLIC上下文相关寄存器区地址
@@ -29,12 +42,12 @@ pub const PLIC_CONTEXT_BASE: usize = PLIC_BASE + 0x200_000;
 pub const PLIC_CONTEXT_STRIDE: usize = 0x1000;
 pub fn plic_context_addr(hart_id: usize) -> usize {
     let context_id = plic_context_id_s(hart_id);
-    PLIC_CONTEXT_BASE + context_id * PLIC_CONTEXT_STRIDE
+    mmio_va(PLIC_CONTEXT_BASE + context_id * PLIC_CONTEXT_STRIDE)
 }
 pub const PLIC_CLAIM_COMPLETE_OFFSET: usize = 0x200_004;
 pub fn plic_claim_complete_addr(hart_id: usize) -> usize {
     let context_id = plic_context_id_s(hart_id);
-    PLIC_BASE + PLIC_CLAIM_COMPLETE_OFFSET + context_id * PLIC_CONTEXT_STRIDE
+    mmio_va(PLIC_BASE + PLIC_CLAIM_COMPLETE_OFFSET + context_id * PLIC_CONTEXT_STRIDE)
 }
 pub const MTIME_OFFSET: usize = 0xBFF8;
 // pub const MTIME_OFFSET: usize = 0x7FF8;
@@ -93,19 +106,32 @@ impl SystemControl for QemuVirt {
         // 向 TEST 设备的特定寄存器写入一个值来关闭 QEMU
         // 0x5555 是一个约定的“成功退出”代码
         unsafe {
-            let addr = VIRT_TEST_ADDR as *mut u32;
+            let addr = mmio_va(VIRT_TEST_ADDR) as *mut u32;
             write_volatile(addr, FINISHER_PASS as u32);
         }
         // 如果上面的代码成功，程序不会执行到这里
-        // 如果失败了，就进入死循环
-        loop {}
+        // 如果失败了，就停在 wfi 上，不再空转
+        loop {
+            unsafe { core::arch::asm!("wfi") }
+        }
     }
 
     fn reboot(&self) -> ! {
         unsafe {
-            let addr = VIRT_TEST_ADDR as *mut u32;
+            let addr = mmio_va(VIRT_TEST_ADDR) as *mut u32;
             write_volatile(addr, FINISHER_RESET as u32);
         }
         loop {}
     }
+
+    /// 高 16 位是退出码，QEMU 以 (code << 1) | 1 作为进程退出状态
+    fn fail(&self, code: u16) -> ! {
+        unsafe {
+            let addr = mmio_va(VIRT_TEST_ADDR) as *mut u32;
+            write_volatile(addr, ((code as u32) << 16) | FINISHER_FAIL as u32);
+        }
+        loop {
+            unsafe { core::arch::asm!("wfi") }
+        }
+    }
 }
@@ -1 +1,12 @@
 pub const PHYS_VIRT_OFFSET: usize = 0xFFFF_FFFF_0000_0000;
+/// 支持的最大 hart 数，run.sh 以 -smp 1 启动
+pub const MAX_HARTS: usize = 1;
+
+use crate::console::log::Level;
+
+/// 启动时的全局日志级别
+pub const LOG_LEVEL: Level = Level::Info;
+/// 按模块覆盖日志级别，模块路径不含 crate 名，例如 ("mm::buddy", Level::Debug)
+pub const LOG_MODULE_LEVELS: &[(&str, Level)] = &[];
+/// 执行器的 worker 任务数
+pub const EXECUTOR_WORKERS: usize = 2;
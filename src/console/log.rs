@@ -0,0 +1,253 @@
+// src/console/log.rs
+//! 内核日志：带级别和时间戳的记录同时写到控制台和一块静态环形缓冲区，
+//! 之后可以通过 syslog 系统调用（shell 中的 dmesg）读回。
+//! 整个路径不分配堆内存，锁会关中断，所以在分配器初始化之前和 trap 上下文中都能用。
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{
+    config::{LOG_LEVEL, LOG_MODULE_LEVELS},
+    console::console_write_bytes,
+    data_struct::lock::IrqLock,
+    trap::interrupts::get_time_ms,
+};
+
+/// 环形缓冲区大小，写满后覆盖最旧的日志
+pub const LOG_BUF_SIZE: usize = 16 * 1024;
+/// 单条日志的最大长度，超出部分截断
+const MAX_RECORD: usize = 256;
+const MAX_FILTERS: usize = 8;
+const CRATE_PREFIX: &str = "charlotte_os::";
+
+// syslog 系统调用的操作码，与 Linux 一致
+pub const SYSLOG_ACTION_READ_ALL: usize = 3;
+pub const SYSLOG_ACTION_CLEAR: usize = 5;
+pub const SYSLOG_ACTION_CONSOLE_LEVEL: usize = 8;
+pub const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+pub const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    pub fn from_num(num: usize) -> Option<Level> {
+        match num {
+            1 => Some(Level::Error),
+            2 => Some(Level::Warn),
+            3 => Some(Level::Info),
+            4 => Some(Level::Debug),
+            5 => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// 没有匹配到模块过滤规则时使用的级别
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+/// 级别不高于它的日志才会打印到控制台，其余只进缓冲区
+static CONSOLE_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+struct LogRing {
+    buf: [u8; LOG_BUF_SIZE],
+    // 下一个写入位置
+    head: usize,
+    len: usize,
+    // 模块路径前缀（不含 crate 名）到级别的过滤规则，最长前缀优先
+    filters: [Option<(&'static str, Level)>; MAX_FILTERS],
+}
+
+static LOG: IrqLock<LogRing> = IrqLock::new(LogRing::new());
+
+impl LogRing {
+    const fn new() -> Self {
+        Self {
+            buf: [0; LOG_BUF_SIZE],
+            head: 0,
+            len: 0,
+            filters: [None; MAX_FILTERS],
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.buf[self.head] = byte;
+            self.head = (self.head + 1) % LOG_BUF_SIZE;
+        }
+        self.len = (self.len + bytes.len()).min(LOG_BUF_SIZE);
+    }
+
+    /// 把最新的日志复制到 out，空间不够时保留最后的部分，返回复制的字节数
+    fn copy_latest(&self, out: &mut [u8]) -> usize {
+        let count = self.len.min(out.len());
+        let start = (self.head + LOG_BUF_SIZE - count) % LOG_BUF_SIZE;
+        for (i, slot) in out[..count].iter_mut().enumerate() {
+            *slot = self.buf[(start + i) % LOG_BUF_SIZE];
+        }
+        count
+    }
+
+    fn level_for(&self, module: &str) -> Level {
+        self.filters
+            .iter()
+            .flatten()
+            .filter(|(prefix, _)| module.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or_else(
+                || level_from_u8(MAX_LEVEL.load(Ordering::Relaxed)),
+                |(_, level)| *level,
+            )
+    }
+}
+
+fn level_from_u8(num: u8) -> Level {
+    Level::from_num(num as usize).unwrap_or(Level::Info)
+}
+
+/// 栈上的定长格式化缓冲，写满后丢弃多余内容
+struct RecordBuf {
+    buf: [u8; MAX_RECORD],
+    len: usize,
+}
+
+impl Write for RecordBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(MAX_RECORD - self.len);
+        self.buf[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module: &'static str, args: fmt::Arguments) {
+    let module = module.strip_prefix(CRATE_PREFIX).unwrap_or(module);
+    if level > LOG.lock().level_for(module) {
+        return;
+    }
+    // 格式化在锁外进行，参数的 Display 实现里再打日志也不会死锁
+    let ms = get_time_ms();
+    let mut record = RecordBuf {
+        buf: [0; MAX_RECORD],
+        len: 0,
+    };
+    let _ = write!(
+        record,
+        "[{:>5}.{:03}] {:<5} {}: {}",
+        ms / 1000,
+        ms % 1000,
+        level.name(),
+        module,
+        args
+    );
+    // 截断的日志也要保证以换行结尾
+    if record.len == MAX_RECORD {
+        record.len -= 1;
+    }
+    record.buf[record.len] = b'\n';
+    record.len += 1;
+    let mut log = LOG.lock();
+    log.push(&record.buf[..record.len]);
+    if level as u8 <= CONSOLE_LEVEL.load(Ordering::Relaxed) {
+        console_write_bytes(&record.buf[..record.len]);
+    }
+}
+
+/// 设置全局日志级别
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// 设置打印到控制台的级别
+pub fn set_console_level(level: Level) {
+    CONSOLE_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// 为某个模块（如 "mm::buddy"）单独设置级别，前缀相同的子模块一并生效。规则槽已满时返回 false
+pub fn set_module_level(module: &'static str, level: Level) -> bool {
+    let mut log = LOG.lock();
+    if let Some(slot) = log
+        .filters
+        .iter_mut()
+        .find(|slot| slot.is_some_and(|(prefix, _)| prefix == module))
+    {
+        *slot = Some((module, level));
+        return true;
+    }
+    match log.filters.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some((module, level));
+            true
+        }
+        None => false,
+    }
+}
+
+/// 按 config 中的配置设置日志级别，在输出第一条日志之前调用
+pub fn init_log() {
+    set_max_level(LOG_LEVEL);
+    for &(module, level) in LOG_MODULE_LEVELS {
+        set_module_level(module, level);
+    }
+}
+
+pub fn read_log(out: &mut [u8]) -> usize {
+    LOG.lock().copy_latest(out)
+}
+
+pub fn log_len() -> usize {
+    LOG.lock().len
+}
+
+pub fn clear_log() {
+    LOG.lock().len = 0;
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::console::log::_log($level, module_path!(), format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::console::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::console::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::console::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::console::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::console::log::Level::Trace, $($arg)*));
+}
@@ -1,11 +1,77 @@
-use crate::bsp::qemu_virt::{LSR, THR, UART_BASE};
+pub mod log;
+
+use crate::bsp::qemu_virt::{LSR, THR, UART_BASE, mmio_va};
+use crate::mm::mmio_mapped;
+use crate::syslib::errno::Errno;
+#[cfg(feature = "uart_interrupt")]
+use crate::trap::interrupts::service::uart_service::UART_SERVICE;
 use crate::{UART, userlib::syscall::sys_write_byte};
 // use crate::driver::Uart; // 引入统一的 Uart 类型
 use core::fmt::{self, Write};
 use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// 为 true 时控制台输出进入 UART_SERVICE 的发送缓冲区，由执行器上的发送服务在 THR 空中断后发出；
+/// 早期启动和 panic 时为 false，直接走 SBI 轮询输出
+static CONSOLE_BUFFERED: AtomicBool = AtomicBool::new(false);
+
+/// 初始化 UART 并切换到中断驱动的缓冲输出，必须在最终页表生效、中断开启之后调用
+pub fn enable_buffered_console() {
+    lazy_static::initialize(&UART);
+    #[cfg(feature = "uart_interrupt")]
+    CONSOLE_BUFFERED.store(true, Ordering::Release);
+}
+
+/// panic 时调用：把缓冲区中剩余的内容用轮询方式发出，之后所有输出都走轮询
+pub fn force_polling_console() {
+    if CONSOLE_BUFFERED.swap(false, Ordering::AcqRel) {
+        #[cfg(feature = "uart_interrupt")]
+        UART_SERVICE.try_flush_polling();
+    }
+}
+
+fn sbi_write_bytes(bytes: &[u8]) {
+    for &byte in bytes {
+        #[allow(deprecated)]
+        sbi_rt::legacy::console_putchar(byte as usize);
+    }
+}
+
+/// 控制台字节输出，可以在任何上下文中调用，不会阻塞任务。
+/// 发送缓冲区满时退化为轮询，先把缓冲区发空再继续
+pub fn console_write_bytes(bytes: &[u8]) {
+    #[cfg(feature = "uart_interrupt")]
+    if CONSOLE_BUFFERED.load(Ordering::Acquire) {
+        UART_SERVICE.write_or_flush(bytes);
+        return;
+    }
+    sbi_write_bytes(bytes);
+}
+
+/// 系统调用路径上的控制台输出，返回写入的字节数。
+/// 发送缓冲区满时把当前任务登记为等待者并返回 EAGAIN，由调用者阻塞任务后重试
+pub fn console_write_from_task(bytes: &[u8]) -> Result<usize, Errno> {
+    #[cfg(feature = "uart_interrupt")]
+    if CONSOLE_BUFFERED.load(Ordering::Acquire) && !bytes.is_empty() {
+        let count = UART_SERVICE.enqueue(bytes);
+        if count > 0 {
+            return Ok(count);
+        }
+        return Err(UART_SERVICE.wait_for_space());
+    }
+    sbi_write_bytes(bytes);
+    Ok(bytes.len())
+}
 
 pub fn _print(args: fmt::Arguments) {
-    UART.lock().write_fmt(args).unwrap();
+    struct ConsoleWriter;
+    impl Write for ConsoleWriter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            console_write_bytes(s.as_bytes());
+            Ok(())
+        }
+    }
+    ConsoleWriter.write_fmt(args).unwrap();
 }
 
 pub fn user_print(args: fmt::Arguments) {
@@ -30,6 +96,17 @@ macro_rules! user_println {
     ($($arg:tt)*) => ($crate::user_print!("{}\n", format_args!($($arg)*)));
 }
 
+/// 格式化输出到当前任务的某个文件描述符
+#[macro_export]
+macro_rules! fd_print {
+    ($fd:expr, $($arg:tt)*) => ($crate::userlib::io::fd_print($fd, format_args!($($arg)*)));
+}
+#[macro_export]
+macro_rules! fd_println {
+    ($fd:expr) => ($crate::fd_print!($fd, "\n"));
+    ($fd:expr, $($arg:tt)*) => ($crate::fd_print!($fd, "{}\n", format_args!($($arg)*)));
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
@@ -41,8 +118,8 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 fn polling_putchar(c: u8) {
-    let lsr_ptr = (UART_BASE + LSR) as *mut u8;
-    let thr_ptr = (UART_BASE + THR) as *mut u8;
+    let lsr_ptr = (mmio_va(UART_BASE) + LSR) as *mut u8;
+    let thr_ptr = (mmio_va(UART_BASE) + THR) as *mut u8;
     unsafe {
         // 等待发送保持寄存器为空
         while (read_volatile(lsr_ptr) & (1 << 5)) == 0 {}
@@ -66,6 +143,11 @@ pub fn _polling_print(args: fmt::Arguments) {
         }
     }
 
+    // 最终页表生效前 UART 没有映射，只能借 SBI 输出
+    if !mmio_mapped() {
+        SbiWriter.write_fmt(args).unwrap();
+        return;
+    }
     // 使用这个临时的写入器来处理格式化参数
     PollingWriter.write_fmt(args).unwrap();
 }
@@ -116,44 +198,3 @@ macro_rules! sbi_println {
         $crate::sbi_print!("{}\n", format_args!($($arg)*))
     };
 }
-
-pub fn early_print_str(s: &str) {
-    for b in s.bytes() {
-        #[allow(deprecated)]
-        sbi_rt::legacy::console_putchar(b as usize);
-    }
-}
-
-pub fn early_print_hex(mut val: usize) {
-    early_print_str("0x");
-    if val == 0 {
-        early_print_str("0");
-        return;
-    }
-
-    // 64 位机器，十六进制最多 16 个字符
-    let mut buf = [0u8; 16];
-    let mut i = 16;
-
-    while val > 0 {
-        i -= 1;
-        let digit = (val & 0xF) as u8;
-        // 转换为 ASCII 码
-        buf[i] = if digit < 10 {
-            b'0' + digit
-        } else {
-            b'a' + digit - 10
-        };
-        val >>= 4;
-    }
-
-    // 将栈上的字符依次打印出来
-    for &b in &buf[i..] {
-        #[allow(deprecated)]
-        sbi_rt::legacy::console_putchar(b as usize);
-    }
-}
-
-pub fn early_println() {
-    early_print_str("\n");
-}
@@ -22,6 +22,20 @@ impl<T> IrqLock<T> {
             saved_status,
         }
     }
+    /// 尝试加锁，锁已被占用时立即返回 None（同样会关中断，失败时恢复）
+    pub fn try_lock(&self) -> Option<IrqLockGuard<'_, T>> {
+        let saved_status = read_and_disable_supervisor_interrupts();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqLockGuard {
+                _guard: guard,
+                saved_status,
+            }),
+            None => {
+                restore_interrupts(saved_status);
+                None
+            }
+        }
+    }
 }
 unsafe impl<T: Send> Sync for IrqLock<T> {}
 unsafe impl<T: Send> Send for IrqLock<T> {}
@@ -0,0 +1,86 @@
+// src/debug/backtrace.rs
+//! 沿帧指针回溯调用栈。内核以 -Cforce-frame-pointers=yes 编译，
+//! 每个函数的 s0 指向调用者的栈顶，返回地址保存在 s0-8，调用者的 s0 保存在 s0-16。
+
+use core::{arch::asm, fmt};
+
+use crate::{
+    console::_polling_print,
+    debug::ksyms,
+    mm::{PAGE_SIZE_BITS, RAM_END_PPN, RAM_START_PPN, buddy::phys_to_virt},
+};
+
+const MAX_FRAMES: usize = 32;
+
+unsafe extern "C" {
+    static _skernel: u8;
+    static _ekernel: u8;
+}
+
+/// 栈只可能位于内核镜像（启动栈）或线性映射的物理内存（任务栈）中，
+/// 越界的 fp 说明栈已损坏或到达了调用链的起点
+fn stack_range() -> (usize, usize) {
+    unsafe {
+        if RAM_END_PPN == 0 {
+            (&raw const _skernel as usize, &raw const _ekernel as usize)
+        } else {
+            (
+                phys_to_virt(RAM_START_PPN << PAGE_SIZE_BITS),
+                phys_to_virt(RAM_END_PPN << PAGE_SIZE_BITS),
+            )
+        }
+    }
+}
+
+/// 从调用者开始逐帧回溯，对每一帧的返回地址调用 f
+#[inline(never)]
+pub fn walk(f: impl FnMut(usize)) {
+    let fp: usize;
+    unsafe { asm!("mv {}, s0", out(reg) fp) };
+    walk_from(fp, f);
+}
+
+/// 从帧指针 fp 开始回溯，用于被中断的任务（fp 取自保存的 s0）
+pub fn walk_from(mut fp: usize, mut f: impl FnMut(usize)) {
+    let (low, high) = stack_range();
+    for _ in 0..MAX_FRAMES {
+        if !fp.is_multiple_of(8) || fp < low + 16 || fp > high {
+            break;
+        }
+        let (ra, prev_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        if ra == 0 {
+            break;
+        }
+        f(ra);
+        if prev_fp == fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
+
+/// 用轮询 UART 打印调用栈，不取任何锁，持锁时 panic 也能输出
+pub fn print_backtrace() {
+    write_backtrace(_polling_print);
+}
+
+/// 用 print 逐行输出调用栈，任务中 panic 时通过系统调用输出
+#[inline(never)]
+pub fn write_backtrace(print: fn(fmt::Arguments)) {
+    print(format_args!("backtrace:\n"));
+    let mut index = 0;
+    walk(|ra| {
+        // 返回地址可能已经是下一个函数（调用不返回的函数位于末尾时），用 ra-1 查找调用点
+        match ksyms::lookup(ra - 1) {
+            Some((name, offset)) => print(format_args!(
+                "  #{:<2} {:#018x} {}+{:#x}\n",
+                index,
+                ra,
+                name,
+                offset + 1
+            )),
+            None => print(format_args!("  #{:<2} {:#018x} <unknown>\n", index, ra)),
+        }
+        index += 1;
+    });
+}
@@ -0,0 +1,84 @@
+// src/debug/gdb/memory.rs
+//! 调试桩的内存访问。访问前先查页表，地址无效时返回错误而不是在调试桩里触发缺页；
+//! 代码段没有写权限，写入断点时临时给叶子页表项加上 W。
+
+use core::arch::asm;
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::mm::{
+    PAGE_SIZE,
+    address::VirtAddr,
+    kernel_page_table,
+    pagetable::{PTEFlags, PageTableEntry},
+};
+
+fn leaf_pte(va: usize) -> Option<&'static mut PageTableEntry> {
+    // 非规范地址会被 VirtAddr::from 截断成另一个地址，必须先排除
+    let addr = VirtAddr::from(va);
+    if addr.0 != va {
+        return None;
+    }
+    kernel_page_table().find_leaf_pte(addr.floor())
+}
+
+/// [addr, addr + len) 覆盖的每一页都已映射且满足 check
+fn all_pages(addr: usize, len: usize, check: impl Fn(PTEFlags) -> bool) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    let mut page = addr & !(PAGE_SIZE - 1);
+    while page < end {
+        match leaf_pte(page) {
+            Some(pte) if check(pte.flags()) => {}
+            _ => return false,
+        }
+        match page.checked_add(PAGE_SIZE) {
+            Some(next) => page = next,
+            None => break,
+        }
+    }
+    true
+}
+
+fn sfence(va: usize) {
+    unsafe { asm!("sfence.vma {}, zero", in(reg) va) };
+}
+
+/// 读取 buf.len() 个字节，任何一页不可读时不读取并返回 false
+pub fn read(addr: usize, buf: &mut [u8]) -> bool {
+    if !all_pages(addr, buf.len(), |flags| flags.contains(PTEFlags::R)) {
+        return false;
+    }
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = unsafe { read_volatile((addr + i) as *const u8) };
+    }
+    true
+}
+
+/// 写入 data，允许写只读的代码页；写完后刷新指令缓存
+pub fn write(addr: usize, data: &[u8]) -> bool {
+    if !all_pages(addr, data.len(), |flags| {
+        flags.intersects(PTEFlags::W | PTEFlags::X)
+    }) {
+        return false;
+    }
+    let writable = PTEFlags::W.bits() as usize;
+    for (i, &byte) in data.iter().enumerate() {
+        let va = addr + i;
+        let Some(pte) = leaf_pte(va) else {
+            return false;
+        };
+        let original = pte.bits;
+        if original & writable == 0 {
+            pte.bits |= writable;
+            sfence(va);
+        }
+        unsafe { write_volatile(va as *mut u8, byte) };
+        if pte.bits != original {
+            pte.bits = original;
+            sfence(va);
+        }
+    }
+    unsafe { asm!("fence.i") };
+    true
+}
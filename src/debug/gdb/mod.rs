@@ -0,0 +1,790 @@
+// src/debug/gdb/mod.rs
+//! 运行在第二个串口上的 GDB 远程调试桩（Remote Serial Protocol）。
+//! 没有第二个串口时（例如 QEMU virt），启动参数带 `gdb=console` 可以让调试桩与控制台共用串口，
+//! 这时控制台的输入都交给调试桩，输出与 GDB 报文混在一起，GDB 会丢弃报文以外的字节。
+//!
+//! ebreak 陷入和调试串口的接收中断（GDB 连接、Ctrl-C）都会进入调试桩，
+//! 之后在关中断的状态下轮询串口处理 GDB 的请求，直到 GDB 让目标继续运行。
+//! 断点是写进代码里的 ebreak，单步由 step 模块解码下一条指令后放临时断点实现。
+//! GDB 的线程对应调度器中的任务，线程号为任务号加一（0 在协议里表示“任意线程”）。
+
+mod memory;
+mod packet;
+mod step;
+
+use core::arch::asm;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use fdt::Fdt;
+use spin::mutex::SpinMutex;
+
+use crate::bsp::qemu_virt::{QemuVirt, UART_BASE, mmio_va};
+use crate::debug::ksyms;
+use crate::driver::Uart;
+use crate::driver::plic::PLIC;
+use crate::system::SystemControl;
+use crate::task::SCHEDULER;
+use crate::task::context::TaskContext;
+use crate::{info, warn};
+use packet::{Connection, HexWriter, Incoming, PACKET_SIZE, Response};
+
+/// 设备树中第二个 ns16550a 的物理地址，0 表示没有调试口
+static PORT_BASE: AtomicUsize = AtomicUsize::new(0);
+/// 调试口的 PLIC 中断号，0 表示没有中断
+static PORT_IRQ: AtomicU32 = AtomicU32::new(0);
+/// 调试桩与控制台共用串口，接收中断由控制台驱动转交过来
+static SHARES_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+static STUB: SpinMutex<Option<GdbStub>> = SpinMutex::new(None);
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const MAX_BREAKPOINTS: usize = 32;
+const EBREAK: u32 = 0x0010_0073;
+const C_EBREAK: u16 = 0x9002;
+
+const SIE_MASK: usize = (1 << 1) | (1 << 5) | (1 << 9); // SSIE | STIE | SEIE
+const SSTATUS_SPIE: usize = 1 << 5;
+
+/// x0-x31 和 pc，与 GDB 的 riscv:rv64 寄存器编号一致
+const NUM_REGS: usize = 33;
+
+const TARGET_XML: &[u8] = br#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+<architecture>riscv:rv64</architecture>
+<feature name="org.gnu.gdb.riscv.cpu">
+<reg name="zero" bitsize="64" type="int" regnum="0"/>
+<reg name="ra" bitsize="64" type="code_ptr"/>
+<reg name="sp" bitsize="64" type="data_ptr"/>
+<reg name="gp" bitsize="64" type="data_ptr"/>
+<reg name="tp" bitsize="64" type="data_ptr"/>
+<reg name="t0" bitsize="64" type="int"/>
+<reg name="t1" bitsize="64" type="int"/>
+<reg name="t2" bitsize="64" type="int"/>
+<reg name="fp" bitsize="64" type="data_ptr"/>
+<reg name="s1" bitsize="64" type="int"/>
+<reg name="a0" bitsize="64" type="int"/>
+<reg name="a1" bitsize="64" type="int"/>
+<reg name="a2" bitsize="64" type="int"/>
+<reg name="a3" bitsize="64" type="int"/>
+<reg name="a4" bitsize="64" type="int"/>
+<reg name="a5" bitsize="64" type="int"/>
+<reg name="a6" bitsize="64" type="int"/>
+<reg name="a7" bitsize="64" type="int"/>
+<reg name="s2" bitsize="64" type="int"/>
+<reg name="s3" bitsize="64" type="int"/>
+<reg name="s4" bitsize="64" type="int"/>
+<reg name="s5" bitsize="64" type="int"/>
+<reg name="s6" bitsize="64" type="int"/>
+<reg name="s7" bitsize="64" type="int"/>
+<reg name="s8" bitsize="64" type="int"/>
+<reg name="s9" bitsize="64" type="int"/>
+<reg name="s10" bitsize="64" type="int"/>
+<reg name="s11" bitsize="64" type="int"/>
+<reg name="t3" bitsize="64" type="int"/>
+<reg name="t4" bitsize="64" type="int"/>
+<reg name="t5" bitsize="64" type="int"/>
+<reg name="t6" bitsize="64" type="int"/>
+<reg name="pc" bitsize="64" type="code_ptr"/>
+</feature>
+</target>
+"#;
+
+/// 在设备树中寻找控制台以外的 ns16550a 作为调试口，在建立页表映射时调用
+pub fn probe_port(fdt: &Fdt) {
+    for node in fdt.all_nodes() {
+        let is_16550 = node
+            .compatible()
+            .is_some_and(|compatible| compatible.all().any(|name| name == "ns16550a"));
+        if !is_16550 {
+            continue;
+        }
+        let Some(region) = node.reg().and_then(|mut reg| reg.next()) else {
+            continue;
+        };
+        let base = region.starting_address as usize;
+        if base == UART_BASE {
+            continue;
+        }
+        let irq = node
+            .interrupts()
+            .and_then(|mut irqs| irqs.next())
+            .unwrap_or(0);
+        PORT_BASE.store(base, Ordering::Relaxed);
+        PORT_IRQ.store(irq as u32, Ordering::Relaxed);
+        info!("GDB stub port: {} at {:#x}, irq {}", node.name, base, irq);
+        return;
+    }
+    let on_console = fdt
+        .find_node("/chosen")
+        .and_then(|chosen| chosen.property("bootargs"))
+        .and_then(|bootargs| bootargs.as_str())
+        .is_some_and(|bootargs| bootargs.split_whitespace().any(|arg| arg == "gdb=console"));
+    if on_console {
+        PORT_BASE.store(UART_BASE, Ordering::Relaxed);
+        SHARES_CONSOLE.store(true, Ordering::Relaxed);
+        info!("GDB stub port: console UART at {:#x}", UART_BASE);
+        return;
+    }
+    info!("GDB stub disabled: no second ns16550a in device tree");
+}
+
+/// 初始化调试口并打开它的接收中断，必须在最终页表生效后调用
+pub fn init() {
+    let base = PORT_BASE.load(Ordering::Relaxed);
+    if base == 0 {
+        return;
+    }
+    let uart = Uart::new(mmio_va(base));
+    let irq = port_irq();
+    // 控制台串口已经由控制台驱动初始化，不能再改它的中断使能
+    if !shares_console() {
+        uart.init_polling(irq.is_some());
+    }
+    *STUB.lock() = Some(GdbStub::new(uart));
+    if let Some(irq) = irq {
+        PLIC::enable(irq);
+    }
+}
+
+pub fn port_irq() -> Option<u32> {
+    match PORT_IRQ.load(Ordering::Relaxed) {
+        0 => None,
+        irq => Some(irq),
+    }
+}
+
+pub fn shares_console() -> bool {
+    SHARES_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// trap_handler 遇到 ebreak 时调用。没有调试器接管时跳过这条指令继续执行
+pub fn handle_breakpoint(ctx: &mut TaskContext) {
+    let pc = ctx.sepc;
+    // 调试桩内部再次陷入时锁已被持有，只能跳过
+    if let Some(stub) = STUB.try_lock().as_deref_mut().and_then(Option::as_mut) {
+        stub.on_breakpoint(ctx);
+        return;
+    }
+    warn!("ebreak at {:#x} without debugger, skipped", pc);
+    ctx.sepc = pc + instruction_len_at(pc);
+}
+
+/// 调试口的接收中断：GDB 发来了新连接的第一个报文或 Ctrl-C。
+/// 与控制台共用串口时由控制台的中断调用，接收 FIFO 中的字节都由这里读走
+pub fn handle_interrupt(ctx: &mut TaskContext) {
+    if let Some(stub) = STUB.try_lock().as_deref_mut().and_then(Option::as_mut) {
+        stub.on_interrupt(ctx);
+    }
+}
+
+fn instruction_len_at(pc: usize) -> usize {
+    let mut low = [0u8; 2];
+    if memory::read(pc, &mut low) {
+        step::insn_len(u16::from_le_bytes(low))
+    } else {
+        4
+    }
+}
+
+fn read_instruction(pc: usize) -> Option<u32> {
+    let mut bytes = [0u8; 4];
+    if !memory::read(pc, &mut bytes[..2]) {
+        return None;
+    }
+    if step::insn_len(u16::from_le_bytes([bytes[0], bytes[1]])) == 4
+        && !memory::read(pc + 2, &mut bytes[2..])
+    {
+        return None;
+    }
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// TaskContext 是 repr(C) 的 31 个 usize：ra, sp, tp, t0, ... , t6, sepc。
+/// 其中没有 x0 和 gp，所以 x1、x2 的下标减一，x4 及之后（包括 pc）的下标减二
+const _: () = assert!(size_of::<TaskContext>() == 31 * size_of::<usize>());
+
+fn context_slot(regno: usize) -> Option<usize> {
+    match regno {
+        1 | 2 => Some(regno - 1),
+        4..=32 => Some(regno - 2),
+        _ => None,
+    }
+}
+
+fn read_reg(ctx: &TaskContext, regno: usize) -> usize {
+    match regno {
+        // gp 不随任务切换，直接读当前值
+        3 => {
+            let gp: usize;
+            unsafe { asm!("mv {}, gp", out(reg) gp) };
+            gp
+        }
+        _ => match context_slot(regno) {
+            Some(slot) => unsafe { *(ctx as *const TaskContext as *const usize).add(slot) },
+            None => 0,
+        },
+    }
+}
+
+fn write_reg(ctx: &mut TaskContext, regno: usize, value: usize) {
+    if let Some(slot) = context_slot(regno) {
+        unsafe { *(ctx as *mut TaskContext as *mut usize).add(slot) = value };
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    len: usize,
+    saved: [u8; 4],
+}
+
+impl Breakpoint {
+    fn insert(addr: usize, len: usize) -> Option<Self> {
+        let mut saved = [0u8; 4];
+        let ebreak = EBREAK.to_le_bytes();
+        let c_ebreak = C_EBREAK.to_le_bytes();
+        let insn: &[u8] = match len {
+            2 => &c_ebreak,
+            4 => &ebreak,
+            _ => return None,
+        };
+        if !memory::read(addr, &mut saved[..len]) || !memory::write(addr, insn) {
+            return None;
+        }
+        Some(Self { addr, len, saved })
+    }
+
+    fn remove(&self) {
+        memory::write(self.addr, &self.saved[..self.len]);
+    }
+}
+
+/// 单步期间放下的临时断点，以及被屏蔽前的中断使能状态
+struct Step {
+    temps: [Option<Breakpoint>; 2],
+    saved_sie: usize,
+    saved_spie: bool,
+}
+
+enum Action {
+    Reply,
+    NoAck,
+    Continue,
+    Step,
+    Detach,
+}
+
+struct GdbStub {
+    conn: Connection,
+    rx: [u8; PACKET_SIZE],
+    target: Target,
+}
+
+/// 调试桩除收发以外的状态。和接收缓冲区分开，处理报文时可以一边借用报文一边修改状态
+struct Target {
+    tx: Response,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    step: Option<Step>,
+    /// 本次停下时被打断的任务对应的线程号，调度器锁被占用时未知
+    current: Option<usize>,
+    /// Hg 选中的线程，None 表示被打断的任务
+    selected: Option<usize>,
+    signal: u8,
+}
+
+impl GdbStub {
+    fn new(uart: Uart) -> Self {
+        Self {
+            conn: Connection::new(uart),
+            rx: [0; PACKET_SIZE],
+            target: Target {
+                tx: Response::new(),
+                breakpoints: [None; MAX_BREAKPOINTS],
+                step: None,
+                current: None,
+                selected: None,
+                signal: SIGTRAP,
+            },
+        }
+    }
+
+    fn on_breakpoint(&mut self, ctx: &mut TaskContext) {
+        let pc = ctx.sepc;
+        let stepped = self.target.finish_step();
+        // 编译进代码的 ebreak 不是调试桩放的，继续运行时要跳过它
+        let foreign = !stepped && !self.target.has_breakpoint(pc);
+        self.target.stop(ctx, SIGTRAP);
+        self.send_stop_reply();
+        self.session(ctx, None);
+        if foreign && ctx.sepc == pc {
+            ctx.sepc = pc + instruction_len_at(pc);
+        }
+    }
+
+    fn on_interrupt(&mut self, ctx: &mut TaskContext) {
+        // 只消费到报文开头或 Ctrl-C 为止，零散的 ack 字节直接丢弃
+        while let Some(byte) = self.conn.try_read_byte() {
+            let pending = match byte {
+                0x03 => None,
+                b'$' => match self.conn.receive_body(&mut self.rx) {
+                    Some(len) => Some(len),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            self.target.stop(ctx, SIGINT);
+            if pending.is_none() {
+                self.send_stop_reply();
+            }
+            self.session(ctx, pending);
+            return;
+        }
+    }
+
+    /// 处理请求直到 GDB 让目标继续运行。pending 是进入前已经收到的报文长度
+    fn session(&mut self, ctx: &mut TaskContext, mut pending: Option<usize>) {
+        loop {
+            let len = match pending.take() {
+                Some(len) => len,
+                None => match self.conn.receive(&mut self.rx) {
+                    Incoming::Packet(len) => len,
+                    Incoming::Interrupt => {
+                        self.send_stop_reply();
+                        continue;
+                    }
+                },
+            };
+            self.target.tx.clear();
+            match self.target.handle_packet(ctx, &self.rx[..len]) {
+                Action::Reply => self.conn.send(self.target.tx.as_bytes()),
+                Action::NoAck => {
+                    self.conn.send(b"OK");
+                    self.conn.set_no_ack();
+                }
+                Action::Continue => return,
+                Action::Step => {
+                    if self.target.start_step(ctx) {
+                        return;
+                    }
+                    self.conn.send(b"E01");
+                }
+                Action::Detach => {
+                    self.target.remove_all_breakpoints();
+                    self.conn.send(b"OK");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn send_stop_reply(&mut self) {
+        self.target.tx.clear();
+        self.target.write_stop_reply();
+        self.conn.send(self.target.tx.as_bytes());
+    }
+}
+
+impl Target {
+    fn stop(&mut self, ctx: &TaskContext, signal: u8) {
+        self.signal = signal;
+        self.selected = None;
+        self.current = SCHEDULER.try_lock().and_then(|mut scheduler| {
+            let tcb = scheduler.current_tcb()?;
+            // sscratch 指向的上下文不是当前任务时（例如启动阶段），不把它当成任务
+            core::ptr::eq(&tcb.context, ctx).then_some(tcb.task_id + 1)
+        });
+    }
+
+    fn write_stop_reply(&mut self) {
+        let _ = write!(self.tx, "T{:02x}", self.signal);
+        if let Some(tid) = self.current {
+            let _ = write!(self.tx, "thread:{:x};", tid);
+        }
+    }
+
+    fn has_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints.iter().flatten().any(|bp| bp.addr == addr)
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(bp) = slot.take() {
+                bp.remove();
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, ctx: &mut TaskContext, packet: &[u8]) -> Action {
+        let Some((&command, args)) = packet.split_first() else {
+            return Action::Reply;
+        };
+        match command {
+            b'?' => self.write_stop_reply(),
+            b'q' => self.handle_query(ctx, args),
+            b'Q' if args == b"StartNoAckMode" => return Action::NoAck,
+            b'H' => {
+                // Hg 选择读写寄存器的线程；Hc 只能继续整个系统，忽略
+                if let Some((&b'g', tid)) = args.split_first() {
+                    self.selected = parse_thread(tid);
+                }
+                self.tx.push(b"OK");
+            }
+            b'T' => {
+                let alive = parse_thread(args).is_some_and(|tid| self.thread_exists(tid));
+                self.tx.push(if alive { b"OK" } else { b"E01" });
+            }
+            b'g' => {
+                let selected = self.selected;
+                let regs = self.with_context(ctx, selected, |context| {
+                    let mut values = [0usize; NUM_REGS];
+                    for (regno, value) in values.iter_mut().enumerate() {
+                        *value = read_reg(context, regno);
+                    }
+                    values
+                });
+                match regs {
+                    Some(values) => values.iter().for_each(|&v| self.tx.push_hex_le(v)),
+                    None => self.tx.push(b"E01"),
+                }
+            }
+            b'G' => {
+                let selected = self.selected;
+                let mut values = [0usize; NUM_REGS];
+                let mut parsed = args.len() >= NUM_REGS * 16;
+                for (value, hex) in values.iter_mut().zip(args.chunks_exact(16)) {
+                    match packet::parse_hex_le(hex) {
+                        Some(v) => *value = v,
+                        None => parsed = false,
+                    }
+                }
+                let written = parsed
+                    && self
+                        .with_context(ctx, selected, |context| {
+                            for (regno, &value) in values.iter().enumerate() {
+                                write_reg(context, regno, value);
+                            }
+                        })
+                        .is_some();
+                self.tx.push(if written { b"OK" } else { b"E01" });
+            }
+            b'p' => {
+                let selected = self.selected;
+                let value = packet::parse_hex(args)
+                    .filter(|&regno| regno < NUM_REGS)
+                    .and_then(|regno| {
+                        self.with_context(ctx, selected, |context| read_reg(context, regno))
+                    });
+                match value {
+                    Some(value) => self.tx.push_hex_le(value),
+                    None => self.tx.push(b"E01"),
+                }
+            }
+            b'P' => {
+                let selected = self.selected;
+                let written = split_once(args, b'=')
+                    .and_then(|(regno, value)| {
+                        Some((packet::parse_hex(regno)?, packet::parse_hex_le(value)?))
+                    })
+                    .filter(|&(regno, _)| regno < NUM_REGS)
+                    .and_then(|(regno, value)| {
+                        self.with_context(ctx, selected, |context| write_reg(context, regno, value))
+                    })
+                    .is_some();
+                self.tx.push(if written { b"OK" } else { b"E01" });
+            }
+            b'm' => {
+                let range = split_once(args, b',').and_then(|(addr, len)| {
+                    Some((packet::parse_hex(addr)?, packet::parse_hex(len)?))
+                });
+                match range {
+                    Some((addr, len)) if len <= PACKET_SIZE / 2 => self.read_memory(addr, len),
+                    _ => self.tx.push(b"E01"),
+                }
+            }
+            b'M' => {
+                let ok = split_once(args, b':').is_some_and(|(range, data)| {
+                    let Some((addr, len)) = split_once(range, b',').and_then(|(addr, len)| {
+                        Some((packet::parse_hex(addr)?, packet::parse_hex(len)?))
+                    }) else {
+                        return false;
+                    };
+                    write_hex_memory(addr, len, data)
+                });
+                self.tx.push(if ok { b"OK" } else { b"E01" });
+            }
+            b'Z' | b'z' => self.handle_breakpoint_packet(command == b'Z', args),
+            b'c' | b's' | b'C' | b'S' => {
+                // c[addr] / s[addr] / Csig[;addr] / Ssig[;addr]
+                let addr = if command.is_ascii_uppercase() {
+                    split_once(args, b';').map(|(_, addr)| addr)
+                } else {
+                    Some(args).filter(|addr| !addr.is_empty())
+                };
+                if let Some(addr) = addr.and_then(packet::parse_hex) {
+                    ctx.sepc = addr;
+                }
+                return if command.eq_ignore_ascii_case(&b's') {
+                    Action::Step
+                } else {
+                    Action::Continue
+                };
+            }
+            b'v' => return self.handle_v_packet(args),
+            b'D' => return Action::Detach,
+            // 没有进程可杀，kill 就是关机
+            b'k' => QemuVirt.shutdown(),
+            _ => {}
+        }
+        Action::Reply
+    }
+
+    fn handle_query(&mut self, ctx: &TaskContext, args: &[u8]) {
+        if args.starts_with(b"Supported") {
+            let _ = write!(
+                self.tx,
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;vContSupported+",
+                PACKET_SIZE
+            );
+        } else if let Some(rest) = args.strip_prefix(b"Xfer:features:read:target.xml:") {
+            let range = split_once(rest, b',').and_then(|(offset, len)| {
+                Some((packet::parse_hex(offset)?, packet::parse_hex(len)?))
+            });
+            match range {
+                Some((offset, len)) => {
+                    let start = offset.min(TARGET_XML.len());
+                    let end = start.saturating_add(len).min(TARGET_XML.len());
+                    self.tx
+                        .push(if end == TARGET_XML.len() { b"l" } else { b"m" });
+                    self.tx.push(&TARGET_XML[start..end]);
+                }
+                None => self.tx.push(b"E00"),
+            }
+        } else if args == b"Attached" {
+            self.tx.push(b"1");
+        } else if args == b"C" {
+            if let Some(tid) = self.current {
+                let _ = write!(self.tx, "QC{:x}", tid);
+            }
+        } else if args == b"fThreadInfo" {
+            self.tx.push(b"m");
+            match SCHEDULER.try_lock() {
+                Some(scheduler) => {
+                    for (i, tcb) in scheduler.tasks().enumerate() {
+                        if i > 0 {
+                            self.tx.push(b",");
+                        }
+                        let _ = write!(self.tx, "{:x}", tcb.task_id + 1);
+                    }
+                }
+                None => {
+                    let _ = write!(self.tx, "{:x}", self.current.unwrap_or(1));
+                }
+            }
+        } else if args == b"sThreadInfo" {
+            self.tx.push(b"l");
+        } else if let Some(tid) = args.strip_prefix(b"ThreadExtraInfo,") {
+            self.thread_extra_info(ctx, parse_thread(tid));
+        }
+    }
+
+    /// info threads 显示的附加信息：任务状态和它停在哪个函数
+    fn thread_extra_info(&mut self, ctx: &TaskContext, tid: Option<usize>) {
+        let Some(tid) = tid else {
+            return;
+        };
+        let Some(scheduler) = SCHEDULER.try_lock() else {
+            return;
+        };
+        let Some(tcb) = scheduler.get_task(tid - 1) else {
+            return;
+        };
+        let pc = if self.current == Some(tid) {
+            ctx.sepc
+        } else {
+            tcb.context.sepc
+        };
+        let mut writer = HexWriter(&mut self.tx);
+        let _ = write!(writer, "task {} {:?}", tcb.task_id, tcb.status);
+        if let Some((name, offset)) = ksyms::lookup(pc) {
+            let _ = write!(writer, " in {}+{:#x}", name, offset);
+        }
+    }
+
+    fn handle_v_packet(&mut self, args: &[u8]) -> Action {
+        if args == b"Cont?" {
+            self.tx.push(b"vCont;c;C;s;S");
+            return Action::Reply;
+        }
+        let Some(actions) = args.strip_prefix(b"Cont;") else {
+            // 包括 vMustReplyEmpty 在内的其他 v 报文都回复空
+            return Action::Reply;
+        };
+        // 只有被打断的任务在运行，带线程号的单步也只能作用在它身上
+        let step = actions
+            .split(|&b| b == b';')
+            .any(|action| matches!(action.first(), Some(b's' | b'S')));
+        if step { Action::Step } else { Action::Continue }
+    }
+
+    fn handle_breakpoint_packet(&mut self, insert: bool, args: &[u8]) {
+        let mut fields = args.split(|&b| b == b',');
+        let (Some(b"0"), Some(addr), Some(kind)) = (fields.next(), fields.next(), fields.next())
+        else {
+            // 只支持软件断点，其他类型回复空表示不支持
+            return;
+        };
+        let (Some(addr), Some(kind)) = (packet::parse_hex(addr), packet::parse_hex(kind)) else {
+            self.tx.push(b"E01");
+            return;
+        };
+        let existing = self
+            .breakpoints
+            .iter()
+            .position(|slot| slot.is_some_and(|bp| bp.addr == addr));
+        let ok = if insert {
+            existing.is_some()
+                || match self.breakpoints.iter().position(Option::is_none) {
+                    Some(slot) => {
+                        self.breakpoints[slot] = Breakpoint::insert(addr, kind);
+                        self.breakpoints[slot].is_some()
+                    }
+                    None => false,
+                }
+        } else {
+            if let Some(bp) = existing.and_then(|slot| self.breakpoints[slot].take()) {
+                bp.remove();
+            }
+            true
+        };
+        self.tx.push(if ok { b"OK" } else { b"E01" });
+    }
+
+    fn read_memory(&mut self, addr: usize, len: usize) {
+        let mut chunk = [0u8; 64];
+        let mut done = 0;
+        while done < len {
+            let count = (len - done).min(chunk.len());
+            if !memory::read(addr + done, &mut chunk[..count]) {
+                break;
+            }
+            self.tx.push_hex_bytes(&chunk[..count]);
+            done += count;
+        }
+        // 一个字节都读不出来时报错，否则返回能读到的前缀
+        if done == 0 && len > 0 {
+            self.tx.push(b"E01");
+        }
+    }
+
+    fn thread_exists(&self, tid: usize) -> bool {
+        if self.current == Some(tid) {
+            return true;
+        }
+        SCHEDULER
+            .try_lock()
+            .is_some_and(|scheduler| scheduler.get_task(tid - 1).is_some())
+    }
+
+    /// 在线程 tid 的上下文上执行 f。被打断的任务使用陷入时保存的上下文，
+    /// 其他任务使用 TCB 中保存的上下文；调度器锁被占用时只能访问被打断的任务
+    fn with_context<R>(
+        &self,
+        ctx: &mut TaskContext,
+        tid: Option<usize>,
+        f: impl FnOnce(&mut TaskContext) -> R,
+    ) -> Option<R> {
+        match tid {
+            None => Some(f(ctx)),
+            Some(tid) if self.current == Some(tid) => Some(f(ctx)),
+            Some(tid) => {
+                let mut scheduler = SCHEDULER.try_lock()?;
+                let tcb = scheduler.get_task_mut(tid - 1)?;
+                Some(f(&mut tcb.context))
+            }
+        }
+    }
+
+    /// 在下一条指令的所有可能去向放临时断点，并屏蔽中断，保证单步的是被打断的任务
+    fn start_step(&mut self, ctx: &TaskContext) -> bool {
+        let Some(insn) = read_instruction(ctx.sepc) else {
+            return false;
+        };
+        let mut temps = [None; 2];
+        for (slot, target) in temps
+            .iter_mut()
+            .zip(step::next_pcs(ctx.sepc, insn, |regno| read_reg(ctx, regno)))
+        {
+            let Some(target) = target else {
+                continue;
+            };
+            // 已有断点的地址不需要再放
+            if self.has_breakpoint(target) {
+                continue;
+            }
+            *slot = Breakpoint::insert(target, instruction_len_at(target));
+        }
+        let saved_sie: usize;
+        let sstatus: usize;
+        unsafe {
+            asm!("csrrc {}, sie, {}", out(reg) saved_sie, in(reg) SIE_MASK);
+            asm!("csrrc {}, sstatus, {}", out(reg) sstatus, in(reg) SSTATUS_SPIE);
+        }
+        self.step = Some(Step {
+            temps,
+            saved_sie,
+            saved_spie: sstatus & SSTATUS_SPIE != 0,
+        });
+        true
+    }
+
+    /// 单步结束：撤掉临时断点并恢复中断。返回这次陷入是否来自单步
+    fn finish_step(&mut self) -> bool {
+        let Some(step) = self.step.take() else {
+            return false;
+        };
+        for bp in step.temps.iter().flatten() {
+            bp.remove();
+        }
+        unsafe {
+            asm!("csrs sie, {}", in(reg) step.saved_sie & SIE_MASK);
+            if step.saved_spie {
+                asm!("csrs sstatus, {}", in(reg) SSTATUS_SPIE);
+            }
+        }
+        true
+    }
+}
+
+fn split_once(s: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = s.iter().position(|&b| b == separator)?;
+    Some((&s[..index], &s[index + 1..]))
+}
+
+/// 线程号：-1（全部）和 0（任意）都按被打断的任务处理，返回 None
+fn parse_thread(s: &[u8]) -> Option<usize> {
+    if s.starts_with(b"-") {
+        return None;
+    }
+    packet::parse_hex(s).filter(|&tid| tid != 0)
+}
+
+fn write_hex_memory(addr: usize, len: usize, data: &[u8]) -> bool {
+    if data.len() != len * 2 {
+        return false;
+    }
+    let mut chunk = [0u8; 64];
+    for (i, hex) in data.chunks(chunk.len() * 2).enumerate() {
+        let Some(count) = packet::decode_hex(hex, &mut chunk) else {
+            return false;
+        };
+        if !memory::write(addr + i * 64, &chunk[..count]) {
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,233 @@
+// src/debug/gdb/packet.rs
+//! RSP 报文的收发。报文格式为 `$<数据>#<两位十六进制校验和>`，
+//! 数据中的 `$ # } *` 以 `}` 加原字节异或 0x20 的形式转义。
+
+use core::fmt;
+
+use crate::driver::Uart;
+
+/// 收发缓冲区大小，同时通过 qSupported 的 PacketSize 告知 GDB
+pub const PACKET_SIZE: usize = 0x1000;
+/// GDB 在目标运行时按下 Ctrl-C 发送的字节
+const INTERRUPT: u8 = 0x03;
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+pub enum Incoming {
+    Packet(usize),
+    Interrupt,
+}
+
+pub struct Connection {
+    uart: Uart,
+    no_ack: bool,
+}
+
+impl Connection {
+    pub fn new(uart: Uart) -> Self {
+        Self {
+            uart,
+            no_ack: false,
+        }
+    }
+
+    /// QStartNoAckMode 之后双方都不再发送 '+'/'-'
+    pub fn set_no_ack(&mut self) {
+        self.no_ack = true;
+    }
+
+    pub fn try_read_byte(&self) -> Option<u8> {
+        self.uart.getchar_polling()
+    }
+
+    fn read_byte(&self) -> u8 {
+        loop {
+            if let Some(byte) = self.uart.getchar_polling() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// 阻塞直到收到一个校验正确的报文或 Ctrl-C
+    pub fn receive(&mut self, buf: &mut [u8]) -> Incoming {
+        loop {
+            match self.read_byte() {
+                b'$' => {
+                    if let Some(len) = self.receive_body(buf) {
+                        return Incoming::Packet(len);
+                    }
+                }
+                INTERRUPT => return Incoming::Interrupt,
+                // ack 以及连接建立前的杂散字节
+                _ => {}
+            }
+        }
+    }
+
+    /// '$' 已经被读走，读取报文体并去掉转义；校验失败时回复 '-' 并返回 None
+    pub fn receive_body(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let mut len = 0;
+        let mut sum: u8 = 0;
+        let mut overflow = false;
+        let mut escaped = false;
+        loop {
+            let byte = self.read_byte();
+            if byte == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(byte);
+            let value = if escaped {
+                escaped = false;
+                byte ^ 0x20
+            } else if byte == b'}' {
+                escaped = true;
+                continue;
+            } else {
+                byte
+            };
+            match buf.get_mut(len) {
+                Some(slot) => {
+                    *slot = value;
+                    len += 1;
+                }
+                None => overflow = true,
+            }
+        }
+        let high = hex_digit(self.read_byte());
+        let low = hex_digit(self.read_byte());
+        let valid = !overflow && matches!((high, low), (Some(h), Some(l)) if (h << 4 | l) == sum);
+        if !self.no_ack {
+            self.uart.putchar_polling(if valid { b'+' } else { b'-' });
+        }
+        valid.then_some(len)
+    }
+
+    /// 发送一个报文，ack 模式下等到 GDB 回复 '+'，收到 '-' 时重发
+    pub fn send(&mut self, data: &[u8]) {
+        loop {
+            self.uart.putchar_polling(b'$');
+            let mut sum: u8 = 0;
+            for &byte in data {
+                if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                    self.put(b'}', &mut sum);
+                    self.put(byte ^ 0x20, &mut sum);
+                } else {
+                    self.put(byte, &mut sum);
+                }
+            }
+            self.uart.putchar_polling(b'#');
+            self.uart.putchar_polling(HEX[(sum >> 4) as usize]);
+            self.uart.putchar_polling(HEX[(sum & 0xf) as usize]);
+            if self.no_ack {
+                return;
+            }
+            loop {
+                match self.read_byte() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn put(&self, byte: u8, sum: &mut u8) {
+        *sum = sum.wrapping_add(byte);
+        self.uart.putchar_polling(byte);
+    }
+}
+
+/// 待发送的回复，超出 PACKET_SIZE 的部分直接截断
+pub struct Response {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Response {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        let count = bytes.len().min(PACKET_SIZE - self.len);
+        self.buf[self.len..self.len + count].copy_from_slice(&bytes[..count]);
+        self.len += count;
+    }
+
+    pub fn push_hex_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(&[HEX[(byte >> 4) as usize], HEX[(byte & 0xf) as usize]]);
+        }
+    }
+
+    /// 寄存器按目标字节序（小端）逐字节输出
+    pub fn push_hex_le(&mut self, value: usize) {
+        self.push_hex_bytes(&value.to_le_bytes());
+    }
+}
+
+impl fmt::Write for Response {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// 把格式化输出以十六进制编码写入回复，用于 qThreadExtraInfo 等字段
+pub struct HexWriter<'a>(pub &'a mut Response);
+
+impl fmt::Write for HexWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.push_hex_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// 解析大端书写的十六进制数（地址、长度、线程号等）
+pub fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0usize, |acc, &c| Some(acc << 4 | hex_digit(c)? as usize))
+}
+
+/// 把十六进制串解码到 out，返回写入的字节数
+pub fn decode_hex(s: &[u8], out: &mut [u8]) -> Option<usize> {
+    if !s.len().is_multiple_of(2) || s.len() / 2 > out.len() {
+        return None;
+    }
+    for (slot, pair) in out.iter_mut().zip(s.chunks_exact(2)) {
+        *slot = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+    }
+    Some(s.len() / 2)
+}
+
+/// 解析按小端字节序编码的寄存器值
+pub fn parse_hex_le(s: &[u8]) -> Option<usize> {
+    let mut bytes = [0u8; 8];
+    if decode_hex(s, &mut bytes)? != bytes.len() {
+        return None;
+    }
+    Some(usize::from_le_bytes(bytes))
+}
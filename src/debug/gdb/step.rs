@@ -0,0 +1,124 @@
+// src/debug/gdb/step.rs
+//! 软件单步。S 态没有单步陷入，只能解码当前指令，算出所有可能执行的下一条指令地址，
+//! 在这些地址临时放上 ebreak。条件分支的两个去向都放，不去求值条件。
+
+/// 由指令的低 16 位判断长度：最低两位不是 0b11 的是压缩指令
+pub fn insn_len(low_half: u16) -> usize {
+    if low_half & 0b11 == 0b11 { 4 } else { 2 }
+}
+
+fn sign_extend(value: u32, bits: u32) -> isize {
+    let shift = 32 - bits;
+    ((value << shift) as i32 >> shift) as isize
+}
+
+fn bits(insn: u32, high: u32, low: u32) -> u32 {
+    (insn >> low) & ((1 << (high - low + 1)) - 1)
+}
+
+/// pc 处指令执行后可能到达的地址，reg(n) 读取寄存器 xn
+pub fn next_pcs(pc: usize, insn: u32, reg: impl Fn(usize) -> usize) -> [Option<usize>; 2] {
+    let len = insn_len(insn as u16);
+    let fallthrough = pc.wrapping_add(len);
+    let relative = |offset: isize| Some(pc.wrapping_add_signed(offset));
+
+    if len == 4 {
+        let rs1 = bits(insn, 19, 15) as usize;
+        match insn & 0x7f {
+            // JAL
+            0x6f => {
+                let imm = bits(insn, 31, 31) << 20
+                    | bits(insn, 30, 21) << 1
+                    | bits(insn, 20, 20) << 11
+                    | bits(insn, 19, 12) << 12;
+                [relative(sign_extend(imm, 21)), None]
+            }
+            // JALR
+            0x67 => {
+                let imm = sign_extend(bits(insn, 31, 20), 12);
+                [Some(reg(rs1).wrapping_add_signed(imm) & !1), None]
+            }
+            // BRANCH
+            0x63 => {
+                let imm = bits(insn, 31, 31) << 12
+                    | bits(insn, 30, 25) << 5
+                    | bits(insn, 11, 8) << 1
+                    | bits(insn, 7, 7) << 11;
+                [relative(sign_extend(imm, 13)), Some(fallthrough)]
+            }
+            _ => [Some(fallthrough), None],
+        }
+    } else {
+        let quadrant = insn & 0b11;
+        let funct3 = bits(insn, 15, 13);
+        match (quadrant, funct3) {
+            // C.J
+            (0b01, 0b101) => {
+                let imm = bits(insn, 12, 12) << 11
+                    | bits(insn, 11, 11) << 4
+                    | bits(insn, 10, 9) << 8
+                    | bits(insn, 8, 8) << 10
+                    | bits(insn, 7, 7) << 6
+                    | bits(insn, 6, 6) << 7
+                    | bits(insn, 5, 3) << 1
+                    | bits(insn, 2, 2) << 5;
+                [relative(sign_extend(imm, 12)), None]
+            }
+            // C.BEQZ / C.BNEZ
+            (0b01, 0b110 | 0b111) => {
+                let imm = bits(insn, 12, 12) << 8
+                    | bits(insn, 11, 10) << 3
+                    | bits(insn, 6, 5) << 6
+                    | bits(insn, 4, 3) << 1
+                    | bits(insn, 2, 2) << 5;
+                [relative(sign_extend(imm, 9)), Some(fallthrough)]
+            }
+            // C.JR / C.JALR（rs2 为 0 且 rs1 不为 0）
+            (0b10, 0b100) if bits(insn, 6, 2) == 0 && bits(insn, 11, 7) != 0 => {
+                [Some(reg(bits(insn, 11, 7) as usize) & !1), None]
+            }
+            _ => [Some(fallthrough), None],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insn_len, next_pcs};
+    use crate::{kassert_eq, kernel_test};
+
+    const PC: usize = 0xffff_ffff_8020_1000;
+
+    fn regs(n: usize) -> usize {
+        match n {
+            1 => 0xffff_ffff_8020_4242,  // ra
+            10 => 0xffff_ffff_8030_0000, // a0
+            _ => 0,
+        }
+    }
+
+    kernel_test! {
+        fn decodes_control_flow() {
+            // addi a0, a0, 1 / c.nop
+            kassert_eq!(next_pcs(PC, 0x0015_0513, regs), [Some(PC + 4), None]);
+            kassert_eq!(next_pcs(PC, 0x0001, regs), [Some(PC + 2), None]);
+            // jal ra, -2048 / j 0x1000
+            kassert_eq!(next_pcs(PC, 0x801f_f0ef, regs), [Some(PC - 2048), None]);
+            kassert_eq!(next_pcs(PC, 0x0000_106f, regs), [Some(PC + 0x1000), None]);
+            // ret，jalr 的目标清掉最低位
+            kassert_eq!(next_pcs(PC, 0x0000_8067, regs), [Some(0xffff_ffff_8020_4242), None]);
+            // beq a0, a1, -16 两个去向都要
+            kassert_eq!(next_pcs(PC, 0xfeb5_08e3, regs), [Some(PC - 16), Some(PC + 4)]);
+            // c.j -6 / c.beqz a0, 64 / c.bnez a0, -256
+            kassert_eq!(next_pcs(PC, 0xbfed, regs), [Some(PC - 6), None]);
+            kassert_eq!(next_pcs(PC, 0xc121, regs), [Some(PC + 64), Some(PC + 2)]);
+            kassert_eq!(next_pcs(PC, 0xf101, regs), [Some(PC - 256), Some(PC + 2)]);
+            // c.jr ra / c.jalr a0；c.ebreak 不是跳转
+            kassert_eq!(next_pcs(PC, 0x8082, regs), [Some(0xffff_ffff_8020_4242), None]);
+            kassert_eq!(next_pcs(PC, 0x9502, regs), [Some(0xffff_ffff_8030_0000), None]);
+            kassert_eq!(next_pcs(PC, 0x9002, regs), [Some(PC + 2), None]);
+            kassert_eq!(insn_len(0x9002), 2);
+            kassert_eq!(insn_len(0x0073), 4);
+        }
+    }
+}
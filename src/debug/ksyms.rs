@@ -0,0 +1,52 @@
+// src/debug/ksyms.rs
+//! 内核符号表。build.sh 从链接好的内核中导出函数符号，由 build.rs 嵌入 .ksyms 段，
+//! 格式为按地址升序排列的文本行 "<16 位十六进制地址> <函数名>"。
+
+use core::{slice, str};
+
+const KSYMS_LEN: usize = include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.txt")).len();
+
+#[used]
+#[unsafe(link_section = ".ksyms")]
+static KSYMS: [u8; KSYMS_LEN] = *include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.txt"));
+
+unsafe extern "C" {
+    static _ksyms_start: u8;
+    static _ksyms_end: u8;
+    static _text_end: u8;
+}
+
+/// 通过链接脚本给出的边界访问符号表，表的长度不会作为常量编进代码，
+/// 嵌入不同大小的符号表时函数地址保持不变
+fn table() -> &'static [u8] {
+    unsafe {
+        let start = &raw const _ksyms_start;
+        let end = &raw const _ksyms_end;
+        slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+fn parse_line(line: &[u8]) -> Option<(usize, &str)> {
+    let (addr, name) = line.split_at_checked(16)?;
+    let addr = usize::from_str_radix(str::from_utf8(addr).ok()?, 16).ok()?;
+    let name = str::from_utf8(name.get(1..)?).ok()?;
+    Some((addr, name))
+}
+
+/// 查找包含 pc 的函数，返回函数名和 pc 相对函数入口的偏移
+pub fn lookup(pc: usize) -> Option<(&'static str, usize)> {
+    if pc >= &raw const _text_end as usize {
+        return None;
+    }
+    let mut found = None;
+    for line in table().split(|&byte| byte == b'\n') {
+        let Some((addr, name)) = parse_line(line) else {
+            continue;
+        };
+        if addr > pc {
+            break;
+        }
+        found = Some((name, pc - addr));
+    }
+    found
+}
@@ -0,0 +1,7 @@
+// src/debug/mod.rs
+//! 调试支持：内核符号表、调用栈回溯、GDB 远程调试桩和采样分析器。
+
+pub mod backtrace;
+pub mod gdb;
+pub mod ksyms;
+pub mod profile;
@@ -0,0 +1,260 @@
+// src/debug/profile.rs
+//! 基于时钟中断的采样分析器。开启后每个时钟中断记录被打断的 sepc、任务号和帧指针调用链，
+//! 存入当前 hart 的采样缓冲区，再通过 /dev/profile 以文本读出，由宿主机上的
+//! profile_fold.sh 符号化并折叠成火焰图工具可用的格式。
+//!
+//! 采样频率可以高于调度频率：此时时钟中断按采样频率到来，每个调度周期只有一个 tick 进入调度器。
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{
+    bsp::get_hart_id,
+    config::MAX_HARTS,
+    data_struct::{lock::IrqLock, ring_buf::RingBuffer},
+    debug::backtrace,
+    fs::devfs::CharDevice,
+    info,
+    syslib::errno::Errno,
+    task::{SCHEDULER, context::TaskContext},
+};
+
+/// 调度频率，也是默认的采样频率
+pub const SCHED_HZ: usize = 100;
+/// 最高采样频率
+pub const MAX_PROFILE_HZ: usize = 10_000;
+
+// /dev/profile 的 ioctl 命令
+/// 开始采样，参数为采样频率（Hz），必须是 SCHED_HZ 的整数倍，0 表示使用 SCHED_HZ
+pub const PROFILE_START: usize = 0x5001;
+pub const PROFILE_STOP: usize = 0x5002;
+/// 丢弃尚未读出的采样
+pub const PROFILE_RESET: usize = 0x5003;
+
+/// 每个采样记录的最大深度（含 sepc）
+const MAX_DEPTH: usize = 12;
+const SAMPLES_PER_HART: usize = 2048;
+/// 一行采样的最大长度：任务号、MAX_DEPTH 个 16 位十六进制地址和空格、换行
+const MAX_LINE: usize = 24 + MAX_DEPTH * 17;
+
+#[derive(Clone, Copy)]
+struct Sample {
+    /// 被打断的任务，None 表示还没有进入调度器
+    task: Option<usize>,
+    depth: usize,
+    /// pcs[0] 是 sepc，之后是由内向外的返回地址
+    pcs: [usize; MAX_DEPTH],
+}
+
+struct SampleBuffer {
+    samples: RingBuffer<Sample, SAMPLES_PER_HART>,
+    /// 缓冲区满时丢弃的采样数
+    dropped: usize,
+}
+
+static BUFFERS: [IrqLock<SampleBuffer>; MAX_HARTS] = [const {
+    IrqLock::new(SampleBuffer {
+        samples: RingBuffer::new(),
+        dropped: 0,
+    })
+}; MAX_HARTS];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// 每个调度周期内的时钟中断数，即采样频率除以 SCHED_HZ
+static TICKS_PER_SLICE: AtomicUsize = AtomicUsize::new(1);
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn start(hz: usize) -> Result<(), Errno> {
+    let hz = if hz == 0 { SCHED_HZ } else { hz };
+    if hz % SCHED_HZ != 0 || hz > MAX_PROFILE_HZ {
+        return Err(Errno::EINVAL);
+    }
+    TICKS_PER_SLICE.store(hz / SCHED_HZ, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Release);
+    info!("profiling started at {} Hz", hz);
+    Ok(())
+}
+
+pub fn stop() {
+    if !ENABLED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    TICKS_PER_SLICE.store(1, Ordering::Relaxed);
+    let (pending, dropped) = BUFFERS.iter().fold((0, 0), |(pending, dropped), buffer| {
+        let buffer = buffer.lock();
+        (pending + buffer.samples.len(), dropped + buffer.dropped)
+    });
+    info!(
+        "profiling stopped: {} samples pending, {} dropped",
+        pending, dropped
+    );
+}
+
+pub fn reset() {
+    for buffer in BUFFERS.iter() {
+        let mut buffer = buffer.lock();
+        while buffer.samples.pop().is_some() {}
+        buffer.dropped = 0;
+    }
+}
+
+/// 采样期间需要周期性的时钟中断，idle 时也不能停掉 tick
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// 每个调度周期内的时钟中断数，set_next_timer_tick 据此缩短定时间隔
+pub fn ticks_per_slice() -> usize {
+    TICKS_PER_SLICE.load(Ordering::Relaxed)
+}
+
+/// 时钟中断入口调用：开启采样时记录一次采样。返回这个 tick 是否需要进入调度器
+pub fn on_timer_tick(ctx: &TaskContext) -> bool {
+    if !is_enabled() {
+        return true;
+    }
+    record(ctx);
+    TICKS
+        .fetch_add(1, Ordering::Relaxed)
+        .is_multiple_of(ticks_per_slice())
+}
+
+fn record(ctx: &TaskContext) {
+    let Some(buffer) = BUFFERS.get(get_hart_id()) else {
+        return;
+    };
+    let mut sample = Sample {
+        // 调度器锁被打断的代码持有时拿不到任务号，只记录地址
+        task: SCHEDULER
+            .try_lock()
+            .and_then(|mut scheduler| scheduler.current_tcb().map(|tcb| tcb.task_id)),
+        depth: 1,
+        pcs: [0; MAX_DEPTH],
+    };
+    sample.pcs[0] = ctx.sepc;
+    backtrace::walk_from(ctx.s0, |ra| {
+        if sample.depth < MAX_DEPTH {
+            sample.pcs[sample.depth] = ra;
+            sample.depth += 1;
+        }
+    });
+    let mut buffer = buffer.lock();
+    if buffer.samples.push(sample).is_err() {
+        buffer.dropped += 1;
+    }
+}
+
+/// 一行采样：`<任务号或 -> <sepc> <返回地址>...`，地址为 16 位十六进制
+fn format_sample(sample: &Sample, out: &mut impl Write) -> fmt::Result {
+    match sample.task {
+        Some(task) => write!(out, "{}", task)?,
+        None => out.write_char('-')?,
+    }
+    for pc in &sample.pcs[..sample.depth] {
+        write!(out, " {:016x}", pc)?;
+    }
+    out.write_char('\n')
+}
+
+struct LineBuf {
+    buf: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Write for LineBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// /dev/profile：读出并移除已记录的采样，每次只返回完整的行
+pub struct ProfileDevice;
+
+impl CharDevice for ProfileDevice {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut written = 0;
+        for buffer in BUFFERS.iter() {
+            let mut buffer = buffer.lock();
+            while let Some(sample) = buffer.samples.peek() {
+                let mut line = LineBuf {
+                    buf: [0; MAX_LINE],
+                    len: 0,
+                };
+                let _ = format_sample(sample, &mut line);
+                let Some(dest) = buf.get_mut(written..written + line.len) else {
+                    // 一行都放不下时报错，否则让调用者下次再读
+                    return if written == 0 {
+                        Err(Errno::EINVAL)
+                    } else {
+                        Ok(written)
+                    };
+                };
+                dest.copy_from_slice(&line.buf[..line.len]);
+                written += line.len;
+                buffer.samples.pop();
+            }
+        }
+        Ok(written)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<usize, Errno> {
+        match cmd {
+            PROFILE_START => start(arg).map(|()| 0),
+            PROFILE_STOP => {
+                stop();
+                Ok(0)
+            }
+            PROFILE_RESET => {
+                reset();
+                Ok(0)
+            }
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineBuf, MAX_DEPTH, MAX_LINE, Sample, format_sample};
+    use crate::{kassert, kassert_eq, kernel_test};
+
+    kernel_test! {
+        fn formats_samples() {
+            let mut sample = Sample {
+                task: Some(3),
+                depth: 2,
+                pcs: [0; MAX_DEPTH],
+            };
+            sample.pcs[0] = 0xffff_ffff_8020_1234;
+            sample.pcs[1] = 0xffff_ffff_8020_0042;
+            let mut line = LineBuf { buf: [0; MAX_LINE], len: 0 };
+            kassert!(format_sample(&sample, &mut line).is_ok());
+            kassert_eq!(
+                &line.buf[..line.len],
+                b"3 ffffffff80201234 ffffffff80200042\n".as_slice()
+            );
+
+            // 最深、任务号最长的一行也必须放得下
+            sample.task = Some(usize::MAX);
+            sample.depth = MAX_DEPTH;
+            let mut line = LineBuf { buf: [0; MAX_LINE], len: 0 };
+            kassert!(format_sample(&sample, &mut line).is_ok());
+
+            sample.task = None;
+            sample.depth = 1;
+            let mut line = LineBuf { buf: [0; MAX_LINE], len: 0 };
+            kassert!(format_sample(&sample, &mut line).is_ok());
+            kassert_eq!(&line.buf[..line.len], b"- ffffffff80201234\n".as_slice());
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// src/driver/block.rs
+use alloc::{sync::Arc, vec::Vec};
+use thiserror_no_std::Error;
+
+use crate::{data_struct::lock::IrqLock, mm::PAGE_SIZE};
+
+/// 块大小与页大小一致，方便缓存直接用一整页承载一个块
+pub const BLOCK_SIZE: usize = PAGE_SIZE;
+
+/// 块设备在注册表中的编号
+pub type DeviceId = usize;
+
+#[derive(Error, Debug)]
+pub enum BlockError {
+    #[error("Block: no such device")]
+    NoDevice,
+    #[error("Block: block id out of range")]
+    OutOfRange,
+    #[error("Block: device I/O error")]
+    Io,
+    #[error("Block: out of memory")]
+    OutOfMemory,
+    /// 块缓存正在读写这个块，当前任务已经挂到等待队列上，系统调用应返回 EAGAIN 重新执行
+    #[error("Block: buffer busy")]
+    WouldBlock,
+}
+
+/// 块设备驱动需要实现的接口，每次读写恰好一个 BLOCK_SIZE 大小的块
+pub trait BlockDevice: Send + Sync {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError>;
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError>;
+    fn block_count(&self) -> usize;
+}
+
+static BLOCK_DEVICES: IrqLock<Vec<Arc<dyn BlockDevice>>> = IrqLock::new(Vec::new());
+
+/// 注册一个块设备，返回它的设备号
+pub fn register_block_device(device: Arc<dyn BlockDevice>) -> DeviceId {
+    let mut devices = BLOCK_DEVICES.lock();
+    devices.push(device);
+    devices.len() - 1
+}
+
+pub fn get_block_device(id: DeviceId) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.lock().get(id).cloned()
+}
+
+pub fn block_device_count() -> usize {
+    BLOCK_DEVICES.lock().len()
+}
@@ -0,0 +1,50 @@
+// src/driver/entropy.rs
+use spin::mutex::SpinMutex;
+
+use crate::trap::interrupts::get_time;
+
+/// 简单的熵池：把中断到达时刻等不可预测的事件混入状态，输出时用 SplitMix64 搅拌
+pub struct EntropyPool {
+    state: u64,
+    counter: u64,
+}
+
+pub static ENTROPY_POOL: SpinMutex<EntropyPool> = SpinMutex::new(EntropyPool {
+    state: 0x9E37_79B9_7F4A_7C15,
+    counter: 0,
+});
+
+impl EntropyPool {
+    /// 混入一个事件值，通常是中断发生时的 rdtime
+    pub fn add_entropy(&mut self, sample: u64) {
+        self.state = (self.state ^ sample)
+            .rotate_left(23)
+            .wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // 每次输出前再混入当前时间，避免两次读取之间没有新事件时输出可被预测
+        self.add_entropy(get_time() as u64);
+        self.counter = self.counter.wrapping_add(1);
+        let mut z = self
+            .state
+            .wrapping_add(self.counter.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// 在中断路径上调用，把中断时刻作为熵源
+pub fn add_interrupt_entropy() {
+    if let Some(mut pool) = ENTROPY_POOL.try_lock() {
+        pool.add_entropy(get_time() as u64);
+    }
+}
@@ -8,8 +8,12 @@ mod traits;
 pub use traits::*;
 
 // 3. 根据 feature 开关，继续声明具体的实现子模块
-pub(crate) mod uart;
+pub mod block;
+pub mod entropy;
 pub mod plic;
+pub mod rtc;
+pub mod tty;
+pub(crate) mod uart;
+pub mod virtio_blk;
 
-pub use uart::Uart as Uart;
-
+pub use uart::Uart;
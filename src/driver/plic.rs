@@ -1,11 +1,16 @@
 use crate::bsp::get_hart_id;
-use crate::bsp::qemu_virt::{LSR, THR, UART_BASE, plic_claim_complete_addr};
+use crate::bsp::qemu_virt::{
+    plic_claim_complete_addr, plic_context_addr, plic_enable_addr, plic_priority_addr,
+};
+use crate::debug::gdb;
 use crate::{polling_println, println};
 use core::ptr::{read_volatile, write_volatile};
 
 #[derive(Debug)]
 pub enum InterruptRequest {
     UART,
+    RTC,
+    GDB,
     UNKNOWN,
 }
 impl InterruptRequest {
@@ -14,6 +19,8 @@ impl InterruptRequest {
 
         match num {
             10 => InterruptRequest::UART,
+            11 => InterruptRequest::RTC,
+            num if gdb::port_irq() == Some(num) => InterruptRequest::GDB,
             _ => InterruptRequest::UNKNOWN,
             // _ => {InterruptRequest::UART}
         }
@@ -21,12 +28,28 @@ impl InterruptRequest {
     pub fn to_num(&self) -> u32 {
         match self {
             InterruptRequest::UART => 10,
+            InterruptRequest::RTC => 11,
+            InterruptRequest::GDB => gdb::port_irq().unwrap_or(0),
             InterruptRequest::UNKNOWN => 717,
         }
     }
 }
 pub struct PLIC {}
 impl PLIC {
+    /// 以优先级 1 打开当前 hart S 态上下文的某个中断源
+    pub fn enable(irq: u32) {
+        let hart_id = get_hart_id();
+        let irq = irq as usize;
+        let priority_ptr = plic_priority_addr(irq) as *mut u32;
+        let enable_ptr = plic_enable_addr(hart_id, irq) as *mut u32;
+        let threshold_ptr = plic_context_addr(hart_id) as *mut u32;
+        unsafe {
+            write_volatile(priority_ptr, 1);
+            let current_enable = read_volatile(enable_ptr);
+            write_volatile(enable_ptr, current_enable | (1 << (irq % 32)));
+            write_volatile(threshold_ptr, 0);
+        }
+    }
     pub fn claim() -> u32 {
         // println!("PLIC claim :{:?}",plic_claim_complete_addr(get_hart_id()) as *mut u32);
         unsafe { read_volatile(plic_claim_complete_addr(get_hart_id()) as *mut u32) }
@@ -0,0 +1,150 @@
+// src/driver/rtc.rs
+//! QEMU virt 的 goldfish RTC：给出自 Unix 纪元以来的纳秒数，并提供一个闹钟中断。
+//! /dev/rtc 可以设置闹钟，读取时阻塞到闹钟响起。
+
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::{
+    bsp::qemu_virt::{RTC_BASE, RTC_IRQ, mmio_va},
+    data_struct::lock::IrqLock,
+    driver::plic::PLIC,
+    fs::devfs::CharDevice,
+    syslib::errno::Errno,
+    task::sync::Condvar,
+};
+
+// 寄存器偏移
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+const ALARM_LOW: usize = 0x08;
+const ALARM_HIGH: usize = 0x0c;
+const IRQ_ENABLED: usize = 0x10;
+const CLEAR_ALARM: usize = 0x14;
+const CLEAR_INTERRUPT: usize = 0x1c;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+// /dev/rtc 的 ioctl 命令，编号借用 Linux RTC 的 'p' 类型，但参数都是 Unix 秒数而不是 struct rtc_time
+/// 在参数给出的时刻响闹钟，覆盖之前设置的闹钟
+pub const RTC_ALARM_SET: usize = 0x7001;
+/// 取消闹钟
+pub const RTC_ALARM_CLEAR: usize = 0x7002;
+/// 返回当前的 Unix 秒数
+pub const RTC_READ_SECS: usize = 0x7003;
+
+pub struct GoldfishRtc {
+    base: usize,
+}
+
+pub static RTC: GoldfishRtc = GoldfishRtc::new(mmio_va(RTC_BASE));
+
+impl GoldfishRtc {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// 自 Unix 纪元以来的纳秒数。读低 32 位时硬件锁存高 32 位，所以必须先读低位
+    pub fn read_ns(&self) -> u64 {
+        let low = self.read(TIME_LOW) as u64;
+        let high = self.read(TIME_HIGH) as u64;
+        high << 32 | low
+    }
+
+    /// 写低 32 位时闹钟生效，所以先写高位。时刻已经过去时立即触发
+    pub fn set_alarm(&self, ns: u64) {
+        self.write(IRQ_ENABLED, 1);
+        self.write(ALARM_HIGH, (ns >> 32) as u32);
+        self.write(ALARM_LOW, ns as u32);
+    }
+
+    pub fn clear_alarm(&self) {
+        self.write(CLEAR_ALARM, 1);
+        self.write(IRQ_ENABLED, 0);
+    }
+
+    fn ack_interrupt(&self) {
+        self.write(CLEAR_INTERRUPT, 1);
+    }
+}
+
+struct AlarmState {
+    armed: bool,
+    /// 上次读取 /dev/rtc 之后响过的次数
+    fired: usize,
+}
+
+static ALARM: IrqLock<AlarmState> = IrqLock::new(AlarmState {
+    armed: false,
+    fired: 0,
+});
+/// 读取 /dev/rtc 而阻塞的任务，闹钟响起后重新执行 read
+static ALARM_FIRED: Condvar = Condvar::new();
+
+/// 清掉固件或上次运行留下的闹钟并打开 RTC 中断，必须在最终页表生效后调用
+pub fn init() {
+    RTC.clear_alarm();
+    RTC.ack_interrupt();
+    PLIC::enable(RTC_IRQ as u32);
+}
+
+pub fn rtc_interrupt_handler() {
+    RTC.ack_interrupt();
+    {
+        let mut alarm = ALARM.lock();
+        alarm.armed = false;
+        alarm.fired += 1;
+    }
+    ALARM_FIRED.notify_all();
+}
+
+/// /dev/rtc：读出 8 字节的闹钟次数，没有响过时阻塞到闹钟响起
+pub struct RtcDevice;
+
+impl CharDevice for RtcDevice {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let dest = buf.get_mut(..8).ok_or(Errno::EINVAL)?;
+        let mut alarm =
+            ALARM_FIRED.wait_while(ALARM.lock(), |alarm| alarm.fired == 0 && alarm.armed, None)?;
+        // 没有设置闹钟时不会有东西可读
+        if alarm.fired == 0 {
+            return Err(Errno::EINVAL);
+        }
+        dest.copy_from_slice(&(alarm.fired as u64).to_le_bytes());
+        alarm.fired = 0;
+        Ok(8)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<usize, Errno> {
+        match cmd {
+            RTC_ALARM_SET => {
+                let ns = (arg as u64)
+                    .checked_mul(NSEC_PER_SEC)
+                    .ok_or(Errno::EINVAL)?;
+                let mut alarm = ALARM.lock();
+                alarm.armed = true;
+                alarm.fired = 0;
+                RTC.set_alarm(ns);
+                Ok(0)
+            }
+            RTC_ALARM_CLEAR => {
+                ALARM.lock().armed = false;
+                RTC.clear_alarm();
+                Ok(0)
+            }
+            RTC_READ_SECS => Ok((RTC.read_ns() / NSEC_PER_SEC) as usize),
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
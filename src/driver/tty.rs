@@ -0,0 +1,253 @@
+// src/driver/tty.rs
+use bitflags::bitflags;
+use spin::mutex::SpinMutex;
+
+use crate::{
+    console::{console_write_bytes, console_write_from_task},
+    data_struct::ring_buf::RingBuffer,
+    fs::devfs::CharDevice,
+    syslib::errno::Errno,
+    task::{SCHEDULER, signal::Signal, wait_queue::WaitQueue},
+};
+
+// ioctl 命令号，沿用 Linux 的编号，但参数是下面的 TtyMode 位图而不是 termios
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+// 设置前台任务，Ctrl-C / Ctrl-Z 产生的信号发给它
+pub const TIOCSPGRP: usize = 0x5410;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct TtyMode: usize {
+        const ICANON = 1 << 0; // 规范模式：按行编辑，回车后才交给读者
+        const ECHO = 1 << 1;   // 回显输入
+        const ISIG = 1 << 2;   // Ctrl-C / Ctrl-Z 转换为信号
+    }
+}
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const CTRL_Z: u8 = 0x1A;
+const DELETE: u8 = 0x7F;
+
+const MAX_LINE: usize = 1024;
+const INPUT_QUEUE_SIZE: usize = 4096;
+
+/// UART 与读者之间的行规程
+pub struct Tty {
+    mode: TtyMode,
+    // 规范模式下正在编辑、还没提交的一行
+    line: [u8; MAX_LINE],
+    line_len: usize,
+    // 已经可以被读取的数据
+    input: RingBuffer<u8, INPUT_QUEUE_SIZE>,
+    // 规范模式下在空行按 Ctrl-D，下一次读返回 0
+    eof_pending: bool,
+    foreground: Option<usize>,
+    // 通过 sys_read 或 /dev/ttyS0 读取而阻塞的任务，被唤醒后重新执行读取
+    readers: WaitQueue,
+}
+
+pub static TTY: SpinMutex<Tty> = SpinMutex::new(Tty::new());
+
+impl Tty {
+    const fn new() -> Self {
+        Self {
+            mode: TtyMode::ICANON.union(TtyMode::ECHO).union(TtyMode::ISIG),
+            line: [0; MAX_LINE],
+            line_len: 0,
+            input: RingBuffer::new(),
+            eof_pending: false,
+            foreground: None,
+            readers: WaitQueue::new(),
+        }
+    }
+
+    /// 由 UART 接收中断调用，处理一个原始输入字节
+    pub fn receive(&mut self, byte: u8) {
+        if self.mode.contains(TtyMode::ISIG) && (byte == CTRL_C || byte == CTRL_Z) {
+            let (signal, echo) = if byte == CTRL_C {
+                (Signal::SIGINT, b"^C\r\n")
+            } else {
+                (Signal::SIGTSTP, b"^Z\r\n")
+            };
+            self.line_len = 0;
+            self.echo(echo);
+            if let Some(task_id) = self.foreground {
+                SCHEDULER.lock().send_signal(task_id, signal);
+            }
+            return;
+        }
+
+        if !self.mode.contains(TtyMode::ICANON) {
+            self.echo(&[byte]);
+            let _ = self.input.push(byte);
+            self.notify_readers();
+            return;
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                self.echo(b"\r\n");
+                self.commit_line(true);
+            }
+            BACKSPACE | DELETE => self.erase_char(),
+            CTRL_U => {
+                while self.line_len > 0 {
+                    self.erase_char();
+                }
+            }
+            CTRL_D => {
+                if self.line_len == 0 {
+                    self.eof_pending = true;
+                }
+                self.commit_line(false);
+            }
+            _ => {
+                if self.line_len < MAX_LINE {
+                    self.line[self.line_len] = byte;
+                    self.line_len += 1;
+                    self.echo(&[byte]);
+                }
+            }
+        }
+    }
+
+    fn echo(&self, bytes: &[u8]) {
+        if self.mode.contains(TtyMode::ECHO) {
+            console_write_bytes(bytes);
+        }
+    }
+
+    /// 删除行缓冲中的最后一个字符，UTF-8 多字节字符整体删除
+    fn erase_char(&mut self) {
+        if self.line_len == 0 {
+            return;
+        }
+        let mut start = self.line_len - 1;
+        while start > 0 && (self.line[start] & 0xC0) == 0x80 {
+            start -= 1;
+        }
+        let width = match core::str::from_utf8(&self.line[start..self.line_len]) {
+            Ok(s) => s.chars().next().map_or(1, display_width),
+            Err(_) => 1,
+        };
+        self.line_len = start;
+        for _ in 0..width {
+            self.echo(b"\x08 \x08");
+        }
+    }
+
+    fn commit_line(&mut self, newline: bool) {
+        for i in 0..self.line_len {
+            let _ = self.input.push(self.line[i]);
+        }
+        if newline {
+            let _ = self.input.push(b'\n');
+        }
+        self.line_len = 0;
+        self.notify_readers();
+    }
+
+    /// 有新数据时唤醒所有读者，没抢到数据的读者重新阻塞
+    fn notify_readers(&mut self) {
+        if !self.input.is_empty() || self.eof_pending {
+            self.readers.wake_all();
+        }
+    }
+
+    pub fn pop_byte(&mut self) -> Option<u8> {
+        self.input.pop()
+    }
+
+    /// 没有数据可读时挂到读者队列上，deadline 为 None 表示一直等
+    pub fn wait_readable(&self, deadline: Option<usize>) -> Errno {
+        match deadline {
+            Some(deadline) => self.readers.wait_until(deadline),
+            None => self.readers.wait(),
+        }
+    }
+
+    /// 重新执行的带超时读取调用，返回上一次等待是否超时
+    pub fn finish_read_wait(&self) -> bool {
+        self.readers.finish_wait()
+    }
+
+    pub fn set_foreground(&mut self, task_id: Option<usize>) {
+        self.foreground = task_id;
+    }
+}
+
+/// 终端上的显示宽度，东亚宽字符占两列
+pub fn display_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// /dev/ttyS0 字符设备
+pub struct TtyDevice;
+
+impl CharDevice for TtyDevice {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut tty = TTY.lock();
+        let mut count = 0;
+        while count < buf.len() {
+            let Some(byte) = tty.input.pop() else {
+                break;
+            };
+            buf[count] = byte;
+            count += 1;
+            // 规范模式下一次最多读一行
+            if byte == b'\n' && tty.mode.contains(TtyMode::ICANON) {
+                break;
+            }
+        }
+        if count > 0 {
+            return Ok(count);
+        }
+        if tty.eof_pending {
+            tty.eof_pending = false;
+            return Ok(0);
+        }
+        // 系统调用在关中断的 trap 上下文中执行，登记后到阻塞之前不会有字符到达
+        Err(tty.wait_readable(None))
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        console_write_from_task(buf)
+    }
+
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<usize, Errno> {
+        let mut tty = TTY.lock();
+        match cmd {
+            TCGETS => Ok(tty.mode.bits()),
+            TCSETS => {
+                tty.mode = TtyMode::from_bits(arg).ok_or(Errno::EINVAL)?;
+                // 切到原始模式时把未提交的行交给读者
+                if !tty.mode.contains(TtyMode::ICANON) && tty.line_len > 0 {
+                    tty.commit_line(false);
+                }
+                Ok(0)
+            }
+            TIOCSPGRP => {
+                tty.set_foreground(if arg == usize::MAX { None } else { Some(arg) });
+                Ok(0)
+            }
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
@@ -21,10 +21,9 @@ impl Uart {
         Self { base_address }
     }
 }
-#[cfg(feature = "uart_polling")]
-// 现在，编译器就能在这里正确地找到 `SerialPort` Trait 了
-impl SerialPort for Uart {
-    fn init(&mut self) {
+impl Uart {
+    /// 按 38400 8N1 初始化，不经过 PLIC。rx_interrupt 为 true 时打开接收中断
+    pub fn init_polling(&self, rx_interrupt: bool) {
         let ier_ptr = (self.base_address + IER) as *mut u8;
         let fcr_ptr = (self.base_address + FCR) as *mut u8;
         let lcr_ptr = (self.base_address + LCR) as *mut u8;
@@ -41,10 +40,14 @@ impl SerialPort for Uart {
             write_volatile(dlm_ptr, 0x00); // 设置波特率为38400
             //数据格式设置为 “8 位数据位、1 位停止位、无校验”
             write_volatile(lcr_ptr, 0x03u8);
+            if rx_interrupt {
+                write_volatile(ier_ptr, 0x01); // 使能接收中断
+            }
         }
     }
 
-    fn putchar(&mut self, c: u8) -> Result<(), u8> {
+    /// 忙等 THR 空后写入一个字节
+    pub fn putchar_polling(&self, c: u8) {
         let lsr_ptr = (self.base_address + LSR) as *mut u8;
         let thr_ptr = (self.base_address + THR) as *mut u8;
 
@@ -52,10 +55,10 @@ impl SerialPort for Uart {
             while (read_volatile(lsr_ptr) & (1 << 5)) == 0 {}
             write_volatile(thr_ptr, c);
         }
-        Ok(())
     }
 
-    fn getchar(&mut self) -> Option<u8> {
+    /// 接收 FIFO 为空时返回 None，不会阻塞
+    pub fn getchar_polling(&self) -> Option<u8> {
         let lsr_ptr = (self.base_address + LSR) as *mut u8;
         let rhr_ptr = (self.base_address + RHR) as *mut u8;
 
@@ -68,6 +71,22 @@ impl SerialPort for Uart {
         }
     }
 }
+#[cfg(feature = "uart_polling")]
+// 现在，编译器就能在这里正确地找到 `SerialPort` Trait 了
+impl SerialPort for Uart {
+    fn init(&mut self) {
+        self.init_polling(false);
+    }
+
+    fn putchar(&mut self, c: u8) -> Result<(), u8> {
+        self.putchar_polling(c);
+        Ok(())
+    }
+
+    fn getchar(&mut self) -> Option<u8> {
+        self.getchar_polling()
+    }
+}
 // #[cfg(feature = "uart_interrupt")]
 // static RECEIVE_BUFFER: SpinMutex<RingBuffer<u8, 4096>>  = SpinMutex::<RingBuffer<u8, 4096>>::new(RingBuffer::<u8,4096>::new());
 // #[cfg(feature = "uart_interrupt")]
@@ -0,0 +1,330 @@
+// src/driver/virtio_blk.rs
+//! virtio-mmio 上的块设备（virtio-blk），支持 legacy（version 1）和 modern（version 2）两种接口。
+//! 每个设备只用一个请求队列，提交一个请求后轮询 used 环直到设备完成，读写是同步的。
+//! 队列、请求头和状态字节放在从 Buddy 申请的两页里，数据直接在调用者的缓冲区和设备之间传输。
+
+use alloc::sync::Arc;
+use core::{
+    arch::asm,
+    num::NonZeroUsize,
+    ptr::{read_volatile, write_volatile},
+};
+
+use crate::{
+    bsp::qemu_virt::{VIRTIO_MMIO_BASE, VIRTIO_MMIO_COUNT, VIRTIO_MMIO_STRIDE, mmio_va},
+    data_struct::lock::IrqLock,
+    driver::block::{BLOCK_SIZE, BlockDevice, BlockError, register_block_device},
+    info,
+    mm::{
+        BUDDY_ALLOCATOR, PAGE_SIZE,
+        address::{ClearPage, PhysAddr, PhysPageNum},
+        buddy::{phys_to_virt, virt_to_phys},
+    },
+    warn,
+};
+
+// MMIO 寄存器偏移
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028; // 仅 legacy
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c; // 仅 legacy
+const QUEUE_PFN: usize = 0x040; // 仅 legacy
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+/// 设备配置空间，virtio-blk 的前 8 字节是以 512 字节扇区计的容量
+const CONFIG_CAPACITY: usize = 0x100;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const DEVICE_ID_BLOCK: u32 = 2;
+
+// 设备状态位
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+/// VIRTIO_F_VERSION_1 是第 32 位特性，位于第 1 组特性字的最低位
+const FEATURE_VERSION_1: u32 = 1;
+
+const SECTOR_SIZE: usize = 512;
+const SECTORS_PER_BLOCK: usize = BLOCK_SIZE / SECTOR_SIZE;
+
+/// 每个请求用 3 个描述符（请求头、数据、状态），同一时刻只有一个请求
+const QUEUE_SIZE: usize = 8;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+const REQ_IN: u32 = 0;
+const REQ_OUT: u32 = 1;
+const REQ_STATUS_OK: u8 = 0;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+// 两页队列内存的布局：第一页依次是描述符表、avail 环、请求头和状态字节，
+// used 环按 legacy 接口的 QueueAlign 要求放在第二页开头
+const DESC_OFFSET: usize = 0;
+const AVAIL_OFFSET: usize = DESC_OFFSET + QUEUE_SIZE * size_of::<Descriptor>();
+const HEADER_OFFSET: usize = 0x200;
+const STATUS_OFFSET: usize = HEADER_OFFSET + size_of::<RequestHeader>();
+const USED_OFFSET: usize = PAGE_SIZE;
+const QUEUE_PAGES: usize = 2;
+
+/// 保证内存中的队列和 MMIO 写之间的顺序
+fn fence() {
+    unsafe { asm!("fence iorw, iorw") };
+}
+
+struct Queue {
+    /// 队列内存的虚拟地址
+    base: usize,
+    /// 下一个要写入 avail 环的序号
+    avail_idx: u16,
+    /// 已经处理到的 used 环序号
+    used_idx: u16,
+}
+
+impl Queue {
+    fn pa(&self, offset: usize) -> u64 {
+        virt_to_phys(self.base + offset) as u64
+    }
+
+    fn desc(&mut self, index: usize, addr: u64, len: usize, flags: u16) {
+        let desc = (self.base + DESC_OFFSET) as *mut Descriptor;
+        unsafe {
+            write_volatile(
+                desc.add(index),
+                Descriptor {
+                    addr,
+                    len: len as u32,
+                    flags,
+                    next: (index + 1) as u16,
+                },
+            )
+        };
+    }
+
+    /// avail 环：flags、idx，之后是 ring[QUEUE_SIZE]
+    fn avail_ring(&self) -> *mut u16 {
+        (self.base + AVAIL_OFFSET) as *mut u16
+    }
+
+    /// used 环的 idx 字段
+    fn used_idx(&self) -> u16 {
+        unsafe { read_volatile(((self.base + USED_OFFSET) as *const u16).add(1)) }
+    }
+}
+
+pub struct VirtioBlk {
+    /// MMIO 寄存器的虚拟地址
+    regs: usize,
+    blocks: usize,
+    queue: IrqLock<Queue>,
+}
+
+impl VirtioBlk {
+    fn read(regs: usize, offset: usize) -> u32 {
+        unsafe { read_volatile((regs + offset) as *const u32) }
+    }
+
+    fn write(regs: usize, offset: usize, value: u32) {
+        unsafe { write_volatile((regs + offset) as *mut u32, value) }
+    }
+
+    /// 探测一个 virtio-mmio 槽位，是块设备时完成初始化
+    fn probe(regs: usize) -> Option<Self> {
+        if Self::read(regs, MAGIC_VALUE) != MAGIC || Self::read(regs, DEVICE_ID) != DEVICE_ID_BLOCK
+        {
+            return None;
+        }
+        let version = Self::read(regs, VERSION);
+        if version != 1 && version != 2 {
+            warn!("virtio-blk: unsupported MMIO version {}", version);
+            return None;
+        }
+
+        // 复位后依次置 ACKNOWLEDGE、DRIVER，再协商特性
+        Self::write(regs, STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        Self::write(regs, STATUS, status);
+        // 不需要任何设备特性；modern 接口必须接受 VERSION_1
+        Self::write(regs, DRIVER_FEATURES_SEL, 0);
+        Self::write(regs, DRIVER_FEATURES, 0);
+        if version == 1 {
+            Self::write(regs, GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            Self::write(regs, DEVICE_FEATURES_SEL, 1);
+            if Self::read(regs, DEVICE_FEATURES) & FEATURE_VERSION_1 == 0 {
+                Self::write(regs, STATUS, 0);
+                return None;
+            }
+            Self::write(regs, DRIVER_FEATURES_SEL, 1);
+            Self::write(regs, DRIVER_FEATURES, FEATURE_VERSION_1);
+            status |= STATUS_FEATURES_OK;
+            Self::write(regs, STATUS, status);
+            if Self::read(regs, STATUS) & STATUS_FEATURES_OK == 0 {
+                Self::write(regs, STATUS, 0);
+                return None;
+            }
+        }
+
+        Self::write(regs, QUEUE_SEL, 0);
+        if (Self::read(regs, QUEUE_NUM_MAX) as usize) < QUEUE_SIZE {
+            Self::write(regs, STATUS, 0);
+            return None;
+        }
+        let ppn = BUDDY_ALLOCATOR
+            .lock()
+            .alloc(NonZeroUsize::new(QUEUE_PAGES).unwrap())?;
+        for i in 0..QUEUE_PAGES {
+            PhysPageNum(ppn.0 + i).clear();
+        }
+        let queue = Queue {
+            base: phys_to_virt(PhysAddr::from(&ppn).0),
+            avail_idx: 0,
+            used_idx: 0,
+        };
+        Self::write(regs, QUEUE_NUM, QUEUE_SIZE as u32);
+        if version == 1 {
+            Self::write(regs, QUEUE_ALIGN, PAGE_SIZE as u32);
+            Self::write(regs, QUEUE_PFN, ppn.0 as u32);
+        } else {
+            let desc = queue.pa(DESC_OFFSET);
+            let avail = queue.pa(AVAIL_OFFSET);
+            let used = queue.pa(USED_OFFSET);
+            Self::write(regs, QUEUE_DESC_LOW, desc as u32);
+            Self::write(regs, QUEUE_DESC_HIGH, (desc >> 32) as u32);
+            Self::write(regs, QUEUE_DRIVER_LOW, avail as u32);
+            Self::write(regs, QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+            Self::write(regs, QUEUE_DEVICE_LOW, used as u32);
+            Self::write(regs, QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+            Self::write(regs, QUEUE_READY, 1);
+        }
+        Self::write(regs, STATUS, status | STATUS_DRIVER_OK);
+
+        // 容量是 64 位字段，分两次 32 位读取
+        let sectors = Self::read(regs, CONFIG_CAPACITY) as usize
+            | (Self::read(regs, CONFIG_CAPACITY + 4) as usize) << 32;
+        Some(Self {
+            regs,
+            blocks: sectors / SECTORS_PER_BLOCK,
+            queue: IrqLock::new(queue),
+        })
+    }
+
+    /// 提交一个请求并等待完成。buf 是内核线性映射中的地址，物理上连续
+    fn request(&self, kind: u32, block_id: usize, buf: *mut u8) -> Result<(), BlockError> {
+        if block_id >= self.blocks {
+            return Err(BlockError::OutOfRange);
+        }
+        let mut queue = self.queue.lock();
+        let header = RequestHeader {
+            kind,
+            reserved: 0,
+            sector: (block_id * SECTORS_PER_BLOCK) as u64,
+        };
+        unsafe {
+            write_volatile((queue.base + HEADER_OFFSET) as *mut RequestHeader, header);
+            write_volatile((queue.base + STATUS_OFFSET) as *mut u8, u8::MAX);
+        }
+        // 读请求时设备写数据缓冲区，状态字节总是由设备写
+        let data_flags = if kind == REQ_IN { DESC_F_WRITE } else { 0 };
+        let header_pa = queue.pa(HEADER_OFFSET);
+        let status_pa = queue.pa(STATUS_OFFSET);
+        queue.desc(0, header_pa, size_of::<RequestHeader>(), DESC_F_NEXT);
+        queue.desc(
+            1,
+            virt_to_phys(buf as usize) as u64,
+            BLOCK_SIZE,
+            data_flags | DESC_F_NEXT,
+        );
+        queue.desc(2, status_pa, 1, DESC_F_WRITE);
+
+        let avail = queue.avail_ring();
+        let slot = queue.avail_idx as usize % QUEUE_SIZE;
+        queue.avail_idx = queue.avail_idx.wrapping_add(1);
+        unsafe { write_volatile(avail.add(2 + slot), 0) };
+        fence();
+        unsafe { write_volatile(avail.add(1), queue.avail_idx) };
+        fence();
+        Self::write(self.regs, QUEUE_NOTIFY, 0);
+
+        while queue.used_idx() == queue.used_idx {
+            core::hint::spin_loop();
+        }
+        fence();
+        queue.used_idx = queue.used_idx.wrapping_add(1);
+        // 完成中断没有接到 PLIC，这里只清掉设备上的中断状态
+        Self::write(
+            self.regs,
+            INTERRUPT_ACK,
+            Self::read(self.regs, INTERRUPT_STATUS),
+        );
+
+        let status = unsafe { read_volatile((queue.base + STATUS_OFFSET) as *const u8) };
+        if status == REQ_STATUS_OK {
+            Ok(())
+        } else {
+            Err(BlockError::Io)
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.request(REQ_IN, block_id, buf[..BLOCK_SIZE].as_mut_ptr())
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError> {
+        self.request(REQ_OUT, block_id, buf[..BLOCK_SIZE].as_ptr() as *mut u8)
+    }
+
+    fn block_count(&self) -> usize {
+        self.blocks
+    }
+}
+
+/// 探测所有 virtio-mmio 槽位，把找到的块设备注册到块设备表
+pub fn init() {
+    for slot in 0..VIRTIO_MMIO_COUNT {
+        let regs = mmio_va(VIRTIO_MMIO_BASE + slot * VIRTIO_MMIO_STRIDE);
+        if let Some(device) = VirtioBlk::probe(regs) {
+            let blocks = device.blocks;
+            let id = register_block_device(Arc::new(device));
+            info!(
+                "virtio-blk: slot {} registered as block device {}, {} blocks",
+                slot, id, blocks
+            );
+        }
+    }
+}
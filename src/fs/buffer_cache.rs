@@ -0,0 +1,602 @@
+// src/fs/buffer_cache.rs
+use core::num::NonZeroUsize;
+
+use crate::{
+    data_struct::lock::{IrqLock, IrqLockGuard},
+    driver::block::{BLOCK_SIZE, BlockError, DeviceId, get_block_device},
+    mm::{
+        BUDDY_ALLOCATOR,
+        address::{PhysAddr, PhysPageNum},
+        buddy::{BuddySystemFrameAllocator, phys_to_virt},
+    },
+    task::{executor, sync::Condvar},
+};
+
+/// 缓存最多容纳的块数，超过后按 LRU 淘汰
+pub const MAX_BUFFERS: usize = 256;
+/// 后台回写的周期
+pub const FLUSH_INTERVAL_MS: usize = 5000;
+
+type BufferKey = (DeviceId, usize);
+
+/// 槽位下标，NIL 表示空链接
+type Slot = u16;
+const NIL: Slot = Slot::MAX;
+/// 散列桶数，取 2 的幂
+const HASH_BUCKETS: usize = 128;
+
+/// 一个缓存块，数据放在一整页 buddy 物理页里。
+/// 缓存的全部簿记都在这些固定槽位中：LRU 双向链表和散列桶链都是槽位下标，
+/// 回收回调在持有 BUDDY_ALLOCATOR 时运行，整个过程不碰堆内存
+struct Buffer {
+    key: BufferKey,
+    ppn: PhysPageNum,
+    dirty: bool,
+    /// 正在与设备交换数据，期间不持有缓存锁：块不能被访问、淘汰或回收
+    busy: bool,
+    /// LRU 链表中更旧、更新的一项；空闲槽位用 next 串成空闲链表
+    prev: Slot,
+    next: Slot,
+    /// 同一散列桶中的下一项
+    hash_next: Slot,
+}
+
+impl Buffer {
+    const EMPTY: Self = Self {
+        key: (0, 0),
+        ppn: PhysPageNum(0),
+        dirty: false,
+        busy: false,
+        prev: NIL,
+        next: NIL,
+        hash_next: NIL,
+    };
+
+    fn data(&self) -> &[u8] {
+        block_data(self.ppn)
+    }
+    fn data_mut(&mut self) -> &mut [u8] {
+        block_data(self.ppn)
+    }
+}
+
+/// 缓存块所在页的数据。I/O 期间不持有缓存锁，由块的忙碌标记保证只有发起 I/O 的一方访问
+fn block_data<'a>(ppn: PhysPageNum) -> &'a mut [u8] {
+    let va = phys_to_virt(PhysAddr::from(&ppn).0);
+    unsafe { core::slice::from_raw_parts_mut(va as *mut u8, BLOCK_SIZE) }
+}
+
+pub struct BufferCache {
+    slots: [Buffer; MAX_BUFFERS],
+    buckets: [Slot; HASH_BUCKETS],
+    // 链表头是最久未使用的块，链表尾是最近使用的块
+    lru_head: Slot,
+    lru_tail: Slot,
+    /// 用过又释放的槽位
+    free: Slot,
+    /// 从未用过的槽位从这里开始
+    unused: usize,
+    len: usize,
+}
+
+pub static BUFFER_CACHE: IrqLock<BufferCache> = IrqLock::new(BufferCache::new());
+/// 块的 I/O 结束时通知，等待忙碌块或空闲槽位的任务在这里等待
+static BUFFER_IO: Condvar = Condvar::new();
+
+type CacheGuard<'a> = IrqLockGuard<'a, BufferCache>;
+
+fn bucket_of(key: BufferKey) -> usize {
+    let hash = key.0.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ key.1;
+    hash % HASH_BUCKETS
+}
+
+impl BufferCache {
+    pub const fn new() -> Self {
+        Self {
+            slots: [Buffer::EMPTY; MAX_BUFFERS],
+            buckets: [NIL; HASH_BUCKETS],
+            lru_head: NIL,
+            lru_tail: NIL,
+            free: NIL,
+            unused: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.lru().filter(|&slot| self.slots[slot].dirty).count()
+    }
+
+    /// 从最久未使用到最近使用遍历缓存块的槽位
+    fn lru(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut slot = self.lru_head;
+        core::iter::from_fn(move || {
+            let current = slot;
+            (current != NIL).then(|| {
+                slot = self.slots[current as usize].next;
+                current as usize
+            })
+        })
+    }
+
+    fn find(&self, key: BufferKey) -> Option<usize> {
+        let mut slot = self.buckets[bucket_of(key)];
+        while slot != NIL {
+            let buffer = &self.slots[slot as usize];
+            if buffer.key == key {
+                return Some(slot as usize);
+            }
+            slot = buffer.hash_next;
+        }
+        None
+    }
+
+    fn lru_unlink(&mut self, slot: usize) {
+        let Buffer { prev, next, .. } = self.slots[slot];
+        match prev {
+            NIL => self.lru_head = next,
+            prev => self.slots[prev as usize].next = next,
+        }
+        match next {
+            NIL => self.lru_tail = prev,
+            next => self.slots[next as usize].prev = prev,
+        }
+    }
+
+    fn lru_push_back(&mut self, slot: usize) {
+        self.slots[slot].prev = self.lru_tail;
+        self.slots[slot].next = NIL;
+        match self.lru_tail {
+            NIL => self.lru_head = slot as Slot,
+            tail => self.slots[tail as usize].next = slot as Slot,
+        }
+        self.lru_tail = slot as Slot;
+    }
+
+    fn touch(&mut self, slot: usize) {
+        if self.lru_tail != slot as Slot {
+            self.lru_unlink(slot);
+            self.lru_push_back(slot);
+        }
+    }
+
+    /// 占用一个空槽位并加入散列表和 LRU 链表尾
+    fn insert(&mut self, key: BufferKey, ppn: PhysPageNum) -> usize {
+        let slot = if self.free != NIL {
+            let slot = self.free as usize;
+            self.free = self.slots[slot].next;
+            slot
+        } else {
+            self.unused += 1;
+            self.unused - 1
+        };
+        let bucket = bucket_of(key);
+        self.slots[slot] = Buffer {
+            key,
+            ppn,
+            dirty: false,
+            busy: false,
+            prev: NIL,
+            next: NIL,
+            hash_next: self.buckets[bucket],
+        };
+        self.buckets[bucket] = slot as Slot;
+        self.lru_push_back(slot);
+        self.len += 1;
+        slot
+    }
+
+    /// 把块移出缓存，返回它占用的页，由调用者归还
+    fn remove(&mut self, slot: usize) -> PhysPageNum {
+        let key = self.slots[slot].key;
+        let bucket = bucket_of(key);
+        let mut link = self.buckets[bucket];
+        if link == slot as Slot {
+            self.buckets[bucket] = self.slots[slot].hash_next;
+        } else {
+            while self.slots[link as usize].hash_next != slot as Slot {
+                link = self.slots[link as usize].hash_next;
+            }
+            self.slots[link as usize].hash_next = self.slots[slot].hash_next;
+        }
+        self.lru_unlink(slot);
+        self.slots[slot].next = self.free;
+        self.free = slot as Slot;
+        self.len -= 1;
+        self.slots[slot].ppn
+    }
+
+    /// 淘汰时的候选块：最久未使用的干净块，没有时是最久未使用的脏块。正在 I/O 的块不参与
+    fn victim(&self) -> Option<usize> {
+        let mut idle = self.lru().filter(|&slot| !self.slots[slot].busy);
+        let oldest = idle.next()?;
+        let clean = core::iter::once(oldest)
+            .chain(idle)
+            .find(|&slot| !self.slots[slot].dirty);
+        Some(clean.unwrap_or(oldest))
+    }
+
+    /// 把块移出缓存并把页还给伙伴分配器
+    fn remove_and_free(&mut self, slot: usize) {
+        let ppn = self.remove(slot);
+        BUDDY_ALLOCATOR
+            .lock()
+            .dealloc(ppn, NonZeroUsize::new(1).unwrap());
+    }
+
+    /// 丢弃干净块并把页直接还给传入的分配器，返回回收的页数
+    fn reclaim_clean(&mut self, allocator: &mut BuddySystemFrameAllocator, wanted: usize) -> usize {
+        let mut reclaimed = 0;
+        let mut slot = self.lru_head;
+        while slot != NIL && reclaimed < wanted {
+            let current = slot as usize;
+            slot = self.slots[current].next;
+            if self.slots[current].dirty || self.slots[current].busy {
+                continue;
+            }
+            let ppn = self.remove(current);
+            allocator.dealloc(ppn, NonZeroUsize::new(1).unwrap());
+            reclaimed += 1;
+        }
+        reclaimed
+    }
+}
+
+/// I/O 结束后重新加锁，清除块的忙碌标记并唤醒等待的任务
+fn finish_io(cache: &IrqLock<BufferCache>, slot: usize) -> CacheGuard<'_> {
+    let mut guard = cache.lock();
+    guard.slots[slot].busy = false;
+    BUFFER_IO.notify_all();
+    guard
+}
+
+/// 回写一个脏块。写的期间块标记为忙碌，不持有缓存锁
+fn write_back<'a>(
+    cache: &'a IrqLock<BufferCache>,
+    mut guard: CacheGuard<'a>,
+    slot: usize,
+) -> (CacheGuard<'a>, Result<(), BlockError>) {
+    let (key, ppn) = (guard.slots[slot].key, guard.slots[slot].ppn);
+    guard.slots[slot].busy = true;
+    drop(guard);
+    let result = get_block_device(key.0)
+        .ok_or(BlockError::NoDevice)
+        .and_then(|device| device.write_block(key.1, block_data(ppn)));
+    let mut guard = finish_io(cache, slot);
+    if result.is_ok() {
+        guard.slots[slot].dirty = false;
+    }
+    (guard, result)
+}
+
+/// 腾出一个槽位和一页：干净的候选块直接淘汰，脏块先回写，由调用者重新检查后再淘汰。
+/// 所有块都在 I/O 中时挂到 BUFFER_IO 上，返回 WouldBlock
+fn evict_one<'a>(
+    cache: &'a IrqLock<BufferCache>,
+    mut guard: CacheGuard<'a>,
+) -> Result<CacheGuard<'a>, BlockError> {
+    let Some(slot) = guard.victim() else {
+        return BUFFER_IO
+            .wait_while(guard, |cache| cache.victim().is_none(), None)
+            .map_err(|_| BlockError::WouldBlock);
+    };
+    if !guard.slots[slot].dirty {
+        guard.remove_and_free(slot);
+        return Ok(guard);
+    }
+    let (guard, result) = write_back(cache, guard, slot);
+    result.map(|()| guard)
+}
+
+/// 取得某个块的缓存，不在缓存中时从设备读入，返回缓存锁和块所在的槽位。
+/// 设备 I/O 期间不持有缓存锁，块标记为忙碌；遇到忙碌的块时当前任务挂到 BUFFER_IO 上，
+/// 返回 WouldBlock，由系统调用返回 EAGAIN 后重新执行
+fn get_or_load(
+    cache: &IrqLock<BufferCache>,
+    key: BufferKey,
+) -> Result<(CacheGuard<'_>, usize), BlockError> {
+    let mut guard = cache.lock();
+    loop {
+        if let Some(slot) = guard.find(key) {
+            if guard.slots[slot].busy {
+                // 另一个任务正在读入或回写这个块
+                guard = BUFFER_IO
+                    .wait_while(
+                        guard,
+                        |cache| cache.find(key).is_some_and(|slot| cache.slots[slot].busy),
+                        None,
+                    )
+                    .map_err(|_| BlockError::WouldBlock)?;
+                continue;
+            }
+            guard.touch(slot);
+            return Ok((guard, slot));
+        }
+        let device = get_block_device(key.0).ok_or(BlockError::NoDevice)?;
+        if key.1 >= device.block_count() {
+            return Err(BlockError::OutOfRange);
+        }
+        if guard.len >= MAX_BUFFERS {
+            guard = evict_one(cache, guard)?;
+            continue;
+        }
+        // 持有缓存锁时分配，回收回调拿不到锁会直接放弃，所以这里自己淘汰
+        let Some(ppn) = BUDDY_ALLOCATOR.lock().alloc(NonZeroUsize::new(1).unwrap()) else {
+            if guard.len == 0 {
+                return Err(BlockError::OutOfMemory);
+            }
+            guard = evict_one(cache, guard)?;
+            continue;
+        };
+        let slot = guard.insert(key, ppn);
+        guard.slots[slot].busy = true;
+        drop(guard);
+        let result = device.read_block(key.1, block_data(ppn));
+        let mut guard = finish_io(cache, slot);
+        if let Err(err) = result {
+            guard.remove_and_free(slot);
+            return Err(err);
+        }
+        return Ok((guard, slot));
+    }
+}
+
+/// 回写所有脏块，dev 为 None 时回写全部设备。返回第一个出错的结果，但会尽量把其余块写完。
+/// 正在 I/O 的块跳过：它们要么正在读入，要么已经在回写
+fn flush(cache: &IrqLock<BufferCache>, dev: Option<DeviceId>) -> Result<(), BlockError> {
+    let mut result = Ok(());
+    // 回写时放开了锁，按槽位下标遍历，每次重新加锁确认槽位仍是需要回写的块
+    for slot in 0..MAX_BUFFERS {
+        let guard = cache.lock();
+        let buffer = &guard.slots[slot];
+        let pending = guard.find(buffer.key) == Some(slot)
+            && buffer.dirty
+            && !buffer.busy
+            && dev.is_none_or(|d| d == buffer.key.0);
+        if !pending {
+            continue;
+        }
+        // 先回写再合并结果，前面出过错也不跳过后面的块
+        let (_, written) = write_back(cache, guard, slot);
+        result = result.and(written);
+    }
+    result
+}
+
+/// 只读访问一个块
+pub fn read_block<R>(
+    dev: DeviceId,
+    block_id: usize,
+    f: impl FnOnce(&[u8]) -> R,
+) -> Result<R, BlockError> {
+    let (cache, slot) = get_or_load(&BUFFER_CACHE, (dev, block_id))?;
+    Ok(f(cache.slots[slot].data()))
+}
+
+/// 修改一个块，修改后标记为脏块，由后台任务或 sync 回写
+pub fn write_block<R>(
+    dev: DeviceId,
+    block_id: usize,
+    f: impl FnOnce(&mut [u8]) -> R,
+) -> Result<R, BlockError> {
+    let (mut cache, slot) = get_or_load(&BUFFER_CACHE, (dev, block_id))?;
+    let buffer = &mut cache.slots[slot];
+    buffer.dirty = true;
+    Ok(f(buffer.data_mut()))
+}
+
+pub fn sync_all() -> Result<(), BlockError> {
+    flush(&BUFFER_CACHE, None)
+}
+
+/// 注册给伙伴系统的回收回调。分配可能发生在已持有缓存锁的路径上，所以只用 try_lock；
+/// 回调在持有 BUDDY_ALLOCATOR 时运行，只改动缓存槽位，不分配也不释放堆内存
+fn shrink_buffer_cache(allocator: &mut BuddySystemFrameAllocator, pages: NonZeroUsize) -> usize {
+    match BUFFER_CACHE.try_lock() {
+        Some(mut cache) => cache.reclaim_clean(allocator, pages.get()),
+        None => 0,
+    }
+}
+
+pub fn init_buffer_cache() {
+    BUDDY_ALLOCATOR
+        .lock()
+        .register_shrinker(shrink_buffer_cache);
+}
+
+/// 后台回写服务：在执行器上运行，周期性地把脏块写回设备
+pub async fn flush_daemon() {
+    loop {
+        executor::sleep(FLUSH_INTERVAL_MS).await;
+        let _ = sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferCache, MAX_BUFFERS, finish_io, flush, get_or_load};
+    use crate::{
+        data_struct::lock::IrqLock,
+        driver::block::{BlockDevice, BlockError, DeviceId, register_block_device},
+        kassert, kassert_eq, kernel_test,
+        mm::{BUDDY_ALLOCATOR, slub::slab_stats},
+        task::{tcb::TaskStatus, test_support::TestTasks},
+    };
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use lazy_static::lazy_static;
+
+    /// 不保存数据的测试设备：读出的块里每个字都是块号，只记录写了哪些块
+    struct PatternDevice {
+        blocks: usize,
+        writes: AtomicUsize,
+        last_write: AtomicUsize,
+    }
+
+    impl BlockDevice for PatternDevice {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+            for word in buf.chunks_exact_mut(size_of::<usize>()) {
+                word.copy_from_slice(&block_id.to_le_bytes());
+            }
+            Ok(())
+        }
+        fn write_block(&self, block_id: usize, _buf: &[u8]) -> Result<(), BlockError> {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            self.last_write.store(block_id, Ordering::Relaxed);
+            Ok(())
+        }
+        fn block_count(&self) -> usize {
+            self.blocks
+        }
+    }
+
+    lazy_static! {
+        // 设备注册后不能注销，所有测试共用一个
+        static ref PATTERN: (Arc<PatternDevice>, DeviceId) = {
+            let device = Arc::new(PatternDevice {
+                blocks: MAX_BUFFERS * 2,
+                writes: AtomicUsize::new(0),
+                last_write: AtomicUsize::new(usize::MAX),
+            });
+            let id = register_block_device(device.clone());
+            (device, id)
+        };
+    }
+
+    /// 清零写入记录后返回测试设备
+    fn pattern_device() -> (&'static PatternDevice, DeviceId) {
+        let (device, id) = &*PATTERN;
+        device.writes.store(0, Ordering::Relaxed);
+        device.last_write.store(usize::MAX, Ordering::Relaxed);
+        (device, *id)
+    }
+
+    // 与全局缓存分开，测试不受其他设备的块影响
+    static CACHE: IrqLock<BufferCache> = IrqLock::new(BufferCache::new());
+
+    /// 取得块的缓存，返回槽位
+    fn load(dev: DeviceId, block: usize) -> usize {
+        get_or_load(&CACHE, (dev, block)).unwrap().1
+    }
+
+    fn set_dirty(dev: DeviceId, block: usize, dirty: bool) {
+        let (mut cache, slot) = get_or_load(&CACHE, (dev, block)).unwrap();
+        cache.slots[slot].dirty = dirty;
+    }
+
+    /// 块中第一个字，也就是读入它时的块号
+    fn block_id_in(slot: usize) -> usize {
+        let cache = CACHE.lock();
+        let data = cache.slots[slot].data();
+        usize::from_le_bytes(data[..size_of::<usize>()].try_into().unwrap())
+    }
+
+    fn cached(dev: DeviceId, block: usize) -> bool {
+        CACHE.lock().find((dev, block)).is_some()
+    }
+
+    /// 回写并丢弃测试缓存中的所有块
+    fn drain() -> bool {
+        if flush(&CACHE, None).is_err() {
+            return false;
+        }
+        let mut cache = CACHE.lock();
+        cache.reclaim_clean(&mut BUDDY_ALLOCATOR.lock(), MAX_BUFFERS) > 0 && cache.len() == 0
+    }
+
+    kernel_test! {
+        fn full_cache_evicts_least_recently_used() {
+            let (_, dev) = pattern_device();
+            for block in 0..MAX_BUFFERS {
+                load(dev, block);
+            }
+            // 再次访问块 0，块 1 成为最久未使用的块
+            kassert_eq!(block_id_in(load(dev, 0)), 0);
+            kassert_eq!(block_id_in(load(dev, MAX_BUFFERS)), MAX_BUFFERS);
+            kassert_eq!(CACHE.lock().len(), MAX_BUFFERS);
+            kassert!(cached(dev, 0));
+            kassert!(!cached(dev, 1));
+            kassert!(cached(dev, 2));
+            kassert!(drain());
+        }
+    }
+
+    kernel_test! {
+        fn eviction_skips_dirty_buffers_and_writes_back_when_all_are_dirty() {
+            let (device, dev) = pattern_device();
+            for block in 0..MAX_BUFFERS {
+                set_dirty(dev, block, true);
+            }
+            // 块 0 是最近使用的块，但它是唯一的干净块，仍先于脏块被淘汰
+            set_dirty(dev, 0, false);
+            load(dev, MAX_BUFFERS);
+            kassert!(!cached(dev, 0));
+            kassert_eq!(device.writes.load(Ordering::Relaxed), 0);
+            // 现在全是脏块，最久未使用的块 1 先回写再淘汰
+            set_dirty(dev, MAX_BUFFERS, true);
+            load(dev, MAX_BUFFERS + 1);
+            kassert!(!cached(dev, 1));
+            kassert_eq!(device.writes.load(Ordering::Relaxed), 1);
+            kassert_eq!(device.last_write.load(Ordering::Relaxed), 1);
+            kassert!(drain());
+        }
+    }
+
+    kernel_test! {
+        fn shrinker_drops_only_clean_buffers_without_heap_use() {
+            let (device, dev) = pattern_device();
+            for block in 0..4 {
+                set_dirty(dev, block, block % 2 == 0);
+            }
+            let mut cache = CACHE.lock();
+            let before = slab_stats().map(|stats| stats.nr_inuse);
+            let reclaimed = cache.reclaim_clean(&mut BUDDY_ALLOCATOR.lock(), 8);
+            kassert_eq!(slab_stats().map(|stats| stats.nr_inuse), before);
+            kassert_eq!(reclaimed, 2);
+            kassert_eq!(cache.len(), 2);
+            kassert_eq!(cache.dirty_count(), 2);
+            kassert!(cache.find((dev, 0)).is_some());
+            kassert!(cache.find((dev, 1)).is_none());
+            drop(cache);
+            kassert_eq!(device.writes.load(Ordering::Relaxed), 0);
+            kassert!(drain());
+            kassert_eq!(device.writes.load(Ordering::Relaxed), 2);
+        }
+    }
+
+    kernel_test! {
+        fn busy_buffer_makes_readers_wait_until_its_io_ends() {
+            let tasks = TestTasks::spawn(1);
+            let (device, dev) = pattern_device();
+            let slot = load(dev, 0);
+            // 模拟另一个任务正在回写块 0，I/O 期间不持有缓存锁
+            {
+                let mut cache = CACHE.lock();
+                cache.slots[slot].dirty = true;
+                cache.slots[slot].busy = true;
+            }
+            tasks.run(0);
+            kassert!(matches!(get_or_load(&CACHE, (dev, 0)), Err(BlockError::WouldBlock)));
+            tasks.block();
+            // 忙碌的块不会被淘汰、回收或再次回写
+            {
+                let mut cache = CACHE.lock();
+                kassert_eq!(cache.victim(), None);
+                kassert_eq!(cache.reclaim_clean(&mut BUDDY_ALLOCATOR.lock(), 8), 0);
+            }
+            kassert!(flush(&CACHE, None).is_ok());
+            kassert_eq!(device.writes.load(Ordering::Relaxed), 0);
+            // I/O 结束时等待的任务被唤醒，重新执行后拿到这个块
+            drop(finish_io(&CACHE, slot));
+            kassert!(tasks.status_is(0, TaskStatus::Ready));
+            tasks.run(0);
+            kassert_eq!(get_or_load(&CACHE, (dev, 0)).map(|(_, slot)| slot).ok(), Some(slot));
+            kassert!(drain());
+            kassert_eq!(device.writes.load(Ordering::Relaxed), 1);
+        }
+    }
+}
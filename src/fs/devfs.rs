@@ -0,0 +1,242 @@
+// src/fs/devfs.rs
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use spin::mutex::SpinMutex;
+
+use crate::{
+    data_struct::lock::IrqLock,
+    driver::{
+        block::{BLOCK_SIZE, BlockError, DeviceId, block_device_count, get_block_device},
+        entropy::ENTROPY_POOL,
+    },
+    fs::{
+        buffer_cache,
+        file::File,
+        vfs::{FileSystem, dir_listing},
+    },
+    syslib::errno::Errno,
+};
+
+/// 字符设备驱动需要实现的接口。需要等待时与 File 相同：先登记等待再返回 EAGAIN
+pub trait CharDevice: Send + Sync {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno>;
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno>;
+    fn ioctl(&self, _cmd: usize, _arg: usize) -> Result<usize, Errno> {
+        Err(Errno::ENOTTY)
+    }
+}
+
+struct CharDeviceNode {
+    name: String,
+    device: Arc<dyn CharDevice>,
+}
+
+static CHAR_DEVICES: IrqLock<Vec<CharDeviceNode>> = IrqLock::new(Vec::new());
+
+/// 注册一个字符设备，之后可以通过 /dev/<name> 打开
+pub fn register_char_device(name: &str, device: Arc<dyn CharDevice>) {
+    let mut devices = CHAR_DEVICES.lock();
+    devices.retain(|node| node.name != name);
+    devices.push(CharDeviceNode {
+        name: String::from(name),
+        device,
+    });
+}
+
+/// 块设备节点名按注册顺序依次为 vda, vdb, ..., vdz, vdaa, vdab, ...，与 Linux 的命名一致：
+/// 后缀是不含零的 26 进制数，a 到 z 表示 1 到 26
+fn block_device_name(id: DeviceId) -> String {
+    let mut suffix = Vec::new();
+    let mut n = id + 1;
+    while n > 0 {
+        n -= 1;
+        suffix.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    suffix.reverse();
+    format!("vd{}", core::str::from_utf8(&suffix).unwrap())
+}
+
+fn parse_block_device_name(name: &str) -> Option<DeviceId> {
+    let suffix = name.strip_prefix("vd")?.as_bytes();
+    if suffix.is_empty() {
+        return None;
+    }
+    let mut n: DeviceId = 0;
+    for &c in suffix {
+        if !c.is_ascii_lowercase() {
+            return None;
+        }
+        n = n.checked_mul(26)?.checked_add((c - b'a' + 1) as DeviceId)?;
+    }
+    Some(n - 1)
+}
+
+/// /dev 文件系统
+pub struct DevFs;
+
+impl FileSystem for DevFs {
+    fn open(&self, path: &str) -> Result<Arc<dyn File>, Errno> {
+        if path.is_empty() {
+            let mut names: Vec<String> = CHAR_DEVICES
+                .lock()
+                .iter()
+                .map(|node| node.name.clone())
+                .collect();
+            names.extend((0..block_device_count()).map(block_device_name));
+            let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+            return Ok(dir_listing(&names));
+        }
+        let device = CHAR_DEVICES
+            .lock()
+            .iter()
+            .find(|node| node.name == path)
+            .map(|node| node.device.clone());
+        if let Some(device) = device {
+            return Ok(Arc::new(CharDeviceFile { device }));
+        }
+        match parse_block_device_name(path) {
+            Some(dev) if get_block_device(dev).is_some() => Ok(Arc::new(BlockDeviceFile {
+                dev,
+                offset: SpinMutex::new(0),
+            })),
+            _ => Err(Errno::ENOENT),
+        }
+    }
+}
+
+struct CharDeviceFile {
+    device: Arc<dyn CharDevice>,
+}
+
+impl File for CharDeviceFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.device.read(buf)
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        self.device.write(buf)
+    }
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<usize, Errno> {
+        self.device.ioctl(cmd, arg)
+    }
+}
+
+/// 块设备节点，按字节偏移顺序读写，数据经过块缓存
+struct BlockDeviceFile {
+    dev: DeviceId,
+    offset: SpinMutex<usize>,
+}
+
+impl File for BlockDeviceFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.lock();
+        let block_id = *offset / BLOCK_SIZE;
+        let start = *offset % BLOCK_SIZE;
+        let count = buf.len().min(BLOCK_SIZE - start);
+        match buffer_cache::read_block(self.dev, block_id, |data| {
+            buf[..count].copy_from_slice(&data[start..start + count]);
+        }) {
+            Ok(()) => {
+                *offset += count;
+                Ok(count)
+            }
+            // 读到设备末尾
+            Err(BlockError::OutOfRange) => Ok(0),
+            Err(BlockError::WouldBlock) => Err(Errno::EAGAIN),
+            Err(_) => Err(Errno::EIO),
+        }
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.lock();
+        let block_id = *offset / BLOCK_SIZE;
+        let start = *offset % BLOCK_SIZE;
+        let count = buf.len().min(BLOCK_SIZE - start);
+        buffer_cache::write_block(self.dev, block_id, |data| {
+            data[start..start + count].copy_from_slice(&buf[..count]);
+        })
+        .map_err(|err| match err {
+            BlockError::WouldBlock => Errno::EAGAIN,
+            _ => Errno::EIO,
+        })?;
+        *offset += count;
+        Ok(count)
+    }
+}
+
+/// /dev/null：读到 EOF，写入全部丢弃
+pub struct NullDevice;
+
+impl CharDevice for NullDevice {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(0)
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(buf.len())
+    }
+}
+
+/// /dev/zero：读出全零，写入全部丢弃
+pub struct ZeroDevice;
+
+impl CharDevice for ZeroDevice {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(buf.len())
+    }
+}
+
+/// /dev/random：从熵池读取，写入的数据会混入熵池
+pub struct RandomDevice;
+
+impl CharDevice for RandomDevice {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        ENTROPY_POOL.lock().fill_bytes(buf);
+        Ok(buf.len())
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let mut pool = ENTROPY_POOL.lock();
+        for chunk in buf.chunks(8) {
+            let mut bytes = [0u8; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            pool.add_entropy(u64::from_le_bytes(bytes));
+        }
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{block_device_name, parse_block_device_name};
+    use crate::{kassert, kassert_eq, kernel_test};
+
+    kernel_test! {
+        fn block_device_names_go_past_z() {
+            kassert_eq!(block_device_name(0), "vda");
+            kassert_eq!(block_device_name(25), "vdz");
+            kassert_eq!(block_device_name(26), "vdaa");
+            kassert_eq!(block_device_name(27), "vdab");
+            kassert_eq!(block_device_name(26 + 26 * 26), "vdaaa");
+            for id in [0, 1, 25, 26, 51, 52, 701, 702, 100_000] {
+                kassert_eq!(parse_block_device_name(&block_device_name(id)), Some(id));
+            }
+            kassert!(parse_block_device_name("vd").is_none());
+            kassert!(parse_block_device_name("vdA").is_none());
+            kassert!(parse_block_device_name("vda1").is_none());
+            kassert!(parse_block_device_name("vdzzzzzzzzzzzzzzzzzzzz").is_none());
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// src/fs/file.rs
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt;
+
+use crate::syslib::errno::Errno;
+
+/// 可以挂到文件描述符上的对象
+///
+/// 读写在需要等待时应先把当前任务登记到自己的等待队列，再返回 `Errno::EAGAIN`，
+/// 系统调用层会阻塞当前任务，并在唤醒后重新执行这次调用
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno>;
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno>;
+    fn ioctl(&self, _cmd: usize, _arg: usize) -> Result<usize, Errno> {
+        Err(Errno::ENOTTY)
+    }
+}
+
+pub const MAX_FDS: usize = 64;
+
+/// 每个任务自己的文件描述符表
+pub struct FdTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// 分配编号最小的空闲描述符
+    pub fn alloc(&mut self, file: Arc<dyn File>) -> Result<usize, Errno> {
+        if let Some(fd) = self.files.iter().position(|slot| slot.is_none()) {
+            self.files[fd] = Some(file);
+            return Ok(fd);
+        }
+        if self.files.len() >= MAX_FDS {
+            return Err(Errno::EMFILE);
+        }
+        self.files.push(Some(file));
+        Ok(self.files.len() - 1)
+    }
+
+    pub fn get(&self, fd: usize) -> Option<Arc<dyn File>> {
+        self.files.get(fd).and_then(|slot| slot.clone())
+    }
+
+    /// 取出描述符对应的文件，调用者负责在不持有调度器锁时释放它
+    pub fn take(&mut self, fd: usize) -> Option<Arc<dyn File>> {
+        self.files.get_mut(fd).and_then(|slot| slot.take())
+    }
+}
+
+impl fmt::Debug for FdTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FdTable")
+            .field(
+                "open",
+                &self.files.iter().filter(|slot| slot.is_some()).count(),
+            )
+            .finish()
+    }
+}
@@ -0,0 +1,25 @@
+// src/fs/mod.rs
+pub mod buffer_cache;
+pub mod devfs;
+pub mod file;
+pub mod pipe;
+pub mod procfs;
+pub mod vfs;
+
+use alloc::sync::Arc;
+
+use crate::debug::profile::ProfileDevice;
+use crate::driver::rtc::RtcDevice;
+use crate::driver::tty::TtyDevice;
+
+/// 挂载内核自带的伪文件系统并注册基础字符设备
+pub fn init_fs() {
+    vfs::mount("/proc", Arc::new(procfs::ProcFs));
+    vfs::mount("/dev", Arc::new(devfs::DevFs));
+    devfs::register_char_device("ttyS0", Arc::new(TtyDevice));
+    devfs::register_char_device("null", Arc::new(devfs::NullDevice));
+    devfs::register_char_device("zero", Arc::new(devfs::ZeroDevice));
+    devfs::register_char_device("random", Arc::new(devfs::RandomDevice));
+    devfs::register_char_device("profile", Arc::new(ProfileDevice));
+    devfs::register_char_device("rtc", Arc::new(RtcDevice));
+}
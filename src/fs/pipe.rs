@@ -0,0 +1,191 @@
+// src/fs/pipe.rs
+use alloc::sync::Arc;
+use spin::mutex::SpinMutex;
+
+use crate::{
+    data_struct::ring_buf::RingBuffer, fs::file::File, syslib::errno::Errno,
+    task::wait_queue::WaitQueue,
+};
+
+pub const PIPE_BUFFER_SIZE: usize = 4096;
+
+struct PipeInner {
+    buffer: RingBuffer<u8, PIPE_BUFFER_SIZE>,
+    readers: usize,
+    writers: usize,
+}
+
+/// 等待队列有自己的锁，唤醒在释放管道锁之后进行
+struct Pipe {
+    state: SpinMutex<PipeInner>,
+    read_wait_queue: WaitQueue,
+    write_wait_queue: WaitQueue,
+}
+
+type SharedPipe = Arc<Pipe>;
+
+pub struct PipeReadEnd {
+    inner: SharedPipe,
+}
+
+pub struct PipeWriteEnd {
+    inner: SharedPipe,
+}
+
+/// 创建一对匿名管道端点
+pub fn make_pipe() -> (Arc<PipeReadEnd>, Arc<PipeWriteEnd>) {
+    let inner = Arc::new(Pipe {
+        state: SpinMutex::new(PipeInner {
+            buffer: RingBuffer::new(),
+            readers: 1,
+            writers: 1,
+        }),
+        read_wait_queue: WaitQueue::new(),
+        write_wait_queue: WaitQueue::new(),
+    });
+    (
+        Arc::new(PipeReadEnd {
+            inner: inner.clone(),
+        }),
+        Arc::new(PipeWriteEnd { inner }),
+    )
+}
+
+impl File for PipeReadEnd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut pipe = self.inner.state.lock();
+        if pipe.buffer.is_empty() {
+            if pipe.writers == 0 {
+                // 所有写端都已关闭，返回 EOF
+                return Ok(0);
+            }
+            return Err(self.inner.read_wait_queue.wait());
+        }
+        let mut count = 0;
+        while count < buf.len() {
+            match pipe.buffer.pop() {
+                Some(byte) => {
+                    buf[count] = byte;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        drop(pipe);
+        self.inner.write_wait_queue.wake_all();
+        Ok(count)
+    }
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+}
+
+impl File for PipeWriteEnd {
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut pipe = self.inner.state.lock();
+        if pipe.readers == 0 {
+            return Err(Errno::EPIPE);
+        }
+        if pipe.buffer.is_full() {
+            return Err(self.inner.write_wait_queue.wait());
+        }
+        let mut count = 0;
+        for &byte in buf {
+            if pipe.buffer.push(byte).is_err() {
+                break;
+            }
+            count += 1;
+        }
+        drop(pipe);
+        self.inner.read_wait_queue.wake_all();
+        Ok(count)
+    }
+}
+
+impl Drop for PipeReadEnd {
+    fn drop(&mut self) {
+        let mut pipe = self.inner.state.lock();
+        pipe.readers -= 1;
+        let last = pipe.readers == 0;
+        drop(pipe);
+        // 最后一个读端关闭后，等待中的写者醒来会得到 EPIPE
+        if last {
+            self.inner.write_wait_queue.wake_all();
+        }
+    }
+}
+
+impl Drop for PipeWriteEnd {
+    fn drop(&mut self) {
+        let mut pipe = self.inner.state.lock();
+        pipe.writers -= 1;
+        let last = pipe.writers == 0;
+        drop(pipe);
+        // 最后一个写端关闭后，等待中的读者醒来会读到 EOF
+        if last {
+            self.inner.read_wait_queue.wake_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::make_pipe;
+    use crate::{fs::file::File, kassert_eq, kernel_test, syslib::errno::Errno};
+
+    // 测试在启动阶段运行，没有任务可以阻塞，只覆盖不需要等待的路径
+
+    kernel_test! {
+        fn pipe_passes_bytes_in_order() {
+            let (reader, writer) = make_pipe();
+            kassert_eq!(writer.write(b"hello "), Ok(6));
+            kassert_eq!(writer.write(b"pipe"), Ok(4));
+            let mut buf = [0u8; 16];
+            kassert_eq!(reader.read(&mut buf), Ok(10));
+            kassert_eq!(&buf[..10], b"hello pipe");
+            kassert_eq!(reader.write(b"x"), Err(Errno::EBADF));
+            kassert_eq!(writer.read(&mut buf), Err(Errno::EBADF));
+        }
+    }
+
+    kernel_test! {
+        fn reader_sees_eof_after_writers_close() {
+            let (reader, writer) = make_pipe();
+            kassert_eq!(writer.write(b"tail"), Ok(4));
+            drop(writer);
+            // 缓冲区中剩下的数据先读完，之后读到 EOF
+            let mut buf = [0u8; 16];
+            kassert_eq!(reader.read(&mut buf), Ok(4));
+            kassert_eq!(reader.read(&mut buf), Ok(0));
+        }
+    }
+
+    kernel_test! {
+        fn writer_gets_epipe_after_readers_close() {
+            let (reader, writer) = make_pipe();
+            drop(reader);
+            kassert_eq!(writer.write(b"lost"), Err(Errno::EPIPE));
+        }
+    }
+}
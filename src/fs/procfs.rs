@@ -0,0 +1,179 @@
+// src/fs/procfs.rs
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+
+use crate::{
+    bsp::qemu_virt::RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ,
+    fs::{
+        buffer_cache::BUFFER_CACHE,
+        file::File,
+        vfs::{FileSystem, SnapshotFile, dir_listing},
+    },
+    mm::{BUDDY_ALLOCATOR, PAGE_SIZE, RAM_END_PPN, RAM_START_PPN, slub::slab_stats},
+    syslib::errno::Errno,
+    task::{SCHEDULER, tcb::TaskControlBlock},
+    trap::interrupts::{INTERRUPT_STATS, get_time},
+};
+
+/// /proc 文件系统，所有文件都在打开时根据内核当前状态生成
+pub struct ProcFs;
+
+const ROOT_ENTRIES: [&str; 4] = ["meminfo", "tasks", "interrupts", "uptime"];
+
+impl FileSystem for ProcFs {
+    fn open(&self, path: &str) -> Result<Arc<dyn File>, Errno> {
+        let content = match path {
+            "" => return Ok(root_listing()),
+            "meminfo" => meminfo(),
+            "tasks" => tasks(),
+            "interrupts" => interrupts(),
+            "uptime" => uptime(),
+            _ => return open_task_entry(path),
+        };
+        Ok(Arc::new(SnapshotFile::new(content.into_bytes())))
+    }
+}
+
+fn root_listing() -> Arc<dyn File> {
+    let ids: Vec<String> = SCHEDULER
+        .lock()
+        .tasks()
+        .map(|tcb| format!("{}", tcb.task_id))
+        .collect();
+    let mut names: Vec<&str> = ROOT_ENTRIES.to_vec();
+    names.extend(ids.iter().map(|id| id.as_str()));
+    dir_listing(&names)
+}
+
+/// 处理 "<id>" 和 "<id>/status"
+fn open_task_entry(path: &str) -> Result<Arc<dyn File>, Errno> {
+    let (id, entry) = match path.split_once('/') {
+        Some((id, entry)) => (id, Some(entry)),
+        None => (path, None),
+    };
+    let task_id: usize = id.parse().map_err(|_| Errno::ENOENT)?;
+    let status = {
+        let scheduler = SCHEDULER.lock();
+        let tcb = scheduler.get_task(task_id).ok_or(Errno::ENOENT)?;
+        task_status(tcb)
+    };
+    match entry {
+        None => Ok(dir_listing(&["status"])),
+        Some("status") => Ok(Arc::new(SnapshotFile::new(status.into_bytes()))),
+        Some(_) => Err(Errno::ENOENT),
+    }
+}
+
+fn ticks_to_ms(ticks: usize) -> usize {
+    ticks / (RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 1000)
+}
+
+fn task_status(tcb: &TaskControlBlock) -> String {
+    let stats = &tcb.stats;
+    format!(
+        "Id:\t{}\nName:\t{}\nState:\t{:?}\nPriority:\t{}\nStackPages:\t{}\nCpuTimeMs:\t{}\n\
+         UserTimeMs:\t{}\nSystemTimeMs:\t{}\nWaitTimeMs:\t{}\nSleepTimeMs:\t{}\n\
+         VoluntarySwitches:\t{}\nInvoluntarySwitches:\t{}\n",
+        tcb.task_id,
+        tcb.name,
+        tcb.visible_status(),
+        tcb.priority,
+        tcb.page_count,
+        ticks_to_ms(stats.cpu_time()),
+        ticks_to_ms(stats.user_time),
+        ticks_to_ms(stats.system_time),
+        ticks_to_ms(stats.wait_time),
+        ticks_to_ms(stats.sleep_time),
+        stats.voluntary_switches,
+        stats.involuntary_switches
+    )
+}
+
+fn tasks() -> String {
+    let mut out =
+        String::from("ID\tNAME\tSTATE\tPRIO\tSTACK\tCPU(ms)\tUSR\tSYS\tWAIT\tSLEEP\tVCSW\tIVCSW\n");
+    let scheduler = SCHEDULER.lock();
+    for tcb in scheduler.tasks() {
+        let stats = &tcb.stats;
+        let _ = writeln!(
+            out,
+            "{}\t{}\t{:?}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            tcb.task_id,
+            tcb.name,
+            tcb.visible_status(),
+            tcb.priority,
+            tcb.page_count,
+            ticks_to_ms(stats.cpu_time()),
+            ticks_to_ms(stats.user_time),
+            ticks_to_ms(stats.system_time),
+            ticks_to_ms(stats.wait_time),
+            ticks_to_ms(stats.sleep_time),
+            stats.voluntary_switches,
+            stats.involuntary_switches
+        );
+    }
+    out
+}
+
+fn meminfo() -> String {
+    let free_blocks = BUDDY_ALLOCATOR.lock().free_blocks_per_order();
+    let free_pages: usize = free_blocks
+        .iter()
+        .enumerate()
+        .map(|(order, count)| count << order)
+        .sum();
+    let (buffers, dirty) = {
+        let cache = BUFFER_CACHE.lock();
+        (cache.len(), cache.dirty_count())
+    };
+
+    let total_pages = unsafe { RAM_END_PPN - RAM_START_PPN };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "MemTotal:\t{} kB", total_pages * PAGE_SIZE / 1024);
+    let _ = writeln!(out, "MemFree:\t{} kB", free_pages * PAGE_SIZE / 1024);
+    let _ = writeln!(out, "Buffers:\t{} kB", buffers * PAGE_SIZE / 1024);
+    let _ = writeln!(out, "Dirty:\t\t{} kB", dirty * PAGE_SIZE / 1024);
+    let _ = write!(out, "BuddyFree:");
+    for count in free_blocks {
+        let _ = write!(out, " {}", count);
+    }
+    let _ = writeln!(out);
+    let _ = writeln!(out, "\nCACHE\tINUSE\tSLABS\tPAGES");
+    for stats in slab_stats() {
+        let _ = writeln!(
+            out,
+            "kmalloc-{}\t{}\t{}\t{}",
+            stats.object_size,
+            stats.nr_inuse,
+            stats.nr_slabs,
+            stats.nr_slabs * stats.pages_per_slab
+        );
+    }
+    out
+}
+
+fn interrupts() -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "timer:\t{}",
+        INTERRUPT_STATS.timer.load(Ordering::Relaxed)
+    );
+    for (irq, counter) in INTERRUPT_STATS.external.iter().enumerate() {
+        let count = counter.load(Ordering::Relaxed);
+        if count > 0 {
+            let _ = writeln!(out, "{}:\t{}\tPLIC", irq, count);
+        }
+    }
+    out
+}
+
+fn uptime() -> String {
+    let ticks = get_time();
+    let secs = ticks / RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ;
+    let centis =
+        ticks % RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / (RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 100);
+    format!("{}.{:02}\n", secs, centis)
+}
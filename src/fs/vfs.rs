@@ -0,0 +1,105 @@
+// src/fs/vfs.rs
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::mutex::SpinMutex;
+
+use crate::{data_struct::lock::IrqLock, fs::file::File, syslib::errno::Errno};
+
+/// 挂载到 VFS 上的文件系统，path 是去掉挂载点前缀后的相对路径，根目录为空串
+pub trait FileSystem: Send + Sync {
+    fn open(&self, path: &str) -> Result<Arc<dyn File>, Errno>;
+}
+
+struct MountPoint {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+static MOUNTS: IrqLock<Vec<MountPoint>> = IrqLock::new(Vec::new());
+
+/// 把文件系统挂载到一个一级目录上，例如 "/proc"
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) {
+    let path = String::from(path.trim_end_matches('/'));
+    let mut mounts = MOUNTS.lock();
+    mounts.retain(|mount| mount.path != path);
+    mounts.push(MountPoint { path, fs });
+}
+
+/// 按绝对路径打开文件，打开目录会得到一个列出目录项的只读文件
+pub fn open(path: &str) -> Result<Arc<dyn File>, Errno> {
+    if !path.starts_with('/') {
+        return Err(Errno::EINVAL);
+    }
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        return Ok(root_listing());
+    }
+    let (fs, rest) = {
+        let mounts = MOUNTS.lock();
+        let mount = mounts
+            .iter()
+            .filter(|mount| {
+                path == mount.path
+                    || path
+                        .strip_prefix(mount.path.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .max_by_key(|mount| mount.path.len())
+            .ok_or(Errno::ENOENT)?;
+        (mount.fs.clone(), &path[mount.path.len()..])
+    };
+    fs.open(rest.trim_start_matches('/'))
+}
+
+fn root_listing() -> Arc<dyn File> {
+    let mounts = MOUNTS.lock();
+    let names: Vec<&str> = mounts
+        .iter()
+        .map(|mount| mount.path.trim_start_matches('/'))
+        .collect();
+    dir_listing(&names)
+}
+
+/// 把目录项拼成每行一个名字的只读文件
+pub fn dir_listing(names: &[&str]) -> Arc<dyn File> {
+    let mut content = String::new();
+    for name in names {
+        content.push_str(name);
+        content.push('\n');
+    }
+    Arc::new(SnapshotFile::new(content.into_bytes()))
+}
+
+/// 打开时生成好内容、之后顺序读取的只读文件，用于 procfs 这类按需生成的文件
+pub struct SnapshotFile {
+    content: Vec<u8>,
+    offset: SpinMutex<usize>,
+}
+
+impl SnapshotFile {
+    pub fn new(content: Vec<u8>) -> Self {
+        Self {
+            content,
+            offset: SpinMutex::new(0),
+        }
+    }
+}
+
+impl File for SnapshotFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.lock();
+        let remaining = &self.content[*offset..];
+        let count = remaining.len().min(buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        *offset += count;
+        Ok(count)
+    }
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+}
@@ -0,0 +1,132 @@
+// src/ktest.rs
+//! 内核内测试框架。`cargo test` 以 `--cfg test` 构建内核并交给 QEMU 运行，
+//! 内核完成初始化后执行所有用 `kernel_test!` 注册的测试，最后通过 virt 平台的
+//! test finisher 退出：全部通过时为 FINISHER_PASS，否则为 FINISHER_FAIL 并以失败个数作为退出码。
+//!
+//! 测试在调度器启动前、关中断的内核上下文中依次运行。注册项由链接脚本收集到
+//! `.kernel_tests` 段，不依赖 nightly 的 custom_test_frameworks。
+
+use alloc::string::String;
+use core::{
+    slice,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{bsp::qemu_virt::QemuVirt, println, system::SystemControl};
+
+pub struct KernelTest {
+    pub name: &'static str,
+    pub func: fn() -> TestResult,
+}
+
+pub struct TestFailure {
+    pub file: &'static str,
+    pub line: u32,
+    pub message: String,
+}
+
+pub type TestResult = Result<(), TestFailure>;
+
+unsafe extern "C" {
+    static _kernel_tests_start: u8;
+    static _kernel_tests_end: u8;
+}
+
+/// 正在运行的测试，panic 时据此报告是哪个测试失败
+static CURRENT_TEST: AtomicPtr<KernelTest> = AtomicPtr::new(core::ptr::null_mut());
+
+fn registered_tests() -> &'static [KernelTest] {
+    unsafe {
+        let start = &raw const _kernel_tests_start as *const KernelTest;
+        let end = &raw const _kernel_tests_end as *const KernelTest;
+        slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+pub fn current_test() -> Option<&'static str> {
+    let test = CURRENT_TEST.load(Ordering::Acquire);
+    unsafe { test.as_ref().map(|test| test.name) }
+}
+
+/// 依次运行所有测试并退出 QEMU，不会返回
+pub fn run_tests() {
+    let tests = registered_tests();
+    println!("running {} kernel tests", tests.len());
+    let mut failed = 0;
+    for test in tests {
+        CURRENT_TEST.store(test as *const _ as *mut _, Ordering::Release);
+        match (test.func)() {
+            Ok(()) => println!("test {} ... ok", test.name),
+            Err(failure) => {
+                failed += 1;
+                println!("test {} ... FAILED", test.name);
+                println!("    {}:{}: {}", failure.file, failure.line, failure.message);
+            }
+        }
+    }
+    CURRENT_TEST.store(core::ptr::null_mut(), Ordering::Release);
+    println!(
+        "test result: {}. {} passed; {} failed",
+        if failed == 0 { "ok" } else { "FAILED" },
+        tests.len() - failed,
+        failed
+    );
+    if failed == 0 {
+        QemuVirt.shutdown();
+    }
+    QemuVirt.fail(failed.min(u16::MAX as usize) as u16);
+}
+
+/// 注册一个内核测试。测试体中用 `kassert!`/`kassert_eq!` 检查条件，
+/// 失败时记录位置并提前返回，其余测试继续运行；测试中 panic 会直接以失败退出 QEMU
+#[macro_export]
+macro_rules! kernel_test {
+    ($(#[$meta:meta])* fn $name:ident() $body:block) => {
+        $(#[$meta])*
+        fn $name() -> $crate::ktest::TestResult {
+            $body
+            Ok(())
+        }
+
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = ".kernel_tests")]
+            static TEST: $crate::ktest::KernelTest = $crate::ktest::KernelTest {
+                name: concat!(module_path!(), "::", stringify!($name)),
+                func: $name,
+            };
+        };
+    };
+}
+
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr) => {
+        $crate::kassert!($cond, "assertion failed: {}", stringify!($cond))
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::ktest::TestFailure {
+                file: file!(),
+                line: line!(),
+                message: alloc::format!($($arg)+),
+            });
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr) => {
+        match (&$left, &$right) {
+            (left, right) => $crate::kassert!(
+                *left == *right,
+                "assertion `left == right` failed: {} == {}\n      left: {:?}\n     right: {:?}",
+                stringify!($left),
+                stringify!($right),
+                left,
+                right
+            ),
+        }
+    };
+}
@@ -1,17 +1,53 @@
-use crate::bsp::qemu_virt::{FINISHER_FAIL, FINISHER_PASS, VIRT_TEST_ADDR};
-use crate::{polling_println, println, sbi_println};
+use crate::bsp::qemu_virt::QemuVirt;
+use crate::console::force_polling_console;
+use crate::console::user_print;
+use crate::debug::backtrace::{print_backtrace, write_backtrace};
+use crate::mm::mmio_mapped;
+use crate::polling_println;
+use crate::system::SystemControl;
+use crate::task::join::EXIT_PANIC;
+use crate::trap::in_task_context;
+use crate::user_println;
+use crate::userlib::syscall::sys_task_exit;
 use core::panic::PanicInfo;
-use core::ptr::write_volatile;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-const STATUS_CODE: u32 = 7;
+/// panic 时交给 QEMU finisher 的退出码，QEMU 进程以 (STATUS_CODE << 1) | 1 退出
+const STATUS_CODE: u16 = 7;
+
+/// 回溯过程中再次 panic 时不再回溯，直接退出
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// 有任务正在打印回溯。打印中再次 panic 时不再回溯；
+/// 打印经过系统调用可能被抢占，这期间 panic 的其他任务只打印消息
+static TASK_BACKTRACE: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    sbi_println!("{} with message: {}", info, info.message());
-    // let shutdown_cmd: u32 = (STATUS_CODE << 16) | (FINISHER_FAIL as u32);
-    // unsafe{
-    //     write_volatile(VIRT_TEST_ADDR as *mut u32,shutdown_cmd);
-    // }
+    // 任务中的 panic 只结束这个任务，join 它的任务得到 JoinError::Panicked
+    if in_task_context() {
+        user_println!("{} with message: {}", info, info.message());
+        if !TASK_BACKTRACE.swap(true, Ordering::AcqRel) {
+            write_backtrace(user_print);
+            TASK_BACKTRACE.store(false, Ordering::Release);
+        }
+        sys_task_exit(EXIT_PANIC as usize);
+    }
+    force_polling_console();
+    #[cfg(test)]
+    if let Some(name) = crate::ktest::current_test() {
+        polling_println!("test {} ... FAILED (panicked)", name);
+    }
+    polling_println!("{} with message: {}", info, info.message());
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        polling_println!("panicked while printing backtrace");
+    } else {
+        print_backtrace();
+    }
+    // 最终页表生效前 finisher 没有映射，只能请 SBI 关机
+    if mmio_mapped() {
+        QemuVirt.fail(STATUS_CODE);
+    }
     sbi_rt::system_reset(sbi_rt::Shutdown, sbi_rt::SystemFailure);
     loop {}
 }
@@ -6,7 +6,11 @@ mod bsp;
 mod config;
 mod console;
 mod data_struct;
+mod debug;
 mod driver;
+mod fs;
+#[cfg(test)]
+mod ktest;
 mod lang_items;
 mod mm;
 mod syslib;
@@ -15,21 +19,30 @@ mod task;
 mod trap;
 mod userlib;
 
-use crate::bsp::qemu_virt::UART_BASE;
-use crate::config::PHYS_VIRT_OFFSET;
+use crate::bsp::qemu_virt::{UART_BASE, mmio_va};
+use crate::config::{EXECUTOR_WORKERS, PHYS_VIRT_OFFSET};
+use crate::console::enable_buffered_console;
+use crate::console::log::init_log;
 use crate::mm::buddy::{phys_to_virt, virt_to_phys};
 use alloc::vec;
 
+use crate::fs::buffer_cache::{flush_daemon, init_buffer_cache};
+use crate::fs::init_fs;
 use crate::mm::{
     enable_early_mmu, init_buddy_system, setup_memory_and_mapping, switch_to_final_page_table,
     unmap_temp_identity_area,
 };
 use crate::task::SCHEDULER;
 use crate::task::context::TaskContext;
+use crate::task::executor;
+use crate::task::join::JoinHandle;
 use crate::task::scheduler::Scheduler;
+#[cfg(feature = "uart_interrupt")]
+use crate::trap::interrupts::service::uart_service;
 use crate::trap::interrupts::{init_supervisor_interrupts, set_next_timer_tick};
 use crate::trap::trap_entry;
-use crate::userlib::syscall::{sys_read, sys_shutdown, sys_sleep, sys_task_exit};
+use crate::userlib::shell::shell_main;
+use crate::userlib::sync;
 use core::arch::{asm, global_asm};
 use core::slice;
 use driver::{SerialPort, Uart}; // 引入 Trait 和统一的 Uart 类型
@@ -44,7 +57,7 @@ global_asm!(include_str!("entry.S"));
 lazy_static! {
     static ref UART: Mutex<Uart> = {
         // 这段代码只会在第一次访问 UART 时执行一次
-        let mut uart = Uart::new(UART_BASE);
+        let mut uart = Uart::new(mmio_va(UART_BASE));
         // 在创建的同时就完成初始化
         uart.init();
         Mutex::new(uart)
@@ -76,11 +89,17 @@ pub extern "C" fn rust_main(hart_id: usize, dtb_addr: usize) {
 }
 
 fn virt_rust_main(dtb_addr: usize) {
+    init_log();
     setup_memory_and_mapping(dtb_addr);
     switch_to_final_page_table();
     unmap_temp_identity_area();
     init_buddy_system();
-    sbi_println!("Buddy System Allocator initialized");
+    info!("Buddy System Allocator initialized");
+    init_buffer_cache();
+    driver::virtio_blk::init();
+    init_fs();
+    driver::rtc::init();
+    syslib::time::init();
     unsafe {
         asm!("csrw sscratch, {}", in(reg) &raw mut KERNEL_INIT_CONTEXT);
         let stvec_addr = (trap_entry as usize) & !0x3;
@@ -88,31 +107,40 @@ fn virt_rust_main(dtb_addr: usize) {
     }
     // println!("vec ptr: {:#X}", vec.as_ptr() as *const usize as usize);
     // polling_println!("polling");
-    sbi_println!("Hello from Charlotte OS!");
+    info!("Hello from Charlotte OS!");
     // 初始化调度器并创建 idle 任务
     let _ = Scheduler::init();
-    sbi_println!("✓ Scheduler initialized with idle task");
+    info!("✓ Scheduler initialized with idle task");
+    // cargo test 构建：运行所有内核测试后直接退出 QEMU
+    #[cfg(test)]
+    ktest::run_tests();
     // 创建测试任务
     {
         let mut scheduler = SCHEDULER.lock();
-        scheduler
-            .spawn(test_task_a, 8192, 1)
+        let task_a = scheduler
+            .spawn("task_a", test_task_a, 8192, 1)
             .expect("Failed to spawn task A");
         scheduler
-            .spawn(test_task_b, 8192, 1)
+            .spawn("task_b", move || test_task_b(task_a), 8192, 1)
             .expect("Failed to spawn task B");
         scheduler
-            .spawn(shell, 8192, 1)
+            .spawn("shell", shell_main, 16384, 1)
             .expect("Failed to spawn task shell");
     } // 锁在这里释放
+    // 驱动和后台服务作为 future 运行在执行器的 worker 任务上
+    executor::start(EXECUTOR_WORKERS).expect("Failed to spawn executor workers");
+    executor::spawn(flush_daemon());
+    #[cfg(feature = "uart_interrupt")]
+    uart_service::start();
 
-    sbi_println!("All tasks created. Starting scheduler...");
-    sbi_println!("======================================================");
+    info!("All tasks created. Starting scheduler...");
     unsafe {
         set_next_timer_tick();
         init_supervisor_interrupts();
     }
-    sbi_println!("haved set timer");
+    enable_buffered_console();
+    debug::gdb::init();
+    info!("Timer and interrupts enabled");
     // sbi_rt::system_reset(sbi_rt::Shutdown, sbi_rt::NoReason);
     Scheduler::run_scheduler();
 
@@ -120,55 +148,62 @@ fn virt_rust_main(dtb_addr: usize) {
     sbi_rt::system_reset(sbi_rt::Shutdown, sbi_rt::NoReason);
 }
 
+// 两个演示任务之间的同步：A 每完成一轮工作记一次数并发放一个许可，B 逐轮跟上
+static ROUNDS_DONE: sync::Mutex<usize> = sync::Mutex::new(0);
+static ROUND_FINISHED: sync::Condvar = sync::Condvar::new();
+static ROUND_TOKENS: sync::Semaphore = sync::Semaphore::new(0);
+static WORK_TOTAL: sync::RwLock<usize> = sync::RwLock::new(0);
+const DEMO_ROUNDS: usize = 10;
+
 // #[unsafe(no_mangle)]
-fn test_task_a() {
+// 返回完成的迭代次数，由 B 通过 JoinHandle 取得
+fn test_task_a() -> usize {
     user_println!("[Task A] ✓ Start!");
     // let status: usize;
     // unsafe { asm!("csrr {}, sstatus", out(reg) status) }
     // polling_println!("task a sstatus: {:b}", status);
-    let mut a = 0;
-    for _ in 0..10 {
+    for round in 1..=DEMO_ROUNDS {
+        let mut a = 0;
         // 模拟一些工作负载
         for _ in 0..100000 {
             // println!("[Task A] num is:{} ", a);
             a += 1;
             core::hint::spin_loop();
         }
+        *WORK_TOTAL.write() += a;
+        *ROUNDS_DONE.lock() = round;
+        ROUND_FINISHED.notify_all();
+        ROUND_TOKENS.release();
     }
     // println!("[Task A] ✓ Finished!");
     user_println!("[Task A] ✓ Finished!");
     // sys_task_exit();
+    *WORK_TOTAL.read()
 }
-fn test_task_b() {
+fn test_task_b(task_a: JoinHandle<usize>) {
     // println!("[Task B] ✓ Start!");
     user_println!("[Task B] ✓ Start!");
-    let mut b = 0;
-    for _ in 0..10 {
-        // 模拟一些工作负载
-        for _ in 0..100000 {
-            b += 1;
-            core::hint::spin_loop();
+    // 每轮一个许可，第一个许可超时说明 A 迟迟没有被调度
+    while !ROUND_TOKENS.acquire_timeout(1000) {
+        user_println!("[Task B] still waiting for Task A");
+    }
+    for _ in 1..DEMO_ROUNDS {
+        ROUND_TOKENS.acquire();
+    }
+    let mut rounds = ROUNDS_DONE.lock();
+    while *rounds < DEMO_ROUNDS {
+        let (guard, timed_out) = ROUND_FINISHED.wait_timeout(rounds, 1000);
+        rounds = guard;
+        if timed_out {
+            user_println!("[Task B] Task A is at round {}", *rounds);
         }
     }
-    sys_sleep(10000);
+    drop(rounds);
+    match task_a.join() {
+        Ok(total) => user_println!("[Task B] Task A did {} iterations", total),
+        Err(err) => user_println!("[Task B] Task A failed: {:?}", err),
+    }
     // println!("[Task B] ✓ Finished!");
     user_println!("[Task B] ✓ Finished!");
-    sys_shutdown();
-    // sys_task_exit();
-}
-fn shell() {
-    // println!("shell Start!");
-    user_println!("shell Start!");
-    // loop {
-    let char = sys_read(5000);
-    if char > 0 {
-        // println!("read a char:{},ascii {}", char as u8 as char, char);
-        // user_println!("read a char:{},ascii {}", char as u8 as char, char);
-        user_println!("read a ,ascii {}", char);
-        // break;
-    }
-    // }
-    // println!("shell ✓ Finished!");
-    user_println!("shell ✓ Finished!");
     // sys_task_exit();
 }
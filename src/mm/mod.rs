@@ -5,33 +5,32 @@ pub mod bump;
 pub mod memblock;
 pub mod mm_set;
 pub mod pagetable;
+pub mod shm;
 pub mod slub;
 
 use crate::config::PHYS_VIRT_OFFSET;
-use crate::console::{early_print_hex, early_print_str, early_println};
 use crate::data_struct::sync_ref_cell::SyncRefCell;
 use crate::mm::address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use crate::mm::buddy::{phys_to_virt, virt_to_phys};
 use crate::mm::bump::BumpAllocator;
 use crate::mm::memblock::MEMBLOCK;
 use crate::mm::pagetable::{FrameTracker, PTEFlags, PageSize, PageTable};
-use crate::mm::slub::KmemCache;
-use crate::{polling_println, println, sbi_println, virt_rust_main};
-use buddy::BuddySystemFrameAllocator;
+use crate::{debug, info};
+use buddy::{BuddySystemFrameAllocator, LinearMapping};
 use core::alloc::{GlobalAlloc, Layout};
 use core::arch::asm;
 use core::cell::RefCell;
 use core::num::NonZeroUsize;
-use core::ptr::NonNull;
 use core::ptr::{self, write_bytes};
+use core::sync::atomic::{AtomicBool, Ordering};
 use fdt::Fdt;
 use lazy_static::lazy_static;
 use riscv::register::satp::{self, Mode, Satp};
 use spin::Mutex;
 use spin::mutex::SpinMutex;
 
-pub const PAGE_SIZE_BITS: usize = 12;
-pub const PAGE_SIZE: usize = 1 << PAGE_SIZE_BITS; // 4KB
+pub use charlotte_core::address::{PAGE_SIZE, PAGE_SIZE_BITS};
+pub use charlotte_core::page::{Page, PageState};
 
 unsafe extern "C" {
     static _skernel: usize;
@@ -47,29 +46,6 @@ unsafe extern "C" {
     static _bss_start: usize;
     static _bss_end: usize;
 }
-pub enum Slab {
-    Slub {
-        freelist: VirtAddr,
-        inuse: u16,
-        cache: NonNull<KmemCache>,
-        has_next: bool,
-        next_partial: PhysPageNum,
-    },
-    SlubTail {
-        head_page_ppn: PhysPageNum,
-    },
-}
-pub enum PageState {
-    Reserved,
-    Slab(Slab),
-    Free,
-    PageTable,
-}
-
-pub struct Page {
-    pub ref_count: u8,
-    pub state: PageState,
-}
 pub static mut MEM_MAP: &mut [Page] = &mut [];
 pub static mut RAM_START_PPN: usize = 0;
 pub static mut RAM_END_PPN: usize = 0;
@@ -166,22 +142,14 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
         .lock()
         .init_add_memory(PhysAddr(ram_base), ram_size);
 
-    early_print_str("Memblock init: raw_ram ->");
-    early_print_hex(ram_base);
-    early_print_str("..");
-    early_print_hex(ram_end);
-    early_println();
+    info!("Memblock init: raw_ram -> {:#x}..{:#x}", ram_base, ram_end);
 
     // 在 Memblock 中把内核占用的物理内存抠掉
     MEMBLOCK
         .lock()
         .reserve_memory(PhysAddr(skernel), ekernel - skernel);
 
-    early_print_str("Memblock reserve: kernel ->");
-    early_print_hex(skernel);
-    early_print_str("..");
-    early_print_hex(ekernel);
-    early_println();
+    info!("Memblock reserve: kernel -> {:#x}..{:#x}", skernel, ekernel);
 
     // 处理头部旧标准的保留内存声明
     for reserved in fdt.memory_reservations() {
@@ -189,22 +157,22 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
             .lock()
             .reserve_memory(PhysAddr(reserved.address() as usize), reserved.size());
 
-        early_print_str("Memblock reserve: reserve_memory ->");
-        early_print_hex(reserved.address() as usize);
-        early_print_str("..");
-        early_print_hex(reserved.address() as usize + reserved.size());
-        early_println();
+        info!(
+            "Memblock reserve: reserve_memory -> {:#x}..{:#x}",
+            reserved.address() as usize,
+            reserved.address() as usize + reserved.size()
+        );
     }
 
     // 在 Memblock 抠除 DTB 数据本身的占用
     MEMBLOCK
         .lock()
         .reserve_memory(PhysAddr(dtb_addr), fdt.total_size());
-    early_print_str("Memblock reserve: dtb ->");
-    early_print_hex(dtb_addr);
-    early_print_str("..");
-    early_print_hex(dtb_addr + fdt.total_size());
-    early_println();
+    info!(
+        "Memblock reserve: dtb -> {:#x}..{:#x}",
+        dtb_addr,
+        dtb_addr + fdt.total_size()
+    );
 
     // 申请根页表 (此时 Memblock 已经有内存了，可以安心申请)
     let root_pa = MEMBLOCK
@@ -232,11 +200,11 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
                     if base >= ram_base && end <= ram_end {
                         // 落在 RAM 里的区间,这是固件保留区 (比如 SBI)
                         MEMBLOCK.lock().reserve_memory(PhysAddr(base), size);
-                        early_print_str("Memblock reserve: reserve_memory ->");
-                        early_print_hex(base);
-                        early_print_str("..");
-                        early_print_hex(base + size);
-                        early_println();
+                        info!(
+                            "Memblock reserve: reserve_memory -> {:#x}..{:#x}",
+                            base,
+                            base + size
+                        );
                     } else {
                         map_segment(
                             base,
@@ -245,19 +213,16 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
                             BootMapType::Linear,
                             MapAction::Map(PTEFlags::R | PTEFlags::W),
                         );
-                        early_print_str("Mapped MMIO: ");
-                        early_print_str(node.name);
-                        early_print_str(" ->");
-                        early_print_hex(base);
-                        early_print_str("..");
-                        early_print_hex(end);
-                        early_println();
+                        info!("Mapped MMIO: {} -> {:#x}..{:#x}", node.name, base, end);
                     }
                 }
             }
         }
     }
 
+    crate::debug::gdb::probe_port(&fdt);
+    crate::trap::interrupts::sstc::probe(&fdt);
+
     map_segment(
         stext,
         etext,
@@ -272,11 +237,7 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
         BootMapType::Identical,
         MapAction::Map(PTEFlags::R | PTEFlags::X),
     );
-    early_print_str("Mapped text ->");
-    early_print_hex(stext);
-    early_print_str("..");
-    early_print_hex(etext);
-    early_println();
+    info!("Mapped text -> {:#x}..{:#x}", stext, etext);
 
     map_segment(
         srodata,
@@ -292,11 +253,7 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
         BootMapType::Identical,
         MapAction::Map(PTEFlags::R),
     );
-    early_print_str("Mapped rodata ->");
-    early_print_hex(srodata);
-    early_print_str("..");
-    early_print_hex(erodata);
-    early_println();
+    info!("Mapped rodata -> {:#x}..{:#x}", srodata, erodata);
 
     map_segment(
         sdata,
@@ -312,11 +269,7 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
         BootMapType::Identical,
         MapAction::Map(PTEFlags::R | PTEFlags::W),
     );
-    early_print_str("Mapped data ->");
-    early_print_hex(sdata);
-    early_print_str("..");
-    early_print_hex(edata);
-    early_println();
+    info!("Mapped data -> {:#x}..{:#x}", sdata, edata);
 
     map_segment(
         sbss_with_stack,
@@ -332,11 +285,7 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
         BootMapType::Identical,
         MapAction::Map(PTEFlags::R | PTEFlags::W),
     );
-    early_print_str("Mapped bss ->");
-    early_print_hex(sbss_with_stack);
-    early_print_str("..");
-    early_print_hex(ebss);
-    early_println();
+    info!("Mapped bss -> {:#x}..{:#x}", sbss_with_stack, ebss);
 
     // 内核终点到物理内存终点
     let ram_start_after_kernel = align_up(ekernel, PAGE_SIZE);
@@ -347,11 +296,10 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
         BootMapType::Linear,
         MapAction::Map(PTEFlags::R | PTEFlags::W),
     );
-    early_print_str("Mapped kernel ->");
-    early_print_hex(ram_start_after_kernel);
-    early_print_str("..");
-    early_print_hex(ram_end);
-    early_println();
+    info!(
+        "Mapped kernel -> {:#x}..{:#x}",
+        ram_start_after_kernel, ram_end
+    );
 
     map_segment(
         ram_base,
@@ -360,11 +308,10 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
         BootMapType::Linear,
         MapAction::Map(PTEFlags::R | PTEFlags::W),
     );
-    early_print_str("Mapped RAM before kernel ->");
-    early_print_hex(ram_base);
-    early_print_str("..");
-    early_print_hex(skernel);
-    early_println();
+    info!(
+        "Mapped RAM before kernel -> {:#x}..{:#x}",
+        ram_base, skernel
+    );
 
     // 设置内核根页表的PPN
     *BOOT_ROOT_PPN.borrow_mut() = PhysPageNum::from(root_pa);
@@ -382,11 +329,10 @@ pub fn setup_memory_and_mapping(dtb_addr: usize) {
         .early_alloc(mem_map_pages * PAGE_SIZE, PAGE_SIZE)
         .expect("Failed to allocate physical memory for mem_map array");
 
-    early_print_str("Allocated mem_map array: ");
-    early_print_hex(mem_map_pages);
-    early_print_str(" pages at PA ");
-    early_print_hex(mem_map_pa.0);
-    early_println();
+    info!(
+        "Allocated mem_map array: {} pages at PA {:#x}",
+        mem_map_pages, mem_map_pa.0
+    );
 
     unsafe {
         RAM_START_PPN = ram_start_ppn;
@@ -464,7 +410,8 @@ fn align_down(value: usize, align: usize) -> usize {
 }
 
 type LockedBuddyAllocator = SpinMutex<BuddySystemFrameAllocator>;
-pub static BUDDY_ALLOCATOR: LockedBuddyAllocator = SpinMutex::new(BuddySystemFrameAllocator::new());
+pub static BUDDY_ALLOCATOR: LockedBuddyAllocator =
+    SpinMutex::new(BuddySystemFrameAllocator::new(LinearMapping));
 
 pub fn init_buddy_system() {
     let ram_start = unsafe { RAM_START_PPN };
@@ -488,7 +435,7 @@ pub fn init_buddy_system() {
     let mut buddy = BUDDY_ALLOCATOR.lock();
     let mut free_pages_count: usize = 0;
 
-    sbi_println!("Buddy System Allocator initialing:");
+    info!("Buddy System Allocator initialing:");
     // 遍历 Memblock 中剩余的 available
     for i in 0..mb.available.count {
         let region = &mb.available.regions[i];
@@ -510,17 +457,28 @@ pub fn init_buddy_system() {
             }
             free_pages_count += 1;
         }
+        debug!(
+            "  -> Heap start: 0x{:x}, end: 0x{:x}",
+            PhysAddr::from(&PhysPageNum(start_ppn)).0,
+            PhysAddr::from(&PhysPageNum(end_ppn)).0
+        );
         unsafe {
             buddy.add_free_region(start_ppn, end_ppn);
         }
     }
-    sbi_println!(
+    info!(
         "Handover Complete: {} total pages mapped. {} pages handed to Buddy.",
-        total_pages,
-        free_pages_count
+        total_pages, free_pages_count
     );
 }
 
+/// 最终页表生效后设备 MMIO 才有映射，panic 等路径据此决定能否直接访问寄存器
+static FINAL_PAGE_TABLE_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn mmio_mapped() -> bool {
+    FINAL_PAGE_TABLE_ACTIVE.load(Ordering::Acquire)
+}
+
 pub fn switch_to_final_page_table() {
     let root_ppn = BOOT_ROOT_PPN.borrow().0;
 
@@ -532,6 +490,13 @@ pub fn switch_to_final_page_table() {
         satp::write(satp);
         asm!("sfence.vma");
     }
+    FINAL_PAGE_TABLE_ACTIVE.store(true, Ordering::Release);
+}
+
+/// 最终页表的根，所有任务共用这一张页表
+pub fn kernel_page_table() -> &'static mut PageTable {
+    let root_pa = PhysAddr::from(&*BOOT_ROOT_PPN.borrow());
+    unsafe { &mut *(phys_to_virt(root_pa.0) as *mut PageTable) }
 }
 
 pub fn unmap_temp_identity_area() {
@@ -5,7 +5,7 @@ use bitflags::bitflags;
 
 use crate::mm::{
     BUDDY_ALLOCATOR, LockedBuddyAllocator, PAGE_SIZE,
-    address::{PPN_WIDTH_SV39, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
+    address::{ClearPage, PPN_WIDTH_SV39, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
     buddy::phys_to_virt,
     memblock::MEMBLOCK,
 };
@@ -198,6 +198,52 @@ impl PageTable {
         }
         None
     }
+    /// 查找 vpn 所在的叶子项，大页映射会在第一级或第二级就返回
+    pub fn find_leaf_pte(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        let idxs = vpn.indices();
+        let mut entries = &mut self.entries;
+        for i in 0..3 {
+            let pte = &mut entries[idxs[i]];
+            if !pte.is_valid() {
+                return None;
+            }
+            if pte
+                .flags()
+                .intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+            {
+                return Some(pte);
+            }
+            let phys_addr = PhysAddr::from(&pte.ppn()).0;
+            let virt_addr = phys_to_virt(phys_addr);
+            unsafe {
+                entries = &mut *(virt_addr as *mut [PageTableEntry; 512]);
+            }
+        }
+        None
+    }
+    /// 把虚拟地址翻译成物理地址，支持 2MB 和 1GB 大页；没有映射时返回 None
+    pub fn translate(&mut self, va: VirtAddr) -> Option<PhysAddr> {
+        let idxs = va.floor().indices();
+        let mut entries = &mut self.entries;
+        for (level, &idx) in idxs.iter().enumerate() {
+            let pte = &entries[idx];
+            if !pte.is_valid() {
+                return None;
+            }
+            if pte
+                .flags()
+                .intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+            {
+                let page_size = PAGE_SIZE << (9 * (2 - level));
+                return Some(PhysAddr(PhysAddr::from(&pte.ppn()).0 + va.0 % page_size));
+            }
+            let virt_addr = phys_to_virt(PhysAddr::from(&pte.ppn()).0);
+            unsafe {
+                entries = &mut *(virt_addr as *mut [PageTableEntry; 512]);
+            }
+        }
+        None
+    }
     pub fn find_create_pte(
         &mut self,
         vpn: VirtPageNum,
@@ -215,8 +261,9 @@ impl PageTable {
                     .lock()
                     .alloc(NonZeroUsize::new(1).unwrap())
                     .unwrap();
+                // 新的中间页表必须清零，否则残留数据会被当成有效的 PTE
+                frames.push(FrameTracker::new(ppn));
                 *pte = PageTableEntry::new(ppn, PTEFlags::V);
-                frames.push(FrameTracker { ppn });
             }
             let phys_addr = PhysAddr::from(&pte.ppn()).0;
             let virt_addr = phys_to_virt(phys_addr);
@@ -267,3 +314,52 @@ impl PageTable {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PTEFlags, PageSize, PageTable};
+    use crate::mm::{
+        BUDDY_ALLOCATOR,
+        address::{ClearPage, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
+        buddy::phys_to_virt,
+    };
+    use crate::{kassert, kassert_eq, kernel_test};
+    use alloc::vec::Vec;
+    use core::num::NonZeroUsize;
+
+    kernel_test! {
+        fn map_find_unmap() {
+            let before = BUDDY_ALLOCATOR.lock().free_blocks_per_order();
+            let root_ppn = BUDDY_ALLOCATOR.lock().alloc(NonZeroUsize::new(1).unwrap()).unwrap();
+            root_ppn.clear();
+            let root = unsafe {
+                &mut *(phys_to_virt(PhysAddr::from(&root_ppn).0) as *mut PageTable)
+            };
+            let mut frames = Vec::new();
+            // 只在新页表里操作，不切换 satp，所以用哪个虚拟页都可以
+            let vpn = VirtPageNum(0x12345);
+            let target = PhysPageNum(0x80400);
+            root.map(vpn, target, PTEFlags::R | PTEFlags::W, &mut frames);
+            // 三级页表需要新建两张中间页表
+            kassert_eq!(frames.len(), 2);
+            let pte = root.find_pte(vpn).unwrap();
+            kassert!(pte.is_valid());
+            kassert_eq!(pte.ppn().0, target.0);
+            kassert!(pte.flags().contains(PTEFlags::R | PTEFlags::W));
+            kassert!(!pte.flags().contains(PTEFlags::X));
+            kassert_eq!(
+                root.translate(VirtAddr(0x12345_678)).map(|pa| pa.0),
+                Some(0x80400_678)
+            );
+            // 同一张末级页表中的相邻页不需要再分配中间页表
+            root.map(VirtPageNum(0x12346), target, PTEFlags::R, &mut frames);
+            kassert_eq!(frames.len(), 2);
+            root.unmap(vpn, PageSize::FourKB);
+            kassert!(!root.find_pte(vpn).unwrap().is_valid());
+            kassert!(root.translate(VirtAddr(0x12345_678)).is_none());
+            drop(frames);
+            BUDDY_ALLOCATOR.lock().dealloc(root_ppn, NonZeroUsize::new(1).unwrap());
+            kassert_eq!(BUDDY_ALLOCATOR.lock().free_blocks_per_order(), before);
+        }
+    }
+}
@@ -0,0 +1,417 @@
+// src/mm/shm.rs
+//! 共享内存区域。create 从 BUDDY_ALLOCATOR 分配清零的物理页，map 以指定的权限把同一组页映射到
+//! 共享内存窗口中的一段新地址，unmap 撤销一次映射。每页在 MEM_MAP 中的引用计数等于它当前的映射数，
+//! 最后一个映射撤销时页还给伙伴分配器，区域随之消失。
+//!
+//! 映射属于建立它的任务，只有它能撤销。destroy 或创建者退出时区域被销毁：不能再映射，
+//! 没有映射时立即释放，否则等其他任务撤销最后一个映射；任务退出时它的映射全部撤销。
+//! transfer 把一个映射连同它的页移交给另一个任务，IPC 用它在任务之间按页转移缓冲区。
+//!
+//! 所有任务目前共用内核页表（MemorySet 还没有启用），每次 map 都是这张页表里的一个别名：
+//! 不同的任务各自 map 得到不同的地址，页表项的权限只约束经由这个地址的访问。
+//! 它不是任务之间的保护：任何任务都能访问别的任务的读写映射和线性映射中的同一组页，
+//! 按任务隔离要等每个任务有自己的 MemorySet 之后才能做到。
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use core::{arch::asm, num::NonZeroUsize};
+
+use crate::{
+    data_struct::lock::IrqLock,
+    mm::{
+        BUDDY_ALLOCATOR, PAGE_SIZE,
+        address::{ClearPage, PhysPageNum, VirtAddr, VirtPageNum},
+        get_page_state, kernel_page_table,
+        pagetable::{FrameTracker, PTEFlags, PageSize},
+    },
+    syslib::errno::Errno,
+};
+
+// shm_map 的权限，与 Linux mmap 的 PROT_* 一致。只写的页在 RISC-V 上不合法，写权限总是带着读权限
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+
+/// 共享内存窗口：Sv39 高半区的第一个 1GB，内核的线性映射和 MMIO 都不在这里
+const WINDOW_START: usize = 0xFFFF_FFC0_0000_0000;
+const WINDOW_SIZE: usize = 1 << 30;
+/// 一个区域最多的页数
+const MAX_REGION_PAGES: usize = 1024;
+
+struct Region {
+    frames: Vec<PhysPageNum>,
+    /// 创建区域的任务，它退出时区域被销毁
+    creator: usize,
+    /// 已经销毁，不能再映射，最后一个映射撤销时释放
+    destroyed: bool,
+}
+
+struct Mapping {
+    handle: usize,
+    pages: usize,
+    flags: PTEFlags,
+    /// 建立映射的任务，只有它能撤销，它退出时撤销
+    owner: usize,
+}
+
+struct SharedMemory {
+    regions: BTreeMap<usize, Region>,
+    /// 以起始地址为键
+    mappings: BTreeMap<usize, Mapping>,
+    next_handle: usize,
+    /// 窗口中间级页表占用的页，窗口一直存在，这些页不释放
+    page_tables: Vec<FrameTracker>,
+}
+
+static SHM: IrqLock<SharedMemory> = IrqLock::new(SharedMemory {
+    regions: BTreeMap::new(),
+    mappings: BTreeMap::new(),
+    next_handle: 1,
+    page_tables: Vec::new(),
+});
+
+impl SharedMemory {
+    /// 在窗口中找一段能放下 pages 页的空闲地址
+    fn find_free(&self, pages: usize) -> Option<usize> {
+        let len = pages * PAGE_SIZE;
+        let mut start = WINDOW_START;
+        for (&addr, mapping) in &self.mappings {
+            if addr - start >= len {
+                break;
+            }
+            start = addr + mapping.pages * PAGE_SIZE;
+        }
+        (WINDOW_START + WINDOW_SIZE - start >= len).then_some(start)
+    }
+
+    /// 在窗口中新找一段地址，以 flags 映射区域的全部页，返回起始地址。不改变页的引用计数
+    fn map_region(&mut self, handle: usize, flags: PTEFlags) -> Result<usize, Errno> {
+        let addr = self
+            .find_free(self.regions[&handle].frames.len())
+            .ok_or(Errno::ENOMEM)?;
+        let page_table = kernel_page_table();
+        for (i, &ppn) in self.regions[&handle].frames.iter().enumerate() {
+            let vpn = VirtPageNum::from(VirtAddr::from(addr + i * PAGE_SIZE));
+            page_table.map(vpn, ppn, flags, &mut self.page_tables);
+        }
+        // 窗口里的地址可能刚被撤销过映射，丢掉可能残留的旧翻译
+        unsafe { asm!("sfence.vma") };
+        Ok(addr)
+    }
+
+    /// 撤销 addr 开始的 pages 页的页表项，不改变页的引用计数
+    fn unmap_pages(addr: usize, pages: usize) {
+        let page_table = kernel_page_table();
+        for i in 0..pages {
+            page_table.unmap(
+                VirtPageNum::from(VirtAddr::from(addr + i * PAGE_SIZE)),
+                PageSize::FourKB,
+            );
+        }
+    }
+
+    /// 撤销从 addr 开始的映射，这是区域的最后一个映射时释放它的页
+    fn remove_mapping(&mut self, addr: usize) {
+        let Some(Mapping { handle, pages, .. }) = self.mappings.remove(&addr) else {
+            return;
+        };
+        Self::unmap_pages(addr, pages);
+        let mut last = false;
+        for &ppn in &self.regions[&handle].frames {
+            let page = get_page_state(ppn);
+            page.ref_count -= 1;
+            last = page.ref_count == 0;
+        }
+        if last {
+            self.free_region(handle);
+        }
+    }
+
+    /// 销毁区域：没有映射时立即释放，否则等最后一个映射撤销
+    fn destroy_region(&mut self, handle: usize) {
+        let Some(region) = self.regions.get_mut(&handle) else {
+            return;
+        };
+        region.destroyed = true;
+        if get_page_state(region.frames[0]).ref_count == 0 {
+            self.free_region(handle);
+        }
+    }
+
+    fn free_region(&mut self, handle: usize) {
+        if let Some(region) = self.regions.remove(&handle) {
+            dealloc_frames(&region.frames);
+        }
+    }
+}
+
+fn dealloc_frames(frames: &[PhysPageNum]) {
+    let mut buddy = BUDDY_ALLOCATOR.lock();
+    for &ppn in frames {
+        buddy.dealloc(ppn, NonZeroUsize::new(1).unwrap());
+    }
+}
+
+/// 为任务 creator 创建 size 字节（按页向上取整）的区域，返回句柄
+pub fn create(size: usize, creator: usize) -> Result<usize, Errno> {
+    let pages = size.div_ceil(PAGE_SIZE);
+    if pages == 0 || pages > MAX_REGION_PAGES {
+        return Err(Errno::EINVAL);
+    }
+    let mut frames = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        let Some(ppn) = BUDDY_ALLOCATOR.lock().alloc(NonZeroUsize::new(1).unwrap()) else {
+            dealloc_frames(&frames);
+            return Err(Errno::ENOMEM);
+        };
+        ppn.clear();
+        get_page_state(ppn).ref_count = 0;
+        frames.push(ppn);
+    }
+    let mut shm = SHM.lock();
+    let handle = shm.next_handle;
+    shm.next_handle += 1;
+    shm.regions.insert(
+        handle,
+        Region {
+            frames,
+            creator,
+            destroyed: false,
+        },
+    );
+    Ok(handle)
+}
+
+/// 为任务 owner 以 prot 权限映射区域，返回映射的起始地址
+pub fn map(handle: usize, prot: usize, owner: usize) -> Result<usize, Errno> {
+    let flags = match prot {
+        PROT_READ => PTEFlags::R,
+        _ if prot == PROT_READ | PROT_WRITE => PTEFlags::R | PTEFlags::W,
+        _ => return Err(Errno::EINVAL),
+    };
+    let mut shm = SHM.lock();
+    let region = shm
+        .regions
+        .get(&handle)
+        .filter(|region| !region.destroyed)
+        .ok_or(Errno::EBADF)?;
+    // 区域内所有页的映射数相同
+    if get_page_state(region.frames[0]).ref_count == u8::MAX {
+        return Err(Errno::EMFILE);
+    }
+    let pages = region.frames.len();
+    let addr = shm.map_region(handle, flags)?;
+    for &ppn in &shm.regions[&handle].frames {
+        get_page_state(ppn).ref_count += 1;
+    }
+    shm.mappings.insert(
+        addr,
+        Mapping {
+            handle,
+            pages,
+            flags,
+            owner,
+        },
+    );
+    Ok(addr)
+}
+
+/// 把任务 from 从 addr 开始的映射移交给任务 to：以同样的权限在新地址建立 to 的映射，再撤销旧地址，
+/// 返回新地址。区域已经销毁时也可以移交。addr 不是 from 的映射时返回 EINVAL
+pub fn transfer(addr: usize, from: usize, to: usize) -> Result<usize, Errno> {
+    let mut shm = SHM.lock();
+    let (handle, pages, flags) = shm
+        .mappings
+        .get(&addr)
+        .filter(|mapping| mapping.owner == from)
+        .map(|mapping| (mapping.handle, mapping.pages, mapping.flags))
+        .ok_or(Errno::EINVAL)?;
+    let new_addr = shm.map_region(handle, flags)?;
+    SharedMemory::unmap_pages(addr, pages);
+    shm.mappings.remove(&addr);
+    shm.mappings.insert(
+        new_addr,
+        Mapping {
+            handle,
+            pages,
+            flags,
+            owner: to,
+        },
+    );
+    Ok(new_addr)
+}
+
+/// 撤销任务 owner 从 addr 开始的映射。这是区域的最后一个映射时释放它的页。
+/// addr 不是 owner 的映射时返回 EINVAL
+pub fn unmap(addr: usize, owner: usize) -> Result<(), Errno> {
+    let mut shm = SHM.lock();
+    if shm
+        .mappings
+        .get(&addr)
+        .is_none_or(|mapping| mapping.owner != owner)
+    {
+        return Err(Errno::EINVAL);
+    }
+    shm.remove_mapping(addr);
+    Ok(())
+}
+
+/// 销毁区域，之后不能再映射。没有映射时立即释放它的页，否则在最后一个映射撤销时释放
+pub fn destroy(handle: usize) -> Result<(), Errno> {
+    let mut shm = SHM.lock();
+    if shm
+        .regions
+        .get(&handle)
+        .is_none_or(|region| region.destroyed)
+    {
+        return Err(Errno::EBADF);
+    }
+    shm.destroy_region(handle);
+    Ok(())
+}
+
+/// 任务退出时撤销它的所有映射并销毁它创建的区域
+pub fn release_task(task_id: usize) {
+    let mut shm = SHM.lock();
+    let addrs: Vec<usize> = shm
+        .mappings
+        .iter()
+        .filter(|(_, mapping)| mapping.owner == task_id)
+        .map(|(&addr, _)| addr)
+        .collect();
+    for addr in addrs {
+        shm.remove_mapping(addr);
+    }
+    let handles: Vec<usize> = shm
+        .regions
+        .iter()
+        .filter(|(_, region)| region.creator == task_id)
+        .map(|(&handle, _)| handle)
+        .collect();
+    for handle in handles {
+        shm.destroy_region(handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        MAX_REGION_PAGES, PROT_READ, PROT_WRITE, create, destroy, map, release_task, transfer,
+        unmap,
+    };
+    use crate::{
+        kassert, kassert_eq, kernel_test,
+        mm::{
+            PAGE_SIZE,
+            address::{VirtAddr, VirtPageNum},
+            kernel_page_table,
+            pagetable::PTEFlags,
+        },
+        syslib::errno::Errno,
+    };
+
+    // 测试中没有真正的任务，任务 id 只用来区分映射和区域的归属
+    const OWNER: usize = 1;
+    const OTHER: usize = 2;
+
+    kernel_test! {
+        fn mappings_share_frames_until_the_last_unmap() {
+            let handle = create(100, OWNER).unwrap();
+            kassert_eq!(map(handle, PROT_WRITE, OWNER), Err(Errno::EINVAL));
+            let writer = map(handle, PROT_READ | PROT_WRITE, OWNER).unwrap();
+            let reader = map(handle, PROT_READ, OWNER).unwrap();
+            kassert!(writer != reader);
+            unsafe { (writer as *mut u64).write_volatile(0x5eed) };
+            kassert_eq!(unsafe { (reader as *const u64).read_volatile() }, 0x5eed);
+            let pte = kernel_page_table()
+                .find_leaf_pte(VirtPageNum::from(VirtAddr::from(reader)))
+                .unwrap();
+            kassert!(!pte.flags().contains(PTEFlags::W));
+            kassert_eq!(unmap(writer, OWNER), Ok(()));
+            kassert_eq!(unmap(writer, OWNER), Err(Errno::EINVAL));
+            // 还有一个映射，区域仍然存在
+            let again = map(handle, PROT_READ, OWNER).unwrap();
+            kassert_eq!(unmap(again, OWNER), Ok(()));
+            kassert_eq!(unmap(reader, OWNER), Ok(()));
+            kassert_eq!(map(handle, PROT_READ, OWNER), Err(Errno::EBADF));
+        }
+    }
+
+    kernel_test! {
+        fn largest_region_can_be_created_and_mapped() {
+            let size = MAX_REGION_PAGES * PAGE_SIZE;
+            kassert_eq!(create(size + 1, OWNER), Err(Errno::EINVAL));
+            let handle = create(size, OWNER).unwrap();
+            let addr = map(handle, PROT_READ | PROT_WRITE, OWNER).unwrap();
+            let last = (addr + size - 8) as *mut u64;
+            unsafe { last.write_volatile(0x1a57) };
+            kassert_eq!(unsafe { last.read_volatile() }, 0x1a57);
+            kassert_eq!(destroy(handle), Ok(()));
+            kassert_eq!(unmap(addr, OWNER), Ok(()));
+        }
+    }
+
+    kernel_test! {
+        fn only_the_owner_can_unmap() {
+            let handle = create(100, OWNER).unwrap();
+            let addr = map(handle, PROT_READ, OWNER).unwrap();
+            kassert_eq!(unmap(addr, OTHER), Err(Errno::EINVAL));
+            kassert_eq!(unmap(addr, OWNER), Ok(()));
+        }
+    }
+
+    kernel_test! {
+        fn destroy_frees_regions_once_unmapped() {
+            // 从未映射过的区域立即释放
+            let unused = create(100, OWNER).unwrap();
+            kassert_eq!(destroy(unused), Ok(()));
+            kassert_eq!(map(unused, PROT_READ, OWNER), Err(Errno::EBADF));
+            kassert_eq!(destroy(unused), Err(Errno::EBADF));
+            // 仍有映射的区域不能再映射，已有的映射继续可用
+            let handle = create(100, OWNER).unwrap();
+            let addr = map(handle, PROT_READ | PROT_WRITE, OWNER).unwrap();
+            kassert_eq!(destroy(handle), Ok(()));
+            kassert_eq!(destroy(handle), Err(Errno::EBADF));
+            kassert_eq!(map(handle, PROT_READ, OWNER), Err(Errno::EBADF));
+            unsafe { (addr as *mut u64).write_volatile(1) };
+            kassert_eq!(unmap(addr, OWNER), Ok(()));
+        }
+    }
+
+    kernel_test! {
+        fn transfer_moves_the_mapping_to_another_task() {
+            let handle = create(100, OWNER).unwrap();
+            let addr = map(handle, PROT_READ | PROT_WRITE, OWNER).unwrap();
+            unsafe { (addr as *mut u64).write_volatile(0x7a5f) };
+            kassert_eq!(transfer(addr, OTHER, OWNER), Err(Errno::EINVAL));
+            let moved = transfer(addr, OWNER, OTHER).unwrap();
+            kassert!(moved != addr);
+            kassert_eq!(unsafe { (moved as *const u64).read_volatile() }, 0x7a5f);
+            // 旧地址不再有映射，新映射保留原来的权限并属于接收的任务
+            let page_table = kernel_page_table();
+            kassert!(page_table.find_leaf_pte(VirtPageNum::from(VirtAddr::from(addr))).is_none());
+            let pte = page_table
+                .find_leaf_pte(VirtPageNum::from(VirtAddr::from(moved)))
+                .unwrap();
+            kassert!(pte.flags().contains(PTEFlags::W));
+            kassert_eq!(unmap(addr, OWNER), Err(Errno::EINVAL));
+            kassert_eq!(unmap(moved, OWNER), Err(Errno::EINVAL));
+            // 区域销毁后映射仍然可以继续移交
+            kassert_eq!(destroy(handle), Ok(()));
+            let back = transfer(moved, OTHER, OWNER).unwrap();
+            kassert_eq!(unmap(back, OWNER), Ok(()));
+        }
+    }
+
+    kernel_test! {
+        fn exiting_task_releases_its_mappings_and_regions() {
+            let handle = create(100, OWNER).unwrap();
+            let own = map(handle, PROT_READ | PROT_WRITE, OWNER).unwrap();
+            let other = map(handle, PROT_READ, OTHER).unwrap();
+            release_task(OWNER);
+            kassert_eq!(unmap(own, OWNER), Err(Errno::EINVAL));
+            kassert_eq!(map(handle, PROT_READ, OTHER), Err(Errno::EBADF));
+            // 其他任务的映射不受影响
+            kassert_eq!(unsafe { (other as *const u64).read_volatile() }, 0);
+            kassert_eq!(unmap(other, OTHER), Ok(()));
+        }
+    }
+}
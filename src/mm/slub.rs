@@ -1,37 +1,58 @@
-use bitflags::Flag;
+use charlotte_core::{PhysMapper, slub::SlabBackend};
 use core::{
     alloc::GlobalAlloc,
     alloc::Layout,
     num::NonZeroUsize,
-    ptr::{write_unaligned, write_volatile},
+    ptr::{NonNull, null_mut},
 };
-use spin::mutex::SpinMutex;
-use thiserror_no_std::Error;
 
 use crate::{
     data_struct::lock::IrqLock,
     mm::{
-        BUDDY_ALLOCATOR, MEM_MAP, PAGE_SIZE, PAGE_SIZE_BITS, PageState, Slab,
-        address::{PhysAddr, PhysPageNum, VirtAddr},
+        BUDDY_ALLOCATOR, PAGE_SIZE_BITS, Page, PageState,
+        address::{PhysAddr, PhysPageNum},
         buddy::{phys_to_virt, virt_to_phys},
         get_page_state,
     },
 };
-#[derive(Error, Debug)]
-pub enum KernelError {
-    #[error("Slub: out of memory")]
-    OutOfMemory,
+
+/// 内核的 KmemCache 从全局 Buddy 分配器申请页，页描述符保存在 MEM_MAP 中
+pub type KmemCache = charlotte_core::slub::KmemCache<KernelPages>;
+
+pub struct KernelPages;
+
+impl PhysMapper for KernelPages {
+    fn phys_to_virt(&self, pa: usize) -> usize {
+        phys_to_virt(pa)
+    }
+    fn virt_to_phys(&self, va: usize) -> usize {
+        virt_to_phys(va)
+    }
 }
-pub struct KmemCache {
+
+impl SlabBackend for KernelPages {
+    fn alloc_pages(&self, pages: NonZeroUsize) -> Option<PhysPageNum> {
+        BUDDY_ALLOCATOR.lock().alloc(pages)
+    }
+    fn free_pages(&self, ppn: PhysPageNum, pages: NonZeroUsize) {
+        BUDDY_ALLOCATOR.lock().dealloc(ppn, pages);
+    }
+    fn page(&self, ppn: PhysPageNum) -> NonNull<Page> {
+        NonNull::from(get_page_state(ppn))
+    }
+}
+/// 单个 KmemCache 的使用统计
+#[derive(Clone, Copy, Debug)]
+pub struct KmemCacheStats {
     pub object_size: usize,
-    pub active_slab_ppn: Option<PhysPageNum>,
-    pub partial_slabs_head: Option<PhysPageNum>,
-    // pub partial_count: usize,
-    // pub min_partial: usize,
+    pub nr_slabs: usize,
+    pub pages_per_slab: usize,
+    pub nr_inuse: usize,
 }
+pub const KMEM_CACHE_COUNT: usize = 9;
 pub struct SlubAllocator {
     // 管理从2^3=8字节到2^11=2048字节，所以数组长度是11-3+1=9
-    caches: [IrqLock<KmemCache>; 9],
+    caches: [IrqLock<KmemCache>; KMEM_CACHE_COUNT],
 }
 
 unsafe impl GlobalAlloc for SlubAllocator {
@@ -46,11 +67,20 @@ unsafe impl GlobalAlloc for SlubAllocator {
         if actual_size <= 2048 {
             // 路由到对应的 KmemCache
             let index = actual_size.trailing_zeros() as usize - 3;
-            return self.caches[index].lock().alloc();
+            self.caches[index].lock().alloc()
         } else {
-            // 大于 2048B 的，找 Buddy 批发大页
-            unimplemented!()
-            // return buddy_alloc_large(actual_size);
+            // 大于 2048B 的，直接向 Buddy 申请 2 的幂个页，伙伴块按自身大小对齐，满足 align
+            let pages = actual_size >> PAGE_SIZE_BITS;
+            let Some(ppn) = BUDDY_ALLOCATOR
+                .lock()
+                .alloc(NonZeroUsize::new(pages).unwrap())
+            else {
+                return null_mut();
+            };
+            for i in 0..pages {
+                get_page_state(PhysPageNum(ppn.0 + i)).state = PageState::Reserved;
+            }
+            phys_to_virt(PhysAddr::from(&ppn).0) as *mut u8
         }
     }
 
@@ -61,8 +91,7 @@ unsafe impl GlobalAlloc for SlubAllocator {
         if actual_size > 2048 {
             // 大页模式直接还给 Buddy
             let va = ptr as usize;
-            let pa = virt_to_phys(va);
-            let ppn = PhysPageNum::from(pa);
+            let ppn = PhysAddr(virt_to_phys(va)).floor();
             let pages = actual_size >> PAGE_SIZE_BITS;
 
             for i in 0..pages {
@@ -76,364 +105,83 @@ unsafe impl GlobalAlloc for SlubAllocator {
                 .lock()
                 .dealloc(ppn, NonZeroUsize::new(pages).unwrap());
         } else {
-            // SLUB 模式
-            let va = ptr as usize;
-            let pa = virt_to_phys(va);
-            let ppn = PhysPageNum::from(pa);
-
-            let page = get_page_state(ppn);
-
-            match &mut page.state {
-                PageState::Slab(Slab::Slub { cache, .. }) => unsafe {
-                    cache.as_mut().free_object(ptr, ppn);
-                },
-                PageState::Slab(Slab::SlubTail { head_page_ppn }) => {
-                    let head_page = get_page_state(*head_page_ppn);
-                    if let PageState::Slab(Slab::Slub { cache, .. }) = &mut head_page.state {
-                        unsafe {
-                            cache.as_mut().free_object(ptr, *head_page_ppn);
-                        }
-                    } else {
-                        panic!("FATAL: SlubTail does not point to a Slub Head!");
-                    }
-                }
-                _ => {
-                    panic!("FATAL: Tried to dealloc a pointer not belonging to SLUB!");
-                }
-            }
+            // SLUB 模式，与 alloc 按同样的规则找到对象所属的 KmemCache
+            let index = actual_size.trailing_zeros() as usize - 3;
+            unsafe { self.caches[index].lock().free(ptr) };
         }
     }
 }
+/// 获取所有 KmemCache 的使用统计，不做堆分配，可以在任何上下文调用
+pub fn slab_stats() -> [KmemCacheStats; KMEM_CACHE_COUNT] {
+    core::array::from_fn(|i| {
+        let cache = SLUB_ALLOCATOR.caches[i].lock();
+        KmemCacheStats {
+            object_size: cache.object_size,
+            nr_slabs: cache.nr_slabs,
+            pages_per_slab: cache.pages_per_slab(),
+            nr_inuse: cache.nr_inuse,
+        }
+    })
+}
 #[global_allocator]
 static SLUB_ALLOCATOR: SlubAllocator = SlubAllocator {
     caches: [
-        IrqLock::new(KmemCache::new(8)),
-        IrqLock::new(KmemCache::new(16)),
-        IrqLock::new(KmemCache::new(32)),
-        IrqLock::new(KmemCache::new(64)),
-        IrqLock::new(KmemCache::new(128)),
-        IrqLock::new(KmemCache::new(256)),
-        IrqLock::new(KmemCache::new(512)),
-        IrqLock::new(KmemCache::new(1024)),
-        IrqLock::new(KmemCache::new(2048)),
+        IrqLock::new(KmemCache::new(8, KernelPages)),
+        IrqLock::new(KmemCache::new(16, KernelPages)),
+        IrqLock::new(KmemCache::new(32, KernelPages)),
+        IrqLock::new(KmemCache::new(64, KernelPages)),
+        IrqLock::new(KmemCache::new(128, KernelPages)),
+        IrqLock::new(KmemCache::new(256, KernelPages)),
+        IrqLock::new(KmemCache::new(512, KernelPages)),
+        IrqLock::new(KmemCache::new(1024, KernelPages)),
+        IrqLock::new(KmemCache::new(2048, KernelPages)),
     ],
 };
 
-impl KmemCache {
-    pub const fn new(requested_size: usize) -> Self {
-        // 获取当前机器硬件字长
-        let align = core::mem::size_of::<usize>();
-        let min_size = align;
-
-        // 向上取整到字长的倍数
-        let mut actual_size = (requested_size + align - 1) & !(align - 1);
-        if actual_size < min_size {
-            actual_size = min_size;
-        }
-
-        Self {
-            object_size: actual_size,
-            active_slab_ppn: None,
-            partial_slabs_head: None,
+#[cfg(test)]
+mod tests {
+    use super::slab_stats;
+    use crate::{kassert, kassert_eq, kernel_test};
+    use alloc::{boxed::Box, vec::Vec};
+
+    // 64 字节对象所在的 cache 下标
+    const CACHE_64: usize = 3;
+
+    kernel_test! {
+        fn box_uses_matching_cache() {
+            let before = slab_stats()[CACHE_64].nr_inuse;
+            let object = Box::new([0u8; 48]);
+            kassert_eq!(slab_stats()[CACHE_64].nr_inuse, before + 1);
+            kassert_eq!(object.as_ptr() as usize % 64, 0);
+            drop(object);
+            kassert_eq!(slab_stats()[CACHE_64].nr_inuse, before);
         }
     }
 
-    fn alloc_new_slab(&mut self) -> Result<PhysPageNum, KernelError> {
-        if self.object_size < PAGE_SIZE {
-            let ppn = BUDDY_ALLOCATOR
-                .lock()
-                .alloc(NonZeroUsize::new(1).unwrap())
-                .ok_or(KernelError::OutOfMemory)?;
-            // 将slab结构分割成对应大小的node节点
-            let start_va = phys_to_virt(PhysAddr::from(&ppn).0);
-            let mut va = start_va;
-            let end = va + PAGE_SIZE;
-
-            while va < end {
-                let memory_block = va as *mut usize;
-
-                let next_va = va + self.object_size;
-                let next_addr = if next_va < end { next_va } else { 0 };
-
-                unsafe {
-                    write_volatile(memory_block, next_addr);
-                }
-                va += self.object_size;
-            }
-
-            let original_head = self.partial_slabs_head;
-            let (has_next, next_partial) = if let Some(head_ppn) = original_head {
-                (true, head_ppn)
-            } else {
-                (false, PhysPageNum(0))
-            };
-            let mut slab = Slab::Slub {
-                freelist: VirtAddr::from(start_va),
-                inuse: 0,
-                cache: self.into(),
-                has_next,
-                next_partial,
-            };
-            // 头插法插入
-            self.partial_slabs_head = Some(ppn);
-            // 更改新获得的内存对应的MEM_MAP的信息为slab
-            let page = get_page_state(ppn);
-            page.ref_count = 1;
-            page.state = PageState::Slab(slab);
-
-            return Ok(ppn);
-        } else {
-            // 大页分配逻辑
-            let required_pages = (self.object_size + PAGE_SIZE - 1) >> PAGE_SIZE_BITS;
-
-            // Buddy System 只能分配 2 的次幂个页，向上取整到 2 的幂
-            let allocate_pages = required_pages.next_power_of_two();
-
-            let ppn = BUDDY_ALLOCATOR
-                .lock()
-                .alloc(core::num::NonZeroUsize::new(allocate_pages).unwrap())
-                .ok_or(KernelError::OutOfMemory)?;
-
-            let start_va = phys_to_virt(PhysAddr::from(&ppn).0);
-            let mut va = start_va;
-            let end = start_va + (allocate_pages << PAGE_SIZE_BITS);
-
-            while va + self.object_size <= end {
-                let memory_block = va as *mut usize;
-                let next_va = va + self.object_size;
-
-                let next_addr = if next_va + self.object_size <= end {
-                    next_va
-                } else {
-                    0
-                };
-
-                unsafe {
-                    write_volatile(memory_block, next_addr);
-                }
-                va += self.object_size;
-            }
-
-            let original_head = self.partial_slabs_head;
-            let (has_next, next_partial) = if let Some(head_ppn) = original_head {
-                (true, head_ppn)
-            } else {
-                (false, PhysPageNum(0))
-            };
-
-            let slab_head = Slab::Slub {
-                freelist: VirtAddr::from(start_va),
-                inuse: 0,
-                cache: self.into(),
-                has_next,
-                next_partial,
-            };
-
-            self.partial_slabs_head = Some(ppn);
-
-            let current_ppn = PhysPageNum(ppn.0);
-            let page = get_page_state(current_ppn);
-            page.ref_count = 1;
-            page.state = PageState::Slab(slab_head);
-            // 后面的页标记为Tail
-            for i in 1..allocate_pages {
-                let current_ppn = PhysPageNum(ppn.0 + i);
-                let page = get_page_state(current_ppn);
-                page.ref_count = 1;
-
-                page.state = PageState::Slab(Slab::SlubTail {
-                    head_page_ppn: ppn, // 全部指回头Slab
-                });
-            }
-
-            return Ok(ppn);
-        }
-    }
-    fn alloc(&mut self) -> *mut u8 {
-        loop {
-            // 尝试从当前正在使用的 Active Slab 中分配
-            if let Some(ppn) = self.active_slab_ppn {
-                let page = get_page_state(ppn);
-
-                if let PageState::Slab(Slab::Slub {
-                    freelist, inuse, ..
-                }) = &mut page.state
-                {
-                    // 检查 Active Slab 是否已满，
-                    // 由let next_addr = if next_va < end { next_va } else { 0 };这一行保证
-                    if freelist.0 == 0 {
-                        self.active_slab_ppn = None;
-                        continue;
-                    }
-
-                    // 拿走当前 freelist 指向的内存块
-                    let mem_block_ptr = freelist.0 as *mut u8;
-
-                    // 读取这个内存块开头里存的下一个空闲内存块的地址
-                    let next_free_addr = unsafe { *(mem_block_ptr as *mut usize) };
-
-                    *freelist = VirtAddr(next_free_addr);
-                    *inuse += 1;
-
-                    return mem_block_ptr;
-                } else {
-                    panic!("FATAL: active_slab_ppn does not point to a Slub Head!");
-                }
-            }
-            // Active 没有可用内存，从 Partial 获取
-            else if let Some(partial_head) = self.partial_slabs_head.take() {
-                let page = get_page_state(partial_head);
-
-                if let PageState::Slab(Slab::Slub {
-                    has_next,
-                    next_partial,
-                    ..
-                }) = &page.state
-                {
-                    if *has_next {
-                        self.partial_slabs_head = Some(*next_partial);
-                    }
-                    self.active_slab_ppn = Some(partial_head);
-                    continue;
-                } else {
-                    panic!("FATAL: Memory corruption! Found non-Head page in partial_slabs_head");
-                }
-            }
-            // 若partial也没有可用内存，向buddy system申请新的
-            else {
-                match self.alloc_new_slab() {
-                    Ok(new_ppn) => {
-                        self.active_slab_ppn = Some(new_ppn);
-                        continue;
-                    }
-                    Err(_) => {
-                        // OOM
-                        return core::ptr::null_mut();
-                    }
-                }
-            }
+    kernel_test! {
+        fn large_allocations_come_from_buddy() {
+            let before = slab_stats();
+            // 超过最大的 KmemCache，分配整页且按大小对齐
+            let mut large: Vec<u8> = Vec::with_capacity(3 * 4096);
+            kassert_eq!(large.as_ptr() as usize % (4 * 4096), 0);
+            large.resize(3 * 4096, 0x5a);
+            kassert!(large.iter().all(|&byte| byte == 0x5a));
+            drop(large);
+            let after = slab_stats();
+            kassert!(before.iter().zip(after.iter()).all(|(b, a)| b.nr_inuse == a.nr_inuse));
         }
     }
 
-    pub unsafe fn free_object(&mut self, ptr: *mut u8, slab_ppn: PhysPageNum) {
-        let page = get_page_state(slab_ppn);
-
-        if let PageState::Slab(Slab::Slub {
-            freelist,
-            inuse,
-            has_next,
-            next_partial,
-            ..
-        }) = &mut page.state
-        {
-            let was_full = freelist.0 == 0;
-            let is_active = self.active_slab_ppn == Some(slab_ppn);
-
-            let obj_ptr = ptr as *mut usize;
-            // 将当前的 freelist (下一个可用地址) 写入被释放对象的开头
-            unsafe {
-                write_volatile(obj_ptr, freelist.0);
-            }
-            // freelist 更新为当前刚释放的对象
-            *freelist = VirtAddr::from(ptr as usize);
-            *inuse -= 1;
-
-            if *inuse == 0 {
-                if is_active {
-                    self.active_slab_ppn = None;
-                } else {
-                    self.remove_from_partial(slab_ppn);
-                }
-
-                // 当初向 Buddy 申请的页数
-                let required_pages = (self.object_size + PAGE_SIZE - 1) >> PAGE_SIZE_BITS;
-                let allocate_pages = required_pages.max(1).next_power_of_two();
-
-                for i in 0..allocate_pages {
-                    let current_ppn = PhysPageNum(slab_ppn.0 + i);
-                    let current_page = get_page_state(current_ppn);
-                    current_page.state = PageState::Free;
-                    current_page.ref_count = 0;
-                }
-
-                BUDDY_ALLOCATOR.lock().dealloc(
-                    slab_ppn,
-                    core::num::NonZeroUsize::new(allocate_pages).unwrap(),
-                );
-            } else if was_full && !is_active {
-                // 曾经全满,没有保存在kmemcacahe中
-                // 如果它本身就是 active，哪怕曾满了也不用管，因为下一次 alloc 会处理它。
-                // 只有当它既满了，又不是 active 时，才说明它被遗忘了。
-                // 现在它空出了一个位置，把它头插法放回 Partial 备用链表
-
-                let old_head = self.partial_slabs_head;
-                if let Some(old_ppn) = old_head {
-                    *next_partial = old_ppn;
-                    *has_next = true;
-                } else {
-                    *has_next = false;
-                }
-                self.partial_slabs_head = Some(slab_ppn);
+    kernel_test! {
+        fn objects_do_not_overlap() {
+            let before = slab_stats()[CACHE_64].nr_inuse;
+            // 数量超过一个 slab 能容纳的对象数，覆盖申请新 slab 的路径
+            let objects: Vec<Box<[u64; 8]>> = (0..200u64).map(|i| Box::new([i; 8])).collect();
+            for (i, object) in objects.iter().enumerate() {
+                kassert!(object.iter().all(|&word| word == i as u64), "object {} corrupted", i);
             }
-        } else {
-            panic!(
-                "FATAL: free_object called on a non-Slub page! PPN: {:?}",
-                slab_ppn
-            );
-        }
-    }
-    fn remove_from_partial(&mut self, target_ppn: PhysPageNum) {
-        let mut current_opt = self.partial_slabs_head;
-        let mut prev_opt: Option<PhysPageNum> = None;
-
-        while let Some(current_ppn) = current_opt {
-            let current_page = get_page_state(current_ppn);
-
-            // 提取当前节点的 next 指针
-            let next_opt = if let PageState::Slab(Slab::Slub {
-                has_next,
-                next_partial,
-                ..
-            }) = &current_page.state
-            {
-                if *has_next { Some(*next_partial) } else { None }
-            } else {
-                panic!("FATAL: Non-Slub page found in partial_slabs_head!");
-            };
-
-            // 命中目标，开始物理摘除
-            if current_ppn == target_ppn {
-                if let Some(prev_ppn) = prev_opt {
-                    // 目标在链表中间或尾部。让上一个节点直接指向下一个节点
-                    let prev_page = get_page_state(prev_ppn);
-                    if let PageState::Slab(Slab::Slub {
-                        has_next,
-                        next_partial,
-                        ..
-                    }) = &mut prev_page.state
-                    {
-                        if let Some(n) = next_opt {
-                            *next_partial = n;
-                            *has_next = true;
-                        } else {
-                            *has_next = false;
-                        }
-                    }
-                } else {
-                    // 目标就是头节点。直接修改 KmemCache 的头指针
-                    self.partial_slabs_head = next_opt;
-                }
-                return;
-            }
-
-            // 没找到，双指针继续向后推进
-            prev_opt = Some(current_ppn);
-            current_opt = next_opt;
+            drop(objects);
+            kassert_eq!(slab_stats()[CACHE_64].nr_inuse, before);
         }
-
-        panic!(
-            "FATAL: Tried to remove PPN {:?} but it was not in the partial list!",
-            target_ppn
-        );
     }
 }
@@ -0,0 +1,29 @@
+// src/syslib/errno.rs
+
+/// 系统调用错误码，数值与 Linux 保持一致，返回给用户时取负数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(isize)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    EBADF = 9,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EFAULT = 14,
+    EINVAL = 22,
+    EMFILE = 24,
+    ENOTTY = 25,
+    EPIPE = 32,
+    ENOSYS = 38,
+    ETIMEDOUT = 110,
+}
+
+impl Errno {
+    /// 转换成写回 a0 的返回值
+    pub fn as_ret(self) -> usize {
+        (-(self as isize)) as usize
+    }
+}
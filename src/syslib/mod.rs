@@ -1 +1,3 @@
+pub mod errno;
 pub mod syscall;
+pub mod time;
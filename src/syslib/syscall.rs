@@ -1,27 +1,113 @@
-use core::{arch::asm, usize};
+use alloc::{string::String, sync::Arc};
+use core::{arch::asm, sync::atomic::Ordering, usize};
 
 use crate::{
     UART,
-    bsp::qemu_virt::RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ,
-    driver::SerialPort,
+    bsp::qemu_virt::{QemuVirt, RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ},
+    console::{
+        console_write_from_task,
+        log::{
+            LOG_BUF_SIZE, Level, SYSLOG_ACTION_CLEAR, SYSLOG_ACTION_CONSOLE_LEVEL,
+            SYSLOG_ACTION_READ_ALL, SYSLOG_ACTION_SIZE_BUFFER, SYSLOG_ACTION_SIZE_UNREAD,
+            clear_log, log_len, read_log, set_console_level,
+        },
+    },
+    driver::{SerialPort, tty::TTY},
+    fs::{
+        buffer_cache::sync_all,
+        file::{FdTable, File},
+        pipe::make_pipe,
+        vfs,
+    },
+    mm::shm,
     polling_println,
-    task::{SCHEDULER, context::TaskContext, scheduler::Scheduler, tcb::TaskStatus},
-    trap::interrupts::{get_time, service::uart_service::UART_SERVICE},
+    syslib::{
+        errno::Errno,
+        time::{
+            CLOCK_MONOTONIC, CLOCK_REALTIME, NSEC_PER_SEC, TimeSpec, monotonic_ns, ns_to_ticks,
+            realtime_ns,
+        },
+    },
+    system::SystemControl,
+    task::{
+        SCHEDULER,
+        context::TaskContext,
+        executor,
+        futex::{self, FUTEX_PRIVATE_FLAG, FUTEX_REQUEUE, FUTEX_WAIT, FUTEX_WAKE},
+        ipc,
+        join::EXIT_RUNNING,
+        scheduler::Scheduler,
+        signal::{
+            SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, SigAction, SigSet, Signal, pop_signal_frame,
+        },
+        tcb::TaskStatus,
+    },
+    trap::interrupts::get_time,
+    userlib::programs::find_program,
+    warn,
 };
 
+/// 由 shell 启动的程序使用的栈大小
+const PROGRAM_STACK_SIZE: usize = 16384;
+
+// getrusage 的 who 参数，与 Linux 一致。没有父子任务关系，不支持 RUSAGE_CHILDREN
+pub const RUSAGE_SELF: usize = 0;
+pub const RUSAGE_THREAD: usize = 1;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeVal {
+    pub sec: usize,
+    pub usec: usize,
+}
+
+impl TimeVal {
+    fn from_ticks(ticks: usize) -> Self {
+        const FREQ: usize = RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ;
+        Self {
+            sec: ticks / FREQ,
+            usec: ticks % FREQ * 1_000_000 / FREQ,
+        }
+    }
+}
+
+/// 与 Linux 的 struct rusage 布局相同，只填写内核统计了的字段，其余为 0
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rusage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+    pub maxrss: usize,
+    pub ixrss: usize,
+    pub idrss: usize,
+    pub isrss: usize,
+    pub minflt: usize,
+    pub majflt: usize,
+    pub nswap: usize,
+    pub inblock: usize,
+    pub oublock: usize,
+    pub msgsnd: usize,
+    pub msgrcv: usize,
+    pub nsignals: usize,
+    pub nvcsw: usize,
+    pub nivcsw: usize,
+}
+
 pub fn schedule(tcb: &mut TaskContext) -> usize {
     tcb.sepc = tcb.sepc + 4;
-    let next_ctx_ptr = Scheduler::schedule_on_interrupt();
+    let next_ctx_ptr = Scheduler::yield_current();
     unsafe {
         asm!("csrw sscratch, {}", in(reg) next_ctx_ptr);
         (*next_ctx_ptr).sepc
     }
 }
 
+/// 睡满返回 0，被信号提前唤醒时返回 EINTR
 pub fn sleep(tcb: &mut TaskContext) -> usize {
     unsafe {
         tcb.sepc = tcb.sepc + 4;
         let sleep_ms = tcb.a0;
+        tcb.a0 = Errno::EINTR.as_ret();
         const ONE_MS_CYCLES: usize = (RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 1000) as usize;
         let current_time = get_time();
         let target_time = current_time + sleep_ms * ONE_MS_CYCLES;
@@ -34,81 +120,606 @@ pub fn sleep(tcb: &mut TaskContext) -> usize {
 
 pub fn uart_read(ctx: &mut TaskContext) -> usize {
     let timeout_ms = ctx.a0; // 约定：-1 (usize::MAX) 代表无限阻塞
+    // 系统调用在关中断的 trap 上下文中执行，检查和登记之间不会有新字符到达。
+    // 被唤醒后重新执行本调用；数据被别的读者抢走时超时从头计算
+    const ONE_MS_CYCLES: usize = RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 1000;
+    let mut tty = TTY.lock();
+    let timed_out = tty.finish_read_wait();
+    let result = match tty.pop_byte() {
+        Some(c) => Ok(c as usize),
+        // 非阻塞模式或超时：没数据返回 -1
+        None if timeout_ms == 0 || timed_out => Ok(usize::MAX),
+        None if timeout_ms == usize::MAX => Err(tty.wait_readable(None)),
+        None => Err(tty.wait_readable(Some(get_time() + timeout_ms * ONE_MS_CYCLES))),
+    };
+    drop(tty);
+    finish_file_op(ctx, result)
+}
+
+pub fn uart_write_byte(ctx: &mut TaskContext) -> usize {
+    let byte = (ctx.a0 & 0xff) as u8;
+    // 发送缓冲区满时阻塞，唤醒后重新执行这条 ecall
+    let result = console_write_from_task(&[byte]).map(|_| 0);
+    finish_file_op(ctx, result)
+}
+
+pub fn sync(ctx: &mut TaskContext) -> usize {
     ctx.sepc += 4;
-    let mut receive_buffer = UART_SERVICE.receive_buffer.lock();
-    if let Some(c) = receive_buffer.pop() {
-        if c >= 0x80 {
-            ctx.a0 = usize::MAX;
-            return ctx.sepc;
+    ctx.a0 = match sync_all() {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    };
+    ctx.sepc
+}
+
+/// idle 任务专用。还有其他任务就绪时让出 CPU，否则按最早的唤醒时间设置定时器后执行 wfi
+pub fn idle_wait(ctx: &mut TaskContext) -> usize {
+    let idle = {
+        let scheduler = SCHEDULER.lock();
+        if scheduler.get_current_task_id() != 0 {
+            drop(scheduler);
+            return finish_file_op(ctx, Err(Errno::EPERM));
         }
-        ctx.a0 = c as usize;
-        return ctx.sepc;
+        let idle = scheduler.is_idle();
+        if idle {
+            scheduler.program_timer();
+        }
+        idle
+    };
+    if !idle {
+        return schedule(ctx);
+    }
+    // trap 中 sstatus.SIE 为 0，中断不会在这里被响应，但只要 sie 中使能的中断挂起 wfi 就会返回。
+    // 中断在 sret 之后才进入 trap_handler，所以检查就绪队列和 wfi 之间不会丢失唤醒
+    unsafe { asm!("wfi") };
+    finish_file_op(ctx, Ok(0))
+}
+
+/// 阻塞当前任务但不推进 sepc，任务被唤醒后会重新执行同一条 ecall
+fn block_and_restart() -> usize {
+    let next_ctx = SCHEDULER.lock().block_current_task();
+    unsafe {
+        asm!("csrw sscratch, {}", in(reg) next_ctx);
+        (*next_ctx).sepc
+    }
+}
+
+/// 把文件操作的结果写回上下文，EAGAIN 表示已经登记到等待队列，需要阻塞后重试
+fn finish_file_op(ctx: &mut TaskContext, result: Result<usize, Errno>) -> usize {
+    match result {
+        Err(Errno::EAGAIN) => block_and_restart(),
+        Ok(count) => {
+            ctx.sepc += 4;
+            ctx.a0 = count;
+            ctx.sepc
+        }
+        Err(errno) => {
+            ctx.sepc += 4;
+            ctx.a0 = errno.as_ret();
+            ctx.sepc
+        }
+    }
+}
+
+fn current_file(fd: usize) -> Result<Arc<dyn File>, Errno> {
+    SCHEDULER
+        .lock()
+        .current_tcb()
+        .and_then(|tcb| tcb.fd_table.get(fd))
+        .ok_or(Errno::EBADF)
+}
+
+fn install_file(file: Arc<dyn File>) -> Result<usize, Errno> {
+    let mut scheduler = SCHEDULER.lock();
+    let tcb = scheduler.current_tcb().ok_or(Errno::ESRCH)?;
+    tcb.fd_table.alloc(file)
+}
+
+pub fn file_read(ctx: &mut TaskContext) -> usize {
+    let (fd, buf_ptr, len) = (ctx.a0, ctx.a1, ctx.a2);
+    let result = current_file(fd).and_then(|file| {
+        if !file.readable() {
+            return Err(Errno::EBADF);
+        }
+        if buf_ptr == 0 && len > 0 {
+            return Err(Errno::EFAULT);
+        }
+        let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr as *mut u8, len) };
+        file.read(buf)
+    });
+    finish_file_op(ctx, result)
+}
+
+pub fn file_write(ctx: &mut TaskContext) -> usize {
+    let (fd, buf_ptr, len) = (ctx.a0, ctx.a1, ctx.a2);
+    let result = current_file(fd).and_then(|file| {
+        if !file.writable() {
+            return Err(Errno::EBADF);
+        }
+        if buf_ptr == 0 && len > 0 {
+            return Err(Errno::EFAULT);
+        }
+        let buf = unsafe { core::slice::from_raw_parts(buf_ptr as *const u8, len) };
+        file.write(buf)
+    });
+    finish_file_op(ctx, result)
+}
+
+/// a0 为路径字符串指针，a1 为路径长度
+pub fn open(ctx: &mut TaskContext) -> usize {
+    let (path_ptr, path_len) = (ctx.a0, ctx.a1);
+    let result = if path_ptr == 0 {
+        Err(Errno::EFAULT)
+    } else {
+        let bytes = unsafe { core::slice::from_raw_parts(path_ptr as *const u8, path_len) };
+        core::str::from_utf8(bytes)
+            .map_err(|_| Errno::EINVAL)
+            .and_then(vfs::open)
+            .and_then(install_file)
+    };
+    finish_file_op(ctx, result)
+}
+
+/// a0 为文件描述符，a1 为命令号，a2 为参数
+pub fn ioctl(ctx: &mut TaskContext) -> usize {
+    let (fd, cmd, arg) = (ctx.a0, ctx.a1, ctx.a2);
+    let result = current_file(fd).and_then(|file| file.ioctl(cmd, arg));
+    finish_file_op(ctx, result)
+}
+
+pub fn close(ctx: &mut TaskContext) -> usize {
+    let fd = ctx.a0;
+    let file = SCHEDULER
+        .lock()
+        .current_tcb()
+        .and_then(|tcb| tcb.fd_table.take(fd));
+    // 文件在调度器锁释放后才 drop，管道端关闭时需要唤醒等待者
+    let result = file.map(|_| 0).ok_or(Errno::EBADF);
+    finish_file_op(ctx, result)
+}
+
+pub fn pipe(ctx: &mut TaskContext) -> usize {
+    let fds_ptr = ctx.a0 as *mut [usize; 2];
+    let result = if fds_ptr.is_null() {
+        Err(Errno::EFAULT)
+    } else {
+        let (read_end, write_end) = make_pipe();
+        install_file(read_end).and_then(|read_fd| match install_file(write_end) {
+            Ok(write_fd) => {
+                unsafe { fds_ptr.write([read_fd, write_fd]) };
+                Ok(0)
+            }
+            Err(errno) => {
+                let read_end = SCHEDULER
+                    .lock()
+                    .current_tcb()
+                    .and_then(|tcb| tcb.fd_table.take(read_fd));
+                drop(read_end);
+                Err(errno)
+            }
+        })
+    };
+    finish_file_op(ctx, result)
+}
+
+/// 从用户传入的指针和长度构造字符串
+fn user_str<'a>(ptr: usize, len: usize) -> Result<&'a str, Errno> {
+    if ptr == 0 {
+        return Err(Errno::EFAULT);
     }
-    drop(receive_buffer);
+    let bytes = unsafe { core::slice::from_raw_parts(ptr as *const u8, len) };
+    core::str::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
 
-    if timeout_ms == 0 {
-        // 非阻塞模式：没数据直接返回失败
-        ctx.a0 = usize::MAX;
+/// a0 为任务 id，a1 为信号编号
+pub fn kill(ctx: &mut TaskContext) -> usize {
+    let (task_id, signum) = (ctx.a0, ctx.a1);
+    let sepc = ctx.sepc;
+    let result = match Signal::from_num(signum) {
+        Some(signal) if SCHEDULER.lock().send_signal(task_id, signal) => Ok(0),
+        Some(_) => Err(Errno::ESRCH),
+        None => Err(Errno::EINVAL),
+    };
+    // 给自己发 SIGKILL 时上下文已经被改写到退出入口，直接从那里继续
+    if ctx.sepc != sepc {
         return ctx.sepc;
     }
+    finish_file_op(ctx, result)
+}
 
-    // 阻塞或超时模式
-    let task_id = SCHEDULER.lock().get_current_task_id();
-    UART_SERVICE.wait_queue.lock().push_back(task_id);
-    // 如果入队前发生了中断，此时wait队列没有该任务，无法改成ready,必须在入队后再次检查是否有数据
-    let mut receive_buffer = UART_SERVICE.receive_buffer.lock();
-    if !receive_buffer.is_empty() {
-        UART_SERVICE.wait_queue.lock().pop_back(); // 撤销入队
-        let c = receive_buffer.pop().unwrap();
-        if c >= 0x80 {
-            ctx.a0 = usize::MAX;
-            return ctx.sepc;
-        }
-        ctx.a0 = c as usize;
+/// a0 为信号编号，a1 为新的 struct sigaction 指针，a2 为写回原处理方式的指针，都可以为空。
+/// SIGKILL 和 SIGSTOP 的处理方式不能修改
+pub fn rt_sigaction(ctx: &mut TaskContext) -> usize {
+    let (signum, act_ptr, oldact_ptr) = (ctx.a0, ctx.a1, ctx.a2);
+    let result = match Signal::from_num(signum) {
+        Some(signal) if act_ptr == 0 || !signal.is_unblockable() => {
+            if let Some(tcb) = SCHEDULER.lock().current_tcb() {
+                if oldact_ptr != 0 {
+                    unsafe { (oldact_ptr as *mut SigAction).write(tcb.signals.action(signal)) };
+                }
+                if act_ptr != 0 {
+                    let action = unsafe { (act_ptr as *const SigAction).read() };
+                    tcb.signals.set_action(signal, action);
+                }
+            }
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    };
+    finish_file_op(ctx, result)
+}
+
+/// a0 为 SIG_BLOCK/SIG_UNBLOCK/SIG_SETMASK，a1 为新的信号集指针，a2 为写回原屏蔽字的指针，都可以为空。
+/// SIGKILL 和 SIGSTOP 始终不会被屏蔽
+pub fn rt_sigprocmask(ctx: &mut TaskContext) -> usize {
+    let (how, set_ptr, oldset_ptr) = (ctx.a0, ctx.a1, ctx.a2);
+    let result = if set_ptr != 0 && !matches!(how, SIG_BLOCK | SIG_UNBLOCK | SIG_SETMASK) {
+        Err(Errno::EINVAL)
+    } else {
+        if let Some(tcb) = SCHEDULER.lock().current_tcb() {
+            let mask = tcb.signals.mask;
+            if oldset_ptr != 0 {
+                unsafe { (oldset_ptr as *mut SigSet).write(mask) };
+            }
+            if set_ptr != 0 {
+                let set = unsafe { (set_ptr as *const SigSet).read() };
+                tcb.signals.set_mask(match how {
+                    SIG_BLOCK => mask | set,
+                    SIG_UNBLOCK => mask & !set,
+                    _ => set,
+                });
+            }
+        }
+        Ok(0)
+    };
+    // 解除屏蔽后已经未决的信号在返回 U 态前处理
+    finish_file_op(ctx, result)
+}
+
+/// 信号处理函数返回到跳板后发起，sp 指向信号帧。恢复被打断时的上下文和屏蔽字，
+/// a0 等寄存器都取自信号帧，不写返回值。信号帧无效时按 SIGSEGV 的默认动作结束任务
+pub fn rt_sigreturn(ctx: &mut TaskContext) -> usize {
+    let mut scheduler = SCHEDULER.lock();
+    let task_id = scheduler.get_current_task_id();
+    let Some(tcb) = scheduler.current_tcb() else {
         return ctx.sepc;
+    };
+    if !pop_signal_frame(tcb) {
+        warn!(
+            "task {}: bad signal frame at {:#x}",
+            task_id, tcb.context.sp
+        );
+        scheduler.send_signal(task_id, Signal::SIGKILL);
+    }
+    scheduler
+        .current_tcb()
+        .map_or(ctx.sepc, |tcb| tcb.context.sepc)
+}
+
+/// 按名字启动内置程序。a0/a1 为程序名，a2/a3 为参数字符串，
+/// a4/a5 为交给新任务作为标准输入和标准输出的文件描述符，成功时返回新任务 id
+pub fn spawn(ctx: &mut TaskContext) -> usize {
+    let result = user_str(ctx.a0, ctx.a1).and_then(|name| {
+        let program = find_program(name).ok_or(Errno::ENOENT)?;
+        let args = String::from(if ctx.a3 == 0 {
+            ""
+        } else {
+            user_str(ctx.a2, ctx.a3)?
+        });
+        let stdin = current_file(ctx.a4)?;
+        let stdout = current_file(ctx.a5)?;
+        let mut scheduler = SCHEDULER.lock();
+        let task_id = scheduler
+            .spawn(name, move || program(&args), PROGRAM_STACK_SIZE, 1)
+            .map_err(|_| Errno::ENOMEM)?
+            .id();
+        // 新任务在本次系统调用返回之前不会被调度，这里填好它的 0/1/2
+        let fd_table = &mut scheduler.get_task_mut(task_id).unwrap().fd_table;
+        fd_table.alloc(stdin)?;
+        fd_table.alloc(stdout.clone())?;
+        fd_table.alloc(stdout)?;
+        Ok(task_id)
+    });
+    finish_file_op(ctx, result)
+}
+
+/// 等待任务结束或停止，结束返回 0，停止返回 1
+pub fn wait_task(ctx: &mut TaskContext) -> usize {
+    let task_id = ctx.a0;
+    let mut scheduler = SCHEDULER.lock();
+    let current_id = scheduler.get_current_task_id();
+    let result = match scheduler.get_task_mut(task_id) {
+        _ if task_id == current_id => Err(Errno::EINVAL),
+        None => Ok(0),
+        Some(tcb) => match tcb.visible_status() {
+            TaskStatus::Terminated => Ok(0),
+            TaskStatus::Stopped => Ok(1),
+            _ => {
+                tcb.waiters.push(current_id);
+                Err(Errno::EAGAIN)
+            }
+        },
+    };
+    drop(scheduler);
+    finish_file_op(ctx, result)
+}
+
+/// a0 为 who，a1 为用户的 struct rusage 指针
+pub fn getrusage(ctx: &mut TaskContext) -> usize {
+    let (who, usage_ptr) = (ctx.a0, ctx.a1);
+    let result = match who {
+        _ if usage_ptr == 0 => Err(Errno::EFAULT),
+        RUSAGE_SELF | RUSAGE_THREAD => {
+            // 当前任务的用户态时间在进入本次 trap 时已经记过账
+            let stats = SCHEDULER.lock().current_tcb().map(|tcb| tcb.stats);
+            let stats = stats.unwrap_or_default();
+            let usage = Rusage {
+                utime: TimeVal::from_ticks(stats.user_time),
+                stime: TimeVal::from_ticks(stats.system_time),
+                nvcsw: stats.voluntary_switches,
+                nivcsw: stats.involuntary_switches,
+                ..Rusage::default()
+            };
+            unsafe { (usage_ptr as *mut Rusage).write(usage) };
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    };
+    finish_file_op(ctx, result)
+}
+
+pub fn clock_gettime(ctx: &mut TaskContext) -> usize {
+    let (clock_id, ts_ptr) = (ctx.a0, ctx.a1);
+    let ns = match clock_id {
+        CLOCK_REALTIME => Ok(realtime_ns()),
+        CLOCK_MONOTONIC => Ok(monotonic_ns()),
+        _ => Err(Errno::EINVAL),
+    };
+    let result = ns.and_then(|ns| {
+        if ts_ptr == 0 {
+            return Err(Errno::EFAULT);
+        }
+        unsafe { (ts_ptr as *mut TimeSpec).write(TimeSpec::from_ns(ns)) };
+        Ok(0)
+    });
+    finish_file_op(ctx, result)
+}
+
+/// 不支持时区，第二个参数被忽略
+pub fn gettimeofday(ctx: &mut TaskContext) -> usize {
+    let tv_ptr = ctx.a0;
+    if tv_ptr != 0 {
+        let ns = realtime_ns();
+        let tv = TimeVal {
+            sec: (ns / NSEC_PER_SEC) as usize,
+            usec: (ns % NSEC_PER_SEC / 1000) as usize,
+        };
+        unsafe { (tv_ptr as *mut TimeVal).write(tv) };
     }
-    drop(receive_buffer);
+    finish_file_op(ctx, Ok(0))
+}
+
+/// a0 为请求的睡眠时间，a1 可以为空。被信号提前唤醒时返回 EINTR，a1 不为空时写回剩余时间
+pub fn nanosleep(ctx: &mut TaskContext) -> usize {
+    let (req_ptr, rem_ptr) = (ctx.a0, ctx.a1);
+    if req_ptr == 0 {
+        return finish_file_op(ctx, Err(Errno::EFAULT));
+    }
+    let req = unsafe { (req_ptr as *const TimeSpec).read() };
+    let Some(ns) = req.to_ns() else {
+        return finish_file_op(ctx, Err(Errno::EINVAL));
+    };
+    if ns == 0 {
+        return finish_file_op(ctx, Ok(0));
+    }
+    ctx.sepc += 4;
+    ctx.a0 = Errno::EINTR.as_ret();
+    let wake_time = get_time().saturating_add(ns_to_ticks(ns));
     let mut scheduler = SCHEDULER.lock();
-    // 设置为-1代表进入阻塞状态
-    ctx.a0 = usize::MAX;
-    // 此时timeout_ms > 0
-    if timeout_ms < usize::MAX {
-        // 带超时：计算唤醒时间并加入定时器堆
-        const ONE_MS_CYCLES: usize = RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 1000;
-        let current_time = get_time();
-        let wake_time = current_time + timeout_ms * ONE_MS_CYCLES;
+    if let Some(tcb) = scheduler.current_tcb() {
+        tcb.sleep_rem = (rem_ptr != 0).then_some((wake_time, rem_ptr));
+    }
+    let next_ctx = scheduler.set_current_task_sleep(wake_time);
+    unsafe {
+        asm!("csrw sscratch, {}", in(reg) next_ctx);
+        (*next_ctx).sepc
+    }
+}
+
+/// 内核 worker 任务调用：轮询一批就绪的 future，返回轮询的个数；没有就绪的 future 时睡眠，被唤醒后重新执行
+pub fn run_executor(ctx: &mut TaskContext) -> usize {
+    finish_file_op(ctx, executor::run_once())
+}
 
-        let next_ctx = scheduler.set_current_task_sleep(wake_time);
-        unsafe {
+/// 创建 IPC 端点，返回句柄
+pub fn ipc_create(ctx: &mut TaskContext) -> usize {
+    finish_file_op(ctx, Ok(ipc::create()))
+}
+
+/// a0 为端点句柄
+pub fn ipc_close(ctx: &mut TaskContext) -> usize {
+    finish_file_op(ctx, ipc::close(ctx.a0).map(|()| 0))
+}
+
+/// IPC 的收发按 task::ipc 的寄存器约定：a0–a5 为消息，a6 传入端点句柄和 IPC_GRANT，
+/// 返回 0、GRANTED 或负的错误码
+fn finish_ipc(ctx: &mut TaskContext, result: Result<Option<*mut TaskContext>, Errno>) -> usize {
+    match result {
+        // 当前任务已经阻塞，返回值由对方或打断它的信号填写
+        Ok(Some(next_ctx)) => unsafe {
             asm!("csrw sscratch, {}", in(reg) next_ctx);
-            return (*next_ctx).sepc;
+            (*next_ctx).sepc
+        },
+        Ok(None) => ctx.sepc,
+        Err(errno) => {
+            ctx.a6 = errno.as_ret();
+            ctx.sepc
         }
+    }
+}
+
+/// 醒来后不重新执行：取出 a6 中的句柄，再把返回位置和成功的返回值设好
+fn start_ipc(ctx: &mut TaskContext) -> usize {
+    let handle = ctx.a6;
+    ctx.sepc += 4;
+    ctx.a6 = 0;
+    handle
+}
+
+pub fn ipc_send(ctx: &mut TaskContext) -> usize {
+    let handle = start_ipc(ctx);
+    finish_ipc(ctx, ipc::send(handle, false))
+}
+
+pub fn ipc_call(ctx: &mut TaskContext) -> usize {
+    let handle = start_ipc(ctx);
+    finish_ipc(ctx, ipc::send(handle, true))
+}
+
+pub fn ipc_recv(ctx: &mut TaskContext) -> usize {
+    let handle = start_ipc(ctx);
+    finish_ipc(ctx, ipc::recv(handle))
+}
+
+pub fn ipc_reply(ctx: &mut TaskContext) -> usize {
+    let flags = start_ipc(ctx);
+    finish_ipc(ctx, ipc::reply(flags).map(|()| None))
+}
+
+/// a0 为区域大小（字节），返回共享内存区域的句柄
+pub fn shm_create(ctx: &mut TaskContext) -> usize {
+    let task_id = SCHEDULER.lock().get_current_task_id();
+    finish_file_op(ctx, shm::create(ctx.a0, task_id))
+}
+
+/// a0 为区域句柄，a1 为 PROT_READ 或 PROT_READ | PROT_WRITE，返回映射的起始地址
+pub fn shm_map(ctx: &mut TaskContext) -> usize {
+    let task_id = SCHEDULER.lock().get_current_task_id();
+    finish_file_op(ctx, shm::map(ctx.a0, ctx.a1, task_id))
+}
+
+/// a0 为当前任务 shm_map 返回的地址
+pub fn shm_unmap(ctx: &mut TaskContext) -> usize {
+    let task_id = SCHEDULER.lock().get_current_task_id();
+    finish_file_op(ctx, shm::unmap(ctx.a0, task_id).map(|()| 0))
+}
+
+/// a0 为区域句柄
+pub fn shm_destroy(ctx: &mut TaskContext) -> usize {
+    finish_file_op(ctx, shm::destroy(ctx.a0).map(|()| 0))
+}
+
+/// futex(uaddr, op, val, timeout, uaddr2)，参数与 Linux 相同：WAIT 的 a3 为相对超时的 timespec，
+/// 可以为空；REQUEUE 的 a3 为最多移动的等待者个数
+pub fn futex(ctx: &mut TaskContext) -> usize {
+    let uaddr = ctx.a0;
+    let count = ctx.a2;
+    let result = match ctx.a1 & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => return futex_wait(ctx),
+        FUTEX_WAKE => futex::key_of(uaddr).map(|key| futex::wake(key, count)),
+        FUTEX_REQUEUE => futex::key_of(uaddr).and_then(|key| {
+            let new_key = futex::key_of(ctx.a4)?;
+            Ok(futex::requeue(key, count, new_key, ctx.a3))
+        }),
+        _ => Err(Errno::ENOSYS),
+    };
+    finish_file_op(ctx, result)
+}
+
+fn futex_wait(ctx: &mut TaskContext) -> usize {
+    let (uaddr, expected, timeout_ptr) = (ctx.a0, ctx.a2 as u32, ctx.a3);
+    let deadline = if timeout_ptr == 0 {
+        None
     } else {
-        // 无限阻塞
-        unsafe {
-            let next_ctx = scheduler.block_current_task();
+        let timeout = unsafe { (timeout_ptr as *const TimeSpec).read() };
+        let Some(ns) = timeout.to_ns() else {
+            return finish_file_op(ctx, Err(Errno::EINVAL));
+        };
+        Some(get_time().saturating_add(ns_to_ticks(ns)))
+    };
+    let key = match futex::key_of(uaddr) {
+        Ok(key) => key,
+        Err(errno) => return finish_file_op(ctx, Err(errno)),
+    };
+    // 醒来后不重新执行：返回值先设为 EINTR，被唤醒时由 wake 改成 0，超时由定时器改成 ETIMEDOUT
+    ctx.sepc += 4;
+    ctx.a0 = Errno::EINTR.as_ret();
+    match futex::wait(uaddr, key, expected, deadline) {
+        Ok(next_ctx) => unsafe {
             asm!("csrw sscratch, {}", in(reg) next_ctx);
-            return (*next_ctx).sepc;
+            (*next_ctx).sepc
+        },
+        // 值已经改变，不能走 finish_file_op，那里的 EAGAIN 表示阻塞重试
+        Err(errno) => {
+            ctx.a0 = errno.as_ret();
+            ctx.sepc
         }
     }
 }
 
-pub fn uart_write_byte(ctx: &mut TaskContext) -> usize {
-    ctx.sepc += 4;
-    let byte = (ctx.a0 & 0xff) as u8;
-    #[allow(deprecated)]
-    sbi_rt::legacy::console_putchar(byte as usize);
-    // let _ = UART.lock().putchar(byte);
-    ctx.a0 = 0;
-    ctx.sepc
+/// a0 为操作码，a1/a2 为用户缓冲区，设置控制台级别时 a2 为级别
+pub fn syslog(ctx: &mut TaskContext) -> usize {
+    let (action, buf_ptr, len) = (ctx.a0, ctx.a1, ctx.a2);
+    let result = match action {
+        SYSLOG_ACTION_READ_ALL => {
+            if buf_ptr == 0 && len > 0 {
+                Err(Errno::EFAULT)
+            } else {
+                let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr as *mut u8, len) };
+                Ok(read_log(buf))
+            }
+        }
+        SYSLOG_ACTION_CLEAR => {
+            clear_log();
+            Ok(0)
+        }
+        SYSLOG_ACTION_CONSOLE_LEVEL => Level::from_num(len)
+            .map(|level| {
+                set_console_level(level);
+                0
+            })
+            .ok_or(Errno::EINVAL),
+        SYSLOG_ACTION_SIZE_UNREAD => Ok(log_len()),
+        SYSLOG_ACTION_SIZE_BUFFER => Ok(LOG_BUF_SIZE),
+        _ => Err(Errno::EINVAL),
+    };
+    finish_file_op(ctx, result)
 }
 
-pub fn exit_current_task() {
-    let mut scheduler = SCHEDULER.lock();
-    let id = scheduler.get_current_task_id();
-    if let Some(tcb) = scheduler.get_task_list()[id].as_mut() {
-        tcb.status = TaskStatus::Terminated;
-        scheduler.get_zombie_queue().push(id);
+pub fn reboot() -> ! {
+    QemuVirt.reboot()
+}
+
+/// code 为退出码，写入与 JoinHandle 共享的退出字
+pub fn exit_current_task(code: usize) {
+    let exited = {
+        let mut scheduler = SCHEDULER.lock();
+        let id = scheduler.get_current_task_id();
+        match scheduler.get_task_list()[id].as_mut() {
+            Some(tcb) => {
+                tcb.status = TaskStatus::Terminated;
+                // 任务 id 会被重用，还没到期的超时回调不能落到新任务身上
+                if let Some(timeout) = tcb.timeout.take() {
+                    timeout.cancel();
+                }
+                let fd_table = core::mem::replace(&mut tcb.fd_table, FdTable::new());
+                let exit_code = tcb.exit_code.clone();
+                scheduler.get_zombie_queue().push(id);
+                scheduler.wake_waiters(id);
+                Some((id, fd_table, exit_code))
+            }
+            None => None,
+        }
+    };
+    // 关闭文件、释放共享内存和唤醒 join 的任务都要拿其他锁，必须在调度器锁之外进行
+    if let Some((id, fd_table, exit_code)) = exited {
+        drop(fd_table);
+        shm::release_task(id);
+        // EXIT_RUNNING 表示还在运行，不能用作退出码
+        exit_code.store((code as u32).min(EXIT_RUNNING - 1), Ordering::Release);
+        if let Ok(key) = futex::key_of(exit_code.as_ptr() as usize) {
+            futex::wake(key, usize::MAX);
+        }
     }
 }
 
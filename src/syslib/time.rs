@@ -0,0 +1,155 @@
+// src/syslib/time.rs
+//! 计时。启动时读一次 RTC，记下墙上时间与单调时间的差，之后的墙上时间都由 rdtime 推算，
+//! 不再访问 RTC，也不会因为 RTC 的精度而倒退。
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    bsp::qemu_virt::RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ, driver::rtc::RTC, info,
+    trap::interrupts::get_time,
+};
+
+// clock_gettime 的时钟编号，与 Linux 一致
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// 与 Linux 的 struct timespec 布局相同
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn from_ns(ns: u64) -> Self {
+        Self {
+            sec: (ns / NSEC_PER_SEC) as usize,
+            nsec: (ns % NSEC_PER_SEC) as usize,
+        }
+    }
+
+    /// nsec 超出范围时返回 None
+    pub fn to_ns(self) -> Option<u64> {
+        if self.nsec as u64 >= NSEC_PER_SEC {
+            return None;
+        }
+        (self.sec as u64)
+            .checked_mul(NSEC_PER_SEC)?
+            .checked_add(self.nsec as u64)
+    }
+}
+
+/// 墙上时间减去单调时间，单位纳秒
+static REALTIME_OFFSET_NS: AtomicU64 = AtomicU64::new(0);
+
+pub fn ticks_to_ns(ticks: usize) -> u64 {
+    (ticks as u128 * NSEC_PER_SEC as u128 / RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ as u128) as u64
+}
+
+/// 向上取整，保证睡眠不会比要求的短
+pub fn ns_to_ticks(ns: u64) -> usize {
+    (ns as u128 * RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ as u128).div_ceil(NSEC_PER_SEC as u128)
+        as usize
+}
+
+/// 启动以来的纳秒数
+pub fn monotonic_ns() -> u64 {
+    ticks_to_ns(get_time())
+}
+
+/// 自 Unix 纪元以来的纳秒数
+pub fn realtime_ns() -> u64 {
+    monotonic_ns() + REALTIME_OFFSET_NS.load(Ordering::Relaxed)
+}
+
+/// 从 RTC 读出墙上时间作为计时基准，必须在最终页表生效后调用
+pub fn init() {
+    let rtc_ns = RTC.read_ns();
+    REALTIME_OFFSET_NS.store(rtc_ns.saturating_sub(monotonic_ns()), Ordering::Relaxed);
+    info!(
+        "Wall clock: {}",
+        DateTime::from_unix_secs(rtc_ns / NSEC_PER_SEC)
+    );
+}
+
+/// UTC 日历时间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn from_unix_secs(secs: u64) -> Self {
+        let (days, rem) = (secs / 86400, secs % 86400);
+        // 按 Howard Hinnant 的 civil_from_days，把 3 月 1 日作为一年的开始，闰日落在年末
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z % 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = yoe + era * 400 + (month <= 2) as u64;
+        Self {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem % 3600 / 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DateTime, TimeSpec, ns_to_ticks, ticks_to_ns};
+    use crate::{kassert_eq, kernel_test};
+    use alloc::format;
+
+    kernel_test! {
+        fn converts_unix_time_to_utc() {
+            kassert_eq!(format!("{}", DateTime::from_unix_secs(0)), "1970-01-01 00:00:00 UTC");
+            // 闰年的 2 月 29 日和年末
+            kassert_eq!(
+                format!("{}", DateTime::from_unix_secs(951_782_400)),
+                "2000-02-29 00:00:00 UTC"
+            );
+            kassert_eq!(
+                format!("{}", DateTime::from_unix_secs(1_735_689_599)),
+                "2024-12-31 23:59:59 UTC"
+            );
+        }
+    }
+
+    kernel_test! {
+        fn converts_between_ns_and_ticks() {
+            kassert_eq!(ticks_to_ns(10_000_000), 1_000_000_000);
+            // 不足一个 tick 的部分向上取整
+            kassert_eq!(ns_to_ticks(1), 1);
+            kassert_eq!(ns_to_ticks(1_000_000_000), 10_000_000);
+            kassert_eq!(TimeSpec::from_ns(1_500_000_000), TimeSpec { sec: 1, nsec: 500_000_000 });
+            kassert_eq!(TimeSpec { sec: 0, nsec: 1_000_000_000 }.to_ns(), None);
+        }
+    }
+}
@@ -5,4 +5,6 @@ pub trait SystemControl {
     /// 关闭系统
     fn shutdown(&self) -> !;
     fn reboot(&self) -> !;
+    /// 以失败状态退出，code 作为退出码交给宿主（QEMU 的进程退出码）
+    fn fail(&self, code: u16) -> !;
 }
\ No newline at end of file
@@ -0,0 +1,256 @@
+// src/task/executor.rs
+//! 内核异步执行器。驱动和服务写成 async fn，作为 future 交给执行器，
+//! 不必为每个正在进行的操作占用一个任务和一个栈。
+//!
+//! future 由几个内核 worker 任务轮询：worker 通过 run_executor 系统调用进入内核，
+//! 在 trap 上下文中（关中断）轮询就绪的 future，所以 future 可以直接使用 IrqLock 保护的内核数据，
+//! 但每次轮询都必须很快返回。没有就绪的 future 时 worker 睡在等待队列上。
+//!
+//! Waker 可以在中断处理、定时器回调等任意内核上下文中调用，只做入队和唤醒 worker。
+
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc, task::Wake};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    bsp::qemu_virt::RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ,
+    data_struct::lock::IrqLock,
+    syslib::errno::Errno,
+    task::{
+        SCHEDULER,
+        scheduler::SchedulerError,
+        timer::{self, TimerHandle},
+        wait_queue::WaitQueue,
+    },
+    trap::interrupts::get_time,
+    userlib::syscall::sys_run_executor,
+};
+
+/// worker 每次进入内核最多轮询的次数，之后回到 U 态，给时钟中断和调度留出机会
+pub const POLL_BUDGET: usize = 16;
+
+const WORKER_STACK_SIZE: usize = 4096;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Task {
+    /// 完成后为 None
+    future: IrqLock<Option<BoxFuture>>,
+    /// 已经在运行队列中，重复唤醒不会重复入队
+    queued: AtomicBool,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            EXECUTOR.run_queue.lock().push_back(self.clone());
+            EXECUTOR.workers.wake(1);
+        }
+    }
+}
+
+struct Executor {
+    run_queue: IrqLock<VecDeque<Arc<Task>>>,
+    /// 没有事可做的 worker
+    workers: WaitQueue,
+}
+
+static EXECUTOR: Executor = Executor {
+    run_queue: IrqLock::new(VecDeque::new()),
+    workers: WaitQueue::new(),
+};
+
+/// 把 future 交给执行器运行。只能在内核上下文（初始化代码、trap 处理、其他 future）中调用
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    let task = Arc::new(Task {
+        future: IrqLock::new(Some(Box::pin(future))),
+        queued: AtomicBool::new(false),
+    });
+    task.wake_by_ref();
+}
+
+/// 最多轮询 budget 个就绪的 future，返回实际轮询的个数
+pub fn poll_ready(budget: usize) -> usize {
+    let mut polled = 0;
+    while polled < budget {
+        // 取出任务后立刻放开队列锁，future 在轮询中唤醒别的任务或自己时要入队
+        let Some(task) = EXECUTOR.run_queue.lock().pop_front() else {
+            break;
+        };
+        // 先清除入队标记，轮询期间的唤醒会让任务重新入队
+        task.queued.store(false, Ordering::Release);
+        let waker = Waker::from(task.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut slot = task.future.lock();
+        if slot
+            .as_mut()
+            .is_some_and(|future| future.as_mut().poll(&mut cx).is_ready())
+        {
+            *slot = None;
+        }
+        polled += 1;
+    }
+    polled
+}
+
+/// run_executor 系统调用：轮询一批就绪的 future；没有就绪的 future 时返回 EAGAIN，
+/// 让 worker 睡在等待队列上，有 future 被唤醒时重新执行
+pub fn run_once() -> Result<usize, Errno> {
+    match poll_ready(POLL_BUDGET) {
+        0 => {
+            // trap 上下文关中断，检查队列和入队之间不会有唤醒插进来
+            if EXECUTOR.run_queue.lock().is_empty() {
+                Err(EXECUTOR.workers.wait())
+            } else {
+                Ok(0)
+            }
+        }
+        polled => Ok(polled),
+    }
+}
+
+fn worker_main() {
+    loop {
+        sys_run_executor();
+    }
+}
+
+/// 创建 count 个 worker 任务
+pub fn start(count: usize) -> Result<(), SchedulerError> {
+    let mut scheduler = SCHEDULER.lock();
+    for _ in 0..count {
+        scheduler.spawn("kworker", worker_main, WORKER_STACK_SIZE, 1)?;
+    }
+    Ok(())
+}
+
+/// 到达截止时间（rdtime 的 tick）后完成的 future，由内核定时器唤醒
+pub struct Sleep {
+    deadline: usize,
+    timer: Option<TimerHandle>,
+}
+
+/// 睡眠 ms 毫秒
+pub fn sleep(ms: usize) -> Sleep {
+    const ONE_MS_CYCLES: usize = RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 1000;
+    Sleep {
+        deadline: get_time().saturating_add(ms.saturating_mul(ONE_MS_CYCLES)),
+        timer: None,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if get_time() >= self.deadline {
+            return Poll::Ready(());
+        }
+        // 每次轮询都按最新的 waker 重新设置定时器
+        if let Some(old) = self.timer.take() {
+            old.cancel();
+        }
+        let waker = cx.waker().clone();
+        self.timer = Some(timer::add_oneshot(self.deadline, move || {
+            waker.wake_by_ref()
+        }));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.cancel();
+        }
+    }
+}
+
+/// 中断处理函数和 future 之间的单次通知：signal 可以在中断上下文中调用，
+/// 唤醒在 wait 上等待的 future；没有人等待时通知保留到下一次 wait
+pub struct IrqEvent {
+    pending: AtomicBool,
+    waker: IrqLock<Option<Waker>>,
+}
+
+impl IrqEvent {
+    pub const fn new() -> Self {
+        Self {
+            pending: AtomicBool::new(false),
+            waker: IrqLock::new(None),
+        }
+    }
+
+    pub fn signal(&self) {
+        self.pending.store(true, Ordering::Release);
+        if let Some(waker) = self.waker.lock().take() {
+            waker.wake();
+        }
+    }
+
+    /// 等到下一次 signal，之前已经到达的通知会让它立即完成
+    pub fn wait(&self) -> EventWait<'_> {
+        EventWait { event: self }
+    }
+}
+
+pub struct EventWait<'a> {
+    event: &'a IrqEvent,
+}
+
+impl Future for EventWait<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let event = self.event;
+        if event.pending.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        *event.waker.lock() = Some(cx.waker().clone());
+        // 登记 waker 之前到达的通知不会唤醒任何人，需要再检查一次
+        if event.pending.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IrqEvent, poll_ready, spawn};
+    use crate::{kassert_eq, kernel_test};
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static EVENT: IrqEvent = IrqEvent::new();
+
+    kernel_test! {
+        fn events_wake_futures() {
+            let steps = Arc::new(AtomicUsize::new(0));
+            let counter = steps.clone();
+            spawn(async move {
+                counter.fetch_add(1, Ordering::Relaxed);
+                EVENT.wait().await;
+                counter.fetch_add(1, Ordering::Relaxed);
+            });
+            kassert_eq!(poll_ready(8), 1);
+            kassert_eq!(steps.load(Ordering::Relaxed), 1);
+            // 没有通知时 future 不会被再次轮询
+            kassert_eq!(poll_ready(8), 0);
+            EVENT.signal();
+            kassert_eq!(poll_ready(8), 1);
+            kassert_eq!(steps.load(Ordering::Relaxed), 2);
+            // 完成的 future 不再留在执行器中
+            EVENT.signal();
+            kassert_eq!(poll_ready(8), 0);
+        }
+    }
+}
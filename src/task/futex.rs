@@ -0,0 +1,308 @@
+// src/task/futex.rs
+//! futex：以用户字的物理地址为键的等待队列，键散列到固定数量的桶中，同一个键上的等待者按入队顺序唤醒。
+//! WAIT 的比较和入队在同一把桶锁内完成，WAKE 也要拿这把锁，所以修改字之后的唤醒不会丢失。
+//!
+//! 等待的任务醒来后不重新执行系统调用：WAKE 只唤醒任务而不要求字的值改变，重新比较会让任务再次睡下。
+
+use alloc::{collections::vec_deque::VecDeque, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{
+    data_struct::lock::IrqLock,
+    mm::{address::VirtAddr, kernel_page_table},
+    syslib::errno::Errno,
+    task::{SCHEDULER, context::TaskContext, scheduler::Scheduler, tcb::TaskStatus},
+};
+
+// futex 操作码，与 Linux 一致
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_REQUEUE: usize = 3;
+/// 所有任务共用一张页表，私有 futex 和共享 futex 没有区别，忽略这个标志
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+
+const BUCKET_COUNT: usize = 64;
+
+struct Waiter {
+    key: usize,
+    task_id: usize,
+}
+
+static BUCKETS: [IrqLock<VecDeque<Waiter>>; BUCKET_COUNT] =
+    [const { IrqLock::new(VecDeque::new()) }; BUCKET_COUNT];
+
+fn bucket(key: usize) -> &'static IrqLock<VecDeque<Waiter>> {
+    // 字至少 4 字节对齐，去掉低位后做乘法散列
+    let hash = (key >> 2).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    &BUCKETS[hash >> (usize::BITS - BUCKET_COUNT.trailing_zeros())]
+}
+
+/// 用户字的物理地址。字必须 4 字节对齐，所在的页必须已经映射
+pub fn key_of(uaddr: usize) -> Result<usize, Errno> {
+    if !uaddr.is_multiple_of(align_of::<AtomicU32>()) {
+        return Err(Errno::EINVAL);
+    }
+    // 非规范地址会被 VirtAddr::from 截断成另一个地址，必须先排除
+    let va = VirtAddr::from(uaddr);
+    if va.0 != uaddr {
+        return Err(Errno::EFAULT);
+    }
+    kernel_page_table()
+        .translate(va)
+        .map(|pa| pa.0)
+        .ok_or(Errno::EFAULT)
+}
+
+/// 任务是否仍在 key 上等待，已经超时、被信号唤醒或退出的任务留下的是旧条目
+fn waiting_task(scheduler: &Scheduler, task_id: usize, key: usize) -> bool {
+    scheduler
+        .get_task(task_id)
+        .is_some_and(|tcb| tcb.waiting_on == Some(key) && tcb.status == TaskStatus::Blocked)
+}
+
+/// uaddr 处的字等于 expected 时把当前任务挂到 key 上并阻塞，返回下一个要运行的任务；
+/// 值不同时返回 EAGAIN。调用者要先把系统调用的返回值设为 EINTR：被 wake 唤醒时改为 0，
+/// 到 deadline 仍未被唤醒时改为 ETIMEDOUT，被信号打断时保持 EINTR
+pub fn wait(
+    uaddr: usize,
+    key: usize,
+    expected: u32,
+    deadline: Option<usize>,
+) -> Result<*mut TaskContext, Errno> {
+    let word = unsafe { &*(uaddr as *const AtomicU32) };
+    let mut bucket = bucket(key).lock();
+    if word.load(Ordering::SeqCst) != expected {
+        return Err(Errno::EAGAIN);
+    }
+    let mut scheduler = SCHEDULER.lock();
+    let task_id = scheduler.get_current_task_id();
+    bucket.push_back(Waiter { key, task_id });
+    drop(bucket);
+    if let Some(tcb) = scheduler.current_tcb() {
+        tcb.waiting_on = Some(key);
+    }
+    Ok(match deadline {
+        Some(deadline) => {
+            scheduler.block_current_task_until(deadline, move || wait_timed_out(task_id))
+        }
+        None => scheduler.block_current_task(),
+    })
+}
+
+fn wait_timed_out(task_id: usize) {
+    // 等待者可能已经被 REQUEUE 移到别的键上，以 TCB 中记录的键为准
+    let Some(key) = SCHEDULER
+        .lock()
+        .get_task(task_id)
+        .and_then(|tcb| tcb.waiting_on)
+    else {
+        return;
+    };
+    bucket(key)
+        .lock()
+        .retain(|waiter| waiter.task_id != task_id);
+    let mut scheduler = SCHEDULER.lock();
+    if !scheduler.wake_on_timeout(task_id) {
+        return;
+    }
+    if let Some(tcb) = scheduler.get_task_mut(task_id) {
+        tcb.context.a0 = Errno::ETIMEDOUT.as_ret();
+    }
+}
+
+/// 唤醒仍在 key 上等待的任务，它的 FUTEX_WAIT 返回 0
+fn wake_waiter(scheduler: &mut Scheduler, task_id: usize, key: usize) -> bool {
+    if !waiting_task(scheduler, task_id, key) {
+        return false;
+    }
+    if let Some(tcb) = scheduler.get_task_mut(task_id) {
+        tcb.context.a0 = 0;
+    }
+    scheduler.wake_waiting(task_id, key)
+}
+
+/// 按入队顺序唤醒 key 上最多 count 个任务，返回唤醒的个数
+pub fn wake(key: usize, count: usize) -> usize {
+    let mut bucket = bucket(key).lock();
+    let mut scheduler = SCHEDULER.lock();
+    let mut woken = 0;
+    bucket.retain(|waiter| {
+        if waiter.key != key || woken >= count {
+            return true;
+        }
+        if wake_waiter(&mut scheduler, waiter.task_id, key) {
+            woken += 1;
+        }
+        false
+    });
+    woken
+}
+
+/// 唤醒 key 上最多 nr_wake 个任务，再把最多 nr_requeue 个剩下的等待者移到 new_key 的队尾，
+/// 返回唤醒和移动的任务总数
+pub fn requeue(key: usize, nr_wake: usize, new_key: usize, nr_requeue: usize) -> usize {
+    let mut moved = Vec::new();
+    let mut woken = 0;
+    {
+        let mut bucket = bucket(key).lock();
+        let mut scheduler = SCHEDULER.lock();
+        bucket.retain(|waiter| {
+            if waiter.key != key {
+                return true;
+            }
+            if woken < nr_wake {
+                if wake_waiter(&mut scheduler, waiter.task_id, key) {
+                    woken += 1;
+                }
+                return false;
+            }
+            if !waiting_task(&scheduler, waiter.task_id, key) {
+                return false;
+            }
+            if moved.len() >= nr_requeue {
+                return true;
+            }
+            if let Some(tcb) = scheduler.get_task_mut(waiter.task_id) {
+                tcb.waiting_on = Some(new_key);
+            }
+            moved.push(waiter.task_id);
+            false
+        });
+    }
+    // 系统调用在关中断的 trap 上下文中执行，两个桶锁之间不会有别的唤醒插进来
+    let count = woken + moved.len();
+    bucket(new_key)
+        .lock()
+        .extend(moved.into_iter().map(|task_id| Waiter {
+            key: new_key,
+            task_id,
+        }));
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket, key_of, requeue, wait, wake};
+    use crate::{
+        kassert, kassert_eq, kernel_test,
+        mm::buddy::virt_to_phys,
+        syslib::errno::Errno,
+        task::{SCHEDULER, tcb::TaskStatus, test_support::TestTasks, timer},
+        trap::interrupts::get_time,
+    };
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static WORD: AtomicU32 = AtomicU32::new(0);
+    static FIFO_WORD: AtomicU32 = AtomicU32::new(0);
+    static MISMATCH_WORD: AtomicU32 = AtomicU32::new(0);
+    static REQUEUE_FROM: AtomicU32 = AtomicU32::new(0);
+    static REQUEUE_TO: AtomicU32 = AtomicU32::new(0);
+    static TIMEOUT_WORD: AtomicU32 = AtomicU32::new(0);
+
+    fn addr(word: &AtomicU32) -> usize {
+        word as *const AtomicU32 as usize
+    }
+
+    /// 第 index 个任务在 word 上等待值 0 并阻塞
+    fn block_on(
+        tasks: &TestTasks,
+        index: usize,
+        word: &AtomicU32,
+        deadline: Option<usize>,
+    ) -> bool {
+        tasks.run(index);
+        let key = key_of(addr(word)).unwrap();
+        wait(addr(word), key, 0, deadline).is_ok() && tasks.status_is(index, TaskStatus::Blocked)
+    }
+
+    /// 任务的 FUTEX_WAIT 返回值，由唤醒或超时写入 a0
+    fn wait_result(tasks: &TestTasks, index: usize) -> usize {
+        SCHEDULER
+            .lock()
+            .get_task(tasks.id(index))
+            .unwrap()
+            .context
+            .a0
+    }
+
+    kernel_test! {
+        fn keys_are_physical_addresses() {
+            let addr = &WORD as *const AtomicU32 as usize;
+            // 内核镜像在高半区线性映射
+            kassert_eq!(key_of(addr), Ok(virt_to_phys(addr)));
+            kassert_eq!(key_of(addr + 1), Err(Errno::EINVAL));
+            kassert_eq!(key_of(0), Err(Errno::EFAULT));
+        }
+    }
+
+    kernel_test! {
+        fn wake_follows_wait_order() {
+            let tasks = TestTasks::spawn(3);
+            let key = key_of(addr(&FIFO_WORD)).unwrap();
+            for index in [2, 0, 1] {
+                kassert!(block_on(&tasks, index, &FIFO_WORD, None));
+            }
+            kassert_eq!(wake(key, 1), 1);
+            kassert!(tasks.status_is(2, TaskStatus::Ready), "first waiter not woken first");
+            kassert!(tasks.status_is(0, TaskStatus::Blocked));
+            kassert_eq!(wait_result(&tasks, 2), 0);
+            kassert_eq!(wake(key, 1), 1);
+            kassert!(tasks.status_is(0, TaskStatus::Ready));
+            kassert!(tasks.status_is(1, TaskStatus::Blocked));
+            kassert_eq!(wake(key, usize::MAX), 1);
+            kassert!(tasks.status_is(1, TaskStatus::Ready));
+            kassert_eq!(wake(key, usize::MAX), 0);
+        }
+    }
+
+    kernel_test! {
+        fn wait_returns_eagain_when_value_differs() {
+            let tasks = TestTasks::spawn(1);
+            let key = key_of(addr(&MISMATCH_WORD)).unwrap();
+            MISMATCH_WORD.store(1, Ordering::SeqCst);
+            tasks.run(0);
+            kassert_eq!(wait(addr(&MISMATCH_WORD), key, 0, None).err(), Some(Errno::EAGAIN));
+            kassert!(tasks.status_is(0, TaskStatus::Running));
+            kassert_eq!(wake(key, usize::MAX), 0);
+        }
+    }
+
+    kernel_test! {
+        fn requeue_moves_waiters_within_limits() {
+            let tasks = TestTasks::spawn(4);
+            let from = key_of(addr(&REQUEUE_FROM)).unwrap();
+            let to = key_of(addr(&REQUEUE_TO)).unwrap();
+            for index in 0..4 {
+                kassert!(block_on(&tasks, index, &REQUEUE_FROM, None));
+            }
+            // 唤醒一个，再移动两个，最后一个留在原来的键上
+            kassert_eq!(requeue(from, 1, to, 2), 3);
+            kassert!(tasks.status_is(0, TaskStatus::Ready));
+            for index in 1..4 {
+                kassert!(tasks.status_is(index, TaskStatus::Blocked));
+            }
+            kassert_eq!(wake(to, 1), 1);
+            kassert!(tasks.status_is(1, TaskStatus::Ready), "requeued waiters lost their order");
+            kassert_eq!(wake(to, usize::MAX), 1);
+            kassert!(tasks.status_is(2, TaskStatus::Ready));
+            kassert!(tasks.status_is(3, TaskStatus::Blocked));
+            kassert_eq!(wake(from, usize::MAX), 1);
+            kassert!(tasks.status_is(3, TaskStatus::Ready));
+        }
+    }
+
+    kernel_test! {
+        fn timed_out_wait_leaves_bucket() {
+            let tasks = TestTasks::spawn(1);
+            let key = key_of(addr(&TIMEOUT_WORD)).unwrap();
+            let deadline = get_time() + 1000;
+            kassert!(block_on(&tasks, 0, &TIMEOUT_WORD, Some(deadline)));
+            kassert!(bucket(key).lock().iter().any(|waiter| waiter.task_id == tasks.id(0)));
+            timer::run_expired(deadline);
+            kassert!(tasks.status_is(0, TaskStatus::Ready));
+            kassert_eq!(wait_result(&tasks, 0), Errno::ETIMEDOUT.as_ret());
+            kassert!(bucket(key).lock().iter().all(|waiter| waiter.task_id != tasks.id(0)));
+            kassert_eq!(wake(key, usize::MAX), 0);
+        }
+    }
+}
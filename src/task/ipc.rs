@@ -0,0 +1,390 @@
+// src/task/ipc.rs
+//! 同步消息传递 IPC。端点是内核对象，任务通过句柄使用它：send 和 recv 在端点上会合，
+//! 先到的一方阻塞，后到的一方在内核中直接把消息从发送者的寄存器复制到接收者的寄存器。
+//! call 是 send 之后接着等待回复，接收者收到 call 后用 reply 回复，不需要知道调用者是谁。
+//!
+//! 寄存器约定：消息为 a0–a5 六个字，a6 传入端点句柄；返回时 a6 为 0、GRANTED 或负的错误码，
+//! recv 和 call 收到的消息放回 a0–a5，recv 还在 a7 中返回发送者的任务 id。
+//! 阻塞中的任务被信号打断时返回 EINTR，端点被关闭时返回 EPIPE。
+//!
+//! 句柄是全局的且不重复使用，拿到句柄的任务都可以使用端点，关闭后旧句柄返回 EBADF。
+//!
+//! 较大的数据按页转移：发送方把自己的一个共享内存映射（shm_map 返回的地址）放在 a5，
+//! 并在 a6 的句柄上加 IPC_GRANT。消息交付时映射移交给接收方，出现在新地址上，a5 改为新地址，
+//! 接收方的 a6 返回 GRANTED；发送方的旧地址随即失效，之后由接收方撤销或继续转移。
+//! 移交失败时（a5 不是发送方的映射、窗口已满）发送方得到错误，这条消息不会交付。
+
+use alloc::{collections::btree_map::BTreeMap, collections::vec_deque::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    data_struct::lock::IrqLock,
+    mm::shm,
+    syslib::errno::Errno,
+    task::{
+        SCHEDULER,
+        context::TaskContext,
+        scheduler::Scheduler,
+        tcb::{TaskControlBlock, TaskStatus},
+    },
+};
+
+/// 一条消息的字数
+pub const MESSAGE_WORDS: usize = 6;
+pub type Message = [usize; MESSAGE_WORDS];
+
+/// 与句柄一起放在 a6 中：消息的 GRANT_WORD 是发送方的共享内存映射，随消息移交给接收方
+pub const IPC_GRANT: usize = 1 << (usize::BITS - 1);
+/// 转移页时存放映射地址的消息字，即 a5
+pub const GRANT_WORD: usize = MESSAGE_WORDS - 1;
+/// 收到的消息带着移交过来的映射时 a6 的返回值
+pub const GRANTED: usize = 1;
+
+struct Sender {
+    task_id: usize,
+    /// 以 call 发送，被接收后继续等待回复
+    call: bool,
+    /// 消息带着要移交的映射
+    grant: bool,
+}
+
+/// 在端点上阻塞的任务。阻塞的任务以端点地址为等待键，超时、被信号唤醒或退出的任务留下的旧条目出队时跳过
+struct Endpoint {
+    senders: VecDeque<Sender>,
+    receivers: VecDeque<usize>,
+}
+
+type EndpointRef = Arc<IrqLock<Endpoint>>;
+
+static ENDPOINTS: IrqLock<BTreeMap<usize, EndpointRef>> = IrqLock::new(BTreeMap::new());
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(1);
+
+fn lookup(handle: usize) -> Result<EndpointRef, Errno> {
+    ENDPOINTS.lock().get(&handle).cloned().ok_or(Errno::EBADF)
+}
+
+/// 在端点上收发时的等待键
+fn key_of(endpoint: &EndpointRef) -> usize {
+    Arc::as_ptr(endpoint) as usize
+}
+
+/// 等待回复时的等待键。端点地址至少 8 字节对齐，加 1 后不会与其他等待键冲突
+fn reply_key(key: usize) -> usize {
+    key | 1
+}
+
+fn message(context: &TaskContext) -> Message {
+    [
+        context.a0, context.a1, context.a2, context.a3, context.a4, context.a5,
+    ]
+}
+
+fn set_message(context: &mut TaskContext, message: &Message) {
+    [
+        context.a0, context.a1, context.a2, context.a3, context.a4, context.a5,
+    ] = *message;
+}
+
+fn is_waiting(tcb: &TaskControlBlock, key: usize) -> bool {
+    tcb.waiting_on == Some(key) && tcb.status == TaskStatus::Blocked
+}
+
+/// 分开 a6 中的句柄和 IPC_GRANT
+fn split_handle(word: usize) -> (usize, bool) {
+    (word & !IPC_GRANT, word & IPC_GRANT != 0)
+}
+
+/// grant 时把消息中 from 的映射移交给 to，并把消息中的地址换成新地址
+fn deliver(message: &mut Message, grant: bool, from: usize, to: usize) -> Result<(), Errno> {
+    if grant {
+        message[GRANT_WORD] = shm::transfer(message[GRANT_WORD], from, to)?;
+    }
+    Ok(())
+}
+
+/// 消息交付后接收方 a6 的返回值
+fn received(grant: bool) -> usize {
+    if grant { GRANTED } else { 0 }
+}
+
+/// 结束仍在 key 上等待的任务的阻塞，f 负责填写它的返回值。任务已经离开时返回 false
+fn complete(
+    scheduler: &mut Scheduler,
+    task_id: usize,
+    key: usize,
+    f: impl FnOnce(&mut TaskControlBlock),
+) -> bool {
+    let Some(tcb) = scheduler
+        .get_task_mut(task_id)
+        .filter(|tcb| is_waiting(tcb, key))
+    else {
+        return false;
+    };
+    tcb.context.a6 = 0;
+    f(tcb);
+    scheduler.wake_waiting(task_id, key)
+}
+
+/// 阻塞当前任务。被信号打断时系统调用返回 EINTR，正常完成时由对方把 a6 改成 0
+fn block_current(scheduler: &mut Scheduler, key: usize) -> *mut TaskContext {
+    if let Some(tcb) = scheduler.current_tcb() {
+        tcb.waiting_on = Some(key);
+        tcb.context.a6 = Errno::EINTR.as_ret();
+    }
+    scheduler.block_current_task()
+}
+
+/// 创建端点，返回句柄
+pub fn create() -> usize {
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    let endpoint = Endpoint {
+        senders: VecDeque::new(),
+        receivers: VecDeque::new(),
+    };
+    ENDPOINTS
+        .lock()
+        .insert(handle, Arc::new(IrqLock::new(endpoint)));
+    handle
+}
+
+/// 关闭端点，阻塞在上面收发的任务返回 EPIPE。已经被接收的 call 仍然可以得到回复
+pub fn close(handle: usize) -> Result<(), Errno> {
+    let endpoint = ENDPOINTS.lock().remove(&handle).ok_or(Errno::EBADF)?;
+    let key = key_of(&endpoint);
+    let Endpoint { senders, receivers } = &mut *endpoint.lock();
+    let mut scheduler = SCHEDULER.lock();
+    let senders = senders.drain(..).map(|sender| sender.task_id);
+    for task_id in senders.chain(receivers.drain(..)) {
+        complete(&mut scheduler, task_id, key, |tcb| {
+            tcb.context.a6 = Errno::EPIPE.as_ret();
+        });
+    }
+    Ok(())
+}
+
+/// 以当前任务 a0–a5 中的消息执行 send 或 call，handle 可以带 IPC_GRANT。
+/// 消息已经交给接收者、不需要等待时返回 None，否则阻塞当前任务并返回下一个要运行的任务
+pub fn send(handle: usize, call: bool) -> Result<Option<*mut TaskContext>, Errno> {
+    let (handle, grant) = split_handle(handle);
+    let endpoint = lookup(handle)?;
+    let key = key_of(&endpoint);
+    let mut endpoint = endpoint.lock();
+    let mut scheduler = SCHEDULER.lock();
+    let task_id = scheduler.get_current_task_id();
+    let message = scheduler
+        .current_tcb()
+        .map(|tcb| message(&tcb.context))
+        .ok_or(Errno::ESRCH)?;
+    while let Some(receiver) = endpoint.receivers.pop_front() {
+        if scheduler
+            .get_task(receiver)
+            .is_none_or(|tcb| !is_waiting(tcb, key))
+        {
+            continue;
+        }
+        let mut message = message;
+        if let Err(errno) = deliver(&mut message, grant, task_id, receiver) {
+            endpoint.receivers.push_front(receiver);
+            return Err(errno);
+        }
+        complete(&mut scheduler, receiver, key, |tcb| {
+            set_message(&mut tcb.context, &message);
+            tcb.context.a6 = received(grant);
+            tcb.context.a7 = task_id;
+            tcb.ipc_reply_to = call.then_some((task_id, reply_key(key)));
+        });
+        return Ok(call.then(|| block_current(&mut scheduler, reply_key(key))));
+    }
+    endpoint.senders.push_back(Sender {
+        task_id,
+        call,
+        grant,
+    });
+    Ok(Some(block_current(&mut scheduler, key)))
+}
+
+/// 接收一条消息放进当前任务的 a0–a5，发送者的任务 id 放进 a7。
+/// 已经有发送者在等待时返回 None，否则阻塞当前任务并返回下一个要运行的任务
+pub fn recv(handle: usize) -> Result<Option<*mut TaskContext>, Errno> {
+    let endpoint = lookup(handle)?;
+    let key = key_of(&endpoint);
+    let mut endpoint = endpoint.lock();
+    let mut scheduler = SCHEDULER.lock();
+    let receiver = scheduler.get_current_task_id();
+    while let Some(Sender {
+        task_id,
+        call,
+        grant,
+    }) = endpoint.senders.pop_front()
+    {
+        let Some(mut message) = scheduler
+            .get_task(task_id)
+            .filter(|tcb| is_waiting(tcb, key))
+            .map(|tcb| message(&tcb.context))
+        else {
+            continue;
+        };
+        if let Err(errno) = deliver(&mut message, grant, task_id, receiver) {
+            complete(&mut scheduler, task_id, key, |tcb| {
+                tcb.context.a6 = errno.as_ret();
+            });
+            continue;
+        }
+        if call {
+            // 调用者继续阻塞，改为等待回复
+            if let Some(tcb) = scheduler.get_task_mut(task_id) {
+                tcb.waiting_on = Some(reply_key(key));
+            }
+        } else {
+            complete(&mut scheduler, task_id, key, |_| {});
+        }
+        let tcb = scheduler.current_tcb().ok_or(Errno::ESRCH)?;
+        set_message(&mut tcb.context, &message);
+        tcb.context.a6 = received(grant);
+        tcb.context.a7 = task_id;
+        tcb.ipc_reply_to = call.then_some((task_id, reply_key(key)));
+        return Ok(None);
+    }
+    endpoint
+        .receivers
+        .push_back(scheduler.get_current_task_id());
+    Ok(Some(block_current(&mut scheduler, key)))
+}
+
+/// 以当前任务 a0–a5 中的消息回复最近一次收到的 call，flags 可以是 IPC_GRANT。
+/// 没有待回复的 call 时返回 EINVAL，调用者已经因为信号或退出而离开时返回 ESRCH。
+/// 映射移交失败时返回错误，这次 call 仍然等待回复
+pub fn reply(flags: usize) -> Result<(), Errno> {
+    let grant = split_handle(flags).1;
+    let mut scheduler = SCHEDULER.lock();
+    let replier = scheduler.get_current_task_id();
+    let tcb = scheduler.current_tcb().ok_or(Errno::ESRCH)?;
+    let (caller, key) = tcb.ipc_reply_to.take().ok_or(Errno::EINVAL)?;
+    let mut message = message(&tcb.context);
+    if scheduler
+        .get_task(caller)
+        .is_none_or(|tcb| !is_waiting(tcb, key))
+    {
+        return Err(Errno::ESRCH);
+    }
+    if let Err(errno) = deliver(&mut message, grant, replier, caller) {
+        if let Some(tcb) = scheduler.current_tcb() {
+            tcb.ipc_reply_to = Some((caller, key));
+        }
+        return Err(errno);
+    }
+    complete(&mut scheduler, caller, key, |tcb| {
+        set_message(&mut tcb.context, &message);
+        tcb.context.a6 = received(grant);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        GRANT_WORD, GRANTED, IPC_GRANT, Message, close, create, message, recv, send, set_message,
+    };
+    use crate::{
+        kassert, kassert_eq, kernel_test,
+        mm::shm::{self, PROT_READ, PROT_WRITE},
+        syslib::errno::Errno,
+        task::{SCHEDULER, context::TaskContext, tcb::TaskStatus, test_support::TestTasks},
+    };
+
+    /// 第 index 个任务在端点上 recv 并阻塞
+    fn block_in_recv(tasks: &TestTasks, index: usize, handle: usize) -> bool {
+        tasks.run(index);
+        matches!(recv(handle), Ok(Some(_))) && tasks.status_is(index, TaskStatus::Blocked)
+    }
+
+    /// 第 index 个任务以 message 执行 send，返回 send 的结果是否为立即交付
+    fn send_as(
+        tasks: &TestTasks,
+        index: usize,
+        handle: usize,
+        message: &Message,
+    ) -> Result<bool, Errno> {
+        tasks.run(index);
+        if let Some(tcb) = SCHEDULER.lock().current_tcb() {
+            set_message(&mut tcb.context, message);
+        }
+        send(handle, false).map(|next| next.is_none())
+    }
+
+    /// 任务收到的消息、a6 和 a7
+    fn received_by(tasks: &TestTasks, index: usize) -> (Message, usize, usize) {
+        let scheduler = SCHEDULER.lock();
+        let context = &scheduler.get_task(tasks.id(index)).unwrap().context;
+        (message(context), context.a6, context.a7)
+    }
+
+    kernel_test! {
+        fn handles_are_not_reused() {
+            let first = create();
+            let second = create();
+            kassert!(first != second);
+            kassert_eq!(close(first), Ok(()));
+            kassert_eq!(close(first), Err(Errno::EBADF));
+            let third = create();
+            kassert!(third != first);
+            kassert_eq!(close(second), Ok(()));
+            kassert_eq!(close(third), Ok(()));
+        }
+    }
+
+    kernel_test! {
+        fn grant_moves_the_mapping_to_the_receiver() {
+            let tasks = TestTasks::spawn(2);
+            let handle = create();
+            kassert!(block_in_recv(&tasks, 0, handle));
+            let region = shm::create(100, tasks.id(1)).unwrap();
+            let addr = shm::map(region, PROT_READ | PROT_WRITE, tasks.id(1)).unwrap();
+            unsafe { (addr as *mut u64).write_volatile(0x9a6e) };
+            kassert_eq!(send_as(&tasks, 1, handle | IPC_GRANT, &[7, 0, 0, 0, 0, addr]), Ok(true));
+            kassert!(tasks.status_is(0, TaskStatus::Ready));
+            let (message, ret, sender) = received_by(&tasks, 0);
+            let moved = message[GRANT_WORD];
+            kassert_eq!(message[0], 7);
+            kassert_eq!(ret, GRANTED);
+            kassert_eq!(sender, tasks.id(1));
+            kassert!(moved != addr);
+            kassert_eq!(unsafe { (moved as *const u64).read_volatile() }, 0x9a6e);
+            // 发送方失去了映射，接收方可以撤销它
+            kassert_eq!(shm::unmap(addr, tasks.id(1)), Err(Errno::EINVAL));
+            kassert_eq!(shm::destroy(region), Ok(()));
+            kassert_eq!(shm::unmap(moved, tasks.id(0)), Ok(()));
+            kassert_eq!(close(handle), Ok(()));
+        }
+    }
+
+    kernel_test! {
+        fn failed_grant_leaves_the_receiver_waiting() {
+            let tasks = TestTasks::spawn(2);
+            let handle = create();
+            kassert!(block_in_recv(&tasks, 0, handle));
+            // a5 不是发送方的映射
+            kassert_eq!(
+                send_as(&tasks, 1, handle | IPC_GRANT, &[1, 0, 0, 0, 0, 0x1000]),
+                Err(Errno::EINVAL)
+            );
+            kassert!(tasks.status_is(0, TaskStatus::Blocked));
+            // 不带映射的消息照常交付
+            kassert_eq!(send_as(&tasks, 1, handle, &[2, 0, 0, 0, 0, 0x1000]), Ok(true));
+            let (message, ret, _) = received_by(&tasks, 0);
+            kassert_eq!(message, [2, 0, 0, 0, 0, 0x1000]);
+            kassert_eq!(ret, 0);
+            kassert_eq!(close(handle), Ok(()));
+        }
+    }
+
+    kernel_test! {
+        fn messages_use_argument_registers() {
+            let mut context = TaskContext::zero();
+            set_message(&mut context, &[1, 2, 3, 4, 5, 6]);
+            kassert_eq!(context.a5, 6);
+            kassert_eq!(context.a6, 0);
+            kassert_eq!(message(&context), [1, 2, 3, 4, 5, 6]);
+        }
+    }
+}
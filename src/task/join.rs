@@ -0,0 +1,96 @@
+// src/task/join.rs
+//! 等待任务结束并取得返回值。Scheduler::spawn 把任务闭包包一层，返回值存进与 JoinHandle 共享的槽里；
+//! 任务退出时内核把退出码写进共享的退出字并以 futex 唤醒等待者，所以任务 id 被回收重用也不会等错任务。
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+
+use crate::{task::signal::Signal, userlib::syscall::sys_futex_wait};
+
+/// 退出字的初始值，表示任务还在运行
+pub const EXIT_RUNNING: u32 = u32::MAX;
+/// 任务函数正常返回
+pub const EXIT_SUCCESS: u32 = 0;
+/// 任务 panic，与 Rust 程序 panic 后的退出码相同
+pub const EXIT_PANIC: u32 = 101;
+/// 被信号终止时的退出码为 EXIT_SIGNAL_BASE + 信号编号
+pub const EXIT_SIGNAL_BASE: u32 = 128;
+
+/// 任务没有正常返回的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    Panicked,
+    Killed(Signal),
+    /// 任务函数返回前以其他退出码调用了 sys_task_exit
+    Exited(u32),
+}
+
+impl JoinError {
+    fn from_code(code: u32) -> JoinError {
+        match code {
+            EXIT_PANIC => JoinError::Panicked,
+            _ => code
+                .checked_sub(EXIT_SIGNAL_BASE)
+                .and_then(|signo| Signal::from_num(signo as usize))
+                .map_or(JoinError::Exited(code), JoinError::Killed),
+        }
+    }
+}
+
+/// 已创建任务的句柄。丢弃句柄不影响任务运行，只是不再能取得它的结果
+pub struct JoinHandle<T> {
+    task_id: usize,
+    exit_code: Arc<AtomicU32>,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(super) fn new(
+        task_id: usize,
+        exit_code: Arc<AtomicU32>,
+        result: Arc<Mutex<Option<T>>>,
+    ) -> Self {
+        Self {
+            task_id,
+            exit_code,
+            result,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.task_id
+    }
+
+    /// 阻塞到任务结束，返回任务函数的返回值。只能在任务中调用
+    pub fn join(self) -> Result<T, JoinError> {
+        let code = loop {
+            let code = self.exit_code.load(Ordering::Acquire);
+            if code != EXIT_RUNNING {
+                break code;
+            }
+            sys_futex_wait(&self.exit_code, EXIT_RUNNING, None);
+        };
+        self.result
+            .lock()
+            .take()
+            .ok_or_else(|| JoinError::from_code(code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EXIT_PANIC, EXIT_SIGNAL_BASE, JoinError};
+    use crate::{kassert_eq, kernel_test, task::signal::Signal};
+
+    kernel_test! {
+        fn exit_codes_map_to_errors() {
+            kassert_eq!(JoinError::from_code(EXIT_PANIC), JoinError::Panicked);
+            kassert_eq!(
+                JoinError::from_code(EXIT_SIGNAL_BASE + 9),
+                JoinError::Killed(Signal::SIGKILL)
+            );
+            kassert_eq!(JoinError::from_code(3), JoinError::Exited(3));
+        }
+    }
+}
@@ -1,7 +1,18 @@
 pub mod context;
+pub mod executor;
+pub mod futex;
+pub mod ipc;
+pub mod join;
 pub mod scheduler;
+pub mod signal;
 pub mod switch;
+pub mod sync;
 pub mod tcb;
+#[cfg(test)]
+pub mod test_support;
+pub mod timer;
+pub mod wait_queue;
+pub mod watchdog;
 
 use crate::{data_struct::lock::IrqLock, task::scheduler::Scheduler};
 use lazy_static::lazy_static;
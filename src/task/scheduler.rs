@@ -1,40 +1,51 @@
 use crate::{
+    debug::profile,
+    fs::file::FdTable,
     mm::{
         BUDDY_ALLOCATOR, PAGE_SIZE,
         address::{PhysAddr, PhysPageNum},
         buddy::{phys_to_virt, virt_to_phys},
     },
     polling_println, println,
+    syslib::time::{TimeSpec, ticks_to_ns},
     task::{
         SCHEDULER,
         context::TaskContext,
+        join::{EXIT_RUNNING, EXIT_SIGNAL_BASE, EXIT_SUCCESS, JoinHandle},
+        signal::{
+            DefaultAction, SIG_DFL, SIG_IGN, STOP_SIGNALS, SigAction, Signal, SignalState,
+            push_signal_frame,
+        },
         switch::first_switch_to,
-        tcb::{TaskControlBlock, TaskStatus},
+        tcb::{TaskControlBlock, TaskStats, TaskStatus},
+        timer, watchdog,
     },
     trap::{
-        interrupts::{init_supervisor_interrupts, set_next_timer_tick},
+        enter_task_context,
+        interrupts::{
+            get_time, init_supervisor_interrupts, next_tick_time, set_next_timer_tick,
+            set_timer_deadline,
+        },
         trap_handler,
     },
-    userlib::syscall::sys_task_exit,
-};
-use alloc::{
-    boxed::Box,
-    collections::{binary_heap::BinaryHeap, vec_deque::VecDeque},
-    vec::Vec,
+    userlib::syscall::{sys_idle_wait, sys_task_exit},
 };
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, string::String, sync::Arc, vec::Vec};
 use core::{
     alloc::{GlobalAlloc, Layout, LayoutError},
     arch::asm,
-    cmp::Reverse,
     mem::transmute,
     num::NonZeroUsize,
     ptr::NonNull,
+    sync::atomic::AtomicU32,
 };
+use spin::Mutex;
 
 #[derive(Debug)]
 pub enum SchedulerError {
     SchedulerLayoutError(LayoutError),
     MemoryAllocationError,
+    StackTooSmall,
 }
 
 impl From<LayoutError> for SchedulerError {
@@ -42,19 +53,14 @@ impl From<LayoutError> for SchedulerError {
         Self::SchedulerLayoutError(value)
     }
 }
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct SleepEntry {
-    wake_time: usize,
-    task_id: usize,
-}
-
 type TaskId = usize;
+/// 任务栈的最小大小
+pub const MIN_STACK_SIZE: usize = PAGE_SIZE;
 pub struct Scheduler {
     current_task_id: Option<TaskId>,
     ready_queue: VecDeque<TaskId>,
     task_list: Vec<Option<TaskControlBlock>>,
     zombie_queue: Vec<TaskId>,
-    blocked_queue: BinaryHeap<Reverse<SleepEntry>>,
 }
 unsafe impl Send for Scheduler {}
 impl Scheduler {
@@ -64,7 +70,6 @@ impl Scheduler {
             ready_queue: VecDeque::new(),
             task_list: Vec::new(),
             zombie_queue: Vec::new(),
-            blocked_queue: BinaryHeap::new(),
         }
     }
     pub fn get_current_task_id(&self) -> TaskId {
@@ -76,20 +81,42 @@ impl Scheduler {
     pub fn get_task_list(&mut self) -> &mut Vec<Option<TaskControlBlock>> {
         &mut self.task_list
     }
+    pub fn get_task(&self, task_id: TaskId) -> Option<&TaskControlBlock> {
+        self.task_list.get(task_id).and_then(|slot| slot.as_ref())
+    }
+    pub fn tasks(&self) -> impl Iterator<Item = &TaskControlBlock> {
+        self.task_list.iter().flatten()
+    }
+    pub fn get_task_mut(&mut self, task_id: TaskId) -> Option<&mut TaskControlBlock> {
+        self.task_list
+            .get_mut(task_id)
+            .and_then(|slot| slot.as_mut())
+    }
+    pub fn current_tcb(&mut self) -> Option<&mut TaskControlBlock> {
+        let id = self.current_task_id?;
+        self.task_list[id].as_mut()
+    }
     pub fn init() -> Result<(), SchedulerError> {
         let mut scheduler = SCHEDULER.lock();
-        scheduler.spawn(idle_task, 4096, 0)
+        scheduler.spawn("idle", idle_task, 4096, 0).map(|_| ())
     }
 
-    pub fn spawn<F>(
+    /// 创建任务，栈大小向上取整到整页且不能小于 MIN_STACK_SIZE。
+    /// 返回的 JoinHandle 可以在任务中等待它结束并取得返回值
+    pub fn spawn<F, T>(
         &mut self,
+        name: &str,
         task: F,
         stack_size: usize,
         priority: u8,
-    ) -> Result<(), SchedulerError>
+    ) -> Result<JoinHandle<T>, SchedulerError>
     where
-        F: FnOnce() + Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
     {
+        if stack_size < MIN_STACK_SIZE {
+            return Err(SchedulerError::StackTooSmall);
+        }
         let task_id = self
             .task_list
             .iter()
@@ -115,7 +142,13 @@ impl Scheduler {
         // let stack_base = stack_ptr.as_ptr() as usize;
         // let stack_top = (stack_base + stack_size) & !0xF; // 栈顶（高地址，对齐）
 
-        let task_box: Box<dyn FnOnce() + Send> = Box::new(task);
+        let exit_code = Arc::new(AtomicU32::new(EXIT_RUNNING));
+        let result = Arc::new(Mutex::new(None));
+        let slot = result.clone();
+        let task_box: Box<dyn FnOnce() + Send> = Box::new(move || {
+            let value = task();
+            *slot.lock() = Some(value);
+        });
         let raw_fat_ptr = Box::into_raw(task_box);
 
         // Rust 的胖指针布局通常是 (data_ptr, vtable_ptr)
@@ -132,12 +165,27 @@ impl Scheduler {
         // task_context.ra = entry_point as *mut usize as usize;
         let tcb = TaskControlBlock {
             task_id,
+            name: String::from(name),
             stack_base: stack_ptr,
             page_count: pages,
             entry_point: (data_ptr, vtable_ptr),
             priority,
             status: TaskStatus::Ready,
             context: task_context,
+            fd_table: FdTable::new(),
+            stats: TaskStats {
+                since: get_time(),
+                ..TaskStats::default()
+            },
+            waiters: Vec::new(),
+            timeout: None,
+            waiting_on: None,
+            timed_out: false,
+            signals: SignalState::new(),
+            exit_code: exit_code.clone(),
+            ipc_reply_to: None,
+            sleep_rem: None,
+            stopped: false,
         };
         if task_id == self.task_list.len() {
             self.task_list.push(Some(tcb));
@@ -145,92 +193,256 @@ impl Scheduler {
             self.task_list[task_id] = Some(tcb);
         }
         self.ready_queue.push_back(task_id);
-        Ok(())
+        Ok(JoinHandle::new(task_id, exit_code, result))
     }
+    /// 睡到 wake_time。睡满时系统调用返回 0，被信号提前唤醒时保留调用者预设的 EINTR
     pub fn set_current_task_sleep(&mut self, wake_time: usize) -> *mut TaskContext {
-        let current_id = self.current_task_id;
-
-        if let Some(cur) = current_id {
-            if let Some(tcb) = self.task_list[cur].as_mut() {
-                if matches!(tcb.status, TaskStatus::Running) {
-                    tcb.status = TaskStatus::Blocked;
-                    self.blocked_queue.push(Reverse(SleepEntry {
-                        wake_time,
-                        task_id: cur,
-                    }));
-                    let mut next_id = self.ready_queue.pop_front().unwrap_or(0);
-                    if next_id == 0 && !self.ready_queue.is_empty() {
-                        next_id = self.ready_queue.pop_front().unwrap();
-                        self.ready_queue.push_back(0);
-                    }
-                    let next_tcb = self.task_list[next_id].as_mut().expect("next task missing");
-                    next_tcb.status = TaskStatus::Running;
-                    self.current_task_id = Some(next_id);
-                    return &mut next_tcb.context as *mut TaskContext;
-                }
+        let task_id = self.get_current_task_id();
+        self.block_current_task_until(wake_time, move || {
+            let mut scheduler = SCHEDULER.lock();
+            if !scheduler.wake_on_timeout(task_id) {
+                return;
+            }
+            if let Some(tcb) = scheduler.get_task_mut(task_id) {
+                tcb.context.a0 = 0;
             }
+        })
+    }
+    /// 阻塞当前任务，到 deadline 时调用 on_timeout。任务在此之前被唤醒时定时器随之取消
+    pub fn block_current_task_until(
+        &mut self,
+        deadline: usize,
+        on_timeout: impl FnMut() + Send + 'static,
+    ) -> *mut TaskContext {
+        self.arm_current_timeout(deadline, on_timeout);
+        self.block_current_task()
+    }
+    /// 为当前任务设置超时定时器但不阻塞，用于稍后由 finish_file_op 阻塞的系统调用
+    pub fn arm_current_timeout(
+        &mut self,
+        deadline: usize,
+        on_timeout: impl FnMut() + Send + 'static,
+    ) {
+        let handle = timer::add_oneshot(deadline, on_timeout);
+        if let Some(old) = self
+            .current_tcb()
+            .and_then(|tcb| tcb.timeout.replace(handle))
+        {
+            old.cancel();
         }
-        unreachable!();
+    }
+    /// 超时定时器到期：仍在阻塞的任务被唤醒，系统调用的返回值由阻塞前设置好。返回是否唤醒了任务
+    pub fn wake_on_timeout(&mut self, task_id: TaskId) -> bool {
+        let Some(tcb) = self.get_task_mut(task_id) else {
+            return false;
+        };
+        if tcb.timeout.take().is_some() && matches!(tcb.status, TaskStatus::Blocked) {
+            self.end_blocking(task_id);
+            return true;
+        }
+        false
     }
     pub fn set_task_ready(&mut self, task_id: usize) {
-        if let Some(tcb) = self.task_list[task_id].as_mut() {
-            if matches!(tcb.status, TaskStatus::Blocked) {
-                // 这里不删除堆中的元素，留给finish_sleep自动pop出去
-                tcb.status = TaskStatus::Ready;
-                self.ready_queue.push_back(task_id);
+        if self.task_list[task_id]
+            .as_ref()
+            .is_some_and(|tcb| matches!(tcb.status, TaskStatus::Blocked))
+        {
+            self.end_blocking(task_id);
+        }
+    }
+    /// 结束任务的阻塞并放回就绪队列。在阻塞中被停止的任务转为 Stopped，收到 SIGCONT 后才运行
+    fn end_blocking(&mut self, task_id: TaskId) {
+        let Some(tcb) = self.get_task_mut(task_id) else {
+            return;
+        };
+        tcb.make_ready(get_time());
+        if core::mem::take(&mut tcb.stopped) {
+            tcb.status = TaskStatus::Stopped;
+        } else {
+            self.ready_queue.push_back(task_id);
+        }
+    }
+    /// 唤醒仍在等待 key（WaitQueue 的地址或 futex 的键）的任务，已经因为超时、信号等原因离开的任务不受影响
+    pub fn wake_waiting(&mut self, task_id: TaskId, key: usize) -> bool {
+        let Some(tcb) = self.get_task_mut(task_id) else {
+            return false;
+        };
+        if tcb.waiting_on != Some(key) || tcb.status != TaskStatus::Blocked {
+            return false;
+        }
+        self.end_blocking(task_id);
+        true
+    }
+    /// 向任务发送信号。SIGKILL、SIGSTOP 和 SIGCONT 的继续动作立即生效；
+    /// 其他信号记为未决，未被屏蔽时打断阻塞中的任务，在它回到 U 态前处理
+    pub fn send_signal(&mut self, task_id: TaskId, signal: Signal) -> bool {
+        // idle 任务不接受信号
+        if task_id == 0 {
+            return false;
+        }
+        let Some(tcb) = self
+            .task_list
+            .get_mut(task_id)
+            .and_then(|slot| slot.as_mut())
+        else {
+            return false;
+        };
+        if matches!(tcb.status, TaskStatus::Terminated) {
+            return true;
+        }
+        match signal {
+            Signal::SIGKILL => {
+                redirect_to_exit(tcb, signal);
+                tcb.stopped = false;
+                if matches!(tcb.status, TaskStatus::Blocked | TaskStatus::Stopped) {
+                    tcb.make_ready(get_time());
+                    self.ready_queue.push_back(task_id);
+                }
+                return true;
             }
+            Signal::SIGSTOP => {
+                self.stop_task(task_id);
+                return true;
+            }
+            Signal::SIGCONT => {
+                if matches!(tcb.status, TaskStatus::Stopped) {
+                    tcb.make_ready(get_time());
+                    self.ready_queue.push_back(task_id);
+                }
+                // 仍在阻塞的任务继续等待，之后被唤醒时直接就绪
+                tcb.stopped = false;
+                // 停止信号和 SIGCONT 互相抵消
+                tcb.signals.pending &= !STOP_SIGNALS;
+            }
+            _ if signal.default_action() == DefaultAction::Stop => {
+                tcb.signals.pending &= !Signal::SIGCONT.bit();
+            }
+            _ => {}
+        }
+        if tcb.signals.is_ignored(signal) {
+            return true;
         }
+        tcb.signals.pending |= signal.bit();
+        // 被打断的系统调用按各自的约定重新执行或提前返回
+        if !tcb.signals.is_blocked(signal) && matches!(tcb.status, TaskStatus::Blocked) {
+            let now = get_time();
+            if let Some((deadline, rem)) = tcb.sleep_rem.take() {
+                let left = TimeSpec::from_ns(ticks_to_ns(deadline.saturating_sub(now)));
+                unsafe { (rem as *mut TimeSpec).write(left) };
+            }
+            self.end_blocking(task_id);
+        }
+        true
     }
-    pub fn wake_up_task_with_result(&mut self, task_id: usize, result: u8) {
-        if let Some(tcb) = self.task_list[task_id].as_mut() {
-            if matches!(tcb.status, TaskStatus::Blocked) {
-                tcb.status = TaskStatus::Ready;
-                tcb.context.a0 = result as usize; // 将字符写入任务上下文的 a0
-                self.ready_queue.push_back(task_id);
+    /// 停止任务并通知等待它的任务
+    fn stop_task(&mut self, task_id: TaskId) {
+        let Some(tcb) = self.get_task_mut(task_id) else {
+            return;
+        };
+        match tcb.status {
+            TaskStatus::Ready => {
+                // 在就绪队列中等待的时间照常累计
+                tcb.stats.wait_time += get_time() - tcb.stats.since;
+                tcb.status = TaskStatus::Stopped;
+                self.ready_queue.retain(|&id| id != task_id);
+            }
+            // 运行中的任务在下一次调度时不会被放回就绪队列
+            TaskStatus::Running => tcb.status = TaskStatus::Stopped,
+            // 阻塞中的任务保持 Blocked，否则唤醒它的事件会因为状态不符被丢掉
+            TaskStatus::Blocked if !tcb.stopped => tcb.stopped = true,
+            _ => return,
+        }
+        // 等待它的任务需要知道它停下了
+        self.wake_waiters(task_id);
+    }
+    /// 当前任务执行了非法指令或访问了非法地址。信号被屏蔽或忽略时恢复默认动作，
+    /// 否则重新执行出错的指令只会再次出错。返回是否由用户的处理函数处理
+    pub fn force_signal(&mut self, signal: Signal) -> bool {
+        let Some(tcb) = self.current_tcb() else {
+            return false;
+        };
+        let state = &mut tcb.signals;
+        if state.is_blocked(signal) || state.action(signal).handler == SIG_IGN {
+            state.mask &= !signal.bit();
+            state.set_action(signal, SigAction::DEFAULT);
+        }
+        state.pending |= signal.bit();
+        state.action(signal).handler != SIG_DFL
+    }
+    /// trap 返回前调用：处理即将回到 U 态的任务的未屏蔽未决信号，返回它继续执行的地址。
+    /// 默认动作为停止时会切换到下一个任务并更新 sscratch
+    pub fn deliver_signals(mut sepc: usize) -> usize {
+        // 与记账相同，调度器锁被打断的代码持有时跳过
+        let Some(mut scheduler) = SCHEDULER.try_lock() else {
+            return sepc;
+        };
+        loop {
+            let Some(tcb) = scheduler.current_tcb() else {
+                return sepc;
+            };
+            if tcb.task_id == 0 {
+                return sepc;
+            }
+            let Some(signal) = tcb.signals.take_deliverable() else {
+                return sepc;
+            };
+            let action = tcb.signals.action(signal);
+            match action.handler {
+                SIG_DFL => match signal.default_action() {
+                    DefaultAction::Terminate => {
+                        redirect_to_exit(tcb, signal);
+                        return tcb.context.sepc;
+                    }
+                    DefaultAction::Stop => {
+                        tcb.context.sepc = sepc;
+                        let task_id = tcb.task_id;
+                        scheduler.stop_task(task_id);
+                        let next_ctx = scheduler.prepare_next_task(false);
+                        unsafe {
+                            asm!("csrw sscratch, {}", in(reg) next_ctx);
+                            sepc = (*next_ctx).sepc;
+                        }
+                    }
+                    DefaultAction::Ignore | DefaultAction::Continue => {}
+                },
+                SIG_IGN => {}
+                _ => {
+                    tcb.context.sepc = sepc;
+                    if !push_signal_frame(tcb, signal, action) {
+                        // 栈上放不下信号帧，按 SIGSEGV 的默认动作结束任务
+                        redirect_to_exit(tcb, Signal::SIGSEGV);
+                    }
+                    return tcb.context.sepc;
+                }
             }
         }
     }
+    /// 唤醒所有在等待某个任务结束或停止的任务
+    pub fn wake_waiters(&mut self, task_id: TaskId) {
+        let waiters = match self.get_task_mut(task_id) {
+            Some(tcb) => core::mem::take(&mut tcb.waiters),
+            None => return,
+        };
+        for waiter in waiters {
+            self.set_task_ready(waiter);
+        }
+    }
     pub fn block_current_task(&mut self) -> *mut TaskContext {
         let current_id = self.current_task_id;
 
         if let Some(cur) = current_id {
             if let Some(tcb) = self.task_list[cur].as_mut() {
                 if matches!(tcb.status, TaskStatus::Running) {
+                    tcb.leave_cpu(get_time(), true);
                     tcb.status = TaskStatus::Blocked;
-                    let mut next_id = self.ready_queue.pop_front().unwrap_or(0);
-                    if next_id == 0 && !self.ready_queue.is_empty() {
-                        next_id = self.ready_queue.pop_front().unwrap();
-                        self.ready_queue.push_back(0);
-                    }
-                    let next_tcb = self.task_list[next_id].as_mut().expect("next task missing");
-                    next_tcb.status = TaskStatus::Running;
-                    self.current_task_id = Some(next_id);
-                    return &mut next_tcb.context as *mut TaskContext;
+                    return self.pick_next_task();
                 }
             }
         }
         unreachable!();
     }
-    pub fn finish_sleep(&mut self, current_mtime: usize) {
-        loop {
-            if let Some(Reverse(entry)) = self.blocked_queue.peek() {
-                if entry.wake_time > current_mtime {
-                    break;
-                }
-            } else {
-                break;
-            }
-            // 堆顶任务 wake_time <= current_mtime
-            let Reverse(entry) = self.blocked_queue.pop().unwrap();
-            if let Some(tcb) = self.task_list[entry.task_id].as_mut() {
-                if matches!(tcb.status, TaskStatus::Blocked) {
-                    tcb.status = TaskStatus::Ready;
-                    self.ready_queue.push_back(entry.task_id);
-                }
-            }
-        }
-    }
-    fn prepare_next_task(&mut self) -> *mut TaskContext {
+    /// 切换到下一个任务。voluntary 表示当前任务是主动让出（yield 或退出）而不是被抢占
+    fn prepare_next_task(&mut self, voluntary: bool) -> *mut TaskContext {
         // 回收僵尸任务，但跳过当前任务（如果它刚退出的话）z
         // 因为我们还在当前任务的栈上运行，不能立刻释放它
         let current_id = self.current_task_id;
@@ -270,9 +482,16 @@ impl Scheduler {
 
         if let Some(cur) = current_id {
             if let Some(tcb) = self.task_list[cur].as_mut() {
-                if matches!(tcb.status, TaskStatus::Running) {
-                    tcb.status = TaskStatus::Ready;
-                    self.ready_queue.push_back(cur);
+                let now = get_time();
+                match tcb.status {
+                    TaskStatus::Running => {
+                        tcb.leave_cpu(now, voluntary);
+                        tcb.make_ready(now);
+                        self.ready_queue.push_back(cur);
+                    }
+                    // 运行中被信号停止
+                    TaskStatus::Stopped => tcb.leave_cpu(now, false),
+                    _ => {}
                 }
             }
         }
@@ -283,19 +502,47 @@ impl Scheduler {
         //     next_tcb.status = TaskStatus::Running;
         //     &mut next_tcb.context as *mut TaskContext
         // };
+        // polling_println!("after: {:?}", self.current_task_id);
+        self.pick_next_task()
+        // unsafe {
+        //     __switch_to(next_ctx_ptr);
+        // }
+    }
+    /// 从就绪队列取出下一个任务并标记为运行态，队列里只剩 idle 时才运行 idle
+    fn pick_next_task(&mut self) -> *mut TaskContext {
         let mut next_id = self.ready_queue.pop_front().unwrap_or(0);
         if next_id == 0 && !self.ready_queue.is_empty() {
             next_id = self.ready_queue.pop_front().unwrap();
             self.ready_queue.push_back(0);
         }
         let next_tcb = self.task_list[next_id].as_mut().expect("next task missing");
+        let now = get_time();
+        next_tcb.stats.wait_time += now - next_tcb.stats.since;
+        next_tcb.stats.since = now;
         next_tcb.status = TaskStatus::Running;
         self.current_task_id = Some(next_id);
-        // polling_println!("after: {:?}", self.current_task_id);
         &mut next_tcb.context as *mut TaskContext
-        // unsafe {
-        //     __switch_to(next_ctx_ptr);
-        // }
+    }
+    /// 只剩 idle 任务可以运行
+    pub fn is_idle(&self) -> bool {
+        self.current_task_id == Some(0) && self.ready_queue.iter().all(|&id| id == 0)
+    }
+    /// 按调度结果设置下一次时钟中断：有任务运行时取时间片结束和最早的定时器中较早的一个；
+    /// 只剩 idle 时只在最早的定时器到期时触发，没有定时器就不再产生时钟中断。看门狗只在有任务运行时检查
+    pub fn program_timer(&self) {
+        let idle = self.is_idle();
+        watchdog::set_armed(!idle);
+        let next_wake = timer::next_deadline();
+        let slice_end = if idle && !profile::is_enabled() {
+            None
+        } else {
+            Some(next_tick_time())
+        };
+        let deadline = match (next_wake, slice_end) {
+            (Some(wake), Some(end)) => Some(wake.min(end)),
+            (wake, end) => wake.or(end),
+        };
+        set_timer_deadline(deadline);
     }
     pub fn mark_current_running(&mut self) {
         if let Some(id) = self.current_task_id {
@@ -309,10 +556,11 @@ impl Scheduler {
         // 获取第一个任务的上下文指针
         let next_ctx_ptr = {
             let mut scheduler = SCHEDULER.lock();
-            scheduler.prepare_next_task()
+            scheduler.prepare_next_task(false)
         }; // 锁在这里被释放！
         // polling_println!("here");
         // 在锁释放后执行上下文切换
+        enter_task_context();
         unsafe {
             first_switch_to(next_ctx_ptr);
         }
@@ -321,7 +569,88 @@ impl Scheduler {
     }
     pub fn schedule_on_interrupt() -> *mut TaskContext {
         let mut scheduler = SCHEDULER.lock();
-        scheduler.prepare_next_task()
+        let next_ctx = scheduler.prepare_next_task(false);
+        scheduler.program_timer();
+        next_ctx
+    }
+    /// 当前任务主动让出 CPU（yield 系统调用、任务退出）
+    pub fn yield_current() -> *mut TaskContext {
+        let mut scheduler = SCHEDULER.lock();
+        let next_ctx = scheduler.prepare_next_task(true);
+        // idle 让出给刚被唤醒的任务时时钟可能已经关闭，按新的当前任务重新设置
+        scheduler.program_timer();
+        next_ctx
+    }
+    /// trap 入口调用：从上次记账到现在当前任务都在 U 态运行
+    pub fn account_trap_entry() {
+        Self::account_current(|stats, now| stats.user_time += now - stats.since);
+    }
+    /// trap 返回前调用：此时的当前任务可能已经换成了新调度上来的任务
+    pub fn account_trap_exit() {
+        Self::account_current(|stats, now| stats.system_time += now - stats.since);
+    }
+    fn account_current(f: impl FnOnce(&mut TaskStats, usize)) {
+        // 调度器锁被打断的代码持有时（例如在持锁代码中命中断点）跳过这一次记账
+        let Some(mut scheduler) = SCHEDULER.try_lock() else {
+            return;
+        };
+        if let Some(tcb) = scheduler.current_tcb() {
+            let now = get_time();
+            f(&mut tcb.stats, now);
+            tcb.stats.since = now;
+        }
+    }
+}
+
+/// 测试用：内核测试在调度器启动前运行，借这些方法在调度器中摆出任务运行和阻塞的状态
+#[cfg(test)]
+impl Scheduler {
+    /// 任务离开就绪队列，之后只在 run_for_test 时运行
+    pub fn park_for_test(&mut self, task_id: TaskId) {
+        self.ready_queue.retain(|&id| id != task_id);
+    }
+    /// 让任务成为当前任务，就像它刚被调度上 CPU
+    pub fn run_for_test(&mut self, task_id: TaskId) {
+        self.ready_queue.retain(|&id| id != task_id);
+        if let Some(tcb) = self.get_task_mut(task_id) {
+            tcb.status = TaskStatus::Running;
+            self.current_task_id = Some(task_id);
+        }
+    }
+    /// 当前任务像 finish_file_op 中那样阻塞，之后没有当前任务
+    pub fn block_for_test(&mut self) {
+        if let Some(tcb) = self.current_tcb() {
+            tcb.status = TaskStatus::Blocked;
+        }
+        self.current_task_id = None;
+    }
+    /// 撤下从未运行过的任务，取消它的超时定时器并归还栈和任务闭包
+    pub fn remove_for_test(&mut self, task_id: TaskId) {
+        self.ready_queue.retain(|&id| id != task_id);
+        self.zombie_queue.retain(|&id| id != task_id);
+        if self.current_task_id == Some(task_id) {
+            self.current_task_id = None;
+        }
+        if let Some(tcb) = self.task_list.get_mut(task_id).and_then(Option::take) {
+            free_task(tcb);
+        }
+    }
+}
+
+/// 归还从未运行过的任务的栈和任务闭包
+#[cfg(test)]
+fn free_task(mut tcb: TaskControlBlock) {
+    if let Some(timeout) = tcb.timeout.take() {
+        timeout.cancel();
+    }
+    let stack_pa = PhysAddr(virt_to_phys(tcb.stack_base.as_ptr() as usize));
+    BUDDY_ALLOCATOR.lock().dealloc(
+        PhysPageNum::from(stack_pa),
+        NonZeroUsize::new(tcb.page_count).unwrap(),
+    );
+    unsafe {
+        let task: *mut (dyn FnOnce() + Send) = transmute(tcb.entry_point);
+        drop(Box::from_raw(task));
     }
 }
 
@@ -335,14 +664,26 @@ pub extern "C" fn trampoline(data_ptr: usize, vtable_ptr: usize) -> ! {
         let task = Box::from_raw(raw_fat_ptr);
         task();
     }
-    sys_task_exit();
+    sys_task_exit(EXIT_SUCCESS as usize);
     unreachable!();
     // Scheduler::exit_current_task(); // 通知调度器该任务结束，永不返回
 }
+/// 被信号终止的任务从这里恢复执行，以 redirect_to_exit 设置的退出码主动退出
+extern "C" fn signal_exit(code: usize) -> ! {
+    sys_task_exit(code);
+    unreachable!();
+}
+/// 让任务下次运行时直接进入退出流程，由它自己走 sys_task_exit 回收资源
+fn redirect_to_exit(tcb: &mut TaskControlBlock, signal: Signal) {
+    tcb.context.sepc = signal_exit as *const () as usize;
+    tcb.context.ra = signal_exit as *const () as usize;
+    tcb.context.a0 = (EXIT_SIGNAL_BASE as usize) + signal as usize;
+}
+/// 没有其他任务可运行时在内核中执行 wfi 等待中断，有任务就绪时让出 CPU。
+/// 任务运行在 U 态，不能直接执行 wfi
 fn idle_task() {
     loop {
-        core::hint::spin_loop();
-        // println!("idle!");
+        sys_idle_wait();
     }
 }
 // #[unsafe(naked)]
@@ -350,3 +691,264 @@ fn idle_task() {
 // pub unsafe extern "C" fn idle() {
 //     naked_asm!("idle_loop:", "wfi", "j idle_loop")
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::{MIN_STACK_SIZE, Scheduler, SchedulerError, TaskId, free_task, signal_exit};
+    use crate::{
+        kassert, kassert_eq, kernel_test,
+        mm::BUDDY_ALLOCATOR,
+        syslib::{
+            errno::Errno,
+            syscall::exit_current_task,
+            time::{NSEC_PER_SEC, TimeSpec, ns_to_ticks},
+        },
+        task::{
+            SCHEDULER, join::EXIT_SIGNAL_BASE, signal::Signal, tcb::TaskStatus,
+            test_support::TestTasks, timer, wait_queue::WaitQueue,
+        },
+        trap::interrupts::get_time,
+    };
+
+    fn noop() {}
+
+    fn status(scheduler: &Scheduler, id: TaskId) -> &TaskStatus {
+        &scheduler.get_task(id).unwrap().status
+    }
+
+    /// 测试用的调度器从不运行任务，结束时手动归还栈和任务闭包
+    fn release(mut scheduler: Scheduler) {
+        for tcb in scheduler.task_list.drain(..).flatten() {
+            free_task(tcb);
+        }
+    }
+
+    kernel_test! {
+        fn spawn_reuses_free_slots() {
+            let before = BUDDY_ALLOCATOR.lock().free_blocks_per_order();
+            let mut scheduler = Scheduler::new();
+            for expected in 0..3 {
+                kassert_eq!(scheduler.spawn("noop", noop, 4096, 1).map(|h| h.id()).ok(), Some(expected));
+            }
+            kassert!(scheduler.ready_queue.iter().eq([0, 1, 2].iter()));
+            let tcb = scheduler.task_list[1].take().unwrap();
+            scheduler.ready_queue.retain(|&id| id != 1);
+            let mut single = Scheduler::new();
+            single.task_list.push(Some(tcb));
+            release(single);
+            kassert_eq!(scheduler.spawn("noop", noop, 8192, 1).map(|h| h.id()).ok(), Some(1));
+            kassert_eq!(scheduler.get_task(1).unwrap().page_count, 2);
+            release(scheduler);
+            kassert_eq!(BUDDY_ALLOCATOR.lock().free_blocks_per_order(), before);
+        }
+    }
+
+    kernel_test! {
+        fn spawn_checks_stack_size() {
+            let mut scheduler = Scheduler::new();
+            let too_small = scheduler.spawn("tiny", noop, MIN_STACK_SIZE - 1, 1);
+            kassert!(matches!(too_small, Err(SchedulerError::StackTooSmall)));
+            let handle = scheduler.spawn("worker", || 42usize, MIN_STACK_SIZE + 1, 1).unwrap();
+            let tcb = scheduler.get_task(handle.id()).unwrap();
+            kassert_eq!(tcb.name.as_str(), "worker");
+            kassert_eq!(tcb.page_count, 2);
+            release(scheduler);
+        }
+    }
+
+    kernel_test! {
+        fn stop_and_continue() {
+            let mut scheduler = Scheduler::new();
+            for _ in 0..3 {
+                scheduler.spawn("noop", noop, 4096, 1).ok();
+            }
+            kassert!(!scheduler.send_signal(0, Signal::SIGSTOP), "idle task accepted a signal");
+            kassert!(!scheduler.send_signal(7, Signal::SIGSTOP));
+            kassert!(scheduler.send_signal(1, Signal::SIGSTOP));
+            kassert_eq!(status(&scheduler, 1), &TaskStatus::Stopped);
+            kassert!(!scheduler.ready_queue.contains(&1));
+            kassert!(scheduler.send_signal(1, Signal::SIGCONT));
+            kassert_eq!(status(&scheduler, 1), &TaskStatus::Ready);
+            kassert!(scheduler.ready_queue.iter().eq([0, 2, 1].iter()));
+            release(scheduler);
+        }
+    }
+
+    kernel_test! {
+        fn stopping_a_blocked_task_keeps_its_wakeup() {
+            const KEY: usize = 0x1000;
+            let mut scheduler = Scheduler::new();
+            for _ in 0..3 {
+                scheduler.spawn("noop", noop, 4096, 1).ok();
+            }
+            for id in [1, 2] {
+                scheduler.ready_queue.retain(|&tid| tid != id);
+                let tcb = scheduler.get_task_mut(id).unwrap();
+                tcb.status = TaskStatus::Blocked;
+                tcb.waiting_on = Some(KEY);
+            }
+            scheduler.send_signal(1, Signal::SIGSTOP);
+            let tcb = scheduler.get_task(1).unwrap();
+            kassert_eq!(tcb.status, TaskStatus::Blocked);
+            kassert_eq!(tcb.visible_status(), &TaskStatus::Stopped);
+            // 停止期间的唤醒不丢失，任务停在 Stopped，SIGCONT 后才回到就绪队列
+            kassert!(scheduler.wake_waiting(1, KEY));
+            kassert_eq!(status(&scheduler, 1), &TaskStatus::Stopped);
+            kassert!(!scheduler.ready_queue.contains(&1));
+            scheduler.send_signal(1, Signal::SIGCONT);
+            kassert_eq!(status(&scheduler, 1), &TaskStatus::Ready);
+            kassert!(scheduler.ready_queue.contains(&1));
+            // 还没被唤醒就继续的任务接着等待
+            scheduler.send_signal(2, Signal::SIGSTOP);
+            scheduler.send_signal(2, Signal::SIGCONT);
+            let tcb = scheduler.get_task(2).unwrap();
+            kassert_eq!(tcb.visible_status(), &TaskStatus::Blocked);
+            kassert!(scheduler.wake_waiting(2, KEY));
+            kassert!(scheduler.ready_queue.contains(&2));
+            release(scheduler);
+        }
+    }
+
+    kernel_test! {
+        fn kill_redirects_to_exit() {
+            let mut scheduler = Scheduler::new();
+            for _ in 0..2 {
+                scheduler.spawn("noop", noop, 4096, 1).ok();
+            }
+            scheduler.send_signal(1, Signal::SIGSTOP);
+            kassert!(scheduler.send_signal(1, Signal::SIGKILL));
+            // 被终止的任务回到就绪队列，下次运行时从 signal_exit 开始
+            let tcb = scheduler.get_task(1).unwrap();
+            kassert_eq!(tcb.status, TaskStatus::Ready);
+            kassert_eq!(tcb.context.sepc, signal_exit as *const () as usize);
+            kassert_eq!(tcb.context.a0, EXIT_SIGNAL_BASE as usize + Signal::SIGKILL as usize);
+            kassert!(scheduler.ready_queue.contains(&1));
+            release(scheduler);
+        }
+    }
+
+    kernel_test! {
+        fn pending_signals_interrupt_blocked_tasks() {
+            let mut scheduler = Scheduler::new();
+            for _ in 0..3 {
+                scheduler.spawn("noop", noop, 4096, 1).ok();
+            }
+            for id in [1, 2] {
+                scheduler.ready_queue.retain(|&tid| tid != id);
+                scheduler.get_task_mut(id).unwrap().status = TaskStatus::Blocked;
+            }
+            scheduler.get_task_mut(2).unwrap().signals.set_mask(Signal::SIGUSR1.bit());
+            // 默认忽略的信号直接丢弃
+            kassert!(scheduler.send_signal(1, Signal::SIGCHLD));
+            kassert_eq!(scheduler.get_task(1).unwrap().signals.pending, 0);
+            kassert_eq!(status(&scheduler, 1), &TaskStatus::Blocked);
+            // 未屏蔽的信号唤醒阻塞的任务，屏蔽的只记为未决
+            scheduler.send_signal(1, Signal::SIGUSR1);
+            scheduler.send_signal(2, Signal::SIGUSR1);
+            kassert_eq!(status(&scheduler, 1), &TaskStatus::Ready);
+            kassert!(scheduler.ready_queue.contains(&1));
+            kassert_eq!(status(&scheduler, 2), &TaskStatus::Blocked);
+            let signals = &scheduler.get_task(2).unwrap().signals;
+            kassert_eq!(signals.pending, Signal::SIGUSR1.bit());
+            release(scheduler);
+        }
+    }
+
+    kernel_test! {
+        fn interrupted_nanosleep_reports_time_left() {
+            let mut scheduler = Scheduler::new();
+            for _ in 0..2 {
+                scheduler.spawn("noop", noop, 4096, 1).ok();
+            }
+            scheduler.ready_queue.retain(|&tid| tid != 1);
+            let mut rem = TimeSpec::default();
+            let deadline = get_time() + ns_to_ticks(10 * NSEC_PER_SEC);
+            let tcb = scheduler.get_task_mut(1).unwrap();
+            tcb.status = TaskStatus::Blocked;
+            tcb.sleep_rem = Some((deadline, &mut rem as *mut TimeSpec as usize));
+            scheduler.send_signal(1, Signal::SIGUSR1);
+            kassert_eq!(status(&scheduler, 1), &TaskStatus::Ready);
+            kassert!(scheduler.get_task(1).unwrap().sleep_rem.is_none());
+            kassert!(rem.sec == 9 || rem.sec == 10, "rem {}.{:09}", rem.sec, rem.nsec);
+            release(scheduler);
+        }
+    }
+
+    kernel_test! {
+        fn exit_cancels_pending_timeout() {
+            let queue = WaitQueue::new();
+            let deadline = get_time() + 1000;
+            let tasks = TestTasks::spawn(1);
+            let id = tasks.id(0);
+            tasks.run(0);
+            kassert_eq!(queue.wait_until(deadline), Errno::EAGAIN);
+            exit_current_task(0);
+            kassert!(SCHEDULER.lock().get_task(id).unwrap().timeout.is_none());
+            drop(tasks);
+            // 新任务重用了这个 id，并阻塞在别的事件上
+            let reused = TestTasks::spawn(1);
+            kassert_eq!(reused.id(0), id);
+            reused.run(0);
+            reused.block();
+            // 旧任务的超时到期也不会唤醒它
+            timer::run_expired(deadline);
+            kassert!(reused.status_is(0, TaskStatus::Blocked));
+        }
+    }
+
+    kernel_test! {
+        fn accounts_switches_and_waits() {
+            let mut scheduler = Scheduler::new();
+            for _ in 0..3 {
+                scheduler.spawn("noop", noop, 4096, 1).ok();
+            }
+            // 就绪队列中还有其他任务时不会选中 idle
+            scheduler.prepare_next_task(false);
+            kassert_eq!(scheduler.current_task_id, Some(1));
+            // 时间片用完被抢占
+            scheduler.prepare_next_task(false);
+            kassert_eq!(scheduler.current_task_id, Some(2));
+            let stats = scheduler.get_task(1).unwrap().stats;
+            kassert_eq!((stats.voluntary_switches, stats.involuntary_switches), (0, 1));
+            // 阻塞和 yield 都算主动让出
+            scheduler.block_current_task();
+            kassert_eq!(scheduler.current_task_id, Some(1));
+            let blocked_at = scheduler.get_task(2).unwrap().stats.since;
+            scheduler.prepare_next_task(true);
+            scheduler.set_task_ready(2);
+            let stats = scheduler.get_task(2).unwrap().stats;
+            kassert_eq!((stats.voluntary_switches, stats.involuntary_switches), (1, 0));
+            kassert_eq!(stats.sleep_time, stats.since - blocked_at);
+            let stats = scheduler.get_task(1).unwrap().stats;
+            kassert_eq!((stats.voluntary_switches, stats.involuntary_switches), (1, 1));
+            // 等待时间在被选中时结算
+            let ready_at = scheduler.get_task(2).unwrap().stats.since;
+            let waited = scheduler.get_task(2).unwrap().stats.wait_time;
+            while scheduler.current_task_id != Some(2) {
+                scheduler.prepare_next_task(false);
+            }
+            let stats = scheduler.get_task(2).unwrap().stats;
+            kassert_eq!(stats.wait_time - waited, stats.since - ready_at);
+            release(scheduler);
+        }
+    }
+
+    kernel_test! {
+        fn stopping_wakes_waiters() {
+            let mut scheduler = Scheduler::new();
+            for _ in 0..3 {
+                scheduler.spawn("noop", noop, 4096, 1).ok();
+            }
+            scheduler.ready_queue.retain(|&id| id != 2);
+            scheduler.get_task_mut(2).unwrap().status = TaskStatus::Blocked;
+            scheduler.get_task_mut(1).unwrap().waiters.push(2);
+            scheduler.send_signal(1, Signal::SIGSTOP);
+            kassert_eq!(status(&scheduler, 2), &TaskStatus::Ready);
+            kassert!(scheduler.get_task(1).unwrap().waiters.is_empty());
+            // 已经就绪的任务不会被重复加入队列
+            scheduler.set_task_ready(2);
+            kassert_eq!(scheduler.ready_queue.iter().filter(|&&id| id == 2).count(), 1);
+            release(scheduler);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// src/task/signal.rs
+//! 信号：每个任务有一组未决信号、一个屏蔽字和每个信号的处理方式。
+//! 信号先记为未决，任务回到 U 态之前才真正处理：执行默认动作，或者在用户栈上压入信号帧并转去执行处理函数，
+//! 处理函数返回到 sigreturn 跳板，由 rt_sigreturn 从信号帧恢复被打断的上下文和屏蔽字。
+//! SIGKILL 和 SIGSTOP 不能被捕获、忽略或屏蔽，发送时立即生效。
+
+use crate::{
+    task::{context::TaskContext, tcb::TaskControlBlock},
+    userlib::syscall::sigreturn_trampoline,
+};
+
+/// 支持的信号，编号与 Linux 一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Signal {
+    SIGHUP = 1,
+    SIGINT = 2,
+    SIGQUIT = 3,
+    SIGILL = 4,
+    SIGTRAP = 5,
+    SIGABRT = 6,
+    SIGBUS = 7,
+    SIGFPE = 8,
+    SIGKILL = 9,
+    SIGUSR1 = 10,
+    SIGSEGV = 11,
+    SIGUSR2 = 12,
+    SIGPIPE = 13,
+    SIGALRM = 14,
+    SIGTERM = 15,
+    SIGCHLD = 17,
+    SIGCONT = 18,
+    SIGSTOP = 19,
+    SIGTSTP = 20,
+    SIGTTIN = 21,
+    SIGTTOU = 22,
+    SIGURG = 23,
+    SIGWINCH = 28,
+}
+
+/// 信号的默认处理动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+/// 按编号排列的信号和名字，用于编号和名字的查找
+const SIGNALS: [(Signal, &str); 23] = [
+    (Signal::SIGHUP, "HUP"),
+    (Signal::SIGINT, "INT"),
+    (Signal::SIGQUIT, "QUIT"),
+    (Signal::SIGILL, "ILL"),
+    (Signal::SIGTRAP, "TRAP"),
+    (Signal::SIGABRT, "ABRT"),
+    (Signal::SIGBUS, "BUS"),
+    (Signal::SIGFPE, "FPE"),
+    (Signal::SIGKILL, "KILL"),
+    (Signal::SIGUSR1, "USR1"),
+    (Signal::SIGSEGV, "SEGV"),
+    (Signal::SIGUSR2, "USR2"),
+    (Signal::SIGPIPE, "PIPE"),
+    (Signal::SIGALRM, "ALRM"),
+    (Signal::SIGTERM, "TERM"),
+    (Signal::SIGCHLD, "CHLD"),
+    (Signal::SIGCONT, "CONT"),
+    (Signal::SIGSTOP, "STOP"),
+    (Signal::SIGTSTP, "TSTP"),
+    (Signal::SIGTTIN, "TTIN"),
+    (Signal::SIGTTOU, "TTOU"),
+    (Signal::SIGURG, "URG"),
+    (Signal::SIGWINCH, "WINCH"),
+];
+
+impl Signal {
+    pub fn from_num(num: usize) -> Option<Signal> {
+        SIGNALS
+            .iter()
+            .map(|&(signal, _)| signal)
+            .find(|&signal| signal as usize == num)
+    }
+
+    /// 不带 SIG 前缀的名字，例如 "INT"
+    pub fn from_name(name: &str) -> Option<Signal> {
+        SIGNALS
+            .iter()
+            .find(|&&(_, signal_name)| signal_name == name)
+            .map(|&(signal, _)| signal)
+    }
+
+    pub fn default_action(self) -> DefaultAction {
+        match self {
+            Signal::SIGCHLD | Signal::SIGURG | Signal::SIGWINCH => DefaultAction::Ignore,
+            Signal::SIGSTOP | Signal::SIGTSTP | Signal::SIGTTIN | Signal::SIGTTOU => {
+                DefaultAction::Stop
+            }
+            Signal::SIGCONT => DefaultAction::Continue,
+            _ => DefaultAction::Terminate,
+        }
+    }
+
+    /// 在信号集中对应的位
+    pub const fn bit(self) -> SigSet {
+        1 << (self as usize - 1)
+    }
+
+    /// 不能被捕获、忽略或屏蔽
+    pub fn is_unblockable(self) -> bool {
+        matches!(self, Signal::SIGKILL | Signal::SIGSTOP)
+    }
+}
+
+/// 信号集，第 n 号信号对应第 n - 1 位，与 Linux 的 sigset_t 一致
+pub type SigSet = u64;
+
+const UNBLOCKABLE: SigSet = Signal::SIGKILL.bit() | Signal::SIGSTOP.bit();
+/// 默认动作为停止的信号，收到 SIGCONT 时丢弃
+pub const STOP_SIGNALS: SigSet =
+    Signal::SIGSTOP.bit() | Signal::SIGTSTP.bit() | Signal::SIGTTIN.bit() | Signal::SIGTTOU.bit();
+
+// rt_sigprocmask 的 how 参数
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+// 特殊的处理函数地址
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// 处理函数执行期间不自动屏蔽这个信号本身
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// 处理函数执行一次后恢复默认动作
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// rt_sigaction 的参数，布局与 RISC-V Linux 内核的 struct sigaction 一致。
+/// handler 为 SIG_DFL、SIG_IGN 或 extern "C" fn(signo: usize) 的地址，mask 为处理函数执行期间额外屏蔽的信号
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: SigSet,
+}
+
+impl SigAction {
+    pub const DEFAULT: SigAction = SigAction {
+        handler: SIG_DFL,
+        flags: 0,
+        mask: 0,
+    };
+
+    pub const IGNORE: SigAction = SigAction {
+        handler: SIG_IGN,
+        ..SigAction::DEFAULT
+    };
+
+    pub fn handler(handler: extern "C" fn(usize)) -> SigAction {
+        SigAction {
+            handler: handler as usize,
+            ..SigAction::DEFAULT
+        }
+    }
+}
+
+const SIGNAL_COUNT: usize = 64;
+
+/// 任务的信号状态，保存在 TCB 中
+#[derive(Debug)]
+pub struct SignalState {
+    pub pending: SigSet,
+    pub mask: SigSet,
+    actions: [SigAction; SIGNAL_COUNT],
+}
+
+impl SignalState {
+    pub const fn new() -> Self {
+        Self {
+            pending: 0,
+            mask: 0,
+            actions: [SigAction::DEFAULT; SIGNAL_COUNT],
+        }
+    }
+
+    pub fn action(&self, signal: Signal) -> SigAction {
+        self.actions[signal as usize - 1]
+    }
+
+    /// 改成忽略的信号即使已经未决也会被丢弃
+    pub fn set_action(&mut self, signal: Signal, action: SigAction) {
+        self.actions[signal as usize - 1] = action;
+        if self.is_ignored(signal) {
+            self.pending &= !signal.bit();
+        }
+    }
+
+    pub fn set_mask(&mut self, mask: SigSet) {
+        self.mask = mask & !UNBLOCKABLE;
+    }
+
+    pub fn is_blocked(&self, signal: Signal) -> bool {
+        self.mask & signal.bit() != 0
+    }
+
+    /// 发送时就可以丢弃的信号：显式忽略，或者采用默认动作而默认动作是忽略
+    pub fn is_ignored(&self, signal: Signal) -> bool {
+        match self.action(signal).handler {
+            SIG_IGN => true,
+            SIG_DFL => signal.default_action() == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+
+    /// 取出编号最小的未屏蔽未决信号
+    pub fn take_deliverable(&mut self) -> Option<Signal> {
+        let deliverable = self.pending & !self.mask;
+        if deliverable == 0 {
+            return None;
+        }
+        let bit = deliverable & deliverable.wrapping_neg();
+        self.pending &= !bit;
+        Signal::from_num(bit.trailing_zeros() as usize + 1)
+    }
+}
+
+/// 调用处理函数前压在用户栈上的信号帧，rt_sigreturn 从这里恢复
+#[repr(C)]
+pub struct SignalFrame {
+    /// 被打断时的完整上下文，sepc 为返回后继续执行的位置
+    pub context: TaskContext,
+    /// 进入处理函数前的屏蔽字
+    pub mask: SigSet,
+}
+
+/// 在任务栈上压入信号帧，让任务回到 U 态时以信号编号为参数执行处理函数，返回到 sigreturn 跳板。
+/// 调用者要先把 context.sepc 设为被打断的位置。栈上放不下时返回 false
+pub fn push_signal_frame(tcb: &mut TaskControlBlock, signal: Signal, action: SigAction) -> bool {
+    let size = size_of::<SignalFrame>();
+    let frame_addr = tcb.context.sp.wrapping_sub(size) & !0xf;
+    if !tcb.stack_contains(frame_addr, size) {
+        return false;
+    }
+    let frame = SignalFrame {
+        context: tcb.context,
+        mask: tcb.signals.mask,
+    };
+    unsafe { (frame_addr as *mut SignalFrame).write(frame) };
+    let mut mask = tcb.signals.mask | action.mask;
+    if action.flags & SA_NODEFER == 0 {
+        mask |= signal.bit();
+    }
+    tcb.signals.set_mask(mask);
+    if action.flags & SA_RESETHAND != 0 {
+        tcb.signals.set_action(signal, SigAction::DEFAULT);
+    }
+    let context = &mut tcb.context;
+    context.sp = frame_addr;
+    context.a0 = signal as usize;
+    context.ra = sigreturn_trampoline as *const () as usize;
+    context.sepc = action.handler;
+    true
+}
+
+/// 处理函数返回后 sp 指向信号帧，从中恢复上下文和屏蔽字。sp 不指向任务栈内的信号帧时返回 false
+pub fn pop_signal_frame(tcb: &mut TaskControlBlock) -> bool {
+    let frame_addr = tcb.context.sp;
+    if !frame_addr.is_multiple_of(16) || !tcb.stack_contains(frame_addr, size_of::<SignalFrame>()) {
+        return false;
+    }
+    let frame = unsafe { (frame_addr as *const SignalFrame).read() };
+    tcb.context = frame.context;
+    tcb.signals.set_mask(frame.mask);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SigAction, Signal, SignalState};
+    use crate::{kassert, kassert_eq, kernel_test};
+
+    kernel_test! {
+        fn deliverable_in_signal_order() {
+            let mut state = SignalState::new();
+            state.pending = Signal::SIGTERM.bit() | Signal::SIGUSR1.bit() | Signal::SIGINT.bit();
+            state.set_mask(Signal::SIGINT.bit() | Signal::SIGKILL.bit());
+            // SIGKILL 不能被屏蔽
+            kassert_eq!(state.mask, Signal::SIGINT.bit());
+            kassert_eq!(state.take_deliverable(), Some(Signal::SIGUSR1));
+            kassert_eq!(state.take_deliverable(), Some(Signal::SIGTERM));
+            kassert_eq!(state.take_deliverable(), None);
+            kassert_eq!(state.pending, Signal::SIGINT.bit());
+            // 改成忽略时丢弃已经未决的信号
+            state.set_action(Signal::SIGINT, SigAction::IGNORE);
+            kassert_eq!(state.pending, 0);
+            kassert!(state.is_ignored(Signal::SIGCHLD));
+            kassert!(!state.is_ignored(Signal::SIGTERM));
+        }
+    }
+
+    kernel_test! {
+        fn names_and_numbers() {
+            kassert_eq!(Signal::from_num(10), Some(Signal::SIGUSR1));
+            kassert_eq!(Signal::from_num(16), None);
+            kassert_eq!(Signal::from_num(0), None);
+            kassert_eq!(Signal::from_name("WINCH"), Some(Signal::SIGWINCH));
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// src/task/sync.rs
+//! 内核使用的条件变量，建立在 WaitQueue 上，配合 IrqLock 等锁的守卫使用。
+//! 与 WaitQueue 一样只能在系统调用等 trap 上下文中使用，按重新执行的约定等待：条件还不满足时把当前任务
+//! 挂到队列上并返回 EAGAIN，调用者把它交给 finish_file_op，任务被唤醒后重新执行同一条系统调用再检查。
+//! deadline 是 rdtime 的 tick，为 None 时一直等，到期条件仍不满足时返回 ETIMEDOUT。
+//!
+//! trap 上下文关中断，检查条件和挂到队列之间不会漏掉唤醒。守卫在系统调用返回前释放，
+//! 内核里没有跨越等待持有的锁；U 态任务使用 userlib::sync 中基于 futex 的 Mutex、Semaphore 和 RwLock。
+//!
+//! 唤醒的任务只是重新执行系统调用，可能被别的任务抢先改变了条件，这时它再次等待。
+
+use core::ops::DerefMut;
+
+use crate::{syslib::errno::Errno, task::wait_queue::WaitQueue};
+
+/// 条件变量，notify 可以在中断处理函数中调用
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// condition 成立时放开守卫并等待，返回 EAGAIN；不成立时带着守卫返回
+    pub fn wait_while<G: DerefMut>(
+        &self,
+        mut guard: G,
+        mut condition: impl FnMut(&mut G::Target) -> bool,
+        deadline: Option<usize>,
+    ) -> Result<G, Errno> {
+        self.waiters
+            .wait_event(|| !condition(&mut guard), deadline)?;
+        Ok(guard)
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condvar;
+    use crate::{
+        data_struct::lock::IrqLock,
+        kassert, kassert_eq, kernel_test,
+        syslib::errno::Errno,
+        task::{tcb::TaskStatus, test_support::TestTasks, timer},
+        trap::interrupts::get_time,
+    };
+
+    /// 以当前任务等待 ready 变为 true，返回 wait_while 的结果
+    fn wait_ready(
+        condvar: &Condvar,
+        ready: &IrqLock<bool>,
+        deadline: Option<usize>,
+    ) -> Result<(), Errno> {
+        condvar
+            .wait_while(ready.lock(), |ready| !*ready, deadline)
+            .map(|_| ())
+    }
+
+    kernel_test! {
+        fn notify_wakes_blocked_waiter() {
+            let tasks = TestTasks::spawn(2);
+            let condvar = Condvar::new();
+            let ready = IrqLock::new(false);
+            tasks.run(0);
+            kassert_eq!(wait_ready(&condvar, &ready, None), Err(Errno::EAGAIN));
+            tasks.block();
+            // 另一个任务改变条件并通知
+            tasks.run(1);
+            *ready.lock() = true;
+            condvar.notify_all();
+            kassert!(tasks.status_is(0, TaskStatus::Ready));
+            // 等待者重新执行系统调用，这次条件成立
+            tasks.run(0);
+            kassert_eq!(wait_ready(&condvar, &ready, None), Ok(()));
+        }
+    }
+
+    kernel_test! {
+        fn notify_all_wakes_every_waiter() {
+            let tasks = TestTasks::spawn(3);
+            let condvar = Condvar::new();
+            let ready = IrqLock::new(false);
+            for index in [0, 1] {
+                tasks.run(index);
+                kassert_eq!(wait_ready(&condvar, &ready, None), Err(Errno::EAGAIN));
+                tasks.block();
+            }
+            tasks.run(2);
+            condvar.notify_all();
+            kassert!(tasks.status_is(0, TaskStatus::Ready));
+            kassert!(tasks.status_is(1, TaskStatus::Ready));
+            // 被唤醒时条件仍不成立的等待者再次等待
+            tasks.run(1);
+            kassert_eq!(wait_ready(&condvar, &ready, None), Err(Errno::EAGAIN));
+        }
+    }
+
+    kernel_test! {
+        fn wait_times_out_at_deadline() {
+            let tasks = TestTasks::spawn(1);
+            let condvar = Condvar::new();
+            let ready = IrqLock::new(false);
+            // 截止时间已过，不再等待
+            tasks.run(0);
+            kassert_eq!(wait_ready(&condvar, &ready, Some(get_time())), Err(Errno::ETIMEDOUT));
+            let deadline = get_time() + 1000;
+            kassert_eq!(wait_ready(&condvar, &ready, Some(deadline)), Err(Errno::EAGAIN));
+            tasks.block();
+            // 超时定时器到期，任务醒来重新执行，得到 ETIMEDOUT
+            timer::run_expired(deadline);
+            kassert!(tasks.status_is(0, TaskStatus::Ready));
+            tasks.run(0);
+            kassert_eq!(wait_ready(&condvar, &ready, Some(deadline)), Err(Errno::ETIMEDOUT));
+            // 超时后的通知不会再唤醒它
+            tasks.block();
+            condvar.notify_all();
+            kassert!(tasks.status_is(0, TaskStatus::Blocked));
+        }
+    }
+}
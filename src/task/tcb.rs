@@ -1,24 +1,119 @@
-use core::{alloc::Layout, ptr::NonNull};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{ptr::NonNull, sync::atomic::AtomicU32};
 
-use crate::task::context::TaskContext;
+use crate::{
+    fs::file::FdTable,
+    mm::PAGE_SIZE,
+    task::{context::TaskContext, signal::SignalState, timer::TimerHandle},
+};
 
 #[derive(PartialEq, Debug)]
 pub enum TaskStatus {
     Ready,
     Running,
     Blocked,
+    Stopped,
     Terminated,
 }
 
 #[derive(Debug)]
 pub struct TaskControlBlock {
     pub task_id: usize,
+    pub name: String,
     pub entry_point: (usize, usize),
     pub stack_base: NonNull<u8>,
     pub page_count: usize,
     pub priority: u8,
     pub status: TaskStatus,
     pub context: TaskContext,
+    pub fd_table: FdTable,
+    pub stats: TaskStats,
+    // 等待本任务结束或停止的任务
+    pub waiters: Vec<usize>,
+    // 带超时阻塞时的超时定时器，任务被唤醒时取消
+    pub timeout: Option<TimerHandle>,
+    // 正在等待的 WaitQueue 的地址或 futex 的键，任务被唤醒时清除
+    pub waiting_on: Option<usize>,
+    // 上一次在 WaitQueue 上的等待是否因超时结束
+    pub timed_out: bool,
+    // 未决信号、屏蔽字和各信号的处理方式
+    pub signals: SignalState,
+    // 退出码，与 JoinHandle 共享，任务退出时写入并唤醒等待者
+    pub exit_code: Arc<AtomicU32>,
+    // 最近一次 IPC 接收到的 call 的调用者和它等待回复的键，reply 时取出
+    pub ipc_reply_to: Option<(usize, usize)>,
+    // nanosleep 的截止时间和写回剩余时间的用户指针，被信号提前唤醒时写回，任务被唤醒时清除
+    pub sleep_rem: Option<(usize, usize)>,
+    // 在阻塞中被停止：状态仍是 Blocked，等待的事件照常送达，阻塞结束时转为 Stopped
+    pub stopped: bool,
+}
+
+/// 任务的 CPU 时间和调度统计，时间单位为 rdtime 的 tick
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TaskStats {
+    /// 在 U 态运行的时间
+    pub user_time: usize,
+    /// 在 trap 处理（系统调用、中断、调度）中花费的时间
+    pub system_time: usize,
+    /// 在就绪队列中等待的时间
+    pub wait_time: usize,
+    /// 睡眠或阻塞的时间
+    pub sleep_time: usize,
+    /// 睡眠、阻塞或 yield 主动让出 CPU 的次数
+    pub voluntary_switches: usize,
+    /// 时间片用完或被信号停止而让出 CPU 的次数
+    pub involuntary_switches: usize,
+    /// 运行中为上一次记账的时间点，其他状态为进入该状态的时间点
+    pub since: usize,
+}
+
+impl TaskStats {
+    pub fn cpu_time(&self) -> usize {
+        self.user_time + self.system_time
+    }
+}
+
+impl TaskControlBlock {
+    /// 把任务标记为就绪，从阻塞状态醒来时累计睡眠时间并取消超时定时器。调用者负责放入就绪队列
+    pub fn make_ready(&mut self, now: usize) {
+        if let Some(timeout) = self.timeout.take() {
+            timeout.cancel();
+        }
+        self.waiting_on = None;
+        self.sleep_rem = None;
+        if self.status == TaskStatus::Blocked {
+            self.stats.sleep_time += now - self.stats.since;
+        }
+        self.status = TaskStatus::Ready;
+        self.stats.since = now;
+    }
+
+    /// 对外显示的状态，在阻塞中被停止的任务显示为 Stopped
+    pub fn visible_status(&self) -> &TaskStatus {
+        if self.stopped {
+            &TaskStatus::Stopped
+        } else {
+            &self.status
+        }
+    }
+
+    /// [addr, addr + len) 是否落在任务的栈内
+    pub fn stack_contains(&self, addr: usize, len: usize) -> bool {
+        let base = self.stack_base.as_ptr() as usize;
+        let top = base + self.page_count * PAGE_SIZE;
+        addr >= base && addr.checked_add(len).is_some_and(|end| end <= top)
+    }
+
+    /// 任务离开 CPU：从上次记账到现在都在内核中
+    pub fn leave_cpu(&mut self, now: usize, voluntary: bool) {
+        self.stats.system_time += now - self.stats.since;
+        self.stats.since = now;
+        if voluntary {
+            self.stats.voluntary_switches += 1;
+        } else {
+            self.stats.involuntary_switches += 1;
+        }
+    }
 }
 
 unsafe impl Send for TaskContext {}
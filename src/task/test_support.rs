@@ -0,0 +1,59 @@
+// src/task/test_support.rs
+//! 内核测试用的任务。测试在调度器启动前运行，没有真正的当前任务，WaitQueue、futex 等
+//! 只能在 trap 上下文中使用的接口无法直接调用。这里在全局调度器中创建不会运行的任务，
+//! 轮流把它们设为当前任务来模拟系统调用；阻塞只是改变任务状态，与 finish_file_op 阻塞任务时相同。
+
+use alloc::vec::Vec;
+
+use crate::task::{SCHEDULER, tcb::TaskStatus};
+
+pub struct TestTasks {
+    ids: Vec<usize>,
+}
+
+fn noop() {}
+
+impl TestTasks {
+    pub fn spawn(count: usize) -> Self {
+        let mut scheduler = SCHEDULER.lock();
+        let ids = (0..count)
+            .map(|_| {
+                let id = scheduler.spawn("test", noop, 4096, 1).unwrap().id();
+                // 阻塞时调度器选中的下一个任务总是 idle，不会是别的测试任务
+                scheduler.park_for_test(id);
+                id
+            })
+            .collect();
+        Self { ids }
+    }
+
+    pub fn id(&self, index: usize) -> usize {
+        self.ids[index]
+    }
+
+    /// 第 index 个任务成为当前任务，接下来的调用就像它发起的系统调用
+    pub fn run(&self, index: usize) {
+        SCHEDULER.lock().run_for_test(self.ids[index]);
+    }
+
+    /// 当前任务在系统调用返回 EAGAIN 后阻塞
+    pub fn block(&self) {
+        SCHEDULER.lock().block_for_test();
+    }
+
+    pub fn status_is(&self, index: usize, status: TaskStatus) -> bool {
+        SCHEDULER
+            .lock()
+            .get_task(self.ids[index])
+            .is_some_and(|tcb| tcb.status == status)
+    }
+}
+
+impl Drop for TestTasks {
+    fn drop(&mut self) {
+        let mut scheduler = SCHEDULER.lock();
+        for &id in &self.ids {
+            scheduler.remove_for_test(id);
+        }
+    }
+}
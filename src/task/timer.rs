@@ -0,0 +1,237 @@
+// src/task/timer.rs
+//! 内核定时器。每个 hart 一个按到期时间排序的队列，支持单次和周期定时器，
+//! 到期时在时钟中断中调用回调。添加定时器返回句柄，可以随时取消。
+//!
+//! 回调在 trap 上下文中、关中断且不持有定时器队列锁时执行，可以在回调中添加或取消定时器。
+
+use alloc::{
+    boxed::Box,
+    collections::{binary_heap::BinaryHeap, btree_map::BTreeMap},
+};
+use core::cmp::Reverse;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    bsp::get_hart_id, config::MAX_HARTS, data_struct::lock::IrqLock,
+    trap::interrupts::advance_timer_deadline,
+};
+
+pub type TimerCallback = Box<dyn FnMut() + Send>;
+
+/// 定时器句柄，取消已经到期或已取消的定时器没有效果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle {
+    hart: usize,
+    id: u64,
+}
+
+impl TimerHandle {
+    /// 取消定时器，返回它是否还在等待到期。周期定时器在回调中取消自己也会生效
+    pub fn cancel(self) -> bool {
+        TIMERS
+            .get(self.hart)
+            .is_some_and(|queue| queue.lock().cancel(self.id))
+    }
+}
+
+struct Timer {
+    deadline: usize,
+    /// 周期，None 表示单次定时器
+    period: Option<usize>,
+    /// 回调正在执行时为 None
+    callback: Option<TimerCallback>,
+}
+
+struct TimerQueue {
+    timers: BTreeMap<u64, Timer>,
+    /// 取消的定时器不从堆中删除，出堆时与 timers 中的到期时间不符即丢弃
+    heap: BinaryHeap<Reverse<(usize, u64)>>,
+}
+
+static TIMERS: [IrqLock<TimerQueue>; MAX_HARTS] =
+    [const { IrqLock::new(TimerQueue::new()) }; MAX_HARTS];
+/// 定时器编号不复用，过期的句柄不会取消别的定时器
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+impl TimerQueue {
+    const fn new() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn insert(&mut self, id: u64, deadline: usize, period: Option<usize>, callback: TimerCallback) {
+        self.timers.insert(
+            id,
+            Timer {
+                deadline,
+                period,
+                callback: Some(callback),
+            },
+        );
+        self.heap.push(Reverse((deadline, id)));
+    }
+
+    fn cancel(&mut self, id: u64) -> bool {
+        let Some(timer) = self.timers.remove(&id) else {
+            return false;
+        };
+        // 大量取消后重建堆，避免失效的条目越积越多
+        if self.heap.len() > 2 * self.timers.len() + 16 {
+            self.heap = self
+                .timers
+                .iter()
+                .filter(|(_, timer)| timer.callback.is_some())
+                .map(|(&id, timer)| Reverse((timer.deadline, id)))
+                .collect();
+        }
+        timer.callback.is_some()
+    }
+
+    /// 丢弃堆顶的失效条目，返回最早的有效条目
+    fn peek(&mut self) -> Option<(usize, u64)> {
+        while let Some(&Reverse((deadline, id))) = self.heap.peek() {
+            match self.timers.get(&id) {
+                Some(timer) if timer.deadline == deadline && timer.callback.is_some() => {
+                    return Some((deadline, id));
+                }
+                _ => {
+                    self.heap.pop();
+                }
+            }
+        }
+        None
+    }
+
+    /// 取出一个在 now 之前到期的定时器的回调。单次定时器随之删除，周期定时器留在表中等待 rearm
+    fn pop_expired(&mut self, now: usize) -> Option<(u64, TimerCallback, bool)> {
+        let (_, id) = self.peek().filter(|&(deadline, _)| deadline <= now)?;
+        self.heap.pop();
+        let timer = self.timers.get_mut(&id)?;
+        if timer.period.is_none() {
+            let callback = self.timers.remove(&id)?.callback?;
+            return Some((id, callback, false));
+        }
+        Some((id, timer.callback.take()?, true))
+    }
+
+    /// 周期定时器的回调执行完后放回队列。错过的周期合并成一次，不补发
+    fn rearm(&mut self, id: u64, callback: TimerCallback, now: usize) {
+        // 回调执行期间被取消
+        let Some(timer) = self.timers.get_mut(&id) else {
+            return;
+        };
+        let period = timer.period.unwrap_or(1).max(1);
+        let mut next = timer.deadline + period;
+        if next <= now {
+            next = now + period - (now - timer.deadline) % period;
+        }
+        timer.deadline = next;
+        timer.callback = Some(callback);
+        self.heap.push(Reverse((next, id)));
+    }
+}
+
+fn add(deadline: usize, period: Option<usize>, callback: TimerCallback) -> TimerHandle {
+    let hart = get_hart_id();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    TIMERS[hart].lock().insert(id, deadline, period, callback);
+    // 比已经设置的时钟中断更早到期时提前中断
+    advance_timer_deadline(deadline);
+    TimerHandle { hart, id }
+}
+
+/// 在 deadline（rdtime 的 tick）到期后调用一次 callback
+pub fn add_oneshot(deadline: usize, callback: impl FnMut() + Send + 'static) -> TimerHandle {
+    add(deadline, None, Box::new(callback))
+}
+
+/// 从 first 开始每隔 period 个 tick 调用一次 callback，直到被取消
+pub fn add_periodic(
+    first: usize,
+    period: usize,
+    callback: impl FnMut() + Send + 'static,
+) -> TimerHandle {
+    add(first, Some(period.max(1)), Box::new(callback))
+}
+
+/// 当前 hart 上最早的到期时间
+pub fn next_deadline() -> Option<usize> {
+    TIMERS[get_hart_id()]
+        .lock()
+        .peek()
+        .map(|(deadline, _)| deadline)
+}
+
+/// 时钟中断调用：执行当前 hart 上所有在 now 之前到期的定时器
+pub fn run_expired(now: usize) {
+    let queue = &TIMERS[get_hart_id()];
+    loop {
+        let Some((id, mut callback, periodic)) = queue.lock().pop_expired(now) else {
+            break;
+        };
+        callback();
+        if periodic {
+            queue.lock().rearm(id, callback, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimerQueue;
+    use crate::{kassert, kassert_eq, kernel_test};
+    use alloc::{boxed::Box, sync::Arc, vec::Vec};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    fn expired(queue: &mut TimerQueue, now: usize) -> Vec<u64> {
+        let mut ids = Vec::new();
+        while let Some((id, mut callback, periodic)) = queue.pop_expired(now) {
+            callback();
+            if periodic {
+                queue.rearm(id, callback, now);
+            }
+            ids.push(id);
+        }
+        ids
+    }
+
+    kernel_test! {
+        fn expires_in_order_and_cancels() {
+            let mut queue = TimerQueue::new();
+            queue.insert(1, 30, None, Box::new(|| {}));
+            queue.insert(2, 10, None, Box::new(|| {}));
+            queue.insert(3, 20, None, Box::new(|| {}));
+            kassert!(queue.cancel(3));
+            kassert!(!queue.cancel(3), "cancelled twice");
+            kassert_eq!(queue.peek(), Some((10, 2)));
+            kassert!(expired(&mut queue, 5).is_empty());
+            kassert_eq!(expired(&mut queue, 30), [2, 1]);
+            kassert!(!queue.cancel(1), "cancelled an expired timer");
+            kassert_eq!(queue.peek(), None);
+        }
+    }
+
+    kernel_test! {
+        fn periodic_timers_rearm() {
+            let count = Arc::new(AtomicUsize::new(0));
+            let mut queue = TimerQueue::new();
+            let counter = count.clone();
+            queue.insert(1, 10, Some(10), Box::new(move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }));
+            kassert_eq!(expired(&mut queue, 10), [1]);
+            kassert_eq!(queue.peek(), Some((20, 1)));
+            // 错过的周期只触发一次，下一次对齐到原来的周期上
+            kassert_eq!(expired(&mut queue, 45), [1]);
+            kassert_eq!(queue.peek(), Some((50, 1)));
+            kassert_eq!(count.load(Ordering::Relaxed), 2);
+            // 回调执行期间取消，不再放回队列
+            let (id, callback, _) = queue.pop_expired(50).unwrap();
+            kassert!(!queue.cancel(id));
+            queue.rearm(id, callback, 50);
+            kassert_eq!(queue.peek(), None);
+        }
+    }
+}
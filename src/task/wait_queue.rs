@@ -0,0 +1,122 @@
+// src/task/wait_queue.rs
+//! 等待队列，只能在系统调用等 trap 上下文中使用。条件不满足时 wait 把当前任务挂到队列上并返回
+//! EAGAIN，finish_file_op 随即阻塞任务；被唤醒后任务重新执行同一条系统调用，再次检查条件。
+//!
+//! 任务在 TCB 中记录自己正在等的队列，超时、被信号唤醒或退出后留在队列里的旧条目
+//! 不会在之后误唤醒它，也不会占掉真正等待者的唤醒名额。
+
+use alloc::collections::vec_deque::VecDeque;
+
+use crate::{
+    data_struct::lock::IrqLock, syslib::errno::Errno, task::SCHEDULER, trap::interrupts::get_time,
+};
+
+pub struct WaitQueue {
+    waiters: IrqLock<VecDeque<usize>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: IrqLock::new(VecDeque::new()),
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// 把当前任务挂到队列上，返回让系统调用阻塞并重新执行的 EAGAIN
+    pub fn wait(&self) -> Errno {
+        self.enqueue(None)
+    }
+
+    /// 与 wait 相同，但到 deadline（rdtime 的 tick）时即使没有被唤醒也重新执行系统调用，
+    /// 此时 finish_wait 返回 true
+    pub fn wait_until(&self, deadline: usize) -> Errno {
+        self.enqueue(Some(deadline))
+    }
+
+    fn enqueue(&self, deadline: Option<usize>) -> Errno {
+        let key = self.key();
+        let task_id = {
+            let mut scheduler = SCHEDULER.lock();
+            let task_id = scheduler.get_current_task_id();
+            if let Some(deadline) = deadline {
+                scheduler.arm_current_timeout(deadline, move || {
+                    let mut scheduler = SCHEDULER.lock();
+                    if !scheduler.wake_on_timeout(task_id) {
+                        return;
+                    }
+                    if let Some(tcb) = scheduler.get_task_mut(task_id) {
+                        tcb.timed_out = true;
+                    }
+                });
+            }
+            if let Some(tcb) = scheduler.current_tcb() {
+                tcb.waiting_on = Some(key);
+            }
+            task_id
+        };
+        let mut waiters = self.waiters.lock();
+        if !waiters.contains(&task_id) {
+            waiters.push_back(task_id);
+        }
+        Errno::EAGAIN
+    }
+
+    /// 带超时的等待在重新执行的系统调用开头调用：把当前任务从队列中撤下，返回上一次等待是否超时
+    pub fn finish_wait(&self) -> bool {
+        let (task_id, timed_out) = {
+            let mut scheduler = SCHEDULER.lock();
+            let task_id = scheduler.get_current_task_id();
+            let timed_out = scheduler
+                .current_tcb()
+                .is_some_and(|tcb| core::mem::take(&mut tcb.timed_out));
+            (task_id, timed_out)
+        };
+        self.waiters.lock().retain(|&id| id != task_id);
+        timed_out
+    }
+
+    /// cond 成立时返回 Ok(0)，否则挂起当前任务；到 deadline 仍不成立时返回 ETIMEDOUT
+    pub fn wait_event(
+        &self,
+        mut cond: impl FnMut() -> bool,
+        deadline: Option<usize>,
+    ) -> Result<usize, Errno> {
+        let timed_out = self.finish_wait();
+        if cond() {
+            return Ok(0);
+        }
+        match deadline {
+            None => Err(self.wait()),
+            Some(deadline) if timed_out || deadline <= get_time() => Err(Errno::ETIMEDOUT),
+            Some(deadline) => Err(self.wait_until(deadline)),
+        }
+    }
+
+    /// 按先来先到唤醒最多 count 个仍在等待本队列的任务，返回唤醒的个数
+    pub fn wake(&self, count: usize) -> usize {
+        let key = self.key();
+        let mut waiters = self.waiters.lock();
+        if waiters.is_empty() {
+            return 0;
+        }
+        let mut scheduler = SCHEDULER.lock();
+        let mut woken = 0;
+        while woken < count {
+            let Some(task_id) = waiters.pop_front() else {
+                break;
+            };
+            if scheduler.wake_waiting(task_id, key) {
+                woken += 1;
+            }
+        }
+        woken
+    }
+
+    pub fn wake_all(&self) -> usize {
+        self.wake(usize::MAX)
+    }
+}
@@ -0,0 +1,66 @@
+// src/task/watchdog.rs
+//! 调度看门狗：周期检查就绪任务在就绪队列中等待的时间，
+//! 超过阈值说明调度器没有按时轮转，打印一次警告。
+//! 检查在时钟中断中执行，只能发现调度问题，发现不了关中断导致的死锁。
+//! 只在有任务可以运行时检查，由调度器设置时钟中断时开启或停止。
+
+use crate::{
+    bsp::qemu_virt::RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ,
+    data_struct::lock::IrqLock,
+    task::{
+        SCHEDULER,
+        tcb::TaskStatus,
+        timer::{self, TimerHandle},
+    },
+    trap::interrupts::get_time,
+    warn,
+};
+
+/// 检查间隔，1 秒
+const CHECK_INTERVAL: usize = RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ;
+/// 就绪任务等待超过这个时间就报警，2 秒
+const STARVATION_LIMIT: usize = 2 * RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ;
+
+/// 正在运行的周期检查，只剩 idle 任务时取消
+static WATCHDOG: IrqLock<Option<TimerHandle>> = IrqLock::new(None);
+
+/// 有任务可以运行时开始周期检查，只剩 idle 时停止，空闲的系统不会被看门狗唤醒
+pub fn set_armed(armed: bool) {
+    let mut watchdog = WATCHDOG.lock();
+    if armed == watchdog.is_some() {
+        return;
+    }
+    *watchdog = match watchdog.take() {
+        Some(handle) => {
+            handle.cancel();
+            None
+        }
+        None => Some(timer::add_periodic(
+            get_time() + CHECK_INTERVAL,
+            CHECK_INTERVAL,
+            check,
+        )),
+    };
+}
+
+fn check() {
+    // 被打断的代码持有调度器锁时跳过这一次
+    let Some(scheduler) = SCHEDULER.try_lock() else {
+        return;
+    };
+    let now = get_time();
+    // idle 任务在有其他任务就绪时本来就不会被选中
+    for tcb in scheduler.tasks().filter(|tcb| tcb.task_id != 0) {
+        let waited = now - tcb.stats.since;
+        // 每次饥饿只在刚越过阈值的那次检查中报告
+        if tcb.status == TaskStatus::Ready
+            && (STARVATION_LIMIT..STARVATION_LIMIT + CHECK_INTERVAL).contains(&waited)
+        {
+            warn!(
+                "task {} has been ready for {} ms without running",
+                tcb.task_id,
+                waited / (RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 1000)
+            );
+        }
+    }
+}
@@ -1,7 +1,12 @@
 pub mod service;
+pub mod sstc;
 
+use crate::bsp::get_hart_id;
 use crate::bsp::qemu_virt::RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ;
+use crate::config::MAX_HARTS;
+use crate::debug::profile;
 use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use sbi_rt::set_timer;
 // SIE.STIE 在第 5 位, SIE.SEIE 在第 9 位
 const SIE_STIE_MASK: usize = 1 << 5;
@@ -24,6 +29,31 @@ impl InterruptCause {
     }
 }
 
+/// PLIC 中断源编号的上限
+pub const MAX_IRQ: usize = 128;
+
+/// 各类中断的累计次数，供 /proc/interrupts 使用
+pub struct InterruptStats {
+    pub timer: AtomicUsize,
+    pub external: [AtomicUsize; MAX_IRQ],
+}
+
+pub static INTERRUPT_STATS: InterruptStats = InterruptStats {
+    timer: AtomicUsize::new(0),
+    external: [const { AtomicUsize::new(0) }; MAX_IRQ],
+};
+
+impl InterruptStats {
+    pub fn record_timer(&self) {
+        self.timer.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn record_external(&self, irq: usize) {
+        if let Some(counter) = self.external.get(irq) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
 pub unsafe fn init_supervisor_interrupts() {
     unsafe {
         // 使能 S 模式时钟中断
@@ -95,11 +125,36 @@ pub fn get_time() -> usize {
         current_time
     }
 }
+/// 时间片长度，0.01 即 10 毫秒
+pub const TIME_SLICE_CYCLES: usize = RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 100;
+
+/// 下一个周期性 tick 的时间。开启高频采样时按采样间隔计算
+pub fn next_tick_time() -> usize {
+    get_time() + TIME_SLICE_CYCLES / profile::ticks_per_slice()
+}
+
 pub unsafe fn set_next_timer_tick() {
-    // 0.01 即 10 毫秒
-    const TEN_MS_CYCLES: usize = (RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ / 100) as usize;
-    // const TEN_MS_CYCLES: usize = (RISCV_ACLINT_DEFAULT_TIMEBASE_FREQ) as usize;
-    let current_time = get_time();
-    let target_time = current_time + TEN_MS_CYCLES;
-    let _ = set_timer(target_time as u64);
+    set_timer_deadline(Some(next_tick_time()));
+}
+
+/// 各 hart 当前设置的时钟中断时间，usize::MAX 表示没有设置
+static TIMER_DEADLINE: [AtomicUsize; MAX_HARTS] =
+    [const { AtomicUsize::new(usize::MAX) }; MAX_HARTS];
+
+/// 在 deadline 触发下一次时钟中断，None 表示不再需要时钟中断
+pub fn set_timer_deadline(deadline: Option<usize>) {
+    let deadline = deadline.unwrap_or(usize::MAX);
+    TIMER_DEADLINE[get_hart_id()].store(deadline, Ordering::Relaxed);
+    if sstc::enabled() {
+        sstc::set_stimecmp(deadline as u64);
+    } else {
+        let _ = set_timer(deadline as u64);
+    }
+}
+
+/// 如果 deadline 早于已经设置的时钟中断，提前到 deadline
+pub fn advance_timer_deadline(deadline: usize) {
+    if deadline < TIMER_DEADLINE[get_hart_id()].load(Ordering::Relaxed) {
+        set_timer_deadline(Some(deadline));
+    }
 }
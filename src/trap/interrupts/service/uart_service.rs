@@ -1,72 +1,204 @@
-use crate::bsp::qemu_virt::{ISR, LSR, RHR, THR, UART_BASE};
+use crate::bsp::qemu_virt::{IER, ISR, LSR, RHR, THR, UART_BASE, mmio_va};
+use crate::data_struct::lock::IrqLock;
 use crate::data_struct::ring_buf::RingBuffer;
-use crate::task::SCHEDULER;
-use crate::task::scheduler::Scheduler;
-use crate::{UART, polling_print, polling_println};
+use crate::driver::tty::TTY;
+use crate::syslib::errno::Errno;
 #[cfg(feature = "uart_interrupt")]
-use alloc::collections::vec_deque::VecDeque;
-use core::fmt::Write;
+use crate::task::executor::{self, IrqEvent};
+use crate::task::wait_queue::WaitQueue;
+use crate::warn;
 use core::ptr::{read_volatile, write_volatile};
-use spin::mutex::SpinMutex;
 
 const UART_FIFO_CAPACITY: usize = 16;
+const TRANSMIT_BUFFER_SIZE: usize = 4096;
+/// 中断处理函数收下、还没交给 TTY 的字节，满了之后新到的字节被丢弃
+const RECEIVE_BUFFER_SIZE: usize = 256;
+const IER_TX_EMPTY: u8 = 0x02;
+const LSR_TX_EMPTY: u8 = 1 << 5;
+
+/// UART 寄存器的虚拟地址
+fn uart_reg(offset: usize) -> *mut u8 {
+    (mmio_va(UART_BASE) + offset) as *mut u8
+}
+
 #[cfg(feature = "uart_interrupt")]
 pub static UART_SERVICE: UartService = UartService::new();
 #[cfg(feature = "uart_interrupt")]
 pub struct UartService {
-    pub receive_buffer: SpinMutex<RingBuffer<u8, 4096>>,
-    pub transmit_buffer: SpinMutex<RingBuffer<u8, 4096>>,
-    pub wait_queue: SpinMutex<VecDeque<usize>>,
+    pub transmit_buffer: IrqLock<RingBuffer<u8, TRANSMIT_BUFFER_SIZE>>,
+    // 因发送缓冲区满而阻塞的任务
+    write_waiters: WaitQueue,
+    receive_buffer: IrqLock<RingBuffer<u8, RECEIVE_BUFFER_SIZE>>,
+    // 中断处理函数只做最少的工作，其余由执行器上的 transmit 和 receive 完成
+    tx_ready: IrqEvent,
+    rx_ready: IrqEvent,
 }
 #[cfg(feature = "uart_interrupt")]
 impl UartService {
     const fn new() -> Self {
         UartService {
-            receive_buffer: SpinMutex::<RingBuffer<u8, 4096>>::new(RingBuffer::<u8, 4096>::new()),
-            transmit_buffer: SpinMutex::<RingBuffer<u8, 4096>>::new(RingBuffer::<u8, 4096>::new()),
-            wait_queue: SpinMutex::<VecDeque<usize>>::new(VecDeque::new()),
+            transmit_buffer: IrqLock::new(RingBuffer::new()),
+            write_waiters: WaitQueue::new(),
+            receive_buffer: IrqLock::new(RingBuffer::new()),
+            tx_ready: IrqEvent::new(),
+            rx_ready: IrqEvent::new(),
+        }
+    }
+
+    /// 打开 THR 空中断，发送保持寄存器空闲时硬件会立刻触发一次中断开始发送
+    fn kick(&self) {
+        unsafe {
+            let current_ier = read_volatile(uart_reg(IER));
+            write_volatile(uart_reg(IER), current_ier | IER_TX_EMPTY);
+        }
+    }
+
+    /// 把尽可能多的字节放进发送缓冲区，返回放入的字节数
+    pub fn enqueue(&self, bytes: &[u8]) -> usize {
+        let mut tr = self.transmit_buffer.lock();
+        let mut count = 0;
+        for &byte in bytes {
+            if tr.push(byte).is_err() {
+                break;
+            }
+            count += 1;
+        }
+        drop(tr);
+        if count > 0 {
+            self.kick();
+        }
+        count
+    }
+
+    /// 不能阻塞的写入者（trap 上下文、内核日志）使用：缓冲区满时用轮询方式先把缓冲区发空
+    pub fn write_or_flush(&self, mut bytes: &[u8]) {
+        loop {
+            let count = self.enqueue(bytes);
+            bytes = &bytes[count..];
+            if bytes.is_empty() {
+                break;
+            }
+            self.flush_polling();
         }
     }
-    pub fn send_data(&self) {
-        // polling_println!("send_data");
+
+    /// 用轮询方式发送缓冲区中的全部内容
+    pub fn flush_polling(&self) {
+        let mut tr = self.transmit_buffer.lock();
+        while let Some(byte) = tr.pop() {
+            polling_send(byte);
+        }
+    }
+
+    /// panic 路径使用：缓冲区锁可能正被持有，拿不到锁时放弃
+    pub fn try_flush_polling(&self) {
+        if let Some(mut tr) = self.transmit_buffer.try_lock() {
+            while let Some(byte) = tr.pop() {
+                polling_send(byte);
+            }
+        }
+    }
+
+    /// 把当前任务挂到写等待队列上，由发送中断在腾出空间后唤醒
+    pub fn wait_for_space(&self) -> Errno {
+        self.write_waiters.wait()
+    }
+
+    /// 写满一次硬件 FIFO，缓冲区中还有数据时重新打开 THR 空中断
+    fn send_data(&self) {
         let mut tr = self.transmit_buffer.lock();
-        // polling_print!("get the lock");
-        // let uart = UART.lock();
-        // polling_print!("get the uart lock");
         for _ in 0..UART_FIFO_CAPACITY {
             // 尝试从软件缓冲区取出一个字符
             if let Some(character) = tr.pop() {
-                let thr_ptr = (UART_BASE + THR) as *mut u8;
                 unsafe {
-                    write_volatile(thr_ptr, character);
+                    write_volatile(uart_reg(THR), character);
                 }
-                // uart.write_to_reg(character);
             } else {
                 break;
             }
         }
-        if tr.is_empty() {
-            use crate::bsp::qemu_virt::IER;
+        let remaining = tr.len();
+        drop(tr);
+        if remaining > 0 {
+            self.kick();
+        }
+        // 腾出一半空间后再唤醒写入者，避免每发几个字节就切换一次任务
+        if remaining <= TRANSMIT_BUFFER_SIZE / 2 {
+            self.write_waiters.wake_all();
+        }
+    }
 
-            // polling_print!("empty");
-            // uart.disable_transmit_interrupt();
-            let ier_ptr = (UART_BASE + IER) as *mut u8;
-            unsafe {
-                let current_ier = read_volatile(ier_ptr);
-                write_volatile(ier_ptr, current_ier & !0x02); // 禁用发送中断
+    /// THR 空中断：关掉发送中断，交给 transmit 发送
+    fn transmit_interrupt(&self) {
+        unsafe {
+            let current_ier = read_volatile(uart_reg(IER));
+            write_volatile(uart_reg(IER), current_ier & !IER_TX_EMPTY); // 禁用发送中断
+        }
+        self.tx_ready.signal();
+    }
+
+    /// 接收中断：把 FIFO 读空，交给 receive 处理
+    fn receive_interrupt(&self) {
+        let mut rx = self.receive_buffer.lock();
+        // 缓冲区满时仍然要读出 RHR 清除中断，多出的字节只能丢弃
+        drain_receive_fifo(|byte| {
+            let _ = rx.push(byte);
+        });
+        drop(rx);
+        self.rx_ready.signal();
+    }
+
+    /// 发送服务：每次 THR 空中断后写满一次硬件 FIFO
+    async fn transmit(&self) {
+        loop {
+            self.tx_ready.wait().await;
+            self.send_data();
+        }
+    }
+
+    /// 接收服务：把中断处理函数收下的字节交给 TTY 行规程
+    async fn receive(&self) {
+        loop {
+            self.rx_ready.wait().await;
+            let mut tty = TTY.lock();
+            while let Some(byte) = self.receive_buffer.lock().pop() {
+                tty.receive(byte);
             }
-            // polling_println!("[send_data] Returning...");
         }
     }
 }
+
+/// 把 UART 的发送和接收服务交给执行器
+#[cfg(feature = "uart_interrupt")]
+pub fn start() {
+    executor::spawn(UART_SERVICE.transmit());
+    executor::spawn(UART_SERVICE.receive());
+}
+
+/// 读出接收 FIFO 中的全部字节，读 RHR 同时清除接收中断
+fn drain_receive_fifo(mut sink: impl FnMut(u8)) {
+    unsafe {
+        while (read_volatile(uart_reg(LSR)) & 0x01) != 0 {
+            //TODO：未来可做硬件流控
+            sink(read_volatile(uart_reg(RHR)));
+        }
+    }
+}
+
+/// 等待发送保持寄存器空闲后写入一个字节
+fn polling_send(byte: u8) {
+    unsafe {
+        while (read_volatile(uart_reg(LSR)) & LSR_TX_EMPTY) == 0 {}
+        write_volatile(uart_reg(THR), byte);
+    }
+}
 const ISR_CAUSE_MASK: u8 = 0b0000_1110; // 我们只关心 Bit 1, 2, 3
 const ISR_RX_AVAILABLE: u8 = 0b0000_0100; // RXRDY (接收数据)
 const ISR_TX_EMPTY: u8 = 0b0000_0010; // TXRDY (发送空)
 const ISR_LINE_STATUS: u8 = 0b0000_0110; // LSR (线路状态)
 
 pub fn uart_interrupt_handler() {
-    let isr_ptr = (UART_BASE + ISR) as *mut u8;
-    let isr_val = unsafe { read_volatile(isr_ptr) };
+    let isr_val = unsafe { read_volatile(uart_reg(ISR)) };
 
     // 文档中 Bit 0 的描述: 1 = no interrupt pending
     if (isr_val & 0x01) == 1 {
@@ -78,37 +210,27 @@ pub fn uart_interrupt_handler() {
     match cause {
         ISR_TX_EMPTY => {
             #[cfg(feature = "uart_interrupt")]
-            UART_SERVICE.send_data();
+            UART_SERVICE.transmit_interrupt();
         }
         ISR_RX_AVAILABLE => {
             // 这是接收中断，【必须】读取 RHR 来清除中断
-            let rbr_ptr = (UART_BASE + RHR) as *mut u8;
-            unsafe {
-                let received_char = read_volatile(rbr_ptr);
-                if received_char >= 0x80 {
-                    return;
-                }
-                //TODO：未来可做硬件流控
-
-                // polling_println!("receive:{}", received_char as char);
-                if let Some(task_id) = UART_SERVICE.wait_queue.lock().pop_back() {
-                    SCHEDULER
-                        .lock()
-                        .wake_up_task_with_result(task_id, received_char);
-                } else {
-                    let _ = UART_SERVICE.receive_buffer.lock().push(received_char);
-                }
+            // FIFO 里可能攒了多个字节，全部读出后交给 TTY 行规程
+            #[cfg(feature = "uart_interrupt")]
+            UART_SERVICE.receive_interrupt();
+            #[cfg(not(feature = "uart_interrupt"))]
+            {
+                let mut tty = TTY.lock();
+                drain_receive_fifo(|byte| tty.receive(byte));
             }
         }
         ISR_LINE_STATUS => {
             // 这是线路状态中断，【必须】读取 LSR 来清除中断
-            let lsr_ptr = (UART_BASE + LSR) as *mut u8;
             unsafe {
-                let _ = read_volatile(lsr_ptr);
+                let _ = read_volatile(uart_reg(LSR));
             }
         }
         _ => {
-            polling_println!("cause:{}", cause);
+            warn!("Unexpected UART interrupt cause {:#x}", cause);
         }
     }
 }
@@ -0,0 +1,97 @@
+// src/trap/interrupts/sstc.rs
+//! Sstc 扩展：S 态直接写 stimecmp 设置时钟中断，不用再经过 SBI 陷入 M 态固件。
+//! 设备树声明支持 Sstc 后还要实际访问一次 stimecmp 确认：固件没有打开 menvcfg.STCE 时
+//! 访问会触发非法指令异常，此时继续使用 SBI。
+
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use fdt::{Fdt, standard_nodes::Cpu};
+
+use crate::info;
+
+/// stimecmp 的 CSR 编号
+const CSR_STIMECMP: usize = 0x14d;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// 探测时临时使用的 trap 入口：跳过触发异常的 csrr 指令并把 a0 清零
+global_asm!(
+    ".section .text",
+    ".align 2",
+    "sstc_probe_trap:",
+    "csrr t0, sepc",
+    "addi t0, t0, 4",
+    "csrw sepc, t0",
+    "li a0, 0",
+    "sret",
+);
+
+unsafe extern "C" {
+    fn sstc_probe_trap();
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 在设备树中检查所有 CPU 是否都支持 Sstc，支持时确认 stimecmp 可以访问。
+/// 必须在打开中断、设置 trap 入口之前调用
+pub fn probe(fdt: &Fdt) {
+    let mut cpus = fdt.cpus().peekable();
+    let declared = cpus.peek().is_some() && cpus.all(cpu_has_sstc);
+    if !declared {
+        info!("Timer: Sstc not present, using SBI set_timer");
+        return;
+    }
+    if !stimecmp_accessible() {
+        info!("Timer: Sstc disabled by firmware, using SBI set_timer");
+        return;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("Timer: using Sstc stimecmp");
+}
+
+/// 新设备树用 riscv,isa-extensions 字符串列表，旧的只有 riscv,isa 字符串，多字母扩展以 '_' 分隔
+fn cpu_has_sstc(cpu: Cpu) -> bool {
+    if let Some(extensions) = cpu.property("riscv,isa-extensions") {
+        return extensions
+            .value
+            .split(|&b| b == 0)
+            .any(|name| name.eq_ignore_ascii_case(b"sstc"));
+    }
+    cpu.property("riscv,isa")
+        .and_then(|isa| isa.as_str())
+        .is_some_and(|isa| {
+            isa.split('_')
+                .skip(1)
+                .any(|ext| ext.eq_ignore_ascii_case("sstc"))
+        })
+}
+
+fn stimecmp_accessible() -> bool {
+    let accessible: usize;
+    unsafe {
+        asm!(
+            "csrr {old}, stvec",
+            "csrw stvec, {probe}",
+            "li a0, 1",
+            "csrr {tmp}, {stimecmp}",
+            "csrw stvec, {old}",
+            old = out(reg) _,
+            probe = in(reg) sstc_probe_trap as *const () as usize,
+            tmp = out(reg) _,
+            stimecmp = const CSR_STIMECMP,
+            out("a0") accessible,
+            out("t0") _,
+        );
+    }
+    accessible != 0
+}
+
+/// 在 deadline 触发时钟中断，u64::MAX 表示不再触发
+pub fn set_stimecmp(deadline: u64) {
+    unsafe {
+        asm!("csrw {stimecmp}, {}", in(reg) deadline, stimecmp = const CSR_STIMECMP);
+    }
+}
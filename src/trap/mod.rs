@@ -1,17 +1,25 @@
+use crate::debug::{gdb, profile};
+use crate::driver::entropy::add_interrupt_entropy;
 use crate::driver::plic::{InterruptRequest, PLIC};
+use crate::driver::rtc::rtc_interrupt_handler;
 use crate::syslib::syscall::{
-    exit_current_task, schedule, sleep, system_quit, uart_read, uart_write_byte,
+    clock_gettime, close, exit_current_task, file_read, file_write, futex, getrusage, gettimeofday,
+    idle_wait, ioctl, ipc_call, ipc_close, ipc_create, ipc_recv, ipc_reply, ipc_send, kill,
+    nanosleep, open, pipe, reboot, rt_sigaction, rt_sigprocmask, rt_sigreturn, run_executor,
+    schedule, shm_create, shm_destroy, shm_map, shm_unmap, sleep, spawn, sync, syslog, system_quit,
+    uart_read, uart_write_byte, wait_task,
 };
-use crate::task::SCHEDULER;
 use crate::task::context::TaskContext;
 use crate::task::scheduler::Scheduler;
+use crate::task::{SCHEDULER, signal::Signal, timer};
 use core::arch::{asm, naked_asm};
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use crate::polling_println;
 use crate::trap::interrupts::service::uart_service::uart_interrupt_handler;
 #[cfg(feature = "uart_interrupt")]
 use crate::trap::interrupts::service::uart_service::{UART_SERVICE, UartService};
-use crate::trap::interrupts::{InterruptCause, get_time, set_next_timer_tick};
+use crate::trap::interrupts::{INTERRUPT_STATS, InterruptCause, get_time};
+use crate::{error, warn};
 
 pub mod interrupts;
 
@@ -108,16 +116,35 @@ pub enum TrapCause {
 
 #[derive(Debug)]
 pub enum ExceptionCause {
+    Breakpoint,
     UserEcall,
+    IllegalInstruction,
+    /// 取指、读、写地址未对齐
+    Misaligned,
+    /// 访问权限错误和缺页
+    AccessFault,
     Unknown,
 }
 impl ExceptionCause {
     fn from_code(code: usize) -> ExceptionCause {
         match code {
+            2 => ExceptionCause::IllegalInstruction,
+            3 => ExceptionCause::Breakpoint,
+            0 | 4 | 6 => ExceptionCause::Misaligned,
+            1 | 5 | 7 | 12 | 13 | 15 => ExceptionCause::AccessFault,
             8 => ExceptionCause::UserEcall,
             _ => ExceptionCause::Unknown,
         }
     }
+    /// 任务触发这个异常时收到的信号
+    fn signal(&self) -> Option<Signal> {
+        match self {
+            ExceptionCause::IllegalInstruction => Some(Signal::SIGILL),
+            ExceptionCause::Misaligned => Some(Signal::SIGBUS),
+            ExceptionCause::AccessFault => Some(Signal::SIGSEGV),
+            _ => None,
+        }
+    }
 }
 pub fn parse_trap_cause(scause: usize) -> TrapCause {
     const INTERRUPT_BIT: usize = 1 << (usize::BITS - 1) as usize;
@@ -129,14 +156,24 @@ pub fn parse_trap_cause(scause: usize) -> TrapCause {
         TrapCause::Exception(ExceptionCause::from_code(code))
     }
 }
-fn plic_handler() {
+fn plic_handler(tcb: &mut TaskContext) {
     let irq_num = PLIC::claim();
+    INTERRUPT_STATS.record_external(irq_num as usize);
+    add_interrupt_entropy();
     let irq = InterruptRequest::num_to_irq(irq_num);
 
     match irq {
-        InterruptRequest::UART => uart_interrupt_handler(),
+        InterruptRequest::UART => {
+            // 先让调试桩读走接收的字节，控制台只处理发送中断
+            if gdb::shares_console() {
+                gdb::handle_interrupt(tcb);
+            }
+            uart_interrupt_handler()
+        }
+        InterruptRequest::RTC => rtc_interrupt_handler(),
+        InterruptRequest::GDB => gdb::handle_interrupt(tcb),
         InterruptRequest::UNKNOWN => {
-            polling_println!("irq: {}", irq_num);
+            warn!("Unhandled external interrupt, irq {}", irq_num);
             // println!("Unknown External Interrupt!");
         }
     }
@@ -145,8 +182,43 @@ fn plic_handler() {
     PLIC::complete(InterruptRequest::to_num(&irq));
     // polling_println!("[plic_handler] Returning...");
 }
+/// 是否正在执行任务代码。任务与内核共享地址空间和 panic 处理函数，panic 时据此决定只结束任务还是停机
+static IN_TASK: AtomicBool = AtomicBool::new(false);
+
+pub fn in_task_context() -> bool {
+    IN_TASK.load(Ordering::Relaxed)
+}
+
+/// 调度器第一次切换到任务之前调用，之后由 trap 的进出维护
+pub fn enter_task_context() {
+    IN_TASK.store(true, Ordering::Relaxed);
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn trap_handler(tcb: &mut TaskContext, scause: usize) -> usize {
+    IN_TASK.store(false, Ordering::Relaxed);
+    // 进出 trap 时记账，区分任务的用户态时间和内核态时间
+    Scheduler::account_trap_entry();
+    let sepc = handle_trap(tcb, scause);
+    // 回到 U 态之前处理即将运行的任务的未决信号
+    let sepc = Scheduler::deliver_signals(sepc);
+    Scheduler::account_trap_exit();
+    // sret 按 SPP 返回，内核初始化期间的 trap 仍回到 S 态
+    IN_TASK.store(from_user_mode(), Ordering::Relaxed);
+    sepc
+}
+
+/// trap 是否来自 U 态，即由任务触发；sret 也回到这个特权级
+fn from_user_mode() -> bool {
+    const SSTATUS_SPP: usize = 1 << 8;
+    let sstatus: usize;
+    unsafe {
+        asm!("csrr {}, sstatus", out(reg) sstatus);
+    }
+    sstatus & SSTATUS_SPP == 0
+}
+
+fn handle_trap(tcb: &mut TaskContext, scause: usize) -> usize {
     // polling_println!("Welcome to Interrupt!");
     // polling_println!("scause: {:#x}", scause);
     // let status: usize;
@@ -156,14 +228,17 @@ pub unsafe extern "C" fn trap_handler(tcb: &mut TaskContext, scause: usize) -> u
         TrapCause::Interrupt(InterruptCause::SupervisorTimerInterrupt) => {
             // println!("Welcome to Time Interrupt!");
             // polling_println!("Welcome to Time Interrupt!");
-            let current_time = get_time();
-            {
-                let mut scheduler = SCHEDULER.lock();
-                scheduler.finish_sleep(current_time);
-            }
-            unsafe {
-                set_next_timer_tick();
+            INTERRUPT_STATS.record_timer();
+            add_interrupt_entropy();
+            let sched_tick = profile::on_timer_tick(tcb);
+            // 到期定时器的回调可能唤醒任务，要在调度之前执行
+            timer::run_expired(get_time());
+            // 采样频率高于调度频率时，多出来的 tick 只采样和处理定时器，不调度
+            if !sched_tick {
+                SCHEDULER.lock().program_timer();
+                return tcb.sepc;
             }
+            // 调度后按新的当前任务重新设置定时器
             let next_ctx_ptr = Scheduler::schedule_on_interrupt();
             // 更新 sscratch 指向下一个任务的上下文
             // trap_entry 会恢复这个上下文
@@ -176,11 +251,15 @@ pub unsafe extern "C" fn trap_handler(tcb: &mut TaskContext, scause: usize) -> u
         TrapCause::Interrupt(InterruptCause::SupervisorExternalInterrupt) => {
             // println!("Welcome to External Interrupt!");
             // polling_println!("Welcome to External Interrupt!");
-            plic_handler();
+            plic_handler(tcb);
             // polling_println!("[trap_handler] Returning...");
         }
         TrapCause::Interrupt(InterruptCause::Unknown) => {
-            polling_println!("Unknown interrupt：{}", scause);
+            warn!("Unknown interrupt, scause={:#x}", scause);
+        }
+        TrapCause::Exception(ExceptionCause::Breakpoint) => {
+            // 调试桩可能修改 sepc（跳过 ebreak 或按 GDB 要求跳转）
+            gdb::handle_breakpoint(tcb);
         }
         TrapCause::Exception(ExceptionCause::UserEcall) => {
             let syscall_code = tcb.a7;
@@ -189,25 +268,80 @@ pub unsafe extern "C" fn trap_handler(tcb: &mut TaskContext, scause: usize) -> u
                 1 => return uart_write_byte(tcb),
                 7 => return schedule(tcb),
                 9 => {
-                    exit_current_task();
+                    exit_current_task(tcb.a0);
                     return schedule(tcb);
                 }
                 10 => return system_quit(),
                 17 => return sleep(tcb),
                 27 => return uart_read(tcb),
+                29 => return ioctl(tcb),
+                56 => return open(tcb),
+                57 => return close(tcb),
+                59 => return pipe(tcb),
+                63 => return file_read(tcb),
+                64 => return file_write(tcb),
+                81 => return sync(tcb),
+                98 => return futex(tcb),
+                101 => return nanosleep(tcb),
+                113 => return clock_gettime(tcb),
+                116 => return syslog(tcb),
+                129 => return kill(tcb),
+                134 => return rt_sigaction(tcb),
+                135 => return rt_sigprocmask(tcb),
+                139 => return rt_sigreturn(tcb),
+                165 => return getrusage(tcb),
+                169 => return gettimeofday(tcb),
+                142 => reboot(),
+                220 => return spawn(tcb),
+                260 => return wait_task(tcb),
+                261 => return idle_wait(tcb),
+                264 => return run_executor(tcb),
+                265 => return ipc_create(tcb),
+                266 => return ipc_close(tcb),
+                267 => return ipc_send(tcb),
+                268 => return ipc_recv(tcb),
+                269 => return ipc_call(tcb),
+                270 => return ipc_reply(tcb),
+                271 => return shm_create(tcb),
+                272 => return shm_map(tcb),
+                273 => return shm_unmap(tcb),
+                274 => return shm_destroy(tcb),
                 _ => {}
             }
         }
-        TrapCause::Exception(ExceptionCause::Unknown) => {
+        TrapCause::Exception(
+            cause @ (ExceptionCause::IllegalInstruction
+            | ExceptionCause::Misaligned
+            | ExceptionCause::AccessFault),
+        ) if from_user_mode() => {
+            let stval_value: usize;
+            unsafe {
+                asm!("csrr {}, stval", out(reg) stval_value);
+            }
+            let signal = cause.signal().unwrap();
+            let (task_id, handled) = {
+                let mut scheduler = SCHEDULER.lock();
+                (
+                    scheduler.get_current_task_id(),
+                    scheduler.force_signal(signal),
+                )
+            };
+            // 没有处理函数时任务会被结束，留下一条记录便于排查
+            if !handled {
+                warn!(
+                    "task {} killed by {:?}: stval=0x{:x}, sepc=0x{:x}",
+                    task_id, signal, stval_value, tcb.sepc
+                );
+            }
+        }
+        TrapCause::Exception(_) => {
             let stval_value: usize;
             unsafe {
                 asm!("csrr {}, stval", out(reg) stval_value);
             }
-            polling_println!(
-                "Unknown exception: scause={}, stval=0x{:x}, sepc=0x{:x}",
-                scause,
-                stval_value,
-                tcb.sepc
+            error!(
+                "Unhandled exception: scause={}, stval=0x{:x}, sepc=0x{:x}",
+                scause, stval_value, tcb.sepc
             );
         }
     }
@@ -0,0 +1,60 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::userlib::syscall::{sys_close, sys_open, sys_read_file, sys_write_file};
+
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// 把整个缓冲区写入文件，对端关闭或出错时返回 false
+pub fn write_all(fd: usize, mut buf: &[u8]) -> bool {
+    while !buf.is_empty() {
+        let written = sys_write_file(fd, buf);
+        if written <= 0 {
+            return false;
+        }
+        buf = &buf[written as usize..];
+    }
+    true
+}
+
+/// 读到文件末尾
+pub fn read_to_end(fd: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let count = sys_read_file(fd, &mut buf);
+        if count <= 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..count as usize]);
+    }
+    data
+}
+
+/// 按绝对路径读出整个文件，失败时返回错误码
+pub fn read_file(path: &str) -> Result<Vec<u8>, isize> {
+    let fd = sys_open(path);
+    if fd < 0 {
+        return Err(fd);
+    }
+    let data = read_to_end(fd as usize);
+    sys_close(fd as usize);
+    Ok(data)
+}
+
+pub fn fd_print(fd: usize, args: fmt::Arguments) {
+    struct FdWriter(usize);
+    impl Write for FdWriter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if write_all(self.0, s.as_bytes()) {
+                Ok(())
+            } else {
+                Err(fmt::Error)
+            }
+        }
+    }
+    // 写失败（例如管道读端已关闭）时由调用者自己决定是否退出，这里不 panic
+    let _ = FdWriter(fd).write_fmt(args);
+}
@@ -1 +1,5 @@
+pub mod io;
+pub mod programs;
+pub mod shell;
+pub mod sync;
 pub mod syscall;
@@ -0,0 +1,406 @@
+use alloc::format;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    fd_println,
+    mm::shm::{PROT_READ, PROT_WRITE},
+    task::{
+        ipc::{GRANT_WORD, GRANTED, IPC_GRANT, MESSAGE_WORDS, Message},
+        signal::{SIG_BLOCK, SIG_SETMASK, SigAction, Signal},
+    },
+    userlib::{
+        io::{STDERR, STDIN, STDOUT, read_file, write_all},
+        sync::{Condvar, Mutex},
+        syscall::{
+            sys_ipc_call, sys_ipc_close, sys_ipc_create, sys_ipc_recv, sys_ipc_reply, sys_ipc_send,
+            sys_read_file, sys_shm_create, sys_shm_destroy, sys_shm_map, sys_shm_unmap,
+            sys_sigaction, sys_sigprocmask, sys_sleep, sys_spawn, sys_wait_task,
+        },
+    },
+};
+
+/// 内置程序的入口，参数为以空白分隔的参数字符串。
+/// 程序从 fd 0 读输入、向 fd 1 写输出，由启动者决定它们连到终端还是管道
+pub type Program = fn(&str);
+
+pub static PROGRAMS: [(&str, Program); 9] = [
+    ("echo", echo),
+    ("cat", cat),
+    ("wc", wc),
+    ("upper", upper),
+    ("yes", yes),
+    ("pingpong", pingpong),
+    ("catch", catch),
+    ("ipc", ipc),
+    ("shm", shm),
+];
+
+pub fn find_program(name: &str) -> Option<Program> {
+    PROGRAMS
+        .iter()
+        .find(|(program, _)| *program == name)
+        .map(|(_, entry)| *entry)
+}
+
+/// 逐块处理标准输入，回调返回 false 时停止
+fn for_each_input_chunk(mut f: impl FnMut(&[u8]) -> bool) {
+    let mut buf = [0u8; 256];
+    loop {
+        let count = sys_read_file(STDIN, &mut buf);
+        if count <= 0 || !f(&buf[..count as usize]) {
+            break;
+        }
+    }
+}
+
+fn echo(args: &str) {
+    fd_println!(STDOUT, "{}", args);
+}
+
+/// 没有参数时把标准输入复制到标准输出
+fn cat(args: &str) {
+    if args.is_empty() {
+        for_each_input_chunk(|chunk| write_all(STDOUT, chunk));
+        return;
+    }
+    for path in args.split_whitespace() {
+        match read_file(path) {
+            Ok(data) => {
+                if !write_all(STDOUT, &data) {
+                    return;
+                }
+            }
+            Err(err) => fd_println!(STDERR, "cat: {}: error {}", path, err),
+        }
+    }
+}
+
+fn wc(_args: &str) {
+    let (mut lines, mut words, mut bytes) = (0, 0, 0);
+    let mut in_word = false;
+    for_each_input_chunk(|chunk| {
+        for &byte in chunk {
+            bytes += 1;
+            if byte == b'\n' {
+                lines += 1;
+            }
+            if byte.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                words += 1;
+            }
+        }
+        true
+    });
+    fd_println!(STDOUT, "{}\t{}\t{}", lines, words, bytes);
+}
+
+fn upper(_args: &str) {
+    for_each_input_chunk(|chunk| {
+        let mut buf = [0u8; 256];
+        let out = &mut buf[..chunk.len()];
+        out.copy_from_slice(chunk);
+        out.make_ascii_uppercase();
+        write_all(STDOUT, out)
+    });
+}
+
+/// 不断输出同一行，直到输出端被关闭或任务被信号终止
+fn yes(args: &str) {
+    let text = if args.is_empty() { "y" } else { args };
+    loop {
+        if !write_all(STDOUT, text.as_bytes()) || !write_all(STDOUT, b"\n") {
+            break;
+        }
+    }
+}
+
+/// pingpong 的两个任务共享的状态
+struct Rally {
+    /// 还剩几个来回
+    rounds: usize,
+    pong_turn: bool,
+}
+
+static RALLY: Mutex<Rally> = Mutex::new(Rally {
+    rounds: 0,
+    pong_turn: false,
+});
+static BALL: Condvar = Condvar::new();
+
+/// 两个任务轮流打印 ping 和 pong。本程序以 --pong 参数启动对端，
+/// 两者通过静态变量共享状态，所以同一时间只能运行一组
+fn pingpong(args: &str) {
+    if args == "--pong" {
+        pong();
+        return;
+    }
+    let rounds = match args {
+        "" => 3,
+        n => match n.parse() {
+            Ok(n) => n,
+            Err(_) => {
+                fd_println!(STDERR, "usage: pingpong [rounds]");
+                return;
+            }
+        },
+    };
+    *RALLY.lock() = Rally {
+        rounds,
+        pong_turn: false,
+    };
+    let partner = sys_spawn("pingpong", "--pong", STDIN, STDOUT);
+    if partner < 0 {
+        fd_println!(STDERR, "pingpong: cannot start partner, error {}", partner);
+        return;
+    }
+    let mut rally = RALLY.lock();
+    while rally.rounds > 0 {
+        if rally.pong_turn {
+            rally = BALL.wait(rally);
+            continue;
+        }
+        fd_println!(STDOUT, "ping");
+        rally.pong_turn = true;
+        BALL.notify_one();
+    }
+    drop(rally);
+    sys_wait_task(partner as usize);
+}
+
+/// 对端。发起方被 Ctrl-C 结束后不会再有人击球，等太久就自己退出
+fn pong() {
+    let mut rally = RALLY.lock();
+    while rally.rounds > 0 {
+        if !rally.pong_turn {
+            let (guard, timed_out) = BALL.wait_timeout(rally, 1000);
+            rally = guard;
+            if timed_out {
+                fd_println!(STDERR, "pingpong: partner is not responding");
+                rally.rounds = 0;
+                BALL.notify_all();
+            }
+            continue;
+        }
+        fd_println!(STDOUT, "pong");
+        rally.rounds -= 1;
+        rally.pong_turn = false;
+        BALL.notify_one();
+    }
+}
+
+/// catch 的信号处理函数收到的 SIGINT 个数
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_interrupt(_signo: usize) {
+    INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 捕获 Ctrl-C，收到 count 次（默认 3 次）后退出，期间忽略 Ctrl-Z。
+/// 打印时屏蔽 SIGINT，这时按下的 Ctrl-C 留到打印完再处理
+fn catch(args: &str) {
+    let count = match args {
+        "" => 3,
+        n => match n.parse() {
+            Ok(n) => n,
+            Err(_) => {
+                fd_println!(STDERR, "usage: catch [count]");
+                return;
+            }
+        },
+    };
+    INTERRUPTS.store(0, Ordering::Relaxed);
+    let handler = SigAction::handler(on_interrupt);
+    sys_sigaction(Signal::SIGINT as usize, Some(&handler), None);
+    sys_sigaction(Signal::SIGTSTP as usize, Some(&SigAction::IGNORE), None);
+    fd_println!(STDOUT, "press Ctrl-C {} times to quit", count);
+    let mut seen = 0;
+    while seen < count {
+        // 信号会打断睡眠
+        sys_sleep(1000);
+        let caught = INTERRUPTS.load(Ordering::Relaxed);
+        if caught == seen {
+            continue;
+        }
+        let mut old_mask = 0;
+        sys_sigprocmask(SIG_BLOCK, Some(&Signal::SIGINT.bit()), Some(&mut old_mask));
+        fd_println!(STDOUT, "caught SIGINT ({}/{})", caught.min(count), count);
+        sys_sigprocmask(SIG_SETMASK, Some(&old_mask), None);
+        seen = caught;
+    }
+}
+
+/// ipc 的请求方发出的最后一条消息，服务端收到后退出
+const IPC_QUIT: usize = usize::MAX;
+/// 请求服务端把移交过来的页中的文本改成大写，消息的 a1 是文本长度
+const IPC_UPPER: usize = usize::MAX - 1;
+
+/// 通过 IPC 端点请求服务端计算平方。本程序以 --server <句柄> 参数启动服务端，
+/// 请求方用 call 逐个发送数字并等待回复，再把一页文本随 call 移交给服务端改成大写，
+/// 服务端在回复时把页移交回来，最后用 send 通知服务端退出。
+/// 请求方被 Ctrl-C 结束后服务端会一直阻塞在 recv 上，需要用 kill 结束
+fn ipc(args: &str) {
+    if let Some(handle) = args.strip_prefix("--server ") {
+        match handle.parse() {
+            Ok(endpoint) => square_server(endpoint),
+            Err(_) => fd_println!(STDERR, "ipc: bad endpoint {}", handle),
+        }
+        return;
+    }
+    let count = match args {
+        "" => 3,
+        n => match n.parse() {
+            Ok(n) => n,
+            Err(_) => {
+                fd_println!(STDERR, "usage: ipc [count]");
+                return;
+            }
+        },
+    };
+    let endpoint = sys_ipc_create() as usize;
+    let server = sys_spawn("ipc", &format!("--server {}", endpoint), STDIN, STDOUT);
+    if server < 0 {
+        fd_println!(STDERR, "ipc: cannot start server, error {}", server);
+        sys_ipc_close(endpoint);
+        return;
+    }
+    for n in 1..=count {
+        let mut message: Message = [n, 0, 0, 0, 0, 0];
+        let ret = sys_ipc_call(endpoint, &mut message);
+        if ret < 0 {
+            fd_println!(STDERR, "ipc: call failed, error {}", ret);
+            break;
+        }
+        fd_println!(STDOUT, "{} squared is {}", n, message[0]);
+    }
+    upper_via_pages(endpoint, "moved by page transfer");
+    sys_ipc_send(endpoint, &[IPC_QUIT, 0, 0, 0, 0, 0]);
+    sys_wait_task(server as usize);
+    sys_ipc_close(endpoint);
+}
+
+/// 把 text 写进一页共享内存，随 call 移交给服务端，打印移交回来的结果
+fn upper_via_pages(endpoint: usize, text: &str) {
+    let handle = sys_shm_create(SHM_SIZE);
+    if handle < 0 {
+        fd_println!(STDERR, "ipc: cannot create region, error {}", handle);
+        return;
+    }
+    let addr = sys_shm_map(handle as usize, PROT_READ | PROT_WRITE);
+    // 映射移交之后区域不再需要新的映射，最后一个映射撤销时页被释放
+    sys_shm_destroy(handle as usize);
+    if addr < 0 {
+        fd_println!(STDERR, "ipc: cannot map region, error {}", addr);
+        return;
+    }
+    let len = text.len().min(SHM_SIZE);
+    unsafe { core::ptr::copy_nonoverlapping(text.as_ptr(), addr as *mut u8, len) };
+    let mut message: Message = [IPC_UPPER, len, 0, 0, 0, addr as usize];
+    let ret = sys_ipc_call(endpoint | IPC_GRANT, &mut message);
+    if ret < 0 {
+        fd_println!(STDERR, "ipc: page transfer failed, error {}", ret);
+        sys_shm_unmap(addr as usize);
+        return;
+    }
+    if ret != GRANTED as isize {
+        fd_println!(STDERR, "ipc: server kept the page");
+        return;
+    }
+    let moved = message[GRANT_WORD];
+    let data = unsafe { core::slice::from_raw_parts(moved as *const u8, len) };
+    fd_println!(
+        STDOUT,
+        "page {:#x} came back as {:#x}: {}",
+        addr,
+        moved,
+        core::str::from_utf8(data).unwrap_or("<invalid utf-8>")
+    );
+    sys_shm_unmap(moved);
+}
+
+fn square_server(endpoint: usize) {
+    let mut message = [0; MESSAGE_WORDS];
+    let mut granted = false;
+    while sys_ipc_recv(endpoint, &mut message, &mut granted) >= 0 && message[0] != IPC_QUIT {
+        if message[0] != IPC_UPPER {
+            message[0] = message[0].wrapping_mul(message[0]);
+            sys_ipc_reply(&message, 0);
+            continue;
+        }
+        if !granted {
+            sys_ipc_reply(&message, 0);
+            continue;
+        }
+        let len = message[1].min(SHM_SIZE);
+        let data = unsafe { core::slice::from_raw_parts_mut(message[GRANT_WORD] as *mut u8, len) };
+        data.make_ascii_uppercase();
+        sys_ipc_reply(&message, IPC_GRANT);
+    }
+}
+
+/// shm 程序使用的区域大小。区域开头一个字是消息长度，后面是消息内容
+const SHM_SIZE: usize = 4096;
+
+/// 通过共享内存把参数字符串交给另一个任务。本程序以 --reader <句柄> 参数启动读者，
+/// 写者以读写权限映射区域并写入消息，读者以只读权限映射同一区域后打印出来。
+/// 读者结束后写者撤销映射并销毁区域，区域的页被释放
+fn shm(args: &str) {
+    if let Some(handle) = args.strip_prefix("--reader ") {
+        match handle.parse() {
+            Ok(handle) => shm_reader(handle),
+            Err(_) => fd_println!(STDERR, "shm: bad region {}", handle),
+        }
+        return;
+    }
+    let text = if args.is_empty() {
+        "hello from shared memory"
+    } else {
+        args
+    };
+    let bytes = &text.as_bytes()[..text.len().min(SHM_SIZE - size_of::<usize>())];
+    let handle = sys_shm_create(SHM_SIZE);
+    if handle < 0 {
+        fd_println!(STDERR, "shm: cannot create region, error {}", handle);
+        return;
+    }
+    let addr = sys_shm_map(handle as usize, PROT_READ | PROT_WRITE);
+    if addr < 0 {
+        fd_println!(STDERR, "shm: cannot map region, error {}", addr);
+        return;
+    }
+    let addr = addr as usize;
+    unsafe {
+        (addr as *mut usize).write(bytes.len());
+        let data = (addr + size_of::<usize>()) as *mut u8;
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), data, bytes.len());
+    }
+    let reader = sys_spawn("shm", &format!("--reader {}", handle), STDIN, STDOUT);
+    if reader < 0 {
+        fd_println!(STDERR, "shm: cannot start reader, error {}", reader);
+    } else {
+        sys_wait_task(reader as usize);
+    }
+    sys_shm_unmap(addr);
+    sys_shm_destroy(handle as usize);
+}
+
+fn shm_reader(handle: usize) {
+    let addr = sys_shm_map(handle, PROT_READ);
+    if addr < 0 {
+        fd_println!(STDERR, "shm: cannot map region, error {}", addr);
+        return;
+    }
+    let addr = addr as usize;
+    let data = unsafe {
+        let len = (addr as *const usize).read();
+        core::slice::from_raw_parts((addr + size_of::<usize>()) as *const u8, len)
+    };
+    fd_println!(
+        STDOUT,
+        "reader mapped {:#x}: {}",
+        addr,
+        core::str::from_utf8(data).unwrap_or("<invalid utf-8>")
+    );
+    sys_shm_unmap(addr);
+}
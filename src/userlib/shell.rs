@@ -0,0 +1,583 @@
+use alloc::{
+    collections::vec_deque::VecDeque,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    console::log::{
+        SYSLOG_ACTION_CLEAR, SYSLOG_ACTION_CONSOLE_LEVEL, SYSLOG_ACTION_READ_ALL,
+        SYSLOG_ACTION_SIZE_BUFFER,
+    },
+    debug::profile::{MAX_PROFILE_HZ, PROFILE_START, PROFILE_STOP, SCHED_HZ},
+    driver::rtc::RTC_ALARM_SET,
+    driver::tty::{TCSETS, TIOCSPGRP, TtyMode, display_width},
+    fd_print, fd_println,
+    syslib::{
+        syscall::{RUSAGE_SELF, Rusage, TimeVal},
+        time::{CLOCK_REALTIME, DateTime, TimeSpec},
+    },
+    task::signal::Signal,
+    userlib::{
+        io::{STDERR, STDIN, STDOUT, read_file, write_all},
+        programs::{PROGRAMS, find_program},
+        syscall::{
+            sys_clock_gettime, sys_close, sys_getrusage, sys_gettimeofday, sys_ioctl, sys_kill,
+            sys_nanosleep, sys_open, sys_pipe, sys_read, sys_read_file, sys_reboot, sys_shutdown,
+            sys_spawn, sys_syslog, sys_wait_task,
+        },
+    },
+};
+
+const PROMPT: &str = "charlotte> ";
+const MAX_HISTORY: usize = 32;
+const MAX_LINE: usize = 256;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const CTRL_L: u8 = 0x0C;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+
+const BUILTINS: [(&str, &str); 20] = [
+    ("help", "show this message"),
+    ("ps", "list tasks"),
+    ("times", "show CPU time used by the shell"),
+    ("kill", "kill [-SIGNAL] <id>..."),
+    ("fg", "fg <id>, continue a stopped task"),
+    ("meminfo", "show /proc/meminfo"),
+    ("free", "show memory usage summary"),
+    ("sleep", "sleep <ms>"),
+    ("uptime", "show time since boot"),
+    ("date", "show the current UTC time"),
+    ("alarm", "alarm <secs>, wait for an RTC alarm"),
+    ("ls", "ls [path]"),
+    ("history", "show command history"),
+    ("dmesg", "dmesg [-c | -C | -n <level>], show kernel log"),
+    ("profile", "profile start [hz] | stop | dump, sample stacks"),
+    ("clear", "clear the screen"),
+    ("sync", "write dirty buffers back"),
+    ("reboot", "reboot the machine"),
+    ("shutdown", "power off the machine"),
+    ("programs", "list programs that can be launched"),
+];
+
+/// 方向键转义序列的解析状态
+#[derive(Clone, Copy)]
+enum EscapeState {
+    None,
+    Esc,
+    Csi,
+}
+
+struct Shell {
+    line: String,
+    // 还没凑成完整 UTF-8 字符的字节
+    pending: Vec<u8>,
+    history: VecDeque<String>,
+    // 正在浏览的历史位置，None 表示在编辑新行
+    history_pos: Option<usize>,
+    escape: EscapeState,
+}
+
+/// shell 任务入口。把 /dev/ttyS0 打开为 0/1/2，之后读写都走文件描述符
+pub fn shell_main() {
+    for _ in 0..3 {
+        if sys_open("/dev/ttyS0") < 0 {
+            crate::user_println!("shell: cannot open /dev/ttyS0");
+            return;
+        }
+    }
+    fd_println!(STDOUT, "Charlotte OS shell, type `help` for commands");
+    let mut shell = Shell::new();
+    loop {
+        let line = shell.read_line();
+        shell.execute(&line);
+    }
+}
+
+impl Shell {
+    fn new() -> Self {
+        Self {
+            line: String::new(),
+            pending: Vec::new(),
+            history: VecDeque::new(),
+            history_pos: None,
+            escape: EscapeState::None,
+        }
+    }
+
+    /// 在原始模式下逐字节读取并自行处理行编辑，回车后返回整行
+    fn read_line(&mut self) -> String {
+        // shell 自己回显，Ctrl-C 也作为普通字节交给 shell 处理
+        sys_ioctl(STDIN, TCSETS, TtyMode::empty().bits());
+        sys_ioctl(STDIN, TIOCSPGRP, usize::MAX);
+        self.line.clear();
+        self.pending.clear();
+        self.history_pos = None;
+        self.escape = EscapeState::None;
+        fd_print!(STDOUT, "{}", PROMPT);
+        loop {
+            let ret = sys_read(usize::MAX);
+            if ret < 0 {
+                continue;
+            }
+            let byte = ret as u8;
+            match self.escape {
+                EscapeState::Esc => {
+                    self.escape = if byte == b'[' {
+                        EscapeState::Csi
+                    } else {
+                        EscapeState::None
+                    };
+                    continue;
+                }
+                EscapeState::Csi => {
+                    self.escape = EscapeState::None;
+                    match byte {
+                        b'A' => self.history_prev(),
+                        b'B' => self.history_next(),
+                        _ => {}
+                    }
+                    continue;
+                }
+                EscapeState::None => {}
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    fd_print!(STDOUT, "\n");
+                    return core::mem::take(&mut self.line);
+                }
+                ESC => self.escape = EscapeState::Esc,
+                BACKSPACE | DELETE => self.erase_char(),
+                CTRL_U => {
+                    while !self.line.is_empty() {
+                        self.erase_char();
+                    }
+                }
+                CTRL_C => {
+                    fd_print!(STDOUT, "^C\n{}", PROMPT);
+                    self.line.clear();
+                    self.pending.clear();
+                    self.history_pos = None;
+                }
+                CTRL_L => {
+                    fd_print!(STDOUT, "\x1b[2J\x1b[H{}{}", PROMPT, self.line);
+                }
+                // 空行上的 Ctrl-D 不退出，shell 是系统唯一的交互入口
+                CTRL_D => {}
+                byte if byte < 0x20 => {}
+                byte => self.insert_byte(byte),
+            }
+        }
+    }
+
+    fn insert_byte(&mut self, byte: u8) {
+        if self.line.len() + self.pending.len() >= MAX_LINE {
+            return;
+        }
+        self.pending.push(byte);
+        match core::str::from_utf8(&self.pending) {
+            Ok(s) => {
+                write_all(STDOUT, s.as_bytes());
+                self.line.push_str(s);
+                self.pending.clear();
+            }
+            // 多字节字符还没收全
+            Err(err) if err.error_len().is_none() => {}
+            Err(_) => self.pending.clear(),
+        }
+    }
+
+    fn erase_char(&mut self) {
+        if let Some(c) = self.line.pop() {
+            for _ in 0..display_width(c) {
+                fd_print!(STDOUT, "\x08 \x08");
+            }
+        }
+    }
+
+    /// 把当前行整体替换成另一段文字
+    fn replace_line(&mut self, text: String) {
+        fd_print!(STDOUT, "\r\x1b[K{}{}", PROMPT, text);
+        self.line = text;
+    }
+
+    fn history_prev(&mut self) {
+        if self.history.is_empty() {
+            return;
+        }
+        let pos = match self.history_pos {
+            None => self.history.len() - 1,
+            Some(0) => return,
+            Some(pos) => pos - 1,
+        };
+        self.history_pos = Some(pos);
+        self.replace_line(self.history[pos].clone());
+    }
+
+    fn history_next(&mut self) {
+        let Some(pos) = self.history_pos else {
+            return;
+        };
+        if pos + 1 < self.history.len() {
+            self.history_pos = Some(pos + 1);
+            self.replace_line(self.history[pos + 1].clone());
+        } else {
+            self.history_pos = None;
+            self.replace_line(String::new());
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if self.history.back().is_some_and(|last| last == line) {
+            return;
+        }
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(line.to_string());
+    }
+
+    fn execute(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        self.add_history(line);
+
+        let stages: Vec<Vec<&str>> = line
+            .split('|')
+            .map(|stage| stage.split_whitespace().collect())
+            .collect();
+        if stages.iter().any(|stage| stage.is_empty()) {
+            fd_println!(STDERR, "syntax error near `|`");
+            return;
+        }
+        if stages.len() == 1 && self.run_builtin(&stages[0]) {
+            return;
+        }
+        for stage in &stages {
+            if find_program(stage[0]).is_none() {
+                if BUILTINS.iter().any(|(name, _)| *name == stage[0]) {
+                    fd_println!(STDERR, "{}: builtin cannot be used in a pipeline", stage[0]);
+                } else {
+                    fd_println!(STDERR, "{}: command not found", stage[0]);
+                }
+                return;
+            }
+        }
+        run_pipeline(&stages);
+    }
+
+    /// 执行内建命令，不是内建命令时返回 false
+    fn run_builtin(&mut self, argv: &[&str]) -> bool {
+        let args = &argv[1..];
+        match argv[0] {
+            "help" => {
+                for (name, help) in BUILTINS {
+                    fd_println!(STDOUT, "  {:<10}{}", name, help);
+                }
+                fd_println!(
+                    STDOUT,
+                    "programs can be chained with `|`, e.g. `cat /proc/tasks | wc`"
+                );
+            }
+            "ps" => cat_file("/proc/tasks"),
+            "times" => times(),
+            "meminfo" => cat_file("/proc/meminfo"),
+            "kill" => kill(args),
+            "fg" => match args.first().and_then(|id| id.parse::<usize>().ok()) {
+                Some(id) => {
+                    if sys_kill(id, Signal::SIGCONT as usize) < 0 {
+                        fd_println!(STDERR, "fg: no such task {}", id);
+                    } else {
+                        wait_foreground(&[id]);
+                    }
+                }
+                None => fd_println!(STDERR, "usage: fg <id>"),
+            },
+            "free" => free(),
+            "sleep" => match args.first().and_then(|ms| ms.parse::<u64>().ok()) {
+                Some(ms) => {
+                    sys_nanosleep(&TimeSpec::from_ns(ms * 1_000_000), None);
+                }
+                None => fd_println!(STDERR, "usage: sleep <ms>"),
+            },
+            "uptime" => {
+                if let Ok(data) = read_file("/proc/uptime") {
+                    let secs = String::from_utf8_lossy(&data);
+                    fd_println!(STDOUT, "up {} s", secs.trim());
+                }
+            }
+            "date" => date(),
+            "alarm" => match args.first().and_then(|secs| secs.parse::<usize>().ok()) {
+                Some(secs) => alarm(secs),
+                None => fd_println!(STDERR, "usage: alarm <secs>"),
+            },
+            "ls" => {
+                let path = args.first().copied().unwrap_or("/");
+                cat_file(path);
+            }
+            "history" => {
+                for (i, line) in self.history.iter().enumerate() {
+                    fd_println!(STDOUT, "{:>4}  {}", i + 1, line);
+                }
+            }
+            "dmesg" => dmesg(args),
+            "profile" => profile(args),
+            "clear" => fd_print!(STDOUT, "\x1b[2J\x1b[H"),
+            "sync" => crate::userlib::syscall::sys_sync(),
+            "reboot" => sys_reboot(),
+            "shutdown" => sys_shutdown(),
+            "programs" => {
+                for (name, _) in PROGRAMS.iter() {
+                    fd_println!(STDOUT, "  {}", name);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+fn cat_file(path: &str) {
+    match read_file(path) {
+        Ok(data) => {
+            write_all(STDOUT, &data);
+        }
+        Err(err) => fd_println!(STDERR, "{}: error {}", path, err),
+    }
+}
+
+fn dmesg(args: &[&str]) {
+    match args {
+        [] | ["-c"] => {
+            let size = sys_syslog(SYSLOG_ACTION_SIZE_BUFFER, core::ptr::null_mut(), 0);
+            let mut buf = vec![0u8; size.max(0) as usize];
+            let count = sys_syslog(SYSLOG_ACTION_READ_ALL, buf.as_mut_ptr(), buf.len());
+            if count > 0 {
+                write_all(STDOUT, &buf[..count as usize]);
+            }
+            if args == ["-c"] {
+                sys_syslog(SYSLOG_ACTION_CLEAR, core::ptr::null_mut(), 0);
+            }
+        }
+        ["-C"] => {
+            sys_syslog(SYSLOG_ACTION_CLEAR, core::ptr::null_mut(), 0);
+        }
+        ["-n", level] => {
+            let level = level.parse::<usize>().unwrap_or(0);
+            if sys_syslog(SYSLOG_ACTION_CONSOLE_LEVEL, core::ptr::null_mut(), level) < 0 {
+                fd_println!(STDERR, "dmesg: level must be 1 (error) to 5 (trace)");
+            }
+        }
+        _ => fd_println!(STDERR, "usage: dmesg [-c | -C | -n <level>]"),
+    }
+}
+
+/// 控制 /dev/profile。dump 读出的采样交给宿主机上的 profile_fold.sh 处理
+fn profile(args: &[&str]) {
+    let command = match args {
+        ["start"] => Some((PROFILE_START, 0)),
+        ["start", hz] => match hz.parse::<usize>() {
+            Ok(hz) => Some((PROFILE_START, hz)),
+            Err(_) => None,
+        },
+        ["stop"] => Some((PROFILE_STOP, 0)),
+        ["dump"] => {
+            cat_file("/dev/profile");
+            return;
+        }
+        _ => None,
+    };
+    let Some((cmd, arg)) = command else {
+        fd_println!(STDERR, "usage: profile start [hz] | stop | dump");
+        return;
+    };
+    let fd = sys_open("/dev/profile");
+    if fd < 0 {
+        fd_println!(STDERR, "profile: cannot open /dev/profile");
+        return;
+    }
+    if sys_ioctl(fd as usize, cmd, arg) < 0 {
+        fd_println!(
+            STDERR,
+            "profile: rate must be a multiple of {} Hz, at most {} Hz",
+            SCHED_HZ,
+            MAX_PROFILE_HZ
+        );
+    }
+    sys_close(fd as usize);
+}
+
+fn parse_signal(name: &str) -> Option<Signal> {
+    let name = name.strip_prefix("SIG").unwrap_or(name);
+    Signal::from_name(name).or_else(|| name.parse().ok().and_then(Signal::from_num))
+}
+
+fn kill(args: &[&str]) {
+    let (signal, ids) = match args.first().and_then(|arg| arg.strip_prefix('-')) {
+        Some(name) => match parse_signal(name) {
+            Some(signal) => (signal, &args[1..]),
+            None => {
+                fd_println!(STDERR, "kill: unknown signal {}", name);
+                return;
+            }
+        },
+        None => (Signal::SIGTERM, args),
+    };
+    if ids.is_empty() {
+        fd_println!(STDERR, "usage: kill [-SIGNAL] <id>...");
+        return;
+    }
+    for id in ids {
+        match id.parse::<usize>() {
+            Ok(task_id) if sys_kill(task_id, signal as usize) >= 0 => {}
+            _ => fd_println!(STDERR, "kill: no such task {}", id),
+        }
+    }
+}
+
+/// 与 bash 的 times 类似，打印 shell 自身的用户态和内核态时间以及上下文切换次数
+fn times() {
+    let mut usage = Rusage::default();
+    if sys_getrusage(RUSAGE_SELF, &mut usage) < 0 {
+        fd_println!(STDERR, "times: getrusage failed");
+        return;
+    }
+    fd_println!(
+        STDOUT,
+        "user {}.{:06}s  sys {}.{:06}s  switches {} voluntary, {} involuntary",
+        usage.utime.sec,
+        usage.utime.usec,
+        usage.stime.sec,
+        usage.stime.usec,
+        usage.nvcsw,
+        usage.nivcsw
+    );
+}
+
+fn date() {
+    let mut tv = TimeVal::default();
+    if sys_gettimeofday(&mut tv) < 0 {
+        fd_println!(STDERR, "date: gettimeofday failed");
+        return;
+    }
+    fd_println!(STDOUT, "{}", DateTime::from_unix_secs(tv.sec as u64));
+}
+
+/// 通过 /dev/rtc 设置 secs 秒后的闹钟，阻塞到闹钟响起
+fn alarm(secs: usize) {
+    let mut now = TimeSpec::default();
+    sys_clock_gettime(CLOCK_REALTIME, &mut now);
+    let fd = sys_open("/dev/rtc");
+    if fd < 0 {
+        fd_println!(STDERR, "alarm: cannot open /dev/rtc");
+        return;
+    }
+    let fd = fd as usize;
+    let mut count = [0u8; 8];
+    if sys_ioctl(fd, RTC_ALARM_SET, now.sec + secs) < 0 || sys_read_file(fd, &mut count) < 0 {
+        fd_println!(STDERR, "alarm: failed");
+    } else {
+        fd_println!(
+            STDOUT,
+            "alarm rang at {}",
+            DateTime::from_unix_secs((now.sec + secs) as u64)
+        );
+    }
+    sys_close(fd);
+}
+
+/// 根据 /proc/meminfo 打印内存概况
+fn free() {
+    let Ok(data) = read_file("/proc/meminfo") else {
+        return;
+    };
+    let text = String::from_utf8_lossy(&data);
+    let field = |key: &str| -> usize {
+        text.lines()
+            .find_map(|line| line.strip_prefix(key))
+            .and_then(|rest| rest.trim().trim_end_matches("kB").trim().parse().ok())
+            .unwrap_or(0)
+    };
+    let (total, free, buffers) = (field("MemTotal:"), field("MemFree:"), field("Buffers:"));
+    fd_println!(
+        STDOUT,
+        "{:>12}{:>12}{:>12}{:>12}",
+        "total",
+        "used",
+        "free",
+        "buffers"
+    );
+    fd_println!(
+        STDOUT,
+        "Mem:{:>8}{:>12}{:>12}{:>12}",
+        total,
+        total.saturating_sub(free),
+        free,
+        buffers
+    );
+}
+
+/// 启动一条管道命令，相邻两段之间用匿名管道连接，最后一段输出到终端
+fn run_pipeline(stages: &[Vec<&str>]) {
+    let mut task_ids = Vec::new();
+    let mut stdin = STDIN;
+    for (i, stage) in stages.iter().enumerate() {
+        let (stdout, next_stdin) = if i + 1 == stages.len() {
+            (STDOUT, STDIN)
+        } else {
+            let mut fds = [0usize; 2];
+            if sys_pipe(&mut fds) < 0 {
+                fd_println!(STDERR, "shell: cannot create pipe");
+                if stdin != STDIN {
+                    sys_close(stdin);
+                }
+                break;
+            }
+            (fds[1], fds[0])
+        };
+        let args = stage[1..].join(" ");
+        let ret = sys_spawn(stage[0], &args, stdin, stdout);
+        // 子任务已经持有这些管道端，shell 自己的副本要关掉，否则对端永远等不到 EOF
+        if stdin != STDIN {
+            sys_close(stdin);
+        }
+        if stdout != STDOUT {
+            sys_close(stdout);
+        }
+        if ret < 0 {
+            fd_println!(STDERR, "{}: cannot start, error {}", stage[0], ret);
+            if next_stdin != STDIN {
+                sys_close(next_stdin);
+            }
+            break;
+        }
+        task_ids.push(ret as usize);
+        stdin = next_stdin;
+    }
+    wait_foreground(&task_ids);
+}
+
+/// 把终端交给最后一个任务并等待整条管道结束
+fn wait_foreground(task_ids: &[usize]) {
+    let Some((&last, rest)) = task_ids.split_last() else {
+        return;
+    };
+    sys_ioctl(STDIN, TCSETS, TtyMode::all().bits());
+    sys_ioctl(STDIN, TIOCSPGRP, last);
+    if sys_wait_task(last) == 1 {
+        fd_println!(STDOUT, "\n[{}] Stopped", last);
+        return;
+    }
+    // 最后一段已经结束，前面各段的输出没有人读了，直接结束它们
+    for &task_id in rest {
+        sys_kill(task_id, Signal::SIGKILL as usize);
+        sys_wait_task(task_id);
+    }
+}
@@ -0,0 +1,340 @@
+// src/userlib/sync.rs
+//! 基于 futex 的 Mutex、Condvar、Semaphore 和 RwLock。没有竞争时只有原子操作，不进入内核；
+//! 只有真的需要睡眠或唤醒别人时才发起 futex 系统调用。
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use crate::{
+    syslib::{
+        errno::Errno,
+        time::{CLOCK_MONOTONIC, TimeSpec},
+    },
+    userlib::syscall::{sys_clock_gettime, sys_futex_requeue, sys_futex_wait, sys_futex_wake},
+};
+
+fn now_ns() -> u64 {
+    let mut now = TimeSpec::default();
+    sys_clock_gettime(CLOCK_MONOTONIC, &mut now);
+    now.to_ns().unwrap_or(0)
+}
+
+/// futex 等待者计数，没有等待者时释放不需要系统调用
+struct Waiters(AtomicU32);
+
+impl Waiters {
+    const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    /// word 仍等于 expected 时睡眠，deadline 是单调时钟的纳秒数。返回 false 表示到了截止时间
+    fn wait(&self, word: &AtomicU32, expected: u32, deadline: Option<u64>) -> bool {
+        let timeout = match deadline {
+            Some(deadline) => match deadline.checked_sub(now_ns()) {
+                Some(left) if left > 0 => Some(TimeSpec::from_ns(left)),
+                _ => return false,
+            },
+            None => None,
+        };
+        // 先登记再比较：释放者改完状态字后一定能看到这里的计数
+        self.0.fetch_add(1, Ordering::SeqCst);
+        let ret = sys_futex_wait(word, expected, timeout.as_ref());
+        self.0.fetch_sub(1, Ordering::SeqCst);
+        ret != -(Errno::ETIMEDOUT as isize)
+    }
+
+    fn wake(&self, word: &AtomicU32, count: usize) {
+        if self.0.load(Ordering::SeqCst) > 0 {
+            sys_futex_wake(word, count);
+        }
+    }
+}
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// 已上锁，且可能有任务在等待，解锁时需要唤醒
+const CONTENDED: u32 = 2;
+
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.try_lock().unwrap_or_else(|| self.lock_contended())
+    }
+
+    /// 一旦等待过就按有竞争处理：拿到锁时状态是 CONTENDED，解锁时会唤醒下一个等待者
+    fn lock_contended(&self) -> MutexGuard<'_, T> {
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            sys_futex_wait(&self.state, CONTENDED, None);
+        }
+        MutexGuard { mutex: self }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            sys_futex_wake(&self.state, 1);
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// 条件变量。每次通知递增序号，等待者在序号改变前睡眠，放开锁和睡眠之间的通知不会丢失。
+/// 可能虚假唤醒，调用者需要在循环中重新检查条件
+pub struct Condvar {
+    seq: AtomicU32,
+    /// 等待时使用的 Mutex 的状态字地址，notify_all 把等待者直接移到这个字上
+    mutex: AtomicUsize,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            mutex: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_inner(guard, None).0
+    }
+
+    /// 第二个返回值为 true 表示超时
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout_ms: usize,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let timeout = TimeSpec {
+            sec: timeout_ms / 1000,
+            nsec: timeout_ms % 1000 * 1_000_000,
+        };
+        self.wait_inner(guard, Some(&timeout))
+    }
+
+    fn wait_inner<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<&TimeSpec>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let mutex = guard.mutex;
+        self.mutex
+            .store(&mutex.state as *const AtomicU32 as usize, Ordering::Relaxed);
+        let seq = self.seq.load(Ordering::Relaxed);
+        drop(guard);
+        let ret = sys_futex_wait(&self.seq, seq, timeout);
+        (mutex.lock_contended(), ret == -(Errno::ETIMEDOUT as isize))
+    }
+
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        sys_futex_wake(&self.seq, 1);
+    }
+
+    /// 只唤醒一个等待者，其余的移到 Mutex 上，由前一个持有者解锁时逐个唤醒，避免一起醒来争抢
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        let mutex = self.mutex.load(Ordering::Relaxed);
+        if mutex == 0 {
+            return;
+        }
+        let target = unsafe { &*(mutex as *const AtomicU32) };
+        sys_futex_requeue(&self.seq, 1, usize::MAX, target);
+    }
+}
+
+/// 计数信号量
+pub struct Semaphore {
+    permits: AtomicU32,
+    waiters: Waiters,
+}
+
+impl Semaphore {
+    pub const fn new(permits: u32) -> Self {
+        Self {
+            permits: AtomicU32::new(permits),
+            waiters: Waiters::new(),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.waiters.wait(&self.permits, 0, None);
+        }
+    }
+
+    /// 超过 timeout_ms 毫秒仍没有许可时返回 false。被提前唤醒后重新等待时截止时间不变
+    pub fn acquire_timeout(&self, timeout_ms: usize) -> bool {
+        let deadline = now_ns().saturating_add(timeout_ms as u64 * 1_000_000);
+        loop {
+            if self.try_acquire() {
+                return true;
+            }
+            if !self.waiters.wait(&self.permits, 0, Some(deadline)) {
+                return self.try_acquire();
+            }
+        }
+    }
+
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::SeqCst);
+        self.waiters.wake(&self.permits, 1);
+    }
+}
+
+/// 状态字为读者个数，WRITER 表示有写者持有
+const WRITER: u32 = u32::MAX;
+
+/// 读写锁。不偏向写者，读者持续不断时写者可能一直等待
+pub struct RwLock<T> {
+    state: AtomicU32,
+    waiters: Waiters,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            waiters: Waiters::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |readers| {
+                (readers < WRITER - 1).then_some(readers + 1)
+            })
+            .ok()
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            self.waiters.wait(&self.state, WRITER, None);
+        }
+    }
+
+    /// 写者在状态字改变前睡眠，读者个数变化而锁仍被占用时会醒来重试
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            let state = self.state.load(Ordering::Relaxed);
+            if state != 0 {
+                self.waiters.wait(&self.state, state, None);
+            }
+        }
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // 最后一个读者离开时唤醒等待的写者
+        if self.lock.state.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.lock.waiters.wake(&self.lock.state, usize::MAX);
+        }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::SeqCst);
+        self.lock.waiters.wake(&self.lock.state, usize::MAX);
+    }
+}
@@ -1,10 +1,57 @@
-use core::arch::asm;
+use core::{
+    arch::{asm, naked_asm},
+    sync::atomic::AtomicU32,
+};
+
+use crate::{
+    syslib::{
+        syscall::{Rusage, TimeVal},
+        time::TimeSpec,
+    },
+    task::{
+        futex::{FUTEX_PRIVATE_FLAG, FUTEX_REQUEUE, FUTEX_WAIT, FUTEX_WAKE},
+        ipc::{GRANTED, Message},
+        signal::{SigAction, SigSet},
+    },
+};
 
 const SYS_WRITE_BYTE: usize = 1;
 const SYS_SLEEP: usize = 17;
 const SYS_READ: usize = 27;
 const SYS_TASK_EXIT: usize = 9;
 const SYS_QUIT: usize = 10;
+const SYS_IOCTL: usize = 29;
+const SYS_OPEN: usize = 56;
+const SYS_CLOSE: usize = 57;
+const SYS_PIPE: usize = 59;
+const SYS_READ_FILE: usize = 63;
+const SYS_WRITE_FILE: usize = 64;
+const SYS_SYNC: usize = 81;
+const SYS_FUTEX: usize = 98;
+const SYS_NANOSLEEP: usize = 101;
+const SYS_CLOCK_GETTIME: usize = 113;
+const SYS_SYSLOG: usize = 116;
+const SYS_KILL: usize = 129;
+const SYS_RT_SIGACTION: usize = 134;
+const SYS_RT_SIGPROCMASK: usize = 135;
+const SYS_RT_SIGRETURN: usize = 139;
+const SYS_REBOOT: usize = 142;
+const SYS_GETRUSAGE: usize = 165;
+const SYS_GETTIMEOFDAY: usize = 169;
+const SYS_SPAWN: usize = 220;
+const SYS_WAIT_TASK: usize = 260;
+const SYS_IDLE_WAIT: usize = 261;
+const SYS_RUN_EXECUTOR: usize = 264;
+const SYS_IPC_CREATE: usize = 265;
+const SYS_IPC_CLOSE: usize = 266;
+const SYS_IPC_SEND: usize = 267;
+const SYS_IPC_RECV: usize = 268;
+const SYS_IPC_CALL: usize = 269;
+const SYS_IPC_REPLY: usize = 270;
+const SYS_SHM_CREATE: usize = 271;
+const SYS_SHM_MAP: usize = 272;
+const SYS_SHM_UNMAP: usize = 273;
+const SYS_SHM_DESTROY: usize = 274;
 pub fn sys_sleep(ms: usize) {
     unsafe {
         asm!(
@@ -43,11 +90,25 @@ pub fn sys_write_byte(byte: u8) {
         );
     }
 }
-pub fn sys_task_exit() {
+/// 结束当前任务，code 为交给 JoinHandle 的退出码
+pub fn sys_task_exit(code: usize) {
     unsafe {
         asm!(
             "ecall",
             in("a7") SYS_TASK_EXIT,
+            in("a0") code,
+            options(nostack)
+        );
+    }
+}
+
+/// 只能由 idle 任务调用，没有任务可运行时在内核中等待下一个中断
+pub fn sys_idle_wait() {
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_IDLE_WAIT,
+            lateout("a0") _,
             options(nostack)
         );
     }
@@ -62,3 +123,517 @@ pub fn sys_shutdown() {
         );
     }
 }
+
+pub fn sys_sync() {
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_SYNC,
+            options(nostack)
+        );
+    }
+}
+
+pub fn sys_read_file(fd: usize, buf: &mut [u8]) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_READ_FILE,
+            inlateout("a0") fd => ret,
+            in("a1") buf.as_mut_ptr(),
+            in("a2") buf.len(),
+            options(nostack)
+        );
+    }
+    ret
+}
+
+pub fn sys_write_file(fd: usize, buf: &[u8]) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_WRITE_FILE,
+            inlateout("a0") fd => ret,
+            in("a1") buf.as_ptr(),
+            in("a2") buf.len(),
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 按绝对路径打开文件，成功时返回文件描述符
+pub fn sys_open(path: &str) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_OPEN,
+            inlateout("a0") path.as_ptr() => ret,
+            in("a1") path.len(),
+            options(nostack)
+        );
+    }
+    ret
+}
+
+pub fn sys_close(fd: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_CLOSE,
+            inlateout("a0") fd => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 创建匿名管道，fds[0] 为读端，fds[1] 为写端
+pub fn sys_pipe(fds: &mut [usize; 2]) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_PIPE,
+            inlateout("a0") fds.as_mut_ptr() => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_IOCTL,
+            inlateout("a0") fd => ret,
+            in("a1") cmd,
+            in("a2") arg,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 内核日志操作，action 见 console::log 中的 SYSLOG_ACTION_*。
+/// 读日志时 buf/len 为用户缓冲区，设置控制台级别时 len 为级别
+pub fn sys_syslog(action: usize, buf: *mut u8, len: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_SYSLOG,
+            inlateout("a0") action => ret,
+            in("a1") buf,
+            in("a2") len,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// who 为 syslib::syscall 中的 RUSAGE_*
+pub fn sys_getrusage(who: usize, usage: &mut Rusage) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_GETRUSAGE,
+            inlateout("a0") who => ret,
+            in("a1") usage as *mut Rusage,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+pub fn sys_kill(task_id: usize, signal: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_KILL,
+            inlateout("a0") task_id => ret,
+            in("a1") signal,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 设置信号的处理方式，old 不为空时写回原来的处理方式
+pub fn sys_sigaction(
+    signal: usize,
+    action: Option<&SigAction>,
+    old: Option<&mut SigAction>,
+) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_RT_SIGACTION,
+            inlateout("a0") signal => ret,
+            in("a1") action.map_or(core::ptr::null(), |action| action as *const SigAction),
+            in("a2") old.map_or(core::ptr::null_mut(), |old| old as *mut SigAction),
+            in("a3") size_of::<SigSet>(),
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// how 为 task::signal 中的 SIG_BLOCK、SIG_UNBLOCK 或 SIG_SETMASK，old 不为空时写回原来的屏蔽字
+pub fn sys_sigprocmask(how: usize, set: Option<&SigSet>, old: Option<&mut SigSet>) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_RT_SIGPROCMASK,
+            inlateout("a0") how => ret,
+            in("a1") set.map_or(core::ptr::null(), |set| set as *const SigSet),
+            in("a2") old.map_or(core::ptr::null_mut(), |old| old as *mut SigSet),
+            in("a3") size_of::<SigSet>(),
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 信号处理函数的返回地址。处理函数返回时 sp 正好指向内核压入的信号帧，
+/// 这里不能有函数序言改动 sp，只能直接发起 rt_sigreturn
+#[unsafe(naked)]
+pub extern "C" fn sigreturn_trampoline() -> ! {
+    naked_asm!("li a7, {nr}", "ecall", nr = const SYS_RT_SIGRETURN)
+}
+
+pub fn sys_reboot() {
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_REBOOT,
+            options(nostack)
+        );
+    }
+}
+
+/// 按名字启动内置程序，stdin/stdout 为交给新任务的文件描述符，成功时返回任务 id
+pub fn sys_spawn(name: &str, args: &str, stdin: usize, stdout: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_SPAWN,
+            inlateout("a0") name.as_ptr() => ret,
+            in("a1") name.len(),
+            in("a2") args.as_ptr(),
+            in("a3") args.len(),
+            in("a4") stdin,
+            in("a5") stdout,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 等待任务结束或停止，结束返回 0，停止返回 1
+pub fn sys_wait_task(task_id: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_WAIT_TASK,
+            inlateout("a0") task_id => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+pub fn sys_clock_gettime(clock_id: usize, ts: &mut TimeSpec) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_CLOCK_GETTIME,
+            inlateout("a0") clock_id => ret,
+            in("a1") ts as *mut TimeSpec,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+pub fn sys_gettimeofday(tv: &mut TimeVal) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_GETTIMEOFDAY,
+            inlateout("a0") tv as *mut TimeVal => ret,
+            in("a1") 0,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 被信号提前唤醒时返回 -EINTR，rem 不为 None 时写入剩余时间
+pub fn sys_nanosleep(req: &TimeSpec, rem: Option<&mut TimeSpec>) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_NANOSLEEP,
+            inlateout("a0") req as *const TimeSpec => ret,
+            in("a1") rem.map_or(core::ptr::null_mut(), |ts| ts as *mut TimeSpec),
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 在内核中轮询一批就绪的 future，返回轮询的个数。没有就绪的 future 时睡眠，只由执行器的 worker 任务调用
+pub fn sys_run_executor() -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_RUN_EXECUTOR,
+            lateout("a0") ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 创建 IPC 端点，返回句柄
+pub fn sys_ipc_create() -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_IPC_CREATE,
+            lateout("a0") ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 关闭端点，阻塞在上面收发的任务返回 -EPIPE
+pub fn sys_ipc_close(endpoint: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_IPC_CLOSE,
+            inlateout("a0") endpoint => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 把 message 发到端点上，阻塞到有任务接收，成功返回 0。
+/// endpoint 带上 IPC_GRANT 时 message[GRANT_WORD] 处的共享内存映射移交给接收者
+pub fn sys_ipc_send(endpoint: usize, message: &Message) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_IPC_SEND,
+            inlateout("a0") message[0] => _,
+            in("a1") message[1],
+            in("a2") message[2],
+            in("a3") message[3],
+            in("a4") message[4],
+            in("a5") message[5],
+            inlateout("a6") endpoint => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 从端点接收一条消息放进 message，阻塞到有任务发送，返回发送者的任务 id。
+/// 消息带着移交过来的映射时 granted 置为 true，映射地址在 message[GRANT_WORD]
+pub fn sys_ipc_recv(endpoint: usize, message: &mut Message, granted: &mut bool) -> isize {
+    let (ret, sender): (isize, isize);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a7") SYS_IPC_RECV => sender,
+            lateout("a0") message[0],
+            lateout("a1") message[1],
+            lateout("a2") message[2],
+            lateout("a3") message[3],
+            lateout("a4") message[4],
+            lateout("a5") message[5],
+            inlateout("a6") endpoint => ret,
+            options(nostack)
+        );
+    }
+    *granted = ret == GRANTED as isize;
+    if ret < 0 { ret } else { sender }
+}
+
+/// 发送 message 并等待接收者回复，回复写回 message，成功返回 0，回复带着映射时返回 GRANTED。
+/// endpoint 可以带上 IPC_GRANT，与 sys_ipc_send 相同
+pub fn sys_ipc_call(endpoint: usize, message: &mut Message) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_IPC_CALL,
+            inlateout("a0") message[0],
+            inlateout("a1") message[1],
+            inlateout("a2") message[2],
+            inlateout("a3") message[3],
+            inlateout("a4") message[4],
+            inlateout("a5") message[5],
+            inlateout("a6") endpoint => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 以 message 回复最近一次收到的 call，成功返回 0。flags 为 IPC_GRANT 时移交 message[GRANT_WORD] 处的映射
+pub fn sys_ipc_reply(message: &Message, flags: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_IPC_REPLY,
+            in("a0") message[0],
+            in("a1") message[1],
+            in("a2") message[2],
+            in("a3") message[3],
+            in("a4") message[4],
+            in("a5") message[5],
+            inlateout("a6") flags => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 创建 size 字节的共享内存区域，返回句柄
+pub fn sys_shm_create(size: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_SHM_CREATE,
+            inlateout("a0") size => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// prot 为 mm::shm 中的 PROT_READ 或 PROT_READ | PROT_WRITE，返回映射的起始地址
+pub fn sys_shm_map(handle: usize, prot: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_SHM_MAP,
+            inlateout("a0") handle => ret,
+            in("a1") prot,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 撤销当前任务 sys_shm_map 返回的映射，区域的最后一个映射撤销后它的页被释放
+pub fn sys_shm_unmap(addr: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_SHM_UNMAP,
+            inlateout("a0") addr => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 销毁区域，之后不能再映射。没有映射时立即释放，否则在最后一个映射撤销后释放
+pub fn sys_shm_destroy(handle: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_SHM_DESTROY,
+            inlateout("a0") handle => ret,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// word 的值等于 expected 时睡眠，直到被 sys_futex_wake 唤醒或超过 timeout。
+/// 被唤醒返回 0，值不同返回 -EAGAIN，超时返回 -ETIMEDOUT
+pub fn sys_futex_wait(word: &AtomicU32, expected: u32, timeout: Option<&TimeSpec>) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_FUTEX,
+            inlateout("a0") word as *const AtomicU32 => ret,
+            in("a1") FUTEX_WAIT | FUTEX_PRIVATE_FLAG,
+            in("a2") expected,
+            in("a3") timeout.map_or(core::ptr::null(), |ts| ts as *const TimeSpec),
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 唤醒在 word 上等待的最多 count 个任务，返回唤醒的个数
+pub fn sys_futex_wake(word: &AtomicU32, count: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_FUTEX,
+            inlateout("a0") word as *const AtomicU32 => ret,
+            in("a1") FUTEX_WAKE | FUTEX_PRIVATE_FLAG,
+            in("a2") count,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// 唤醒 word 上最多 nr_wake 个任务，把最多 nr_requeue 个剩下的等待者移到 target 上
+pub fn sys_futex_requeue(
+    word: &AtomicU32,
+    nr_wake: usize,
+    nr_requeue: usize,
+    target: &AtomicU32,
+) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            in("a7") SYS_FUTEX,
+            inlateout("a0") word as *const AtomicU32 => ret,
+            in("a1") FUTEX_REQUEUE | FUTEX_PRIVATE_FLAG,
+            in("a2") nr_wake,
+            in("a3") nr_requeue,
+            in("a4") target as *const AtomicU32,
+            options(nostack)
+        );
+    }
+    ret
+}
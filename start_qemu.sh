@@ -3,7 +3,7 @@
 KERNEL="target/riscv64gc-unknown-none-elf/release/charlotte_os"
 BIOS="rustsbi.bin"
 
-cargo build --release
+./build.sh
 if [ $? -ne 0 ]; then
     echo "编译失败，调试会话中止。"
     exit 1